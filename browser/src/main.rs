@@ -7,7 +7,7 @@ use pixels::{Pixels, SurfaceTexture};
 use rusttype::{Scale, point};
 
 use engine::parser::html::tree_builder::HtmlParser;
-use engine::style::{Stylesheet, Style, Selector, Viewport};
+use engine::style::{load_stylesheets, Stylesheet, Viewport};
 use engine::layout::LayoutEngine;
 use engine::dom::{NodeType, Dom, NodeId};
 use engine::font::FontManager;
@@ -75,22 +75,8 @@ fn find_anchor_recursive(
 
         // If this is a text node, check if any parent is an anchor
         if let NodeType::Text(_) = &dom.nodes[layout.node_id].node_type {
-            // Walk up the DOM tree to find an anchor parent
-            let mut current_node_id = layout.node_id;
-            loop {
-                if let Some(parent_id) = dom.nodes[current_node_id].parent {
-                    if let NodeType::Element(elem) = &dom.nodes[parent_id].node_type {
-                        if elem.tag_name == "a" {
-                            // Found an anchor parent!
-                            if let Some(href) = elem.attributes.iter().find(|(k, _)| k == "href").map(|(_, v)| v.clone()) {
-                                return Some(href);
-                            }
-                        }
-                    }
-                    current_node_id = parent_id;
-                } else {
-                    break;
-                }
+            if let Some(href) = enclosing_anchor_href(dom, layout.node_id) {
+                return Some(href);
             }
         }
 
@@ -104,7 +90,7 @@ fn find_anchor_recursive(
         // If layout tree is incomplete, also search the DOM tree for anchors
         // This handles cases where layout engine doesn't create layout boxes for all elements
         for &child_id in &dom.nodes[layout.node_id].children {
-            if let Some(href) = find_anchor_in_dom(dom, child_id, x, y) {
+            if let Some(href) = find_anchor_in_dom(dom, child_id) {
                 return Some(href);
             }
         }
@@ -114,49 +100,32 @@ fn find_anchor_recursive(
 }
 
 // Search through DOM for anchors, checking if text nodes are at the click position
-fn find_anchor_in_dom(dom: &Dom, node_id: NodeId, x: f32, y: f32) -> Option<String> {
-    // Check if this node or any parent is an anchor
-    if let NodeType::Text(_) = &dom.nodes[node_id].node_type {
-        // Walk up to find anchor parent
-        let mut current_node_id = node_id;
-        loop {
-            if let Some(parent_id) = dom.nodes[current_node_id].parent {
-                if let NodeType::Element(elem) = &dom.nodes[parent_id].node_type {
-                    if elem.tag_name == "a" {
-                        if let Some(href) = elem.attributes.iter().find(|(k, _)| k == "href").map(|(_, v)| v.clone()) {
-                            return Some(href);
-                        }
-                    }
-                }
-                current_node_id = parent_id;
-            } else {
-                break;
-            }
-        }
-    }
-    
-    // Recurse into children
-    for &child_id in &dom.nodes[node_id].children {
-        if let Some(href) = find_anchor_in_dom(dom, child_id, x, y) {
-            return Some(href);
-        }
-    }
-    
-    None
+fn find_anchor_in_dom(dom: &Dom, node_id: NodeId) -> Option<String> {
+    std::iter::once(node_id)
+        .chain(dom.descendants(node_id))
+        .filter(|&id| matches!(dom.nodes[id].node_type, NodeType::Text(_)))
+        .find_map(|id| enclosing_anchor_href(dom, id))
+}
+
+// The href of the nearest <a> ancestor that has one
+fn enclosing_anchor_href(dom: &Dom, node_id: NodeId) -> Option<String> {
+    dom.ancestors(node_id)
+        .filter(|&id| dom.element(id).is_some_and(|el| el.tag_name == "a"))
+        .find_map(|id| dom.get_attribute(id, "href"))
+        .map(str::to_string)
 }
 
-// Helper function to load and parse a page given a URL
 fn load_page(url: &str, network_manager: &NetworkManager) -> (Arc<Dom>, Stylesheet) {
     // Set the document URL for resolving relative URLs
     network_manager.set_document_url(url);
     
     // Fetch HTML from a web URL
-    let html = match fetch_html(url) {
-        Ok(content) => content,
+    let dom = match fetch_html(url) {
+        Ok(parser) => parser.finish(),
         Err(e) => {
             eprintln!("Failed to fetch HTML from {}: {}", url, e);
             eprintln!("Using fallback HTML");
-            format!(
+            let html = format!(
                 r#"
                 <!DOCTYPE html>
                 <html>
@@ -171,11 +140,10 @@ fn load_page(url: &str, network_manager: &NetworkManager) -> (Arc<Dom>, Styleshe
                 </html>
                 "#,
                 url, e
-            )
+            );
+            HtmlParser::new(&html).parse()
         }
     };
-
-    let dom = HtmlParser::new(&html).parse();
     
     // Extract and set the <base href> if present
     if let Some(base_href) = engine::parser::html::extract_base_href(&dom) {
@@ -185,40 +153,15 @@ fn load_page(url: &str, network_manager: &NetworkManager) -> (Arc<Dom>, Styleshe
     
     let dom = Arc::new(dom);
 
-    // Extract CSS from <style> tags in the DOM
-    let css = extract_css_from_dom(&*dom, dom.root());
-    
-    log(&format!("Extracted CSS from <style> tags: {} bytes", css.len()));
-
-    // --- CSS (parse and apply) ---
-    let mut stylesheet = Stylesheet::new();
-    
-    // Parse CSS from style tags and convert to stylesheet rules
-    if !css.is_empty() {
-        log(&format!("CSS extracted: {} bytes", css.len()));
-        let mut css_tokenizer = engine::parser::css::CssTokenizer::new(&css);
-        let tokens = css_tokenizer.tokenize();
-        let mut css_parser = engine::parser::css::CssParser::new(tokens);
-        let css_items = css_parser.parse();
-        log(&format!("=== Parsed {} CSS items from <style> tags ===", css_items.len()));
-        
-        // Convert CSS rules to stylesheet rules
-        for item in css_items {
-            if let engine::parser::css::parser::CssItem::Rule(rule) = item {
-                let selector = convert_css_selector(&rule.selector);
-                let mut style = Style::new();
-                
-                for decl in rule.declarations {
-                    style.properties.insert(decl.property.clone(), decl.value.clone());
-                }
-                
-                stylesheet.add_rule(selector, style);
-            }
-        }
-        log(&format!("Stylesheet now has {} rules", stylesheet.rules.len()));
-    }
+    // Load <style>, <link rel="stylesheet"> and their @imports in cascade order
+    let stylesheet = load_stylesheets(&dom, url, network_manager);
+    log(&format!(
+        "Stylesheet has {} rules and {} media rules",
+        stylesheet.rules.len(),
+        stylesheet.media_rules.len()
+    ));
     
-    // Note: Default styles are now handled by the engine's apply_default_styles() method
+    // Note: Default styles are now user agent declarations in the engine's cascade
     // in style::Stylesheet::compute_style(), so we don't need to add them here
 
     (dom, stylesheet)
@@ -486,7 +429,7 @@ fn draw_text_glyphs(
     let is_italic = layout.style.is_italic();
     let scale = Scale::uniform(font_size);
 
-    if let Some(font) = font_manager.load_font_variant(font_family, is_bold, is_italic) {
+    if let Some(font) = font_manager.load_font_variant(&font_family, is_bold, is_italic) {
         let v_metrics = font.v_metrics(scale);
         let mut x = layout.dimensions.x * scale_factor;
         let y = layout.dimensions.y * scale_factor + v_metrics.ascent;
@@ -547,34 +490,11 @@ fn draw_text_glyphs(
 }
 
 fn extract_title(dom: &engine::dom::Dom) -> String {
-    // Find the title element
-    let title_node = find_title_element(dom, dom.root());
-    if let Some(title_id) = title_node {
-        let title_el = &dom.nodes[title_id];
-        // Get first text child
-        for &child_id in &title_el.children {
-            let child = &dom.nodes[child_id];
-            if let NodeType::Text(text) = &child.node_type {
-                return text.clone();
-            }
-        }
-    }
-    "Grob Browser".to_string()
-}
-
-fn find_title_element(dom: &engine::dom::Dom, node_id: engine::dom::NodeId) -> Option<engine::dom::NodeId> {
-    let node = &dom.nodes[node_id];
-    if let NodeType::Element(el) = &node.node_type {
-        if el.tag_name == "title" {
-            return Some(node_id);
-        }
-    }
-    for &child_id in &node.children {
-        if let Some(found) = find_title_element(dom, child_id) {
-            return Some(found);
-        }
-    }
-    None
+    dom.elements_by_tag_name("title")
+        .next()
+        .map(|id| dom.text_content(id))
+        .filter(|title| !title.is_empty())
+        .unwrap_or_else(|| "Grob Browser".to_string())
 }
 
 // Draw images from img tags
@@ -612,11 +532,9 @@ fn draw_images(frame: &mut [u8], layout: &engine::layout::LayoutBox, dom: &Arc<e
     }
     
     // Also check for CSS background images
-    if let Some(bg) = layout.style.get("background-image").or(layout.style.get("background")) {
-        if let Some(url) = extract_url_from_css_value(bg) {
-            if let Some(img_data) = network.fetch_image(&url) {
-                draw_background_image(frame, layout, &img_data, screen_width, screen_height);
-            }
+    if let Some(url) = layout.style.get_background_image().filter(|url| !url.is_empty() && !url.starts_with("data:")) {
+        if let Some(img_data) = network.fetch_image(url) {
+            draw_background_image(frame, layout, &img_data, screen_width, screen_height);
         }
     }
     
@@ -625,23 +543,6 @@ fn draw_images(frame: &mut [u8], layout: &engine::layout::LayoutBox, dom: &Arc<e
     }
 }
 
-// Extract URL from CSS url(...) value
-fn extract_url_from_css_value(value: &str) -> Option<String> {
-    let value = value.trim().to_lowercase();
-    if let Some(start) = value.find("url(") {
-        let rest = &value[start + 4..];
-        if let Some(end) = rest.find(')') {
-            let url = rest[..end].trim();
-            // Remove quotes if present
-            let url = url.trim_matches(|c| c == '"' || c == '\'');
-            if !url.is_empty() && !url.starts_with("data:") {
-                return Some(url.to_string());
-            }
-        }
-    }
-    None
-}
-
 // Draw a background image
 fn draw_background_image(frame: &mut [u8], layout: &engine::layout::LayoutBox, img: &image::RgbaImage, screen_width: usize, screen_height: usize) {
     let dims = &layout.dimensions;
@@ -748,8 +649,10 @@ fn draw_real_image(frame: &mut [u8], layout: &engine::layout::LayoutBox, img: &i
     eprintln!("Drew real image '{}' ({}x{}) at ({},{})", alt, width, height, x, y);
 }
 
-fn fetch_html(url: &str) -> Result<String, Box<dyn std::error::Error>> {
+/// Fetch a page, parsing its bytes as they arrive
+fn fetch_html(url: &str) -> Result<HtmlParser, Box<dyn std::error::Error>> {
     use reqwest::blocking::Client;
+    use std::io::Read;
     
     let client = Client::builder()
         .timeout(std::time::Duration::from_secs(10))
@@ -757,101 +660,30 @@ fn fetch_html(url: &str) -> Result<String, Box<dyn std::error::Error>> {
     
     eprintln!("Fetching HTML from: {}", url);
     
-    let response = client.get(url).send()?;
+    let mut response = client.get(url).send()?;
     let status = response.status();
     
     if !status.is_success() {
         return Err(format!("{}", status).into());
     }
     
-    let html = response.text()?;
-    eprintln!("Successfully fetched {} bytes from {}", html.len(), url);
-    Ok(html)
-}
-
-fn extract_css_from_dom(dom: &engine::dom::Dom, node_id: engine::dom::NodeId) -> String {
-    log("extract_css_from_dom called");
-    let mut css_content = String::new();
-    
-    fn traverse(dom: &engine::dom::Dom, node_id: engine::dom::NodeId, css: &mut String, depth: usize) {
-        let indent = "  ".repeat(depth);
-        log(&format!("{}traverse node_id: {}", indent, node_id));
-        let node = &dom.nodes[node_id];
-        
-        match &node.node_type {
-            engine::dom::NodeType::Element(elem) => {
-                if elem.tag_name == "style" {
-                    log(&format!("{}FOUND STYLE TAG!", indent));
-                    // Get text content of style tag
-                    for &child_id in &node.children {
-                        let child = &dom.nodes[child_id];
-                        if let engine::dom::NodeType::Text(text) = &child.node_type {
-                            log(&format!("{}  extracting CSS: {}", indent, text));
-                            css.push_str(text);
-                            css.push('\n');
-                        }
-                    }
-                }
-                // Recursively search children
-                for &child_id in &node.children {
-                    traverse(dom, child_id, css, depth + 1);
-                }
-            }
-            _ => {
-                // Recursively search children
-                for &child_id in &node.children {
-                    traverse(dom, child_id, css, depth + 1);
-                }
-            }
-        }
-    }
-    
-    traverse(dom, node_id, &mut css_content, 0);
-    log(&format!("extract_css_from_dom done: {} bytes", css_content.len()));
-    css_content
-}
-
-fn convert_css_selector(css_selector: &engine::parser::css::parser::Selector) -> Selector {
-    use engine::parser::css::parser::Selector as CssSelector;
-    
-    fn extract_tag_and_pseudo(sel: &CssSelector) -> (Option<String>, Option<String>) {
-        match sel {
-            CssSelector::Element(tag) => (Some(tag.clone()), None),
-            CssSelector::PseudoClass(pseudo) => (None, Some(pseudo.clone())),
-            CssSelector::Descendant(parent, child) => {
-                let (p_tag, p_pseudo) = extract_tag_and_pseudo(parent);
-                let (c_tag, c_pseudo) = extract_tag_and_pseudo(child);
-                (c_tag.or(p_tag), c_pseudo.or(p_pseudo))
-            },
-            CssSelector::Child(parent, child) => {
-                let (p_tag, p_pseudo) = extract_tag_and_pseudo(parent);
-                let (c_tag, c_pseudo) = extract_tag_and_pseudo(child);
-                (c_tag.or(p_tag), c_pseudo.or(p_pseudo))
-            },
-            _ => (None, None),
+    // The parser sniffs the encoding from the Content-Type charset and the content
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+    let mut parser = HtmlParser::new_streaming(content_type.as_deref());
+    let mut buffer = [0u8; 16 * 1024];
+    let mut total = 0;
+    loop {
+        let read = response.read(&mut buffer)?;
+        if read == 0 {
+            break;
         }
+        total += read;
+        parser.feed(&buffer[..read]);
     }
-    
-    match css_selector {
-        CssSelector::Element(tag) => Selector::Tag(tag.clone()),
-        CssSelector::Id(id) => Selector::Id(id.clone()),
-        CssSelector::Class(class) => Selector::Class(class.clone()),
-        CssSelector::Descendant(_, _) | CssSelector::Child(_, _) => {
-            let (tag, pseudo) = extract_tag_and_pseudo(css_selector);
-            match (tag, pseudo) {
-                (Some(t), Some(p)) => Selector::TagWithPseudo(t, p),
-                (Some(t), None) => Selector::Tag(t),
-                (None, Some(p)) => Selector::Tag(p),
-                (None, None) => Selector::Any,
-            }
-        },
-        CssSelector::Adjacent(_, child) => convert_css_selector(child),
-        CssSelector::GeneralSibling(_, child) => convert_css_selector(child),
-        CssSelector::Universal => Selector::Any,
-        _ => Selector::Any,
-    }
+    eprintln!("Successfully fetched {} bytes from {}", total, url);
+    Ok(parser)
 }
-
-
-
-
@@ -0,0 +1,185 @@
+// Generational arena holding the nodes of a Dom
+//
+// A NodeId is a slot index plus the generation the slot had when the node was
+// created. Freeing a node bumps its slot's generation and puts the slot on a
+// free list: later nodes reuse the memory, and lookups through the old id
+// return None instead of reaching the new occupant.
+
+use std::fmt;
+use std::ops::{Index, IndexMut};
+
+use super::node::Node;
+
+/// Handle to a node in a Dom
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId {
+    index: u32,
+    generation: u32,
+}
+
+impl NodeId {
+    /// Slot index of the node, unique among live nodes
+    pub fn index(self) -> usize {
+        self.index as usize
+    }
+
+    /// How many times the slot had been freed before this node was created
+    pub fn generation(self) -> u32 {
+        self.generation
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.generation == 0 {
+            write!(f, "#{}", self.index)
+        } else {
+            write!(f, "#{}v{}", self.index, self.generation)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Slot {
+    generation: u32,
+    node: Option<Node>,
+}
+
+/// Node storage of a Dom. Indexing with a dead id panics; use `get` to check.
+#[derive(Debug, Clone, Default)]
+pub struct NodeArena {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    len: usize,
+}
+
+impl NodeArena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store a node, reusing a freed slot if there is one
+    pub fn insert(&mut self, node: Node) -> NodeId {
+        self.len += 1;
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.node = Some(node);
+            return NodeId { index, generation: slot.generation };
+        }
+        let index = u32::try_from(self.slots.len()).expect("too many DOM nodes");
+        self.slots.push(Slot { generation: 0, node: Some(node) });
+        NodeId { index, generation: 0 }
+    }
+
+    /// Free a node's slot, returning the node if the id was live
+    pub fn remove(&mut self, id: NodeId) -> Option<Node> {
+        let slot = self.slots.get_mut(id.index())?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        let node = slot.node.take()?;
+        // A slot whose generation would wrap is retired rather than reused
+        if let Some(generation) = slot.generation.checked_add(1) {
+            slot.generation = generation;
+            self.free.push(id.index);
+        }
+        self.len -= 1;
+        Some(node)
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&Node> {
+        let slot = self.slots.get(id.index())?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        slot.node.as_ref()
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        let slot = self.slots.get_mut(id.index())?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        slot.node.as_mut()
+    }
+
+    /// Whether `id` refers to a live node
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.get(id).is_some()
+    }
+
+    /// Number of live nodes
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of slots, live or free
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Live nodes in slot order
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let id = NodeId { index: index as u32, generation: slot.generation };
+            slot.node.as_ref().map(|node| (id, node))
+        })
+    }
+}
+
+impl Index<NodeId> for NodeArena {
+    type Output = Node;
+
+    fn index(&self, id: NodeId) -> &Node {
+        self.get(id).unwrap_or_else(|| panic!("stale or invalid node id {}", id))
+    }
+}
+
+impl IndexMut<NodeId> for NodeArena {
+    fn index_mut(&mut self, id: NodeId) -> &mut Node {
+        self.get_mut(id).unwrap_or_else(|| panic!("stale or invalid node id {}", id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dom::NodeType;
+
+    fn text(data: &str) -> Node {
+        Node { children: vec![], parent: None, node_type: NodeType::Text(data.to_string()) }
+    }
+
+    #[test]
+    fn test_freed_slots_are_reused_with_new_generation() {
+        let mut arena = NodeArena::new();
+        let a = arena.insert(text("a"));
+        let b = arena.insert(text("b"));
+        assert!(arena.remove(a).is_some());
+        assert!(arena.remove(a).is_none());
+        assert_eq!(arena.len(), 1);
+
+        let c = arena.insert(text("c"));
+        assert_eq!(c.index(), a.index());
+        assert_ne!(c, a);
+        assert_eq!(arena.capacity(), 2);
+
+        // The stale id does not see the new occupant
+        assert!(arena.get(a).is_none());
+        assert!(matches!(&arena[c].node_type, NodeType::Text(t) if t == "c"));
+        assert_eq!(arena.iter().map(|(id, _)| id).collect::<Vec<_>>(), vec![c, b]);
+    }
+
+    #[test]
+    #[should_panic(expected = "stale or invalid node id")]
+    fn test_indexing_with_stale_id_panics() {
+        let mut arena = NodeArena::new();
+        let a = arena.insert(text("a"));
+        arena.remove(a);
+        let _ = &arena[a];
+    }
+}
@@ -0,0 +1,182 @@
+// Id and class indexes for fast element lookups
+//
+// The Dom keeps these up to date in `create_element*`, `set_attribute`,
+// `remove_attribute` and `delete_node`. Attributes edited directly through
+// `Dom::nodes` bypass the index, so go through those methods instead.
+//
+// Every live element is indexed, attached or not; lookups that only want the
+// document's elements filter out detached ones.
+
+use std::collections::{HashMap, HashSet};
+
+use super::arena::NodeId;
+use super::node::Dom;
+
+#[derive(Debug, Default)]
+pub(crate) struct ElementIndex {
+    ids: HashMap<String, HashSet<NodeId>>,
+    classes: HashMap<String, HashSet<NodeId>>,
+}
+
+impl ElementIndex {
+    /// Index a new element's attributes
+    pub(crate) fn insert(&mut self, node: NodeId, attributes: &[(String, String)]) {
+        for (name, value) in attributes {
+            self.update(node, name, None, Some(value));
+        }
+    }
+
+    /// Drop a freed element from the index
+    pub(crate) fn remove(&mut self, node: NodeId, attributes: &[(String, String)]) {
+        for (name, value) in attributes {
+            self.update(node, name, Some(value), None);
+        }
+    }
+
+    /// Record that attribute `name` changed from `old` to `new`
+    pub(crate) fn update(&mut self, node: NodeId, name: &str, old: Option<&str>, new: Option<&str>) {
+        match name {
+            "id" => {
+                if let Some(old) = old {
+                    unlink(&mut self.ids, old, node);
+                }
+                if let Some(new) = new.filter(|id| !id.is_empty()) {
+                    self.ids.entry(new.to_string()).or_default().insert(node);
+                }
+            }
+            "class" => {
+                for class in old.unwrap_or_default().split_ascii_whitespace() {
+                    unlink(&mut self.classes, class, node);
+                }
+                for class in new.unwrap_or_default().split_ascii_whitespace() {
+                    self.classes.entry(class.to_string()).or_default().insert(node);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn unlink(map: &mut HashMap<String, HashSet<NodeId>>, key: &str, node: NodeId) {
+    if let Some(nodes) = map.get_mut(key) {
+        nodes.remove(&node);
+        if nodes.is_empty() {
+            map.remove(key);
+        }
+    }
+}
+
+impl Dom {
+    /// Whether the element's id is `id`. Compares case-insensitively in quirks mode.
+    pub fn has_id(&self, node: NodeId, id: &str) -> bool {
+        if self.is_quirks_mode() {
+            return self.get_attribute(node, "id").is_some_and(|v| v.eq_ignore_ascii_case(id));
+        }
+        self.index.ids.get(id).is_some_and(|nodes| nodes.contains(&node))
+    }
+
+    /// Whether the element's class list contains `class`. Compares
+    /// case-insensitively in quirks mode.
+    pub fn has_class(&self, node: NodeId, class: &str) -> bool {
+        if self.is_quirks_mode() {
+            return self
+                .get_attribute(node, "class")
+                .is_some_and(|v| v.split_ascii_whitespace().any(|c| c.eq_ignore_ascii_case(class)));
+        }
+        self.index.classes.get(class).is_some_and(|nodes| nodes.contains(&node))
+    }
+
+    /// The first element of the document in tree order with the given id
+    pub fn element_by_id(&self, id: &str) -> Option<NodeId> {
+        let nodes = self.index.ids.get(id)?;
+        self.first_in_document(nodes.iter().copied())
+    }
+
+    /// Elements of the document whose class list contains every class in
+    /// `classes` (space separated), in tree order
+    pub fn elements_by_class_name(&self, classes: &str) -> Vec<NodeId> {
+        let classes: Vec<&str> = classes.split_ascii_whitespace().collect();
+        let Some(first) = classes.first() else {
+            return Vec::new();
+        };
+        let has_all = |node: NodeId| classes.iter().all(|class| self.has_class(node, class));
+        if self.is_quirks_mode() {
+            return self.descendants(self.root()).filter(|&node| has_all(node)).collect();
+        }
+        let Some(candidates) = self.index.classes.get(*first) else {
+            return Vec::new();
+        };
+        let mut nodes: Vec<NodeId> = candidates
+            .iter()
+            .copied()
+            .filter(|&node| self.is_connected(node) && has_all(node))
+            .collect();
+        nodes.sort_by(|&a, &b| self.compare_document_order(a, b));
+        nodes
+    }
+
+    /// Whether the node is in the document tree
+    pub fn is_connected(&self, node: NodeId) -> bool {
+        node == self.root() || self.ancestors(node).any(|id| id == self.root())
+    }
+
+    fn first_in_document(&self, nodes: impl Iterator<Item = NodeId>) -> Option<NodeId> {
+        nodes
+            .filter(|&node| self.is_connected(node))
+            .min_by(|&a, &b| self.compare_document_order(a, b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dom::Dom;
+    use crate::parser::html::tree_builder::HtmlParser;
+
+    #[test]
+    fn test_lookups_follow_document_order() {
+        let dom = HtmlParser::new("<p id=x class='a b'></p><div id=x class=b><span class='b a c'></span></div>").parse();
+        let p = dom.elements_by_tag_name("p").next().unwrap();
+        let span = dom.elements_by_tag_name("span").next().unwrap();
+        assert_eq!(dom.element_by_id("x"), Some(p));
+        assert_eq!(dom.elements_by_class_name("a"), vec![p, span]);
+        assert_eq!(dom.elements_by_class_name(" c  a"), vec![span]);
+        assert_eq!(dom.elements_by_class_name(""), vec![]);
+        assert!(dom.has_class(span, "c") && !dom.has_class(span, "d"));
+        assert!(dom.has_id(p, "x") && !dom.has_id(span, "x"));
+    }
+
+    #[test]
+    fn test_index_follows_mutations() {
+        let mut dom = Dom::new();
+        let body = dom.create_element("body", vec![], Some(dom.root()));
+        let a = dom.create_element("div", vec![("id".into(), "one".into()), ("class".into(), "x y".into())], Some(body));
+        assert_eq!(dom.element_by_id("one"), Some(a));
+
+        dom.set_attribute(a, "id", "two").unwrap();
+        dom.set_attribute(a, "class", "y z").unwrap();
+        assert_eq!(dom.element_by_id("one"), None);
+        assert_eq!(dom.element_by_id("two"), Some(a));
+        assert!(dom.elements_by_class_name("x").is_empty());
+        assert_eq!(dom.elements_by_class_name("z"), vec![a]);
+
+        // Detached elements stay indexed but are not part of the document
+        dom.remove_child(body, a).unwrap();
+        assert_eq!(dom.element_by_id("two"), None);
+        assert!(dom.has_id(a, "two"));
+        dom.append_child(body, a).unwrap();
+        assert_eq!(dom.element_by_id("two"), Some(a));
+
+        dom.remove_attribute(a, "class").unwrap();
+        assert!(!dom.has_class(a, "y"));
+        dom.delete_node(a).unwrap();
+        assert_eq!(dom.element_by_id("two"), None);
+    }
+
+    #[test]
+    fn test_quirks_mode_is_case_insensitive() {
+        let dom = HtmlParser::new("<p id=Main class=Note>").parse();
+        let p = dom.elements_by_tag_name("p").next().unwrap();
+        assert!(dom.is_quirks_mode());
+        assert!(dom.has_id(p, "main") && dom.has_class(p, "NOTE"));
+    }
+}
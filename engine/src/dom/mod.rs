@@ -1,2 +1,10 @@
+mod arena;
+mod index;
 pub mod node;
+mod mutation;
+mod query;
+mod serialize;
+mod traversal;
+pub use arena::{NodeArena, NodeId};
 pub use node::*;
+pub use mutation::DomError;
@@ -0,0 +1,348 @@
+// DOM mutation API following the DOM Living Standard
+// Spec Reference: https://dom.spec.whatwg.org/#mutation-algorithms
+//
+// Every mutation keeps `parent` and `children` consistent. Removed nodes stay
+// in the arena, detached, and can be inserted again; `delete_node` frees them.
+
+use super::arena::NodeId;
+use super::node::{Dom, NodeType};
+
+/// Errors from DOM mutations, named after the DOMExceptions they correspond to
+#[derive(Debug, Clone, PartialEq)]
+pub enum DomError {
+    /// A node id is stale, or the reference or old child is not a child of the given parent
+    NotFound,
+    /// The insertion would create a cycle or put a node where it can't go
+    HierarchyRequest,
+    /// The node is the wrong kind for the operation, e.g. attributes on text
+    InvalidNodeType,
+}
+
+impl std::fmt::Display for DomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DomError::NotFound => write!(f, "Node is not a child of the parent"),
+            DomError::HierarchyRequest => write!(f, "Node cannot be inserted at this position"),
+            DomError::InvalidNodeType => write!(f, "Operation is not supported for this node type"),
+        }
+    }
+}
+
+impl std::error::Error for DomError {}
+
+impl Dom {
+    /// Insert `child` as the last child of `parent`, moving it if it already has a parent
+    pub fn append_child(&mut self, parent: NodeId, child: NodeId) -> Result<(), DomError> {
+        self.insert_before(parent, child, None)
+    }
+
+    /// Insert `child` into `parent` before `reference`, or last if there is none.
+    /// Inserting a DocumentFragment moves its children instead.
+    pub fn insert_before(&mut self, parent: NodeId, child: NodeId, reference: Option<NodeId>) -> Result<(), DomError> {
+        self.ensure_pre_insertion_validity(parent, child, reference)?;
+
+        // Inserting a node before itself means before its next sibling
+        let reference = if reference == Some(child) { self.next_sibling(child) } else { reference };
+
+        let nodes: Vec<NodeId> = match self.nodes[child].node_type {
+            NodeType::DocumentFragment => self.nodes[child].children.clone(),
+            _ => vec![child],
+        };
+        for &node in &nodes {
+            self.detach(node);
+        }
+
+        let children = &mut self.nodes[parent].children;
+        let index = reference
+            .and_then(|r| children.iter().position(|&c| c == r))
+            .unwrap_or(children.len());
+        children.splice(index..index, nodes.iter().copied());
+        for node in nodes {
+            self.nodes[node].parent = Some(parent);
+        }
+        Ok(())
+    }
+
+    /// Remove `child` from `parent`. The child and its subtree stay usable, detached.
+    pub fn remove_child(&mut self, parent: NodeId, child: NodeId) -> Result<(), DomError> {
+        if self.nodes.get(child).map(|n| n.parent) != Some(Some(parent)) {
+            return Err(DomError::NotFound);
+        }
+        self.detach(child);
+        Ok(())
+    }
+
+    /// Put `new_child` where `old_child` is in `parent`, detaching `old_child`
+    pub fn replace_child(&mut self, parent: NodeId, new_child: NodeId, old_child: NodeId) -> Result<(), DomError> {
+        if self.nodes.get(old_child).map(|n| n.parent) != Some(Some(parent)) {
+            return Err(DomError::NotFound);
+        }
+        if new_child == old_child {
+            return Ok(());
+        }
+        self.ensure_pre_insertion_validity(parent, new_child, None)?;
+        let reference = match self.next_sibling(old_child) {
+            Some(next) if next == new_child => self.next_sibling(new_child),
+            next => next,
+        };
+        self.detach(old_child);
+        self.insert_before(parent, new_child, reference)
+    }
+
+    /// Detach a node and free it along with its descendants. Their ids become
+    /// stale and their slots are reused by nodes created later.
+    pub fn delete_node(&mut self, id: NodeId) -> Result<(), DomError> {
+        if !self.nodes.contains(id) {
+            return Err(DomError::NotFound);
+        }
+        if id == self.root() {
+            return Err(DomError::HierarchyRequest);
+        }
+        self.detach(id);
+        let mut stack = vec![id];
+        while let Some(node) = stack.pop() {
+            if let Some(removed) = self.nodes.remove(node) {
+                if let NodeType::Element(el) = &removed.node_type {
+                    self.index.remove(node, &el.attributes);
+                }
+                stack.extend(removed.children);
+            }
+        }
+        Ok(())
+    }
+
+    /// The value of an attribute. Names of HTML elements are matched in lowercase.
+    pub fn get_attribute(&self, node: NodeId, name: &str) -> Option<&str> {
+        let el = match &self.nodes.get(node)?.node_type {
+            NodeType::Element(el) => el,
+            _ => return None,
+        };
+        let name = attribute_name(el.is_html(), name);
+        el.attributes.iter().find(|(k, _)| *k == name).map(|(_, v)| v.as_str())
+    }
+
+    /// Set an attribute, replacing any existing value in place
+    pub fn set_attribute(&mut self, node: NodeId, name: &str, value: &str) -> Result<(), DomError> {
+        let el = match &mut self.nodes.get_mut(node).ok_or(DomError::NotFound)?.node_type {
+            NodeType::Element(el) => el,
+            _ => return Err(DomError::InvalidNodeType),
+        };
+        let name = attribute_name(el.is_html(), name);
+        let old = match el.attributes.iter_mut().find(|(k, _)| *k == name) {
+            Some((_, existing)) => Some(std::mem::replace(existing, value.to_string())),
+            None => {
+                el.attributes.push((name.clone(), value.to_string()));
+                None
+            }
+        };
+        self.index.update(node, &name, old.as_deref(), Some(value));
+        Ok(())
+    }
+
+    /// Remove an attribute, returning its value if it was present
+    pub fn remove_attribute(&mut self, node: NodeId, name: &str) -> Result<Option<String>, DomError> {
+        let el = match &mut self.nodes.get_mut(node).ok_or(DomError::NotFound)?.node_type {
+            NodeType::Element(el) => el,
+            _ => return Err(DomError::InvalidNodeType),
+        };
+        let name = attribute_name(el.is_html(), name);
+        let old = el.attributes.iter().position(|(k, _)| *k == name).map(|i| el.attributes.remove(i).1);
+        self.index.update(node, &name, old.as_deref(), None);
+        Ok(old)
+    }
+
+    /// Set the text of a node like `textContent`: text and comments get new
+    /// data, elements and fragments have their children deleted and replaced
+    /// by one text node
+    pub fn set_text(&mut self, node: NodeId, text: &str) -> Result<(), DomError> {
+        match &mut self.nodes.get_mut(node).ok_or(DomError::NotFound)?.node_type {
+            NodeType::Text(data) | NodeType::Comment(data) => {
+                *data = text.to_string();
+                return Ok(());
+            }
+            NodeType::Element(_) | NodeType::DocumentFragment => {}
+            NodeType::Document | NodeType::Doctype { .. } => return Err(DomError::InvalidNodeType),
+        }
+        for child in self.nodes[node].children.clone() {
+            self.delete_node(child)?;
+        }
+        if !text.is_empty() {
+            self.create_text(text, Some(node));
+        }
+        Ok(())
+    }
+
+    /// DOM "ensure pre-insertion validity"
+    fn ensure_pre_insertion_validity(&self, parent: NodeId, child: NodeId, reference: Option<NodeId>) -> Result<(), DomError> {
+        if !self.nodes.contains(parent) || !self.nodes.contains(child) {
+            return Err(DomError::NotFound);
+        }
+        if !matches!(
+            self.nodes[parent].node_type,
+            NodeType::Document | NodeType::DocumentFragment | NodeType::Element(_)
+        ) {
+            return Err(DomError::HierarchyRequest);
+        }
+        // The child may not be the parent or one of its ancestors
+        let mut ancestor = Some(parent);
+        while let Some(id) = ancestor {
+            if id == child {
+                return Err(DomError::HierarchyRequest);
+            }
+            ancestor = self.nodes[id].parent;
+        }
+        if let Some(reference) = reference {
+            if self.nodes.get(reference).map(|n| n.parent) != Some(Some(parent)) {
+                return Err(DomError::NotFound);
+            }
+        }
+        let parent_is_document = matches!(self.nodes[parent].node_type, NodeType::Document);
+        match self.nodes[child].node_type {
+            NodeType::Document => Err(DomError::HierarchyRequest),
+            NodeType::Text(_) if parent_is_document => Err(DomError::HierarchyRequest),
+            NodeType::Doctype { .. } if !parent_is_document => Err(DomError::HierarchyRequest),
+            _ => Ok(()),
+        }
+    }
+
+    /// Remove a node from its parent's children, if it has a parent
+    fn detach(&mut self, node: NodeId) {
+        if let Some(parent) = self.nodes[node].parent.take() {
+            self.nodes[parent].children.retain(|&c| c != node);
+        }
+    }
+}
+
+fn attribute_name(is_html: bool, name: &str) -> String {
+    if is_html {
+        name.to_ascii_lowercase()
+    } else {
+        name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Document with <div> holding <a>, <b> and <c>
+    fn sample() -> (Dom, NodeId, [NodeId; 3]) {
+        let mut dom = Dom::new();
+        let div = dom.create_element("div", vec![], Some(dom.root()));
+        let a = dom.create_element("a", vec![], Some(div));
+        let b = dom.create_element("b", vec![], Some(div));
+        let c = dom.create_element("c", vec![], Some(div));
+        (dom, div, [a, b, c])
+    }
+
+    #[test]
+    fn test_insert_before_and_append_move_nodes() {
+        let (mut dom, div, [a, b, c]) = sample();
+        dom.insert_before(div, c, Some(a)).unwrap();
+        assert_eq!(dom.nodes[div].children, vec![c, a, b]);
+
+        let span = dom.create_element("span", vec![], None);
+        dom.append_child(span, a).unwrap();
+        assert_eq!(dom.nodes[div].children, vec![c, b]);
+        assert_eq!(dom.nodes[a].parent, Some(span));
+
+        // Before itself is a no-op move
+        dom.insert_before(div, b, Some(b)).unwrap();
+        assert_eq!(dom.nodes[div].children, vec![c, b]);
+    }
+
+    #[test]
+    fn test_invalid_insertions_are_rejected() {
+        let (mut dom, div, [a, b, _]) = sample();
+        assert_eq!(dom.append_child(a, div), Err(DomError::HierarchyRequest));
+        assert_eq!(dom.append_child(div, div), Err(DomError::HierarchyRequest));
+        let text = dom.create_text("t", None);
+        assert_eq!(dom.append_child(text, b), Err(DomError::HierarchyRequest));
+        assert_eq!(dom.append_child(dom.root(), text), Err(DomError::HierarchyRequest));
+        let other = dom.create_element("p", vec![], None);
+        assert_eq!(dom.insert_before(div, text, Some(other)), Err(DomError::NotFound));
+        // Nothing moved
+        assert_eq!(dom.nodes[div].children.len(), 3);
+        assert_eq!(dom.nodes[text].parent, None);
+    }
+
+    #[test]
+    fn test_remove_and_replace_child() {
+        let (mut dom, div, [a, b, c]) = sample();
+        assert_eq!(dom.remove_child(a, b), Err(DomError::NotFound));
+        dom.remove_child(div, b).unwrap();
+        assert_eq!(dom.nodes[div].children, vec![a, c]);
+        assert_eq!(dom.nodes[b].parent, None);
+
+        dom.replace_child(div, b, c).unwrap();
+        assert_eq!(dom.nodes[div].children, vec![a, b]);
+        assert_eq!(dom.nodes[c].parent, None);
+
+        // Replacing with the following sibling keeps the order sensible
+        dom.replace_child(div, b, a).unwrap();
+        assert_eq!(dom.nodes[div].children, vec![b]);
+    }
+
+    #[test]
+    fn test_fragment_children_are_inserted() {
+        let (mut dom, div, [a, _, _]) = sample();
+        let fragment = dom.create_document_fragment();
+        let x = dom.create_text("x", Some(fragment));
+        let y = dom.create_element("y", vec![], Some(fragment));
+        dom.insert_before(div, fragment, Some(a)).unwrap();
+        assert_eq!(&dom.nodes[div].children[..3], &[x, y, a]);
+        assert!(dom.nodes[fragment].children.is_empty());
+        assert_eq!(dom.nodes[y].parent, Some(div));
+    }
+
+    #[test]
+    fn test_attributes() {
+        let (mut dom, div, _) = sample();
+        assert_eq!(dom.get_attribute(div, "id"), None);
+        dom.set_attribute(div, "ID", "main").unwrap();
+        dom.set_attribute(div, "class", "x").unwrap();
+        dom.set_attribute(div, "id", "other").unwrap();
+        assert_eq!(dom.get_attribute(div, "Id"), Some("other"));
+        assert_eq!(dom.remove_attribute(div, "class"), Ok(Some("x".to_string())));
+        assert_eq!(dom.remove_attribute(div, "class"), Ok(None));
+
+        let text = dom.create_text("t", Some(div));
+        assert_eq!(dom.set_attribute(text, "id", "x"), Err(DomError::InvalidNodeType));
+    }
+
+    #[test]
+    fn test_delete_node_frees_subtree_and_reuses_slots() {
+        let (mut dom, div, [a, b, _]) = sample();
+        let inner = dom.create_text("x", Some(a));
+        let live = dom.nodes.len();
+        dom.delete_node(a).unwrap();
+        assert_eq!(dom.nodes.len(), live - 2);
+        assert!(dom.nodes.get(a).is_none() && dom.nodes.get(inner).is_none());
+        assert_eq!(dom.nodes[div].children.len(), 2);
+
+        // Stale ids are rejected instead of touching the slot's new occupant
+        let capacity = dom.nodes.capacity();
+        let fresh = dom.create_element("p", vec![], None);
+        assert_eq!(dom.nodes.capacity(), capacity);
+        assert!(fresh.index() == a.index() || fresh.index() == inner.index());
+        assert_eq!(dom.append_child(div, a), Err(DomError::NotFound));
+        assert_eq!(dom.remove_child(div, a), Err(DomError::NotFound));
+        assert_eq!(dom.get_attribute(a, "id"), None);
+        assert_eq!(dom.delete_node(a), Err(DomError::NotFound));
+        assert_eq!(dom.delete_node(dom.root()), Err(DomError::HierarchyRequest));
+        assert_eq!(dom.nodes[b].parent, Some(div));
+    }
+
+    #[test]
+    fn test_set_text() {
+        let (mut dom, div, [a, _, _]) = sample();
+        dom.set_text(div, "hello").unwrap();
+        assert_eq!(dom.nodes[div].children.len(), 1);
+        assert!(dom.nodes.get(a).is_none());
+        let text = dom.nodes[div].children[0];
+        dom.set_text(text, "bye").unwrap();
+        assert!(matches!(&dom.nodes[text].node_type, NodeType::Text(t) if t == "bye"));
+        dom.set_text(div, "").unwrap();
+        assert!(dom.nodes[div].children.is_empty());
+    }
+}
@@ -1,15 +1,62 @@
-pub type NodeId = usize;
+use super::arena::{NodeArena, NodeId};
+use super::index::ElementIndex;
 
 #[derive(Debug, Clone)]
 pub enum NodeType {
+    /// The root of a parsed document
+    Document,
+    /// The root of a detached subtree
+    DocumentFragment,
+    Doctype {
+        name: String,
+        public_id: String,
+        system_id: String,
+    },
     Element(ElementData),
     Text(String),
+    Comment(String),
+}
+
+/// Document rendering mode chosen from the doctype (HTML spec 13.2.6.4.1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuirksMode {
+    #[default]
+    NoQuirks,
+    LimitedQuirks,
+    Quirks,
+}
+
+/// Element namespaces the HTML parser can produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Namespace {
+    #[default]
+    Html,
+    Svg,
+    MathMl,
+}
+
+impl Namespace {
+    pub fn url(&self) -> &'static str {
+        match self {
+            Namespace::Html => "http://www.w3.org/1999/xhtml",
+            Namespace::Svg => "http://www.w3.org/2000/svg",
+            Namespace::MathMl => "http://www.w3.org/1998/Math/MathML",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ElementData {
     pub tag_name: String,
+    /// Attribute names are qualified, e.g. "xlink:href"
     pub attributes: Vec<(String, String)>,
+    pub namespace: Namespace,
+}
+
+impl ElementData {
+    pub fn is_html(&self) -> bool {
+        self.namespace == Namespace::Html
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -21,45 +68,83 @@ pub struct Node {
 
 #[derive(Debug)]
 pub struct Dom {
-    pub nodes: Vec<Node>,
+    pub nodes: NodeArena,
+    pub quirks_mode: QuirksMode,
+    document: NodeId,
+    pub(super) index: ElementIndex,
 }
 
 impl Dom {
+    /// Create a Dom holding only the Document node
     pub fn new() -> Self {
-        Self { nodes: Vec::new() }
+        let mut nodes = NodeArena::new();
+        let document = nodes.insert(Node { children: vec![], parent: None, node_type: NodeType::Document });
+        Self { nodes, quirks_mode: QuirksMode::NoQuirks, document, index: ElementIndex::default() }
     }
 
-    pub fn create_element(&mut self, tag_name: &str, attrs: Vec<(String, String)>, parent: Option<NodeId>) -> NodeId {
-        let id = self.nodes.len();
-        self.nodes.push(Node {
+    fn create_node(&mut self, node_type: NodeType, parent: Option<NodeId>) -> NodeId {
+        let id = self.nodes.insert(Node {
             children: vec![],
             parent,
-            node_type: NodeType::Element(ElementData {
-                tag_name: tag_name.to_string(),
-                attributes: attrs,
-            }),
+            node_type,
         });
+        if let NodeType::Element(el) = &self.nodes[id].node_type {
+            self.index.insert(id, &el.attributes);
+        }
         if let Some(pid) = parent {
             self.nodes[pid].children.push(id);
         }
         id
     }
 
+    pub fn create_element(&mut self, tag_name: &str, attrs: Vec<(String, String)>, parent: Option<NodeId>) -> NodeId {
+        self.create_element_ns(tag_name, attrs, Namespace::Html, parent)
+    }
+
+    pub fn create_element_ns(
+        &mut self,
+        tag_name: &str,
+        attrs: Vec<(String, String)>,
+        namespace: Namespace,
+        parent: Option<NodeId>,
+    ) -> NodeId {
+        let element = ElementData {
+            tag_name: tag_name.to_string(),
+            attributes: attrs,
+            namespace,
+        };
+        self.create_node(NodeType::Element(element), parent)
+    }
+
     pub fn create_text(&mut self, text: &str, parent: Option<NodeId>) -> NodeId {
-        let id = self.nodes.len();
-        self.nodes.push(Node {
-            children: vec![],
-            parent,
-            node_type: NodeType::Text(text.to_string()),
-        });
-        if let Some(pid) = parent {
-            self.nodes[pid].children.push(id);
-        }
-        id
+        self.create_node(NodeType::Text(text.to_string()), parent)
     }
 
+    pub fn create_comment(&mut self, data: &str, parent: Option<NodeId>) -> NodeId {
+        self.create_node(NodeType::Comment(data.to_string()), parent)
+    }
+
+    pub fn create_doctype(&mut self, name: &str, public_id: &str, system_id: &str, parent: Option<NodeId>) -> NodeId {
+        let doctype = NodeType::Doctype {
+            name: name.to_string(),
+            public_id: public_id.to_string(),
+            system_id: system_id.to_string(),
+        };
+        self.create_node(doctype, parent)
+    }
+
+    /// Create a parentless DocumentFragment to build a detached subtree under
+    pub fn create_document_fragment(&mut self) -> NodeId {
+        self.create_node(NodeType::DocumentFragment, None)
+    }
+
+    /// The Document node
     pub fn root(&self) -> NodeId {
-        0
+        self.document
+    }
+
+    pub fn is_quirks_mode(&self) -> bool {
+        self.quirks_mode == QuirksMode::Quirks
     }
 
     pub fn pretty_print(&self, id: NodeId, indent: usize) {
@@ -0,0 +1,269 @@
+// Selector matching and querySelector / querySelectorAll
+// Spec Reference: https://dom.spec.whatwg.org/#scope-match-a-selectors-string
+//                 https://www.w3.org/TR/selectors-4/
+//
+// Status:
+// ✅ Type, universal, id, class and attribute selectors (all operators)
+// ✅ Descendant, child, adjacent and general sibling combinators
+// ✅ Structural pseudo-classes (:root, :empty, :first-child, :nth-child(), ...)
+// ✅ :not(), :is(), :where(), :link / :any-link, :checked, :disabled, :enabled
+// ❌ User action pseudo-classes (:hover, :focus, ...) never match
+
+use super::arena::NodeId;
+use super::node::{Dom, ElementData, NodeType};
+use crate::parser::css::parser::{AttrOperator, Selector};
+use crate::parser::css::{CssParser, CssTokenizer};
+
+/// Form elements that :disabled and :enabled apply to
+const FORM_CONTROLS: &[&str] = &["button", "input", "select", "textarea", "optgroup", "option", "fieldset"];
+
+impl Dom {
+    /// The first element in tree order matching `selectors`, or None if there is
+    /// no match or the selector list is invalid
+    pub fn query_selector(&self, selectors: &str) -> Option<NodeId> {
+        let selectors = parse_selectors(selectors)?;
+        self.descendants(self.root()).find(|&id| selectors.iter().any(|s| self.matches(id, s)))
+    }
+
+    /// All elements in tree order matching `selectors`; empty if the selector list is invalid
+    pub fn query_selector_all(&self, selectors: &str) -> Vec<NodeId> {
+        let Some(selectors) = parse_selectors(selectors) else {
+            return Vec::new();
+        };
+        self.descendants(self.root())
+            .filter(|&id| selectors.iter().any(|s| self.matches(id, s)))
+            .collect()
+    }
+
+    /// Whether the node is an element matching `selector`
+    pub fn matches(&self, node: NodeId, selector: &Selector) -> bool {
+        let Some(el) = self.element(node) else {
+            return false;
+        };
+        match selector {
+            Selector::Universal => true,
+            Selector::Element(tag) => {
+                if el.is_html() {
+                    el.tag_name.eq_ignore_ascii_case(tag)
+                } else {
+                    el.tag_name == *tag
+                }
+            }
+            Selector::Id(id) => self.has_id(node, id),
+            Selector::Class(class) => self.has_class(node, class),
+            Selector::Attribute { name, operator, value } => {
+                let Some(actual) = self.get_attribute(node, name) else {
+                    return false;
+                };
+                match (operator, value) {
+                    (Some(operator), Some(value)) => attribute_matches(operator, actual, value),
+                    _ => true,
+                }
+            }
+            Selector::PseudoClass(name) => self.matches_pseudo_class(node, el, &name.to_ascii_lowercase()),
+            // Pseudo-elements are never elements in the tree
+            Selector::PseudoElement(_) => false,
+            Selector::Compound(parts) => parts.iter().all(|part| self.matches(node, part)),
+            Selector::Not(inner) => !self.matches(node, inner),
+            Selector::Is(list) | Selector::Where(list) => list.iter().any(|s| self.matches(node, s)),
+            Selector::NthChild { a, b, of_type, from_end } => {
+                let position = self.sibling_position(node, *of_type, *from_end);
+                nth_matches(*a, *b, position)
+            }
+            Selector::Descendant(ancestor, subject) => {
+                self.matches(node, subject)
+                    && self.ancestors(node).any(|id| self.matches(id, ancestor))
+            }
+            Selector::Child(parent, subject) => {
+                self.matches(node, subject)
+                    && self.nodes[node].parent.is_some_and(|id| self.matches(id, parent))
+            }
+            Selector::Adjacent(previous, subject) => {
+                self.matches(node, subject)
+                    && self.previous_element_siblings(node).next().is_some_and(|id| self.matches(id, previous))
+            }
+            Selector::GeneralSibling(previous, subject) => {
+                self.matches(node, subject)
+                    && self.previous_element_siblings(node).any(|id| self.matches(id, previous))
+            }
+        }
+    }
+
+    fn matches_pseudo_class(&self, node: NodeId, el: &ElementData, name: &str) -> bool {
+        match name {
+            "root" => self.nodes[node]
+                .parent
+                .is_some_and(|p| matches!(self.nodes[p].node_type, NodeType::Document)),
+            "empty" => self.nodes[node].children.iter().all(|&c| match &self.nodes[c].node_type {
+                NodeType::Element(_) => false,
+                NodeType::Text(text) => text.is_empty(),
+                _ => true,
+            }),
+            "first-child" => self.sibling_position(node, false, false) == 1,
+            "last-child" => self.sibling_position(node, false, true) == 1,
+            "only-child" => self.sibling_position(node, false, false) == 1 && self.sibling_position(node, false, true) == 1,
+            "first-of-type" => self.sibling_position(node, true, false) == 1,
+            "last-of-type" => self.sibling_position(node, true, true) == 1,
+            "only-of-type" => self.sibling_position(node, true, false) == 1 && self.sibling_position(node, true, true) == 1,
+            "link" | "any-link" => {
+                el.is_html()
+                    && matches!(el.tag_name.as_str(), "a" | "area")
+                    && self.get_attribute(node, "href").is_some()
+            }
+            "checked" => match el.tag_name.as_str() {
+                "input" => self.get_attribute(node, "checked").is_some(),
+                "option" => self.get_attribute(node, "selected").is_some(),
+                _ => false,
+            },
+            "disabled" => el.is_html() && FORM_CONTROLS.contains(&el.tag_name.as_str()) && self.get_attribute(node, "disabled").is_some(),
+            "enabled" => el.is_html() && FORM_CONTROLS.contains(&el.tag_name.as_str()) && self.get_attribute(node, "disabled").is_none(),
+            _ => false,
+        }
+    }
+
+    /// 1-based position among element siblings (of the same type if `of_type`),
+    /// counted from the end if `from_end`
+    fn sibling_position(&self, node: NodeId, of_type: bool, from_end: bool) -> usize {
+        let Some(parent) = self.nodes[node].parent else {
+            return 1;
+        };
+        let tag = self.element(node).map(|el| el.tag_name.as_str());
+        let siblings = self.nodes[parent].children.iter().copied().filter(|&id| match self.element(id) {
+            Some(el) => !of_type || Some(el.tag_name.as_str()) == tag,
+            None => false,
+        });
+        let mut siblings: Vec<NodeId> = siblings.collect();
+        if from_end {
+            siblings.reverse();
+        }
+        let index = siblings.iter().position(|&id| id == node);
+        index.map_or(1, |i| i + 1)
+    }
+
+    /// Element siblings before a node, nearest first
+    fn previous_element_siblings(&self, node: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        let siblings: &[NodeId] = match self.nodes[node].parent {
+            Some(parent) => &self.nodes[parent].children,
+            None => &[],
+        };
+        let index = siblings.iter().position(|&id| id == node).unwrap_or(0);
+        siblings[..index].iter().rev().copied().filter(|&id| self.element(id).is_some())
+    }
+}
+
+fn parse_selectors(selectors: &str) -> Option<Vec<Selector>> {
+    let tokens = CssTokenizer::new(selectors).tokenize();
+    CssParser::new(tokens).parse_selector_list()
+}
+
+fn attribute_matches(operator: &AttrOperator, actual: &str, value: &str) -> bool {
+    match operator {
+        AttrOperator::Exact => actual == value,
+        AttrOperator::Contains => !value.is_empty() && actual.split_ascii_whitespace().any(|v| v == value),
+        AttrOperator::Dash => {
+            actual == value || actual.strip_prefix(value).is_some_and(|rest| rest.starts_with('-'))
+        }
+        AttrOperator::Substring => !value.is_empty() && actual.contains(value),
+        AttrOperator::Prefix => !value.is_empty() && actual.starts_with(value),
+        AttrOperator::Suffix => !value.is_empty() && actual.ends_with(value),
+    }
+}
+
+/// Whether a 1-based position is `a*n + b` for some n >= 0
+fn nth_matches(a: i32, b: i32, position: usize) -> bool {
+    let offset = position as i32 - b;
+    if a == 0 {
+        offset == 0
+    } else {
+        offset % a == 0 && offset / a >= 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::html::tree_builder::HtmlParser;
+
+    fn tags(dom: &super::Dom, selectors: &str) -> Vec<String> {
+        dom.query_selector_all(selectors)
+            .into_iter()
+            .map(|id| {
+                let tag = dom.element(id).unwrap().tag_name.clone();
+                match dom.get_attribute(id, "id") {
+                    Some(id) => format!("{}#{}", tag, id),
+                    None => tag,
+                }
+            })
+            .collect()
+    }
+
+    const PAGE: &str = r#"<!DOCTYPE html><title>T</title>
+        <div id=main class="box wide">
+          <p id=p1 lang=en-US>one</p>
+          <p id=p2 class=note data-x="a b">two</p>
+          <span id=s1></span>
+          <p id=p3><a id=a1 href="/x.pdf">link</a></p>
+        </div>
+        <ul><li id=l1><li id=l2><li id=l3><li id=l4></ul>"#;
+
+    #[test]
+    fn test_simple_and_compound_selectors() {
+        let dom = HtmlParser::new(PAGE).parse();
+        assert_eq!(tags(&dom, "title"), vec!["title"]);
+        assert_eq!(tags(&dom, "#main"), vec!["div#main"]);
+        assert_eq!(tags(&dom, "div.box.wide"), vec!["div#main"]);
+        assert_eq!(tags(&dom, "p.note"), vec!["p#p2"]);
+        assert_eq!(tags(&dom, "DIV .note"), vec!["p#p2"]);
+        assert!(tags(&dom, "div.note").is_empty());
+        assert_eq!(tags(&dom, "#l1, #p1"), vec!["p#p1", "li#l1"]);
+        assert_eq!(dom.query_selector("p"), dom.query_selector("#p1"));
+    }
+
+    #[test]
+    fn test_attribute_operators() {
+        let dom = HtmlParser::new(PAGE).parse();
+        assert_eq!(tags(&dom, "[lang]"), vec!["p#p1"]);
+        assert_eq!(tags(&dom, "[lang|=en]"), vec!["p#p1"]);
+        assert_eq!(tags(&dom, "[data-x~=b]"), vec!["p#p2"]);
+        assert_eq!(tags(&dom, "[data-x=\"a b\"]"), vec!["p#p2"]);
+        assert_eq!(tags(&dom, "a[href^='/'][href$=\".pdf\"]"), vec!["a#a1"]);
+        assert_eq!(tags(&dom, "[href*=x]"), vec!["a#a1"]);
+        assert!(tags(&dom, "[href*='']").is_empty());
+    }
+
+    #[test]
+    fn test_combinators() {
+        let dom = HtmlParser::new(PAGE).parse();
+        assert_eq!(tags(&dom, "div > p > a"), vec!["a#a1"]);
+        assert!(tags(&dom, "div > a").is_empty());
+        assert_eq!(tags(&dom, "body a"), vec!["a#a1"]);
+        assert_eq!(tags(&dom, "#p1 + p"), vec!["p#p2"]);
+        assert_eq!(tags(&dom, "#p2 ~ p"), vec!["p#p3"]);
+        assert_eq!(tags(&dom, "span+p"), vec!["p#p3"]);
+    }
+
+    #[test]
+    fn test_structural_pseudo_classes() {
+        let dom = HtmlParser::new(PAGE).parse();
+        assert_eq!(tags(&dom, ":root"), vec!["html"]);
+        assert_eq!(tags(&dom, "li:first-child"), vec!["li#l1"]);
+        assert_eq!(tags(&dom, "li:last-child"), vec!["li#l4"]);
+        assert_eq!(tags(&dom, "li:nth-child(2n+1)"), vec!["li#l1", "li#l3"]);
+        assert_eq!(tags(&dom, "li:nth-child(even)"), vec!["li#l2", "li#l4"]);
+        assert_eq!(tags(&dom, "li:nth-last-child(-n + 2)"), vec!["li#l3", "li#l4"]);
+        assert_eq!(tags(&dom, "#main > p:nth-of-type(3)"), vec!["p#p3"]);
+        assert_eq!(tags(&dom, "#main > :last-of-type"), vec!["span#s1", "p#p3"]);
+        assert_eq!(tags(&dom, "span:empty, a:only-child"), vec!["span#s1", "a#a1"]);
+        assert_eq!(tags(&dom, "#main > p:not(.note)"), vec!["p#p1", "p#p3"]);
+        assert_eq!(tags(&dom, "a:link"), vec!["a#a1"]);
+        assert_eq!(tags(&dom, "#main > :is(span, .note)"), vec!["p#p2", "span#s1"]);
+        assert_eq!(tags(&dom, ":where(ul, #nothing) > li:not(#l1, #l2)"), vec!["li#l3", "li#l4"]);
+    }
+
+    #[test]
+    fn test_invalid_selectors_match_nothing() {
+        let dom = HtmlParser::new(PAGE).parse();
+        assert!(dom.query_selector_all("div >").is_empty());
+        assert!(dom.query_selector_all("p:nth-child(x)").is_empty());
+        assert_eq!(dom.query_selector("p::before"), None);
+    }
+}
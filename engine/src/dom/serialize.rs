@@ -0,0 +1,151 @@
+// HTML serialization: turning a Dom back into markup
+// Spec Reference: https://html.spec.whatwg.org/multipage/parsing.html#serialising-html-fragments
+//
+// Follows the HTML fragment serialization algorithm: void elements get no end
+// tag, text inside raw text elements is written literally, and everything
+// else is escaped. Foreign elements are serialized like HTML ones.
+
+use super::arena::NodeId;
+use super::node::{Dom, NodeType};
+use crate::parser::html::tokenizer::VOID_ELEMENTS;
+
+/// Elements whose text children are serialized without escaping
+const LITERAL_TEXT_ELEMENTS: &[&str] = &["style", "script", "xmp", "iframe", "noembed", "noframes", "plaintext"];
+
+impl Dom {
+    /// Markup for the node and its descendants (`outerHTML`). For a Document or
+    /// DocumentFragment this is the markup of its children.
+    pub fn outer_html(&self, node: NodeId) -> String {
+        let mut out = String::new();
+        match self.nodes[node].node_type {
+            NodeType::Document | NodeType::DocumentFragment => self.serialize_children(node, &mut out),
+            _ => self.serialize_node(node, &mut out),
+        }
+        out
+    }
+
+    /// Markup for the node's children (`innerHTML`)
+    pub fn inner_html(&self, node: NodeId) -> String {
+        let mut out = String::new();
+        self.serialize_children(node, &mut out);
+        out
+    }
+
+    /// The whole document, starting with its doctype
+    pub fn to_html(&self) -> String {
+        self.inner_html(self.root())
+    }
+
+    fn serialize_children(&self, node: NodeId, out: &mut String) {
+        for &child in &self.nodes[node].children {
+            self.serialize_node(child, out);
+        }
+    }
+
+    fn serialize_node(&self, node: NodeId, out: &mut String) {
+        match &self.nodes[node].node_type {
+            NodeType::Element(el) => {
+                out.push('<');
+                out.push_str(&el.tag_name);
+                for (name, value) in &el.attributes {
+                    out.push(' ');
+                    out.push_str(name);
+                    out.push_str("=\"");
+                    escape(value, true, out);
+                    out.push('"');
+                }
+                out.push('>');
+                if el.is_html() && VOID_ELEMENTS.contains(&el.tag_name.as_str()) {
+                    return;
+                }
+                self.serialize_children(node, out);
+                out.push_str("</");
+                out.push_str(&el.tag_name);
+                out.push('>');
+            }
+            NodeType::Text(text) => {
+                let literal = self.nodes[node].parent.is_some_and(|parent| match &self.nodes[parent].node_type {
+                    NodeType::Element(el) => el.is_html() && LITERAL_TEXT_ELEMENTS.contains(&el.tag_name.as_str()),
+                    _ => false,
+                });
+                if literal {
+                    out.push_str(text);
+                } else {
+                    escape(text, false, out);
+                }
+            }
+            NodeType::Comment(data) => {
+                out.push_str("<!--");
+                out.push_str(data);
+                out.push_str("-->");
+            }
+            NodeType::Doctype { name, .. } => {
+                out.push_str("<!DOCTYPE ");
+                out.push_str(name);
+                out.push('>');
+            }
+            NodeType::Document | NodeType::DocumentFragment => self.serialize_children(node, out),
+        }
+    }
+}
+
+/// Escape a string (spec 13.3 "escaping a string"). Attribute mode also escapes quotes.
+fn escape(text: &str, attribute_mode: bool, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '\u{A0}' => out.push_str("&nbsp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' if attribute_mode => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dom::Dom;
+    use crate::parser::html::tree_builder::HtmlParser;
+
+    #[test]
+    fn test_round_trip_document() {
+        let html = "<!DOCTYPE html><html><head><title>a &amp; b</title></head>\
+                    <body><!--note--><p class=\"x\">1 &lt; 2<br>3</p><img src=\"a.png\" alt=\"\"></body></html>";
+        let dom = HtmlParser::new(html).parse();
+        assert_eq!(
+            dom.to_html(),
+            "<!DOCTYPE html><html><head><title>a &amp; b</title></head>\
+             <body><!--note--><p class=\"x\">1 &lt; 2<br>3</p><img src=\"a.png\" alt=\"\"></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_escaping_and_raw_text() {
+        let dom = HtmlParser::new("<p title='say \"hi\" & <go>'>a\u{A0}b > c</p><script>if (a < b && c) {}</script>").parse();
+        let p = dom.query_selector("p").unwrap();
+        assert_eq!(dom.outer_html(p), "<p title=\"say &quot;hi&quot; &amp; &lt;go&gt;\">a&nbsp;b &gt; c</p>");
+        let script = dom.query_selector("script").unwrap();
+        assert_eq!(dom.inner_html(script), "if (a < b && c) {}");
+    }
+
+    #[test]
+    fn test_inner_html_and_foreign_elements() {
+        let dom = HtmlParser::new("<div><svg viewBox=\"0 0 1 1\"><foreignObject><b>x</b></foreignObject><path d=\"M0\"/></svg></div>").parse();
+        let div = dom.query_selector("div").unwrap();
+        assert_eq!(
+            dom.inner_html(div),
+            "<svg viewBox=\"0 0 1 1\"><foreignObject><b>x</b></foreignObject><path d=\"M0\"></path></svg>"
+        );
+    }
+
+    #[test]
+    fn test_serialize_mutated_dom() {
+        let mut dom = Dom::new();
+        let fragment = dom.create_document_fragment();
+        let link = dom.create_element("a", vec![], Some(fragment));
+        dom.set_attribute(link, "href", "/?a=1&b=2").unwrap();
+        dom.set_text(link, "<next>").unwrap();
+        assert_eq!(dom.outer_html(fragment), "<a href=\"/?a=1&amp;b=2\">&lt;next&gt;</a>");
+    }
+}
@@ -0,0 +1,178 @@
+// Tree traversal helpers
+// Spec Reference: https://dom.spec.whatwg.org/#concept-tree-order
+//
+// Iterators borrow the Dom, so collect them before mutating the tree.
+
+use std::cmp::Ordering;
+
+use super::arena::NodeId;
+use super::node::{Dom, ElementData, NodeType};
+
+impl Dom {
+    /// The element data of a node, or None if it is not an element
+    pub fn element(&self, node: NodeId) -> Option<&ElementData> {
+        match &self.nodes.get(node)?.node_type {
+            NodeType::Element(el) => Some(el),
+            _ => None,
+        }
+    }
+
+    /// Descendants of a node in tree order (pre-order), not including the node itself
+    pub fn descendants(&self, node: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        let mut stack: Vec<NodeId> = self.nodes[node].children.iter().rev().copied().collect();
+        std::iter::from_fn(move || {
+            let id = stack.pop()?;
+            stack.extend(self.nodes[id].children.iter().rev().copied());
+            Some(id)
+        })
+    }
+
+    /// Ancestors of a node, nearest first, up to the root of its tree
+    pub fn ancestors(&self, node: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self.nodes[node].parent, |&id| self.nodes[id].parent)
+    }
+
+    /// The sibling after `node`, if any
+    pub fn next_sibling(&self, node: NodeId) -> Option<NodeId> {
+        let (siblings, index) = self.sibling_index(node)?;
+        siblings.get(index + 1).copied()
+    }
+
+    /// The sibling before `node`, if any
+    pub fn previous_sibling(&self, node: NodeId) -> Option<NodeId> {
+        let (siblings, index) = self.sibling_index(node)?;
+        index.checked_sub(1).map(|i| siblings[i])
+    }
+
+    /// Elements of the document with the given tag name in tree order. HTML
+    /// elements match case-insensitively and "*" matches every element.
+    pub fn elements_by_tag_name<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = NodeId> + 'a {
+        self.descendants(self.root()).filter(move |&id| match self.element(id) {
+            Some(_) if tag == "*" => true,
+            Some(el) if el.is_html() => el.tag_name.eq_ignore_ascii_case(tag),
+            Some(el) => el.tag_name == tag,
+            None => false,
+        })
+    }
+
+    /// Like `textContent`: the data of a text or comment node, or the
+    /// concatenated text of all descendant text nodes of anything else
+    pub fn text_content(&self, node: NodeId) -> String {
+        match &self.nodes[node].node_type {
+            NodeType::Text(data) | NodeType::Comment(data) => data.clone(),
+            NodeType::Doctype { .. } => String::new(),
+            _ => self
+                .descendants(node)
+                .filter_map(|id| match &self.nodes[id].node_type {
+                    NodeType::Text(text) => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect(),
+        }
+    }
+
+    /// Compare two nodes by tree order: ancestors come before their descendants
+    /// and earlier siblings' subtrees before later ones. Nodes in different
+    /// trees are ordered consistently by their roots.
+    pub fn compare_document_order(&self, a: NodeId, b: NodeId) -> Ordering {
+        if a == b {
+            return Ordering::Equal;
+        }
+        let path_a = self.path_from_root(a);
+        let path_b = self.path_from_root(b);
+        if path_a[0] != path_b[0] {
+            return path_a[0].cmp(&path_b[0]);
+        }
+        let common = path_a.iter().zip(&path_b).take_while(|(x, y)| x == y).count();
+        match (path_a.get(common), path_b.get(common)) {
+            (None, _) => Ordering::Less,
+            (_, None) => Ordering::Greater,
+            (Some(&x), Some(&y)) => {
+                let siblings = &self.nodes[path_a[common - 1]].children;
+                let position = |id| siblings.iter().position(|&c| c == id);
+                position(x).cmp(&position(y))
+            }
+        }
+    }
+
+    /// The node's parent's children and the node's index among them
+    fn sibling_index(&self, node: NodeId) -> Option<(&[NodeId], usize)> {
+        let siblings = &self.nodes[self.nodes[node].parent?].children;
+        let index = siblings.iter().position(|&c| c == node)?;
+        Some((siblings, index))
+    }
+
+    /// The node and its ancestors, root first
+    fn path_from_root(&self, node: NodeId) -> Vec<NodeId> {
+        let mut path: Vec<NodeId> = std::iter::once(node).chain(self.ancestors(node)).collect();
+        path.reverse();
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use crate::dom::Dom;
+    use crate::parser::html::tree_builder::HtmlParser;
+
+    const PAGE: &str = "<title>Hi</title><div id=a><p id=b>x<b>y</b></p><!--c--><p id=c>z</p></div><P id=d></P>";
+
+    fn ids(dom: &Dom, nodes: impl Iterator<Item = crate::dom::NodeId>) -> Vec<String> {
+        nodes.filter_map(|id| dom.get_attribute(id, "id").map(str::to_string)).collect()
+    }
+
+    #[test]
+    fn test_descendants_ancestors_and_siblings() {
+        let dom = HtmlParser::new(PAGE).parse();
+        let a = dom.element_by_id("a").unwrap();
+        assert_eq!(ids(&dom, dom.descendants(a)), vec!["b", "c"]);
+        assert_eq!(dom.descendants(a).count(), 7);
+
+        let bold = dom.elements_by_tag_name("b").next().unwrap();
+        let ancestors: Vec<_> = dom.ancestors(bold).filter_map(|id| dom.element(id)).map(|el| el.tag_name.as_str()).collect();
+        assert_eq!(ancestors, vec!["p", "div", "body", "html"]);
+        assert_eq!(dom.ancestors(bold).last(), Some(dom.root()));
+
+        let b = dom.element_by_id("b").unwrap();
+        let comment = dom.next_sibling(b).unwrap();
+        assert_eq!(dom.text_content(comment), "c");
+        assert_eq!(dom.previous_sibling(comment), Some(b));
+        assert_eq!(dom.previous_sibling(b), None);
+        assert_eq!(dom.next_sibling(dom.element_by_id("c").unwrap()), None);
+    }
+
+    #[test]
+    fn test_lookup_and_text_content() {
+        let dom = HtmlParser::new(PAGE).parse();
+        assert_eq!(ids(&dom, dom.elements_by_tag_name("p")), vec!["b", "c", "d"]);
+        assert_eq!(ids(&dom, dom.elements_by_tag_name("*")), vec!["a", "b", "c", "d"]);
+        assert_eq!(dom.element_by_id("missing"), None);
+
+        let title = dom.elements_by_tag_name("title").next().unwrap();
+        assert_eq!(dom.text_content(title), "Hi");
+        assert_eq!(dom.text_content(dom.element_by_id("a").unwrap()), "xyz");
+    }
+
+    #[test]
+    fn test_compare_document_order() {
+        let mut dom = HtmlParser::new(PAGE).parse();
+        let [a, b, c, d] = ["a", "b", "c", "d"].map(|id| dom.element_by_id(id).unwrap());
+        assert_eq!(dom.compare_document_order(a, b), Ordering::Less);
+        assert_eq!(dom.compare_document_order(c, b), Ordering::Greater);
+        assert_eq!(dom.compare_document_order(b, d), Ordering::Less);
+        assert_eq!(dom.compare_document_order(dom.root(), a), Ordering::Less);
+        assert_eq!(dom.compare_document_order(c, c), Ordering::Equal);
+
+        let mut all: Vec<_> = vec![d, b, a, c];
+        all.sort_by(|&x, &y| dom.compare_document_order(x, y));
+        assert_eq!(all, vec![a, b, c, d]);
+
+        // Detached nodes still get a consistent order
+        let detached = dom.create_element("span", vec![], None);
+        let order = dom.compare_document_order(detached, a);
+        assert_ne!(order, Ordering::Equal);
+        assert_eq!(dom.compare_document_order(a, detached), order.reverse());
+    }
+}
@@ -42,6 +42,19 @@ fn get_tag_name(dom: &Dom, node_id: NodeId) -> String {
     match &dom.nodes[node_id].node_type {
         crate::dom::NodeType::Element(el) => el.tag_name.clone(),
         crate::dom::NodeType::Text(t) => format!("#text({})", &t[..t.len().min(20)]),
+        crate::dom::NodeType::Document => "#document".to_string(),
+        crate::dom::NodeType::DocumentFragment => "#document-fragment".to_string(),
+        crate::dom::NodeType::Doctype { .. } => "#doctype".to_string(),
+        crate::dom::NodeType::Comment(_) => "#comment".to_string(),
+    }
+}
+
+/// Nodes that never generate boxes: excluded tags, comments and doctypes
+fn is_excluded(dom: &Dom, node_id: NodeId, exclude_tags: &[&str]) -> bool {
+    match &dom.nodes[node_id].node_type {
+        crate::dom::NodeType::Element(el) => exclude_tags.contains(&el.tag_name.as_str()),
+        crate::dom::NodeType::Comment(_) | crate::dom::NodeType::Doctype { .. } => true,
+        _ => false,
     }
 }
 
@@ -137,7 +150,8 @@ impl LayoutEngine {
 
     fn is_root_element(&self, dom: &Dom, node_id: NodeId) -> bool {
         match &dom.nodes[node_id].node_type {
-            crate::dom::NodeType::Element(el) => matches!(el.tag_name.as_str(), "document" | "html" | "body"),
+            crate::dom::NodeType::Document | crate::dom::NodeType::DocumentFragment => true,
+            crate::dom::NodeType::Element(el) => matches!(el.tag_name.as_str(), "html" | "body"),
             _ => false,
         }
     }
@@ -158,21 +172,18 @@ impl LayoutEngine {
         
         // Debug: log all style properties for this element
         layout_log(&format!("  <{}> style props: {:?}", tag, style.properties.keys().collect::<Vec<_>>()));
-        if let Some(margin) = style.get("margin") {
-            layout_log(&format!("  <{}> margin property: '{}'", tag, margin));
-        }
         if let Some(width) = style.get("width") {
             layout_log(&format!("  <{}> width property: '{}'", tag, width));
         }
         
         // Get margin with viewport height awareness for vh units
-        let (body_mt, body_mr, body_mb, body_ml) = style.get_margin_with_viewport(viewport.height);
+        let (body_mt, body_mr, body_mb, body_ml) = style.get_margin(viewport, viewport.width);
         let has_auto_margin = style.has_auto_horizontal_margin();
         layout_log(&format!("  <{}> margins: top={}, right={}, bottom={}, left={}, auto={}", 
             tag, body_mt, body_mr, body_mb, body_ml, has_auto_margin));
         
         // Check for explicit width on body (e.g., width: 60vw)
-        let explicit_width = style.get_width_px(viewport.width);
+        let explicit_width = style.get_width_px(viewport, viewport.width);
         layout_log(&format!("  <{}> explicit_width: {:?}", tag, explicit_width));
         
         // Calculate the actual content width for this root element
@@ -199,11 +210,7 @@ impl LayoutEngine {
         let children = dom.nodes[node_id].children.clone();
 
         for child_id in children {
-            let should_exclude = if let crate::dom::NodeType::Element(el) = &dom.nodes[child_id].node_type {
-                exclude_tags.contains(&el.tag_name.as_str())
-            } else {
-                false
-            };
+            let should_exclude = is_excluded(dom, child_id, exclude_tags);
 
             if should_exclude {
                 continue;
@@ -219,7 +226,7 @@ impl LayoutEngine {
             } else if self.is_list_container(dom, child_id) {
                 // List containers (ul, ol)
                 let child_style = stylesheet.compute_style_with_viewport(dom, child_id, viewport);
-                let (child_mt, _, child_mb, _) = child_style.get_margin_with_viewport(viewport.height);
+                let (child_mt, _, child_mb, _) = child_style.get_margin(viewport, content_width);
                 current_y += child_mt;
                 
                 let list_box = self.layout_list_container(
@@ -233,7 +240,7 @@ impl LayoutEngine {
             } else if self.is_block_element(dom, child_id) {
                 // Get child margins first to properly position
                 let child_style = stylesheet.compute_style_with_viewport(dom, child_id, viewport);
-                let (child_mt, _, child_mb, _) = child_style.get_margin_with_viewport(viewport.height);
+                let (child_mt, _, child_mb, _) = child_style.get_margin(viewport, content_width);
                 
                 // Add top margin before laying out child
                 current_y += child_mt;
@@ -282,14 +289,15 @@ impl LayoutEngine {
 
     fn is_block_element(&self, dom: &Dom, node_id: NodeId) -> bool {
         match &dom.nodes[node_id].node_type {
-            crate::dom::NodeType::Text(_) => false,
+            crate::dom::NodeType::Text(_) | crate::dom::NodeType::Comment(_) | crate::dom::NodeType::Doctype { .. } => false,
+            crate::dom::NodeType::Document | crate::dom::NodeType::DocumentFragment => true,
             crate::dom::NodeType::Element(el) => {
                 // Check if display is explicitly set via style attribute
                 // For now, use HTML default block/inline classification
                 // Per HTML spec, these elements have display: block by default
                 matches!(el.tag_name.to_lowercase().as_str(),
                     // Document structure
-                    "html" | "body" | "document" | "head" | "title" | "meta" | "link" | 
+                    "html" | "body" | "head" | "title" | "meta" | "link" | 
                     "style" | "script" | "noscript" | "template" |
                     // Sections
                     "article" | "aside" | "footer" | "header" | "nav" | "section" | "main" |
@@ -372,10 +380,10 @@ impl LayoutEngine {
         let style = stylesheet.compute_style_with_viewport(dom, node_id, viewport);
         
         // Step 1: Get padding values
-        let (padding_top, padding_right, padding_bottom, padding_left) = style.get_padding();
+        let (padding_top, padding_right, padding_bottom, padding_left) = style.get_padding(viewport, containing_width);
         
         // Step 2: Get margin values with viewport height awareness for vh units
-        let (margin_top, margin_right, margin_bottom, margin_left) = style.get_margin_with_viewport(viewport.height);
+        let (margin_top, margin_right, margin_bottom, margin_left) = style.get_margin(viewport, containing_width);
         let has_auto_margin = style.has_auto_horizontal_margin();
         
         layout_log(&format!("layout_block: <{}> at ({}, {}) containing_width={}", tag, x, y, containing_width));
@@ -383,8 +391,7 @@ impl LayoutEngine {
         layout_log(&format!("  padding: t={}, r={}, b={}, l={}", padding_top, padding_right, padding_bottom, padding_left));
         
         // Check for explicit width
-        let explicit_width = style.get_width_percentage().map(|f| viewport.width * f)
-            .or_else(|| style.get_width_px(viewport.width));
+        let explicit_width = style.get_width_px(viewport, containing_width);
         layout_log(&format!("  explicit_width: {:?}", explicit_width));
         
         // Step 3: Calculate content width
@@ -431,11 +438,7 @@ impl LayoutEngine {
 
         while child_idx < children.len() {
             let child_id = children[child_idx];
-            let should_exclude = if let crate::dom::NodeType::Element(el) = &dom.nodes[child_id].node_type {
-                exclude_tags.contains(&el.tag_name.as_str())
-            } else {
-                false
-            };
+            let should_exclude = is_excluded(dom, child_id, exclude_tags);
 
             if should_exclude {
                 child_idx += 1;
@@ -445,7 +448,7 @@ impl LayoutEngine {
             // Check for list containers first (ul, ol)
             if self.is_list_container(dom, child_id) {
                 let child_style = stylesheet.compute_style_with_viewport(dom, child_id, viewport);
-                let (child_mt, _, child_mb, _) = child_style.get_margin_with_viewport(viewport.height);
+                let (child_mt, _, child_mb, _) = child_style.get_margin(viewport, content_width);
                 current_y += child_mt;
                 
                 let list_box = self.layout_list_container(
@@ -462,7 +465,7 @@ impl LayoutEngine {
                 // Child's containing width is THIS element's content width
                 // Get child margins first to properly position
                 let child_style = stylesheet.compute_style_with_viewport(dom, child_id, viewport);
-                let (child_mt, _, child_mb, _) = child_style.get_margin_with_viewport(viewport.height);
+                let (child_mt, _, child_mb, _) = child_style.get_margin(viewport, content_width);
                 
                 // Add top margin before laying out child
                 current_y += child_mt;
@@ -484,11 +487,7 @@ impl LayoutEngine {
                 
                 while child_idx < children.len() {
                     let next_id = children[child_idx];
-                    let is_excluded = if let crate::dom::NodeType::Element(el) = &dom.nodes[next_id].node_type {
-                        exclude_tags.contains(&el.tag_name.as_str())
-                    } else {
-                        false
-                    };
+                    let is_excluded = is_excluded(dom, next_id, exclude_tags);
                     
                     if is_excluded {
                         child_idx += 1;
@@ -563,11 +562,11 @@ impl LayoutEngine {
         let list_indent = 40.0;
         
         // Get any explicit padding from style, or use default
-        let (padding_top, padding_right, padding_bottom, padding_left) = style.get_padding();
+        let (padding_top, padding_right, padding_bottom, padding_left) = style.get_padding(viewport, containing_width);
         let effective_padding_left = if padding_left > 0.0 { padding_left } else { list_indent };
         
         // Get margins (margin_top/bottom not currently used for list containers)
-        let (_margin_top, margin_right, _margin_bottom, margin_left) = style.get_margin_with_viewport(viewport.height);
+        let (_margin_top, margin_right, _margin_bottom, margin_left) = style.get_margin(viewport, containing_width);
         
         // Calculate content area
         let border_box_x = x + margin_left;
@@ -592,11 +591,7 @@ impl LayoutEngine {
                 self.is_list_container(dom, child_id),
                 self.is_block_element(dom, child_id)));
             
-            let should_exclude = if let crate::dom::NodeType::Element(el) = &dom.nodes[child_id].node_type {
-                exclude_tags.contains(&el.tag_name.as_str())
-            } else {
-                false
-            };
+            let should_exclude = is_excluded(dom, child_id, exclude_tags);
 
             if should_exclude {
                 continue;
@@ -626,7 +621,7 @@ impl LayoutEngine {
             } else if self.is_block_element(dom, child_id) {
                 // Other block element inside list (unusual but possible)
                 let child_style = stylesheet.compute_style_with_viewport(dom, child_id, viewport);
-                let (child_mt, _, child_mb, _) = child_style.get_margin_with_viewport(viewport.height);
+                let (child_mt, _, child_mb, _) = child_style.get_margin(viewport, content_width);
                 current_y += child_mt;
                 
                 let child_box = self.layout_block_element(
@@ -728,11 +723,7 @@ impl LayoutEngine {
 
         while child_idx < children.len() {
             let child_id = children[child_idx];
-            let should_exclude = if let crate::dom::NodeType::Element(el) = &dom.nodes[child_id].node_type {
-                exclude_tags.contains(&el.tag_name.as_str())
-            } else {
-                false
-            };
+            let should_exclude = is_excluded(dom, child_id, exclude_tags);
 
             if should_exclude {
                 child_idx += 1;
@@ -754,7 +745,7 @@ impl LayoutEngine {
             } else if self.is_block_element(dom, child_id) {
                 // Block element inside li
                 let child_style = stylesheet.compute_style_with_viewport(dom, child_id, viewport);
-                let (child_mt, _, child_mb, _) = child_style.get_margin_with_viewport(viewport.height);
+                let (child_mt, _, child_mb, _) = child_style.get_margin(viewport, content_width);
                 current_y += child_mt;
                 
                 let child_box = self.layout_block_element(
@@ -773,11 +764,7 @@ impl LayoutEngine {
                 
                 while child_idx < children.len() {
                     let next_id = children[child_idx];
-                    let is_excluded = if let crate::dom::NodeType::Element(el) = &dom.nodes[next_id].node_type {
-                        exclude_tags.contains(&el.tag_name.as_str())
-                    } else {
-                        false
-                    };
+                    let is_excluded = is_excluded(dom, next_id, exclude_tags);
                     
                     if is_excluded {
                         child_idx += 1;
@@ -870,11 +857,11 @@ impl LayoutEngine {
                 text_log(&format!("  split into {} words: {:?}", words.len(), words));
                 
                 // Measure space width using actual font
-                let space_width = self.measure_text_width(" ", font_manager, font_family, font_size, is_bold, is_italic);
+                let space_width = self.measure_text_width(" ", font_manager, &font_family, font_size, is_bold, is_italic);
                 
                 for (word_idx, word) in words.iter().enumerate() {
                     // Measure word using actual font metrics
-                    let word_width = self.measure_text_width(word, font_manager, font_family, font_size, is_bold, is_italic);
+                    let word_width = self.measure_text_width(word, font_manager, &font_family, font_size, is_bold, is_italic);
                     
                     text_log(&format!("    word[{}] '{}': width={:.2}, space_width={:.2}, current_x={:.2}, available={:.2}", 
                         word_idx, word, word_width, space_width, current_x, x + width - current_x));
@@ -895,7 +882,7 @@ impl LayoutEngine {
                                 for c in remaining_word.chars() {
                                     // Measure character using actual font
                                     let char_str = c.to_string();
-                                    let char_width = self.measure_text_width(&char_str, font_manager, font_family, font_size, is_bold, is_italic);
+                                    let char_width = self.measure_text_width(&char_str, font_manager, &font_family, font_size, is_bold, is_italic);
                                     if accumulated_width + char_width > available && char_count > 0 {
                                         break;
                                     }
@@ -921,7 +908,7 @@ impl LayoutEngine {
                                 remaining_word = rest;
                                 
                                 // Measure chunk using actual font
-                                let chunk_width = self.measure_text_width(chunk, font_manager, font_family, font_size, is_bold, is_italic);
+                                let chunk_width = self.measure_text_width(chunk, font_manager, &font_family, font_size, is_bold, is_italic);
                                 
                                 let word_box = LayoutBox {
                                     node_id: child_id,
@@ -1018,7 +1005,7 @@ impl LayoutEngine {
             layout_log(&format!("  inline_line: no visible content, returning empty box"));
             // Return a zero-height box
             return LayoutBox {
-                node_id: 0,
+                node_id: dom.root(),
                 box_type: BoxType::Block,
                 dimensions: Dimensions { x, y: start_y, width: 0.0, height: 0.0 },
                 style: Style::new(),
@@ -1029,7 +1016,7 @@ impl LayoutEngine {
         
         layout_log(&format!("  inline_line: {} children, height={}", visible_boxes.len(), total_height));
         LayoutBox {
-            node_id: 0,
+            node_id: dom.root(),
             box_type: BoxType::Block,
             dimensions: Dimensions { x, y: start_y, width, height: total_height },
             style: Style::new(),
@@ -1069,8 +1056,8 @@ impl LayoutEngine {
                 let font_size = style.get_font_size();
                 let line_height = font_size * 1.2;
                 let font_family = style.get_font_family();
-                let is_bold = style.get_font_weight() == "bold";
-                let is_italic = style.get_font_style() == "italic";
+                let is_bold = style.is_bold();
+                let is_italic = style.is_italic();
                 let text_width = self.measure_text_width(text, font_manager, &font_family, font_size, is_bold, is_italic);
 
                 LayoutBox {
@@ -1098,11 +1085,7 @@ impl LayoutEngine {
                     let mut max_height = 0.0_f32; // Start with 0 height, don't assume 16px
 
                     for &child_id in &dom.nodes[node_id].children {
-                        let should_exclude = if let crate::dom::NodeType::Element(el) = &dom.nodes[child_id].node_type {
-                            exclude_tags.contains(&el.tag_name.as_str())
-                        } else {
-                            false
-                        };
+                        let should_exclude = is_excluded(dom, child_id, exclude_tags);
 
                         if !should_exclude {
                             let remaining_width = (x + max_width - current_x).max(0.0);
@@ -1126,6 +1109,15 @@ impl LayoutEngine {
                     }
                 }
             }
+            // Comments, doctypes and document nodes have no inline content
+            _ => LayoutBox {
+                node_id,
+                box_type: BoxType::Inline,
+                dimensions: Dimensions { x, y, width: 0.0, height: 0.0 },
+                style,
+                children: vec![],
+                text_content: None,
+            },
         }
     }
 }
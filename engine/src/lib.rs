@@ -8,7 +8,7 @@ pub mod net;
 pub mod font;
 pub mod platform;
 
-pub use style::{Viewport, Breakpoint, MediaCondition, MediaRule};
+pub use style::{Viewport, Breakpoint, DeviceFeatures, MediaCondition, MediaQueryList, MediaRule};
 pub use layout::{CSS_PX_SCALE, BASE_FONT_SIZE};
 
 // Re-export commonly used net module items
@@ -6,7 +6,7 @@
 // - Transform srcset attributes
 // - Update CSS url() references
 
-use crate::dom::{Dom, NodeId, NodeType, ElementData};
+use crate::dom::{Dom, NodeId};
 use crate::net::{NetworkManager, FetchedResource};
 use crate::net::image::{ImageType, detect_image_type, decode_image};
 use std::collections::HashMap;
@@ -152,92 +152,54 @@ impl HtmlRewriter {
     }
     
     fn rewrite_dom(&self, dom: &mut Dom) {
-        self.rewrite_node(dom, dom.root());
-    }
-    
-    fn rewrite_node(&self, dom: &mut Dom, node_id: NodeId) {
-        // Get children first to avoid borrow issues
-        let children: Vec<NodeId> = dom.nodes[node_id].children.clone();
-        
-        // Process this node
-        if let NodeType::Element(ref mut el) = dom.nodes[node_id].node_type {
-            let tag = el.tag_name.to_lowercase();
-            
-            match tag.as_str() {
-                "img" => self.rewrite_img_element(el),
-                "source" => self.rewrite_source_element(el),
-                "link" => self.rewrite_link_element(el),
-                _ => {}
-            }
-            
-            // Rewrite style attribute
-            self.rewrite_style_attribute(el);
-        }
-        
-        // Recurse into children
-        for child_id in children {
-            self.rewrite_node(dom, child_id);
+        let nodes: Vec<NodeId> = dom.descendants(dom.root()).collect();
+        for node_id in nodes {
+            self.rewrite_node(dom, node_id);
         }
     }
     
-    fn rewrite_img_element(&self, el: &mut ElementData) {
-        // Rewrite src attribute
-        if let Some(src_idx) = el.attributes.iter().position(|(k, _)| k.eq_ignore_ascii_case("src")) {
-            let src = el.attributes[src_idx].1.clone();
-            if let Some(processed) = self.find_processed(&src) {
-                el.attributes[src_idx].1 = processed.final_url.clone();
-            }
+    fn rewrite_node(&self, dom: &mut Dom, node_id: NodeId) {
+        let Some(el) = dom.element(node_id) else {
+            return;
+        };
+        match el.tag_name.to_lowercase().as_str() {
+            "img" | "source" => self.rewrite_image_sources(dom, node_id),
+            "link" => self.rewrite_link_element(dom, node_id),
+            _ => {}
         }
         
-        // Rewrite srcset attribute
-        if let Some(srcset_idx) = el.attributes.iter().position(|(k, _)| k.eq_ignore_ascii_case("srcset")) {
-            let srcset = el.attributes[srcset_idx].1.clone();
-            let rewritten = self.rewrite_srcset(&srcset);
-            el.attributes[srcset_idx].1 = rewritten;
-        }
+        // Rewrite style attribute
+        self.rewrite_attribute(dom, node_id, "style", |style| Some(self.rewrite_css_urls(style)));
     }
     
-    fn rewrite_source_element(&self, el: &mut ElementData) {
-        // Rewrite srcset attribute
-        if let Some(srcset_idx) = el.attributes.iter().position(|(k, _)| k.eq_ignore_ascii_case("srcset")) {
-            let srcset = el.attributes[srcset_idx].1.clone();
-            let rewritten = self.rewrite_srcset(&srcset);
-            el.attributes[srcset_idx].1 = rewritten;
-        }
-        
-        // Rewrite src attribute
-        if let Some(src_idx) = el.attributes.iter().position(|(k, _)| k.eq_ignore_ascii_case("src")) {
-            let src = el.attributes[src_idx].1.clone();
-            if let Some(processed) = self.find_processed(&src) {
-                el.attributes[src_idx].1 = processed.final_url.clone();
-            }
-        }
+    /// Rewrite the src and srcset attributes of an img or source element
+    fn rewrite_image_sources(&self, dom: &mut Dom, node_id: NodeId) {
+        self.rewrite_attribute(dom, node_id, "src", |src| self.find_final_url(src));
+        self.rewrite_attribute(dom, node_id, "srcset", |srcset| Some(self.rewrite_srcset(srcset)));
     }
     
-    fn rewrite_link_element(&self, el: &mut ElementData) {
-        let rel = el.attributes.iter()
-            .find(|(k, _)| k.eq_ignore_ascii_case("rel"))
-            .map(|(_, v)| v.to_lowercase())
+    fn rewrite_link_element(&self, dom: &mut Dom, node_id: NodeId) {
+        let rel = dom.get_attribute(node_id, "rel")
+            .map(|v| v.to_lowercase())
             .unwrap_or_default();
         
         if rel.contains("icon") {
-            if let Some(href_idx) = el.attributes.iter().position(|(k, _)| k.eq_ignore_ascii_case("href")) {
-                let href = el.attributes[href_idx].1.clone();
-                if let Some(processed) = self.find_processed(&href) {
-                    el.attributes[href_idx].1 = processed.final_url.clone();
-                }
-            }
+            self.rewrite_attribute(dom, node_id, "href", |href| self.find_final_url(href));
         }
     }
     
-    fn rewrite_style_attribute(&self, el: &mut ElementData) {
-        if let Some(style_idx) = el.attributes.iter().position(|(k, _)| k.eq_ignore_ascii_case("style")) {
-            let style = el.attributes[style_idx].1.clone();
-            let rewritten = self.rewrite_css_urls(&style);
-            el.attributes[style_idx].1 = rewritten;
+    /// Replace an attribute's value with `rewrite(value)`, if present and rewritten
+    fn rewrite_attribute(&self, dom: &mut Dom, node_id: NodeId, name: &str, rewrite: impl Fn(&str) -> Option<String>) {
+        let rewritten = dom.get_attribute(node_id, name).and_then(rewrite);
+        if let Some(value) = rewritten {
+            let _ = dom.set_attribute(node_id, name, &value);
         }
     }
     
+    fn find_final_url(&self, url: &str) -> Option<String> {
+        self.find_processed(url).map(|processed| processed.final_url.clone())
+    }
+    
     fn rewrite_srcset(&self, srcset: &str) -> String {
         let mut parts = Vec::new();
         
@@ -1,4 +1,21 @@
-use super::CssToken;
+// CSS parser following CSS Syntax Module Level 3
+// Spec Reference: https://www.w3.org/TR/css-syntax-3/#parsing
+//
+// Tokens are first grouped into component values (spec 5.4.7): simple blocks
+// and functions hold their contents, so rules, declarations and selectors are
+// parsed from trees of values rather than a flat token stream.
+//
+// Status:
+// ✅ Rule lists with qualified rules and at-rules
+// ✅ Nested rules inside @media, @supports, @document, @layer and @container
+// ✅ Declaration lists with !important
+// ✅ Selectors Level 3 plus :not(), :is(), :where() and :nth-*() from Level 4
+// ⚠️ Rules with invalid selectors are dropped, as the spec requires
+// ❌ Namespace prefixes in selectors (`svg|rect`)
+
+use std::fmt;
+
+use super::tokenizer::CssToken;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Selector {
@@ -17,6 +34,51 @@ pub enum Selector {
     Child(Box<Selector>, Box<Selector>),        // div > p
     Adjacent(Box<Selector>, Box<Selector>),     // h1 + p
     GeneralSibling(Box<Selector>, Box<Selector>), // h1 ~ p
+    Compound(Vec<Selector>),            // div.note[title]
+    Not(Box<Selector>),                 // :not(.hidden)
+    Is(Vec<Selector>),                  // :is(h1, h2)
+    Where(Vec<Selector>),               // :where(ul, ol), with no specificity
+    NthChild {                          // :nth-child(2n+1), :nth-last-of-type(3)
+        a: i32,
+        b: i32,
+        of_type: bool,
+        from_end: bool,
+    },
+}
+
+/// Selector specificity (spec Selectors 4 §17): counts of ids; of classes,
+/// attributes and pseudo-classes; and of type selectors and pseudo-elements
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Specificity(pub u32, pub u32, pub u32);
+
+impl std::ops::Add for Specificity {
+    type Output = Specificity;
+
+    fn add(self, other: Specificity) -> Specificity {
+        Specificity(self.0 + other.0, self.1 + other.1, self.2 + other.2)
+    }
+}
+
+impl Selector {
+    pub fn specificity(&self) -> Specificity {
+        match self {
+            Selector::Universal => Specificity(0, 0, 0),
+            Selector::Id(_) => Specificity(1, 0, 0),
+            Selector::Class(_) | Selector::Attribute { .. } | Selector::PseudoClass(_) | Selector::NthChild { .. } => {
+                Specificity(0, 1, 0)
+            }
+            Selector::Element(_) | Selector::PseudoElement(_) => Specificity(0, 0, 1),
+            Selector::Descendant(left, right)
+            | Selector::Child(left, right)
+            | Selector::Adjacent(left, right)
+            | Selector::GeneralSibling(left, right) => left.specificity() + right.specificity(),
+            Selector::Compound(parts) => parts.iter().map(Selector::specificity).fold(Specificity::default(), |a, b| a + b),
+            // :not() and :is() count as their most specific argument
+            Selector::Not(inner) => inner.specificity(),
+            Selector::Is(list) => list.iter().map(Selector::specificity).max().unwrap_or_default(),
+            Selector::Where(_) => Specificity(0, 0, 0),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -29,10 +91,29 @@ pub enum AttrOperator {
     Suffix,       // $=
 }
 
+/// A preserved token, a function or a simple block (spec 5.4.7)
+#[derive(Debug, Clone, PartialEq)]
+pub enum ComponentValue {
+    Token(CssToken),
+    Function {
+        name: String,
+        value: Vec<ComponentValue>,
+    },
+    Block {
+        /// The opening bracket: '{', '[' or '('
+        open: char,
+        value: Vec<ComponentValue>,
+    },
+}
+
 #[derive(Debug, Clone)]
 pub struct Declaration {
+    /// The property name, lowercased unless it is a custom property
     pub property: String,
+    /// The serialized value, without `!important`
     pub value: String,
+    /// The value's component values, without surrounding whitespace or `!important`
+    pub components: Vec<ComponentValue>,
     pub important: bool,
 }
 
@@ -52,6 +133,12 @@ pub enum CssItem {
     },
 }
 
+/// At-rules whose block holds a list of rules
+const GROUPING_AT_RULES: &[&str] = &["media", "supports", "document", "-moz-document", "layer", "container"];
+
+/// Pseudo-elements that may be written with a single colon (Selectors 3 §7)
+const LEGACY_PSEUDO_ELEMENTS: &[&str] = &["before", "after", "first-line", "first-letter"];
+
 pub struct CssParser {
     tokens: Vec<CssToken>,
     pos: usize,
@@ -62,375 +149,576 @@ impl CssParser {
         Self { tokens, pos: 0 }
     }
 
-    fn peek(&self) -> Option<&CssToken> {
-        self.tokens.get(self.pos)
+    /// Parse a stylesheet: a list of rules (spec 5.3.3)
+    pub fn parse(&mut self) -> Vec<CssItem> {
+        let values = self.component_values();
+        parse_rule_list(&values, true)
     }
 
-    fn next(&mut self) -> Option<&CssToken> {
-        let token = self.tokens.get(self.pos);
-        self.pos += 1;
-        token
+    /// Parse a whole comma-separated selector list, as given to querySelector.
+    /// Returns None unless every token is part of a valid selector.
+    pub fn parse_selector_list(&mut self) -> Option<Vec<Selector>> {
+        let values = self.component_values();
+        parse_selector_list(&values)
     }
 
-    fn expect(&mut self, expected: &CssToken) -> bool {
-        if self.peek() == Some(expected) {
-            self.next();
-            true
-        } else {
-            false
+    /// Parse the contents of a style attribute: a list of declarations (spec 5.3.8)
+    pub fn parse_declaration_list(&mut self) -> Vec<Declaration> {
+        let values = self.component_values();
+        parse_declaration_list(&values)
+    }
+
+    /// Consume the remaining tokens as a list of component values (spec 5.3.10)
+    pub fn component_values(&mut self) -> Vec<ComponentValue> {
+        let mut values = Vec::new();
+        while let Some(value) = self.consume_component_value() {
+            values.push(value);
         }
+        values
     }
 
-    pub fn parse(&mut self) -> Vec<CssItem> {
-        let mut items = Vec::new();
+    /// Spec 5.4.7: consume a component value
+    fn consume_component_value(&mut self) -> Option<ComponentValue> {
+        let token = self.tokens.get(self.pos)?.clone();
+        self.pos += 1;
+        Some(match token {
+            CssToken::OpenBrace => self.consume_block('{', &CssToken::CloseBrace),
+            CssToken::OpenBracket => self.consume_block('[', &CssToken::CloseBracket),
+            CssToken::OpenParen => self.consume_block('(', &CssToken::CloseParen),
+            CssToken::Function(name) => ComponentValue::Function {
+                name,
+                value: self.consume_until(&CssToken::CloseParen),
+            },
+            token => ComponentValue::Token(token),
+        })
+    }
 
-        while self.peek().is_some() {
-            match self.peek() {
-                Some(CssToken::At(_)) => {
-                    items.push(self.parse_at_rule());
-                }
-                _ => {
-                    if let Some(rules) = self.parse_rule() {
-                        for rule in rules {
-                            items.push(CssItem::Rule(rule));
-                        }
-                    } else {
-                        self.next();
-                    }
-                }
+    /// Spec 5.4.8: consume a simple block, after its opening token
+    fn consume_block(&mut self, open: char, close: &CssToken) -> ComponentValue {
+        ComponentValue::Block { open, value: self.consume_until(close) }
+    }
+
+    /// Component values up to and including the closing token, or EOF (spec 5.4.9)
+    fn consume_until(&mut self, close: &CssToken) -> Vec<ComponentValue> {
+        let mut values = Vec::new();
+        while let Some(token) = self.tokens.get(self.pos) {
+            if token == close {
+                self.pos += 1;
+                break;
+            }
+            if let Some(value) = self.consume_component_value() {
+                values.push(value);
             }
         }
+        values
+    }
+}
 
-        items
+impl ComponentValue {
+    pub fn is_whitespace(&self) -> bool {
+        matches!(self, ComponentValue::Token(CssToken::Whitespace))
     }
 
-    fn parse_at_rule(&mut self) -> CssItem {
-        let name = match self.next() {
-            Some(CssToken::At(n)) => n.clone(),
-            _ => String::new(),
-        };
+    fn is_token(&self, token: &CssToken) -> bool {
+        matches!(self, ComponentValue::Token(t) if t == token)
+    }
 
-        // Collect prelude until opening brace
-        let mut prelude = String::new();
-        while let Some(token) = self.peek() {
-            if matches!(token, CssToken::OpenBrace) {
-                break;
+    fn is_block(&self, open: char) -> bool {
+        matches!(self, ComponentValue::Block { open: o, .. } if *o == open)
+    }
+}
+
+/// Serialize a list of component values. Comments are inserted where two
+/// tokens would otherwise run together, so the text re-parses to the same values.
+pub fn serialize(values: &[ComponentValue]) -> String {
+    let mut out = String::new();
+    let mut previous: Option<&ComponentValue> = None;
+    for value in values {
+        if let Some(ComponentValue::Token(a)) = previous {
+            if needs_separator(a, value) {
+                out.push_str("/**/");
+            }
+        }
+        out.push_str(&value.to_string());
+        previous = Some(value);
+    }
+    out
+}
+
+/// Whether token `a` followed directly by `b` would tokenize differently
+fn needs_separator(a: &CssToken, b: &ComponentValue) -> bool {
+    use CssToken::*;
+    let function = Function(std::string::String::new());
+    let b = match b {
+        ComponentValue::Token(token) => token,
+        ComponentValue::Function { .. } => &function,
+        ComponentValue::Block { open: '(', .. } => &OpenParen,
+        ComponentValue::Block { .. } => return false,
+    };
+    let word_like = matches!(b, Ident(_) | Function(_) | Url(_) | BadUrl | Number(_) | Percentage(_) | Dimension { .. } | Cdc);
+    match a {
+        Ident(_) | AtKeyword(_) | Hash { .. } | Dimension { .. } => word_like || matches!(b, Delim('-') | OpenParen),
+        Number(_) => word_like || matches!(b, Delim('%') | Delim('-') | Delim('.')),
+        Delim('-') | Delim('#') | Delim('@') => word_like || matches!(b, Delim('-')),
+        Delim('.') | Delim('+') => matches!(b, Number(_) | Percentage(_) | Dimension { .. }),
+        Delim('/') => matches!(b, Delim('*')),
+        _ => false,
+    }
+}
+
+impl fmt::Display for ComponentValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ComponentValue::Token(token) => write!(f, "{}", token),
+            ComponentValue::Function { name, value } => {
+                write!(f, "{}{})", CssToken::Function(name.clone()), serialize(value))
+            }
+            ComponentValue::Block { open, value } => {
+                let close = match open {
+                    '{' => '}',
+                    '[' => ']',
+                    _ => ')',
+                };
+                write!(f, "{}{}{}", open, serialize(value), close)
             }
-            prelude.push_str(&format!("{:?}", token));
-            self.next();
         }
+    }
+}
 
-        self.expect(&CssToken::OpenBrace);
+/// Trim whitespace from both ends of a list of values
+fn trim(values: &[ComponentValue]) -> &[ComponentValue] {
+    let start = values.iter().position(|v| !v.is_whitespace()).unwrap_or(values.len());
+    let end = values.iter().rposition(|v| !v.is_whitespace()).map_or(start, |i| i + 1);
+    &values[start..end]
+}
 
-        let mut content = Vec::new();
-        let mut depth = 1;
+/// Split a list of values on a top-level token, such as commas or semicolons
+fn split_on<'a>(values: &'a [ComponentValue], separator: &'a CssToken) -> impl Iterator<Item = &'a [ComponentValue]> {
+    values.split(move |v| v.is_token(separator))
+}
 
-        while depth > 0 && self.peek().is_some() {
-            match self.peek() {
-                Some(CssToken::OpenBrace) => {
-                    depth += 1;
-                    self.next();
+/// Spec 5.4.1: consume a list of rules
+fn parse_rule_list(values: &[ComponentValue], top_level: bool) -> Vec<CssItem> {
+    let mut items = Vec::new();
+    let mut pos = 0;
+    while pos < values.len() {
+        match &values[pos] {
+            ComponentValue::Token(CssToken::Whitespace) => pos += 1,
+            ComponentValue::Token(CssToken::Cdo | CssToken::Cdc) if top_level => pos += 1,
+            ComponentValue::Token(CssToken::AtKeyword(name)) => {
+                pos += 1;
+                let prelude_start = pos;
+                while pos < values.len() && !values[pos].is_token(&CssToken::Semicolon) && !values[pos].is_block('{') {
+                    pos += 1;
                 }
-                Some(CssToken::CloseBrace) => {
-                    depth -= 1;
-                    if depth == 0 {
-                        self.next();
-                        break;
+                let prelude = collapse_whitespace(trim(&values[prelude_start..pos]));
+                let content = match values.get(pos) {
+                    Some(ComponentValue::Block { value, .. })
+                        if GROUPING_AT_RULES.contains(&name.to_ascii_lowercase().as_str()) =>
+                    {
+                        parse_rule_list(value, false)
                     }
-                    self.next();
+                    _ => Vec::new(),
+                };
+                pos += 1;
+                items.push(CssItem::AtRule { name: name.clone(), prelude, content });
+            }
+            _ => {
+                // Spec 5.4.3: consume a qualified rule; without a block it is dropped
+                let prelude_start = pos;
+                while pos < values.len() && !values[pos].is_block('{') {
+                    pos += 1;
                 }
-                _ => {
-                    if let Some(rules) = self.parse_rule() {
-                        for rule in rules {
-                            content.push(CssItem::Rule(rule));
-                        }
-                    } else {
-                        self.next();
-                    }
+                let Some(ComponentValue::Block { value, .. }) = values.get(pos) else {
+                    break;
+                };
+                pos += 1;
+                let Some(selectors) = parse_selector_list(&values[prelude_start..pos - 1]) else {
+                    continue;
+                };
+                let declarations = parse_declaration_list(value);
+                for selector in selectors {
+                    items.push(CssItem::Rule(Rule { selector, declarations: declarations.clone() }));
                 }
             }
         }
-
-        CssItem::AtRule {
-            name,
-            prelude,
-            content,
-        }
     }
+    items
+}
 
-    fn parse_rule(&mut self) -> Option<Vec<Rule>> {
-        let mut selectors = vec![self.parse_selector()?];
+/// Spec 5.4.5: consume a list of declarations. Invalid declarations and
+/// at-rules are skipped.
+fn parse_declaration_list(values: &[ComponentValue]) -> Vec<Declaration> {
+    split_on(values, &CssToken::Semicolon).filter_map(parse_declaration).collect()
+}
 
-        // Handle comma-separated selectors (e.g., a:link, a:visited { ... })
-        while self.peek() == Some(&CssToken::Comma) {
-            self.next(); // consume comma
-            if let Some(selector) = self.parse_selector() {
-                selectors.push(selector);
-            }
+/// Spec 5.4.6: consume a declaration
+fn parse_declaration(values: &[ComponentValue]) -> Option<Declaration> {
+    let values = trim(values);
+    let ComponentValue::Token(CssToken::Ident(name)) = values.first()? else {
+        return None;
+    };
+    let rest = trim(&values[1..]);
+    if !rest.first()?.is_token(&CssToken::Colon) {
+        return None;
+    }
+    let mut value = trim(&rest[1..]);
+
+    let mut important = false;
+    if let [head @ .., ComponentValue::Token(CssToken::Delim('!')), ComponentValue::Token(CssToken::Ident(word))]
+    | [head @ .., ComponentValue::Token(CssToken::Delim('!')), ComponentValue::Token(CssToken::Whitespace), ComponentValue::Token(CssToken::Ident(word))] = value
+    {
+        if word.eq_ignore_ascii_case("important") {
+            important = true;
+            value = trim(head);
         }
+    }
 
-        self.expect(&CssToken::OpenBrace);
-
-        let declarations = self.parse_declarations();
-
-        self.expect(&CssToken::CloseBrace);
-
-        // Create a rule for each selector with the same declarations
-        let rules = selectors
-            .into_iter()
-            .map(|selector| Rule {
-                selector,
-                declarations: declarations.clone(),
-            })
-            .collect();
+    // Custom property names are case-sensitive
+    let property = if name.starts_with("--") { name.clone() } else { name.to_ascii_lowercase() };
+    Some(Declaration {
+        property,
+        value: collapse_whitespace(value),
+        components: value.to_vec(),
+        important,
+    })
+}
 
-        Some(rules)
+/// Serialize values with each run of whitespace written as a single space
+fn collapse_whitespace(values: &[ComponentValue]) -> String {
+    let mut collapsed: Vec<ComponentValue> = Vec::with_capacity(values.len());
+    for value in values {
+        if !(value.is_whitespace() && collapsed.last().is_some_and(ComponentValue::is_whitespace)) {
+            collapsed.push(value.clone());
+        }
     }
+    serialize(&collapsed)
+}
 
-    fn parse_selector(&mut self) -> Option<Selector> {
-        let mut selector = self.parse_simple_selector()?;
+/// A comma-separated list of complex selectors; None if any of them is invalid
+fn parse_selector_list(values: &[ComponentValue]) -> Option<Vec<Selector>> {
+    split_on(values, &CssToken::Comma).map(parse_complex_selector).collect()
+}
 
-        loop {
-            match self.peek() {
-                Some(CssToken::Greater) => {
-                    self.next();
-                    let right = self.parse_simple_selector()?;
-                    selector = Selector::Child(Box::new(selector), Box::new(right));
-                }
-                Some(CssToken::Plus) => {
-                    self.next();
-                    let right = self.parse_simple_selector()?;
-                    selector = Selector::Adjacent(Box::new(selector), Box::new(right));
-                }
-                Some(CssToken::Tilde) => {
-                    self.next();
-                    let right = self.parse_simple_selector()?;
-                    selector = Selector::GeneralSibling(Box::new(selector), Box::new(right));
-                }
-                Some(CssToken::Comma) | Some(CssToken::OpenBrace) => break,
-                Some(_) => {
-                    // Descendant selector (implicit whitespace)
-                    if let Some(right) = self.parse_simple_selector() {
-                        selector = Selector::Descendant(Box::new(selector), Box::new(right));
-                    } else {
-                        break;
-                    }
+/// Compound selectors joined by combinators, like `nav > ul li`
+fn parse_complex_selector(values: &[ComponentValue]) -> Option<Selector> {
+    let values = trim(values);
+    let mut pos = 0;
+    let mut selector = parse_compound_selector(values, &mut pos)?;
+    while pos < values.len() {
+        let had_whitespace = values[pos].is_whitespace();
+        while values.get(pos).is_some_and(ComponentValue::is_whitespace) {
+            pos += 1;
+        }
+        let combinator = match &values[pos] {
+            ComponentValue::Token(CssToken::Delim(c @ ('>' | '+' | '~'))) => {
+                pos += 1;
+                while values.get(pos).is_some_and(ComponentValue::is_whitespace) {
+                    pos += 1;
                 }
-                None => break,
+                Some(*c)
             }
-        }
+            _ if had_whitespace => None,
+            _ => return None,
+        };
+        let left = Box::new(selector);
+        let right = Box::new(parse_compound_selector(values, &mut pos)?);
+        selector = match combinator {
+            Some('>') => Selector::Child(left, right),
+            Some('+') => Selector::Adjacent(left, right),
+            Some('~') => Selector::GeneralSibling(left, right),
+            _ => Selector::Descendant(left, right),
+        };
+    }
+    Some(selector)
+}
 
-        Some(selector)
+/// Simple selectors written without whitespace between them, like `a.nav:hover`
+fn parse_compound_selector(values: &[ComponentValue], pos: &mut usize) -> Option<Selector> {
+    let mut parts = Vec::new();
+    while let Some(value) = values.get(*pos) {
+        let is_type_selector = matches!(value, ComponentValue::Token(CssToken::Ident(_) | CssToken::Delim('*')));
+        if value.is_whitespace() || matches!(value, ComponentValue::Token(CssToken::Delim('>' | '+' | '~'))) {
+            break;
+        }
+        // A type selector can only come first
+        if is_type_selector && !parts.is_empty() {
+            return None;
+        }
+        parts.push(parse_simple_selector(values, pos)?);
+    }
+    match parts.len() {
+        0 => None,
+        1 => parts.pop(),
+        _ => Some(Selector::Compound(parts)),
     }
+}
 
-    fn parse_simple_selector(&mut self) -> Option<Selector> {
-        match self.peek() {
-            Some(CssToken::Asterisk) => {
-                self.next();
-                Some(Selector::Universal)
-            }
-            Some(CssToken::Hash(id)) => {
-                let id = id.clone();
-                self.next();
-                Some(Selector::Id(id))
-            }
-            Some(CssToken::Dot(class)) => {
-                let class = class.clone();
-                self.next();
-                Some(Selector::Class(class))
+fn parse_simple_selector(values: &[ComponentValue], pos: &mut usize) -> Option<Selector> {
+    let value = values.get(*pos)?;
+    *pos += 1;
+    match value {
+        ComponentValue::Token(CssToken::Delim('*')) => Some(Selector::Universal),
+        ComponentValue::Token(CssToken::Ident(tag)) => Some(Selector::Element(tag.clone())),
+        ComponentValue::Token(CssToken::Hash { value, is_id: true }) => Some(Selector::Id(value.clone())),
+        ComponentValue::Token(CssToken::Delim('.')) => match values.get(*pos)? {
+            ComponentValue::Token(CssToken::Ident(class)) => {
+                *pos += 1;
+                Some(Selector::Class(class.clone()))
             }
-            Some(CssToken::Ident(tag)) => {
-                let tag = tag.clone();
-                self.next();
-                Some(Selector::Element(tag))
+            _ => None,
+        },
+        ComponentValue::Token(CssToken::Colon) => {
+            let is_element = values.get(*pos)?.is_token(&CssToken::Colon);
+            if is_element {
+                *pos += 1;
             }
-            Some(CssToken::Colon) => {
-                self.next();
-                match self.next() {
-                    Some(CssToken::Ident(name)) => {
-                        let name = name.clone();
+            let next = values.get(*pos)?;
+            *pos += 1;
+            match next {
+                ComponentValue::Token(CssToken::Ident(name)) => {
+                    let name = name.to_ascii_lowercase();
+                    if is_element || LEGACY_PSEUDO_ELEMENTS.contains(&name.as_str()) {
+                        Some(Selector::PseudoElement(name))
+                    } else {
                         Some(Selector::PseudoClass(name))
                     }
-                    _ => None,
                 }
+                ComponentValue::Function { name, value } if !is_element => parse_pseudo_class_function(name, value),
+                _ => None,
             }
-            Some(CssToken::DoubleColon) => {
-                self.next();
-                match self.next() {
-                    Some(CssToken::Ident(name)) => {
-                        let name = name.clone();
-                        Some(Selector::PseudoElement(name))
-                    }
-                    _ => None,
+        }
+        ComponentValue::Block { open: '[', value } => parse_attribute_selector(value),
+        _ => None,
+    }
+}
+
+/// The contents of `[...]`: a name, then optionally an operator, a value and a case flag
+fn parse_attribute_selector(values: &[ComponentValue]) -> Option<Selector> {
+    let values: Vec<&ComponentValue> = values.iter().filter(|v| !v.is_whitespace()).collect();
+    let name = match values.first()? {
+        ComponentValue::Token(CssToken::Ident(name)) => name.clone(),
+        _ => return None,
+    };
+    let (operator, rest) = match &values[1..] {
+        [] => return Some(Selector::Attribute { name, operator: None, value: None }),
+        [ComponentValue::Token(CssToken::Delim('=')), rest @ ..] => (AttrOperator::Exact, rest),
+        [ComponentValue::Token(CssToken::Delim(c)), ComponentValue::Token(CssToken::Delim('=')), rest @ ..] => {
+            let operator = match c {
+                '~' => AttrOperator::Contains,
+                '|' => AttrOperator::Dash,
+                '*' => AttrOperator::Substring,
+                '^' => AttrOperator::Prefix,
+                '$' => AttrOperator::Suffix,
+                _ => return None,
+            };
+            (operator, rest)
+        }
+        _ => return None,
+    };
+    let value = match rest {
+        [ComponentValue::Token(CssToken::Ident(value) | CssToken::String(value)), flag @ ..] if flag.len() <= 1 => {
+            // The i/s case flag is accepted but ignored
+            if let [flag] = flag {
+                if !matches!(flag, ComponentValue::Token(CssToken::Ident(f)) if f.eq_ignore_ascii_case("i") || f.eq_ignore_ascii_case("s")) {
+                    return None;
                 }
             }
-            Some(CssToken::OpenBracket) => {
-                self.next();
-                let name = match self.next() {
-                    Some(CssToken::Ident(n)) => n.clone(),
-                    _ => return None,
-                };
-
-                let (operator, value) = match self.peek() {
-                    Some(CssToken::Equals) => {
-                        self.next();
-                        let val = match self.next() {
-                            Some(CssToken::String(s)) => s.clone(),
-                            Some(CssToken::Ident(s)) => s.clone(),
-                            _ => String::new(),
-                        };
-                        (Some(AttrOperator::Exact), Some(val))
-                    }
-                    Some(CssToken::Includes) => {
-                        self.next();
-                        let val = match self.next() {
-                            Some(CssToken::String(s)) => s.clone(),
-                            Some(CssToken::Ident(s)) => s.clone(),
-                            _ => String::new(),
-                        };
-                        (Some(AttrOperator::Contains), Some(val))
-                    }
-                    Some(CssToken::DashMatch) => {
-                        self.next();
-                        let val = match self.next() {
-                            Some(CssToken::String(s)) => s.clone(),
-                            Some(CssToken::Ident(s)) => s.clone(),
-                            _ => String::new(),
-                        };
-                        (Some(AttrOperator::Dash), Some(val))
-                    }
-                    Some(CssToken::SubstringMatch) => {
-                        self.next();
-                        let val = match self.next() {
-                            Some(CssToken::String(s)) => s.clone(),
-                            Some(CssToken::Ident(s)) => s.clone(),
-                            _ => String::new(),
-                        };
-                        (Some(AttrOperator::Substring), Some(val))
-                    }
-                    Some(CssToken::PrefixMatch) => {
-                        self.next();
-                        let val = match self.next() {
-                            Some(CssToken::String(s)) => s.clone(),
-                            Some(CssToken::Ident(s)) => s.clone(),
-                            _ => String::new(),
-                        };
-                        (Some(AttrOperator::Prefix), Some(val))
-                    }
-                    Some(CssToken::SuffixMatch) => {
-                        self.next();
-                        let val = match self.next() {
-                            Some(CssToken::String(s)) => s.clone(),
-                            Some(CssToken::Ident(s)) => s.clone(),
-                            _ => String::new(),
-                        };
-                        (Some(AttrOperator::Suffix), Some(val))
-                    }
-                    _ => (None, None),
-                };
-
-                self.expect(&CssToken::CloseBracket);
+            value.clone()
+        }
+        _ => return None,
+    };
+    Some(Selector::Attribute { name, operator: Some(operator), value: Some(value) })
+}
 
-                Some(Selector::Attribute {
-                    name,
-                    operator,
-                    value,
-                })
-            }
-            _ => None,
+/// Parse `:name(arguments)`
+fn parse_pseudo_class_function(name: &str, arguments: &[ComponentValue]) -> Option<Selector> {
+    let name = name.to_ascii_lowercase();
+    match name.as_str() {
+        // :not(a, b) matches elements that are neither
+        "not" => {
+            let mut list = parse_selector_list(arguments)?;
+            let inner = if list.len() == 1 { list.pop()? } else { Selector::Is(list) };
+            return Some(Selector::Not(Box::new(inner)));
         }
+        // Forgiving selector lists: invalid selectors are dropped, not fatal
+        "is" | "matches" | "-webkit-any" | "where" => {
+            let list: Vec<Selector> = split_on(arguments, &CssToken::Comma).filter_map(parse_complex_selector).collect();
+            return Some(if name == "where" { Selector::Where(list) } else { Selector::Is(list) });
+        }
+        _ => {}
     }
 
-    fn parse_declarations(&mut self) -> Vec<Declaration> {
-        let mut declarations = Vec::new();
+    let (of_type, from_end) = match name.as_str() {
+        "nth-child" => (false, false),
+        "nth-last-child" => (false, true),
+        "nth-of-type" => (true, false),
+        "nth-last-of-type" => (true, true),
+        _ => return None,
+    };
+    // Whitespace is allowed around the sign, so "2n + 1" reads as "2n+1"
+    let argument: String = arguments.iter().filter(|v| !v.is_whitespace()).map(|v| v.to_string()).collect();
+    let (a, b) = parse_an_plus_b(&argument)?;
+    Some(Selector::NthChild { a, b, of_type, from_end })
+}
 
-        while !matches!(self.peek(), Some(CssToken::CloseBrace) | None) {
-            if let Some(decl) = self.parse_declaration() {
-                declarations.push(decl);
-            } else {
-                self.next();
-            }
+/// Parse the An+B microsyntax used by :nth-child() and friends
+fn parse_an_plus_b(argument: &str) -> Option<(i32, i32)> {
+    let argument = argument.to_ascii_lowercase();
+    match argument.as_str() {
+        "odd" => return Some((2, 1)),
+        "even" => return Some((2, 0)),
+        _ => {}
+    }
+    match argument.split_once('n') {
+        Some((a, b)) => {
+            let a = match a {
+                "" | "+" => 1,
+                "-" => -1,
+                a => a.parse().ok()?,
+            };
+            let b = if b.is_empty() { 0 } else { b.parse().ok()? };
+            Some((a, b))
         }
-
-        declarations
+        None => Some((0, argument.parse().ok()?)),
     }
+}
 
-    fn parse_declaration(&mut self) -> Option<Declaration> {
-        let property = match self.next() {
-            Some(CssToken::Ident(prop)) => prop.clone().to_lowercase(),
-            _ => return None,
-        };
-
-        self.expect(&CssToken::Colon);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::css::CssTokenizer;
 
-        let value = self.parse_property_value();
+    fn parse(css: &str) -> Vec<CssItem> {
+        CssParser::new(CssTokenizer::new(css).tokenize()).parse()
+    }
 
-        let important = if matches!(self.peek(), Some(CssToken::Ident(s)) if s.to_lowercase() == "important") {
-            self.next();
-            true
-        } else {
-            false
-        };
+    fn selectors(css: &str) -> Option<Vec<Selector>> {
+        CssParser::new(CssTokenizer::new(css).tokenize()).parse_selector_list()
+    }
 
-        self.expect(&CssToken::Semicolon);
+    fn rule(item: &CssItem) -> &Rule {
+        match item {
+            CssItem::Rule(rule) => rule,
+            other => panic!("expected a rule, got {:?}", other),
+        }
+    }
 
-        Some(Declaration {
-            property,
-            value,
-            important,
-        })
+    #[test]
+    fn test_rules_and_declarations() {
+        let items = parse("<!-- h1, .a > p { color : red ; margin:0 auto!IMPORTANT; font-family: \"Times New Roman\", serif } -->");
+        assert_eq!(items.len(), 2);
+        let first = rule(&items[0]);
+        assert_eq!(first.selector, Selector::Element("h1".into()));
+        let values: Vec<_> = first.declarations.iter().map(|d| (d.property.as_str(), d.value.as_str(), d.important)).collect();
+        assert_eq!(
+            values,
+            vec![
+                ("color", "red", false),
+                ("margin", "0 auto", true),
+                ("font-family", "\"Times New Roman\", serif", false),
+            ]
+        );
+        assert_eq!(
+            rule(&items[1]).selector,
+            Selector::Child(Box::new(Selector::Class("a".into())), Box::new(Selector::Element("p".into())))
+        );
     }
 
-    fn parse_property_value(&mut self) -> String {
-        let mut value = String::new();
-        let mut depth = 0;
+    #[test]
+    fn test_values_are_serialized_from_component_values() {
+        let items = parse("a { background: url( x.png ) RGB(1,2 , 3); width: calc(100% - 2*1em); x: #ABC 1e3 -.5px }");
+        let values: Vec<_> = rule(&items[0]).declarations.iter().map(|d| d.value.clone()).collect();
+        assert_eq!(values, vec!["url(x.png) RGB(1,2 , 3)", "calc(100% - 2*1em)", "#ABC 1000 -0.5px"]);
+        let width = &rule(&items[0]).declarations[1];
+        assert!(matches!(&width.components[..], [ComponentValue::Function { name, .. }] if name == "calc"));
+    }
 
-        while let Some(token) = self.peek() {
-            match token {
-                CssToken::Semicolon if depth == 0 => break,
-                CssToken::CloseBrace if depth == 0 => break,
-                CssToken::OpenParen | CssToken::OpenBracket => {
-                    depth += 1;
-                    value.push_str(&format!("{:?}", token));
-                    self.next();
-                }
-                CssToken::CloseParen | CssToken::CloseBracket => {
-                    depth -= 1;
-                    value.push_str(&format!("{:?}", token));
-                    self.next();
-                }
-                _ => {
-                    let token_str = self.token_to_string(token);
-                    // Add space between tokens if value is not empty and doesn't end with a space
-                    // and token_str is not empty and doesn't start with punctuation
-                    if !value.is_empty() && !token_str.is_empty() 
-                       && !value.ends_with(' ') && !value.ends_with('(') && !value.ends_with(',')
-                       && !token_str.starts_with(',') && !token_str.starts_with(')') {
-                        value.push(' ');
-                    }
-                    value.push_str(&token_str);
-                    self.next();
-                }
+    #[test]
+    fn test_at_rules() {
+        let items = parse("@import url(a.css) screen; @media screen and (max-width:600px) { p { x: 1 } } @font-face { font-family: f } q { y: 2 }");
+        assert_eq!(items.len(), 4);
+        match &items[0] {
+            CssItem::AtRule { name, prelude, content } => {
+                assert_eq!((name.as_str(), prelude.as_str()), ("import", "url(a.css) screen"));
+                assert!(content.is_empty());
             }
+            other => panic!("unexpected {:?}", other),
         }
+        match &items[1] {
+            CssItem::AtRule { name, prelude, content } => {
+                assert_eq!((name.as_str(), prelude.as_str()), ("media", "screen and (max-width:600px)"));
+                assert_eq!(rule(&content[0]).declarations[0].value, "1");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(&items[2], CssItem::AtRule { name, content, .. } if name == "font-face" && content.is_empty()));
+        assert_eq!(rule(&items[3]).selector, Selector::Element("q".into()));
+    }
 
-        value.trim().to_string()
-    }
-
-    fn token_to_string(&self, token: &CssToken) -> String {
-        match token {
-            CssToken::Ident(s) => s.clone(),
-            CssToken::Number(n) => n.to_string(),
-            CssToken::Percentage(p) => format!("{}%", p),
-            CssToken::Dimension { value, unit } => format!("{}{}", value, unit),
-            CssToken::Color(c) => c.clone(),
-            CssToken::String(s) => format!("\"{}\"", s),
-            CssToken::Url(u) => format!("url({})", u),
-            CssToken::Function(f) => format!("{}(", f),
-            CssToken::Colon => ":".to_string(),
-            CssToken::DoubleColon => "::".to_string(),
-            CssToken::Comma => ",".to_string(),
-            _ => String::new(),
+    #[test]
+    fn test_invalid_rules_and_declarations_are_dropped() {
+        let items = parse("p:unknown() { a: 1 } #1x { b: 2 } div { c; d: ; : e; f: 3 } span {");
+        assert_eq!(items.len(), 2);
+        let div = rule(&items[0]);
+        let props: Vec<_> = div.declarations.iter().map(|d| (d.property.as_str(), d.value.as_str())).collect();
+        assert_eq!(props, vec![("d", ""), ("f", "3")]);
+        assert!(rule(&items[1]).declarations.is_empty());
+    }
+
+    #[test]
+    fn test_selectors() {
+        assert_eq!(
+            selectors("a.b#c[href^='x' i]:hover::before"),
+            Some(vec![Selector::Compound(vec![
+                Selector::Element("a".into()),
+                Selector::Class("b".into()),
+                Selector::Id("c".into()),
+                Selector::Attribute { name: "href".into(), operator: Some(AttrOperator::Prefix), value: Some("x".into()) },
+                Selector::PseudoClass("hover".into()),
+                Selector::PseudoElement("before".into()),
+            ])])
+        );
+        assert_eq!(selectors("p:after"), Some(vec![Selector::Compound(vec![
+            Selector::Element("p".into()),
+            Selector::PseudoElement("after".into()),
+        ])]));
+        assert_eq!(
+            selectors("li:nth-child( -2n + 3 ), :nth-last-of-type(odd)"),
+            Some(vec![
+                Selector::Compound(vec![
+                    Selector::Element("li".into()),
+                    Selector::NthChild { a: -2, b: 3, of_type: false, from_end: false },
+                ]),
+                Selector::NthChild { a: 2, b: 1, of_type: true, from_end: true },
+            ])
+        );
+        assert_eq!(
+            selectors(":not(.a, b)"),
+            Some(vec![Selector::Not(Box::new(Selector::Is(vec![
+                Selector::Class("a".into()),
+                Selector::Element("b".into()),
+            ])))])
+        );
+        assert_eq!(
+            selectors(":where(p, 1x, q)"),
+            Some(vec![Selector::Where(vec![Selector::Element("p".into()), Selector::Element("q".into())])])
+        );
+        for invalid in ["", "a,", "a >", ".5", "a b!", "[x=]", "[x]div", "#1"] {
+            assert_eq!(selectors(invalid), None, "{}", invalid);
         }
     }
+
+    #[test]
+    fn test_specificity() {
+        let specificity = |css| selectors(css).unwrap()[0].specificity();
+        assert_eq!(specificity("*"), Specificity(0, 0, 0));
+        assert_eq!(specificity("#id p"), Specificity(1, 0, 1));
+        assert_eq!(specificity("ul li.a:hover::before"), Specificity(0, 2, 3));
+        assert_eq!(specificity("a[href]:not(#x) + b:nth-child(2)"), Specificity(1, 2, 2));
+        assert!(specificity("#id p") > specificity("p.a.b.c"));
+        assert_eq!(specificity(":is(p, #a .b)"), Specificity(1, 1, 0));
+        assert_eq!(specificity(":not(.a, b)"), Specificity(0, 1, 0));
+        assert_eq!(specificity("ul:where(#a) li"), Specificity(0, 0, 2));
+    }
 }
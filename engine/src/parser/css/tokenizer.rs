@@ -0,0 +1,658 @@
+// CSS tokenizer following CSS Syntax Module Level 3
+// Spec Reference: https://www.w3.org/TR/css-syntax-3/#tokenization
+//
+// Status:
+// ✅ Input preprocessing (newlines, NUL and surrogates)
+// ✅ All token kinds, including <bad-string>, <bad-url>, CDO and CDC
+// ✅ Escapes in identifiers, strings and URLs
+// ✅ Numbers with signs, fractions and exponents (`+.5`, `1e3`)
+// ✅ Byte offsets into the original source for every token
+// ⚠️ unicode-range is not a token (as in the current spec): `U+0-7F`
+//    arrives as ident/number/dimension tokens; use the offsets to re-read it
+
+use std::fmt;
+
+/// The numeric part of number, percentage and dimension tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Numeric {
+    pub value: f32,
+    /// Written without a fraction or exponent
+    pub is_integer: bool,
+    /// Written with an explicit `+` or `-` sign
+    pub signed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CssToken {
+    Ident(String),
+    Function(String),   // name(, the paren is part of the token
+    AtKeyword(String),  // @media
+    Hash {              // #id, #fff
+        value: String,
+        /// The value would be a valid identifier (spec type flag "id")
+        is_id: bool,
+    },
+    String(String),
+    BadString,
+    Url(String),        // url(unquoted)
+    BadUrl,
+    Delim(char),
+    Number(Numeric),
+    Percentage(Numeric),
+    Dimension {
+        value: Numeric,
+        unit: String,
+    },
+    Whitespace,
+    Cdo,                // <!--
+    Cdc,                // -->
+    Colon,
+    Semicolon,
+    Comma,
+    OpenBracket,
+    CloseBracket,
+    OpenParen,
+    CloseParen,
+    OpenBrace,
+    CloseBrace,
+    Eof,
+}
+
+/// A token with the byte range it came from in the source
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: CssToken,
+    pub start: usize,
+    pub end: usize,
+}
+
+pub struct CssTokenizer {
+    /// Preprocessed input
+    input: Vec<char>,
+    /// Byte offset in the original source of each input character, plus the source length
+    offsets: Vec<usize>,
+    pos: usize,
+}
+
+impl CssTokenizer {
+    pub fn new(input: &str) -> Self {
+        // Spec 3.3: preprocess the input stream
+        let mut chars = Vec::with_capacity(input.len());
+        let mut offsets = Vec::with_capacity(input.len() + 1);
+        let mut iter = input.char_indices().peekable();
+        while let Some((offset, c)) = iter.next() {
+            let c = match c {
+                '\r' => {
+                    if matches!(iter.peek(), Some((_, '\n'))) {
+                        iter.next();
+                    }
+                    '\n'
+                }
+                '\u{C}' => '\n',
+                '\0' => '\u{FFFD}',
+                c => c,
+            };
+            chars.push(c);
+            offsets.push(offset);
+        }
+        offsets.push(input.len());
+        Self { input: chars, offsets, pos: 0 }
+    }
+
+    /// All tokens up to, not including, EOF
+    pub fn tokenize(&mut self) -> Vec<CssToken> {
+        self.tokenize_with_offsets().into_iter().map(|t| t.token).collect()
+    }
+
+    /// All tokens up to, not including, EOF, with their source ranges
+    pub fn tokenize_with_offsets(&mut self) -> Vec<SpannedToken> {
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next_spanned_token();
+            if token.token == CssToken::Eof {
+                break;
+            }
+            tokens.push(token);
+        }
+        tokens
+    }
+
+    /// The next token; comments are skipped
+    pub fn next_token(&mut self) -> CssToken {
+        self.next_spanned_token().token
+    }
+
+    pub fn next_spanned_token(&mut self) -> SpannedToken {
+        self.consume_comments();
+        let start = self.offsets[self.pos.min(self.input.len())];
+        let token = self.consume_token();
+        let end = self.offsets[self.pos.min(self.input.len())];
+        SpannedToken { token, start, end }
+    }
+
+    fn peek_at(&self, n: usize) -> Option<char> {
+        self.input.get(self.pos + n).copied()
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.peek_at(0)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+
+    /// Spec 4.3.2: consume comments
+    fn consume_comments(&mut self) {
+        while self.peek() == Some('/') && self.peek_at(1) == Some('*') {
+            self.pos += 2;
+            loop {
+                match self.next() {
+                    Some('*') if self.peek() == Some('/') => {
+                        self.pos += 1;
+                        break;
+                    }
+                    Some(_) => {}
+                    None => return,
+                }
+            }
+        }
+    }
+
+    /// Spec 4.3.1: consume a token
+    fn consume_token(&mut self) -> CssToken {
+        let Some(c) = self.next() else {
+            return CssToken::Eof;
+        };
+        match c {
+            c if is_whitespace(c) => {
+                while self.peek().is_some_and(is_whitespace) {
+                    self.pos += 1;
+                }
+                CssToken::Whitespace
+            }
+            '"' | '\'' => self.consume_string(c),
+            '#' => {
+                if self.peek().is_some_and(is_ident_char) || self.starts_escape(0) {
+                    let is_id = self.starts_ident_sequence(0);
+                    CssToken::Hash { value: self.consume_ident_sequence(), is_id }
+                } else {
+                    CssToken::Delim('#')
+                }
+            }
+            '(' => CssToken::OpenParen,
+            ')' => CssToken::CloseParen,
+            '[' => CssToken::OpenBracket,
+            ']' => CssToken::CloseBracket,
+            '{' => CssToken::OpenBrace,
+            '}' => CssToken::CloseBrace,
+            ',' => CssToken::Comma,
+            ':' => CssToken::Colon,
+            ';' => CssToken::Semicolon,
+            '+' | '.' if self.starts_number(-1) => {
+                self.pos -= 1;
+                self.consume_numeric()
+            }
+            '-' => {
+                if self.starts_number(-1) {
+                    self.pos -= 1;
+                    self.consume_numeric()
+                } else if self.peek() == Some('-') && self.peek_at(1) == Some('>') {
+                    self.pos += 2;
+                    CssToken::Cdc
+                } else if self.starts_ident_sequence(-1) {
+                    self.pos -= 1;
+                    self.consume_ident_like()
+                } else {
+                    CssToken::Delim('-')
+                }
+            }
+            '<' if self.peek() == Some('!') && self.peek_at(1) == Some('-') && self.peek_at(2) == Some('-') => {
+                self.pos += 3;
+                CssToken::Cdo
+            }
+            '@' if self.starts_ident_sequence(0) => CssToken::AtKeyword(self.consume_ident_sequence()),
+            '\\' if self.starts_escape(-1) => {
+                self.pos -= 1;
+                self.consume_ident_like()
+            }
+            c if c.is_ascii_digit() => {
+                self.pos -= 1;
+                self.consume_numeric()
+            }
+            c if is_ident_start(c) => {
+                self.pos -= 1;
+                self.consume_ident_like()
+            }
+            c => CssToken::Delim(c),
+        }
+    }
+
+    /// Spec 4.3.3: consume a numeric token
+    fn consume_numeric(&mut self) -> CssToken {
+        let number = self.consume_number();
+        if self.starts_ident_sequence(0) {
+            CssToken::Dimension { value: number, unit: self.consume_ident_sequence() }
+        } else if self.peek() == Some('%') {
+            self.pos += 1;
+            CssToken::Percentage(number)
+        } else {
+            CssToken::Number(number)
+        }
+    }
+
+    /// Spec 4.3.4: consume an ident-like token
+    fn consume_ident_like(&mut self) -> CssToken {
+        let name = self.consume_ident_sequence();
+        if self.peek() != Some('(') {
+            return CssToken::Ident(name);
+        }
+        self.pos += 1;
+        if !name.eq_ignore_ascii_case("url") {
+            return CssToken::Function(name);
+        }
+        // url( followed by a quoted string is an ordinary function
+        while self.peek().is_some_and(is_whitespace) && self.peek_at(1).is_some_and(is_whitespace) {
+            self.pos += 1;
+        }
+        let quote_at = if self.peek().is_some_and(is_whitespace) { 1 } else { 0 };
+        if matches!(self.peek_at(quote_at), Some('"') | Some('\'')) {
+            CssToken::Function(name)
+        } else {
+            self.consume_url()
+        }
+    }
+
+    /// Spec 4.3.5: consume a string token
+    fn consume_string(&mut self, ending: char) -> CssToken {
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                None => return CssToken::String(value),
+                Some(c) if c == ending => return CssToken::String(value),
+                Some('\n') => {
+                    self.pos -= 1;
+                    return CssToken::BadString;
+                }
+                Some('\\') => match self.peek() {
+                    None => {}
+                    Some('\n') => self.pos += 1,
+                    Some(_) => value.push(self.consume_escape()),
+                },
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    /// Spec 4.3.6: consume a url token, after `url(`
+    fn consume_url(&mut self) -> CssToken {
+        let mut value = String::new();
+        while self.peek().is_some_and(is_whitespace) {
+            self.pos += 1;
+        }
+        loop {
+            match self.next() {
+                None | Some(')') => return CssToken::Url(value),
+                Some(c) if is_whitespace(c) => {
+                    while self.peek().is_some_and(is_whitespace) {
+                        self.pos += 1;
+                    }
+                    if matches!(self.peek(), None | Some(')')) {
+                        self.next();
+                        return CssToken::Url(value);
+                    }
+                    self.consume_bad_url_remnants();
+                    return CssToken::BadUrl;
+                }
+                Some('"') | Some('\'') | Some('(') => {
+                    self.consume_bad_url_remnants();
+                    return CssToken::BadUrl;
+                }
+                Some(c) if is_non_printable(c) => {
+                    self.consume_bad_url_remnants();
+                    return CssToken::BadUrl;
+                }
+                Some('\\') => {
+                    if self.starts_escape(-1) {
+                        value.push(self.consume_escape());
+                    } else {
+                        self.consume_bad_url_remnants();
+                        return CssToken::BadUrl;
+                    }
+                }
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    /// Spec 4.3.7: consume an escaped code point, after the backslash
+    fn consume_escape(&mut self) -> char {
+        let Some(c) = self.next() else {
+            return '\u{FFFD}';
+        };
+        if !c.is_ascii_hexdigit() {
+            return c;
+        }
+        let mut hex = String::from(c);
+        while hex.len() < 6 && self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+            hex.push(self.next().unwrap());
+        }
+        if self.peek().is_some_and(is_whitespace) {
+            self.pos += 1;
+        }
+        u32::from_str_radix(&hex, 16)
+            .ok()
+            .filter(|&code| code != 0)
+            .and_then(char::from_u32)
+            .unwrap_or('\u{FFFD}')
+    }
+
+    /// Spec 4.3.8: whether the characters at `offset` start a valid escape
+    fn starts_escape(&self, offset: isize) -> bool {
+        self.char_at(offset) == Some('\\') && self.char_at(offset + 1).is_some_and(|c| c != '\n')
+    }
+
+    /// Spec 4.3.9: whether the characters at `offset` would start an ident sequence
+    fn starts_ident_sequence(&self, offset: isize) -> bool {
+        match self.char_at(offset) {
+            Some('-') => {
+                self.char_at(offset + 1).is_some_and(|c| is_ident_start(c) || c == '-')
+                    || self.starts_escape(offset + 1)
+            }
+            Some('\\') => self.starts_escape(offset),
+            Some(c) => is_ident_start(c),
+            None => false,
+        }
+    }
+
+    /// Spec 4.3.10: whether the characters at `offset` start a number
+    fn starts_number(&self, offset: isize) -> bool {
+        let digit = |o| self.char_at(o).is_some_and(|c: char| c.is_ascii_digit());
+        match self.char_at(offset) {
+            Some('+') | Some('-') => digit(offset + 1) || (self.char_at(offset + 1) == Some('.') && digit(offset + 2)),
+            Some('.') => digit(offset + 1),
+            Some(c) => c.is_ascii_digit(),
+            None => false,
+        }
+    }
+
+    /// Spec 4.3.11: consume an ident sequence
+    fn consume_ident_sequence(&mut self) -> String {
+        let mut result = String::new();
+        loop {
+            match self.peek() {
+                Some(c) if is_ident_char(c) => {
+                    result.push(c);
+                    self.pos += 1;
+                }
+                Some('\\') if self.starts_escape(0) => {
+                    self.pos += 1;
+                    result.push(self.consume_escape());
+                }
+                _ => return result,
+            }
+        }
+    }
+
+    /// Spec 4.3.12: consume a number
+    fn consume_number(&mut self) -> Numeric {
+        let mut repr = String::new();
+        let mut is_integer = true;
+        let signed = matches!(self.peek(), Some('+') | Some('-'));
+        if signed {
+            repr.push(self.next().unwrap());
+        }
+        self.consume_digits(&mut repr);
+        if self.peek() == Some('.') && self.peek_at(1).is_some_and(|c| c.is_ascii_digit()) {
+            repr.push(self.next().unwrap());
+            self.consume_digits(&mut repr);
+            is_integer = false;
+        }
+        if matches!(self.peek(), Some('e') | Some('E')) {
+            let digit_at = if matches!(self.peek_at(1), Some('+') | Some('-')) { 2 } else { 1 };
+            if self.peek_at(digit_at).is_some_and(|c| c.is_ascii_digit()) {
+                for _ in 0..digit_at {
+                    repr.push(self.next().unwrap());
+                }
+                self.consume_digits(&mut repr);
+                is_integer = false;
+            }
+        }
+        let value = repr.parse::<f64>().unwrap_or(0.0) as f32;
+        Numeric { value, is_integer, signed }
+    }
+
+    fn consume_digits(&mut self, repr: &mut String) {
+        while let Some(c) = self.peek().filter(|c| c.is_ascii_digit()) {
+            repr.push(c);
+            self.pos += 1;
+        }
+    }
+
+    /// Spec 4.3.14: consume the remnants of a bad url
+    fn consume_bad_url_remnants(&mut self) {
+        loop {
+            if self.starts_escape(0) {
+                self.pos += 1;
+                self.consume_escape();
+                continue;
+            }
+            match self.next() {
+                None | Some(')') => return,
+                Some(_) => {}
+            }
+        }
+    }
+
+    fn char_at(&self, offset: isize) -> Option<char> {
+        let index = self.pos as isize + offset;
+        if index < 0 {
+            return None;
+        }
+        self.input.get(index as usize).copied()
+    }
+}
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\n' | '\t' | ' ')
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || !c.is_ascii()
+}
+
+fn is_ident_char(c: char) -> bool {
+    is_ident_start(c) || c.is_ascii_digit() || c == '-'
+}
+
+fn is_non_printable(c: char) -> bool {
+    matches!(c, '\0'..='\u{8}' | '\u{B}' | '\u{E}'..='\u{1F}' | '\u{7F}')
+}
+
+/// Serialize an identifier so that it tokenizes back to the same ident (CSSOM)
+fn write_identifier(f: &mut fmt::Formatter<'_>, ident: &str) -> fmt::Result {
+    let chars: Vec<char> = ident.chars().collect();
+    if chars == ['-'] {
+        return write!(f, "\\-");
+    }
+    for (i, &c) in chars.iter().enumerate() {
+        let leading_digit = c.is_ascii_digit() && (i == 0 || (i == 1 && chars[0] == '-'));
+        if c == '\0' {
+            write!(f, "\u{FFFD}")?;
+        } else if matches!(c, '\u{1}'..='\u{1F}' | '\u{7F}') || leading_digit {
+            write!(f, "\\{:x} ", c as u32)?;
+        } else if is_ident_char(c) {
+            write!(f, "{}", c)?;
+        } else {
+            write!(f, "\\{}", c)?;
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Numeric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.signed && self.value >= 0.0 {
+            write!(f, "+")?;
+        }
+        write!(f, "{}", self.value)
+    }
+}
+
+/// Serializes tokens back to CSS text, so that re-tokenizing gives the same tokens
+impl fmt::Display for CssToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CssToken::Ident(name) => write_identifier(f, name),
+            CssToken::Function(name) => {
+                write_identifier(f, name)?;
+                write!(f, "(")
+            }
+            CssToken::AtKeyword(name) => {
+                write!(f, "@")?;
+                write_identifier(f, name)
+            }
+            CssToken::Hash { value, .. } => {
+                write!(f, "#")?;
+                // Hashes may start with a digit, so only escape what needs it
+                for c in value.chars() {
+                    if is_ident_char(c) {
+                        write!(f, "{}", c)?;
+                    } else {
+                        write!(f, "\\{}", c)?;
+                    }
+                }
+                Ok(())
+            }
+            CssToken::String(value) => {
+                write!(f, "\"")?;
+                for c in value.chars() {
+                    match c {
+                        '"' | '\\' => write!(f, "\\{}", c)?,
+                        '\n' => write!(f, "\\a ")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            CssToken::Url(url) => write!(f, "url({})", url),
+            CssToken::BadString | CssToken::BadUrl | CssToken::Eof => Ok(()),
+            CssToken::Delim(c) => write!(f, "{}", c),
+            CssToken::Number(n) => write!(f, "{}", n),
+            CssToken::Percentage(n) => write!(f, "{}%", n),
+            CssToken::Dimension { value, unit } => {
+                write!(f, "{}", value)?;
+                // A unit like "e3" would re-tokenize as an exponent
+                let rest = unit.get(1..).unwrap_or_default().trim_start_matches(['+', '-']);
+                if unit.starts_with(['e', 'E']) && rest.starts_with(|c: char| c.is_ascii_digit()) {
+                    write!(f, "\\{:x} ", unit.as_bytes()[0])?;
+                    return write_identifier(f, &unit[1..]);
+                }
+                write_identifier(f, unit)
+            }
+            CssToken::Whitespace => write!(f, " "),
+            CssToken::Cdo => write!(f, "<!--"),
+            CssToken::Cdc => write!(f, "-->"),
+            CssToken::Colon => write!(f, ":"),
+            CssToken::Semicolon => write!(f, ";"),
+            CssToken::Comma => write!(f, ","),
+            CssToken::OpenBracket => write!(f, "["),
+            CssToken::CloseBracket => write!(f, "]"),
+            CssToken::OpenParen => write!(f, "("),
+            CssToken::CloseParen => write!(f, ")"),
+            CssToken::OpenBrace => write!(f, "{{"),
+            CssToken::CloseBrace => write!(f, "}}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(css: &str) -> Vec<CssToken> {
+        CssTokenizer::new(css).tokenize()
+    }
+
+    fn number(value: f32, is_integer: bool, signed: bool) -> Numeric {
+        Numeric { value, is_integer, signed }
+    }
+
+    #[test]
+    fn test_selectors_and_blocks() {
+        use CssToken::*;
+        assert_eq!(
+            tokens("a.b > #c::before{x:1}"),
+            vec![
+                Ident("a".into()), Delim('.'), Ident("b".into()), Whitespace, Delim('>'), Whitespace,
+                Hash { value: "c".into(), is_id: true }, Colon, Colon, Ident("before".into()),
+                OpenBrace, Ident("x".into()), Colon, Number(number(1.0, true, false)), CloseBrace,
+            ]
+        );
+        assert_eq!(
+            tokens("#123 [a~='b']"),
+            vec![
+                Hash { value: "123".into(), is_id: false }, Whitespace, OpenBracket, Ident("a".into()),
+                Delim('~'), Delim('='), String("b".into()), CloseBracket,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_numbers() {
+        use CssToken::*;
+        assert_eq!(
+            tokens("1e3 .5 +2 -0.5em 50% 1e 2n+1"),
+            vec![
+                Number(number(1000.0, false, false)), Whitespace,
+                Number(number(0.5, false, false)), Whitespace,
+                Number(number(2.0, true, true)), Whitespace,
+                Dimension { value: number(-0.5, false, true), unit: "em".into() }, Whitespace,
+                Percentage(number(50.0, true, false)), Whitespace,
+                Dimension { value: number(1.0, true, false), unit: "e".into() }, Whitespace,
+                Dimension { value: number(2.0, true, false), unit: "n".into() },
+                Number(number(1.0, true, true)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_escapes_strings_and_urls() {
+        use CssToken::*;
+        assert_eq!(tokens(r"\31 0a \.x"), vec![Ident("10a".into()), Whitespace, Ident(".x".into())]);
+        assert_eq!(tokens("'a\\\nb' \"c\nd"), vec![String("ab".into()), Whitespace, BadString, Whitespace, Ident("d".into())]);
+        assert_eq!(tokens("url( a.png ) url(\"b\")"), vec![
+            Url("a.png".into()), Whitespace, Function("url".into()), String("b".into()), CloseParen,
+        ]);
+        assert_eq!(tokens("url(a b) x"), vec![BadUrl, Whitespace, Ident("x".into())]);
+    }
+
+    #[test]
+    fn test_comments_cdo_cdc_and_at_keywords() {
+        use CssToken::*;
+        assert_eq!(
+            tokens("<!-- /* c */@media -->--x"),
+            vec![Cdo, Whitespace, AtKeyword("media".into()), Whitespace, Cdc, Ident("--x".into())]
+        );
+        assert_eq!(tokens("@ 1"), vec![Delim('@'), Whitespace, Number(number(1.0, true, false))]);
+    }
+
+    #[test]
+    fn test_offsets_refer_to_original_source() {
+        let spans = CssTokenizer::new("a\r\n/**/é{}").tokenize_with_offsets();
+        let ranges: Vec<_> = spans.iter().map(|t| (t.start, t.end)).collect();
+        assert_eq!(ranges, vec![(0, 1), (1, 3), (7, 9), (9, 10), (10, 11)]);
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        for css in ["a.b>#c", "rgb(1, +2, -3.5%)", "'q\"\\\\'", "url(x.png)", "\\31 0px", "1\\65 3", "-\\-x"] {
+            let serialized: std::string::String = tokens(css).iter().map(|t| t.to_string()).collect();
+            assert_eq!(tokens(&serialized), tokens(css), "{}", serialized);
+        }
+    }
+}
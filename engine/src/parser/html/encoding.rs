@@ -0,0 +1,462 @@
+// HTML encoding sniffing following WHATWG HTML Living Standard
+// Spec Reference: https://html.spec.whatwg.org/multipage/parsing.html#determining-the-character-encoding
+//
+// IMPLEMENTATION STATUS:
+// ✅ BOM sniffing
+// ✅ Transport layer (Content-Type charset)
+// ✅ <meta> prescan of the first 1024 bytes
+// ✅ Autodetection - UTF-8 validity only
+// ✅ Default to windows-1252
+// ✅ Incremental decoding of chunked input
+// ❌ Changing the encoding while parsing - not implemented
+
+use encoding_rs::{Decoder, Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252, X_USER_DEFINED};
+
+/// Number of bytes the <meta> prescan looks at (spec 13.2.3.2)
+const PRESCAN_LENGTH: usize = 1024;
+
+/// How sure the sniffing algorithm is about the encoding (spec 13.2.3.1)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EncodingConfidence {
+    Tentative,
+    Certain,
+}
+
+/// Result of the encoding sniffing algorithm
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SniffedEncoding {
+    pub encoding: &'static Encoding,
+    pub confidence: EncodingConfidence,
+}
+
+/// Spec 13.2.3.2 Encoding sniffing algorithm
+///
+/// `content_type` is the value of the Content-Type header, if any.
+pub fn sniff_encoding(bytes: &[u8], content_type: Option<&str>) -> SniffedEncoding {
+    // 1. Byte order mark
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return SniffedEncoding { encoding, confidence: EncodingConfidence::Certain };
+    }
+
+    // 4. Transport layer
+    if let Some(encoding) = content_type
+        .and_then(extract_charset)
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+    {
+        return SniffedEncoding { encoding, confidence: EncodingConfidence::Certain };
+    }
+
+    // 5. Prescan for <meta charset> / <meta http-equiv=content-type>
+    if let Some(encoding) = prescan(bytes) {
+        return SniffedEncoding { encoding, confidence: EncodingConfidence::Tentative };
+    }
+
+    // 7. Autodetection: non-ASCII content that is valid UTF-8 is almost certainly UTF-8.
+    // A sequence cut off at the end of a partial buffer still counts as valid.
+    let valid_utf8 = match std::str::from_utf8(bytes) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    if !bytes.is_ascii() && valid_utf8 {
+        return SniffedEncoding { encoding: UTF_8, confidence: EncodingConfidence::Tentative };
+    }
+
+    // 8. Implementation-defined default
+    SniffedEncoding { encoding: WINDOWS_1252, confidence: EncodingConfidence::Tentative }
+}
+
+/// Sniff the encoding and decode the bytes, dropping any BOM
+pub fn decode_html(bytes: &[u8], content_type: Option<&str>) -> (String, &'static Encoding) {
+    let sniffed = sniff_encoding(bytes, content_type);
+    // decode() lets a BOM override the sniffed encoding, as the spec's decode does
+    let (text, encoding, _had_errors) = sniffed.encoding.decode(bytes);
+    (text.into_owned(), encoding)
+}
+
+/// Decoder for document bytes that arrive in chunks. Bytes are held back
+/// until the prescan window is full, then the encoding is sniffed once.
+pub struct HtmlDecoder {
+    content_type: Option<String>,
+    buffered: Vec<u8>,
+    decoder: Option<Decoder>,
+}
+
+impl HtmlDecoder {
+    pub fn new(content_type: Option<&str>) -> Self {
+        Self {
+            content_type: content_type.map(String::from),
+            buffered: Vec::new(),
+            decoder: None,
+        }
+    }
+
+    /// Decode a chunk, returning whatever text is ready
+    pub fn decode(&mut self, bytes: &[u8]) -> String {
+        if self.decoder.is_none() {
+            self.buffered.extend_from_slice(bytes);
+            if self.buffered.len() < PRESCAN_LENGTH {
+                return String::new();
+            }
+            return self.start_decoding(false);
+        }
+        self.decode_chunk(bytes, false)
+    }
+
+    /// Decode any remaining bytes at the end of the input
+    pub fn finish(&mut self) -> String {
+        if self.decoder.is_none() {
+            return self.start_decoding(true);
+        }
+        self.decode_chunk(&[], true)
+    }
+
+    /// The encoding in use, once it has been sniffed
+    pub fn encoding(&self) -> Option<&'static Encoding> {
+        self.decoder.as_ref().map(|decoder| decoder.encoding())
+    }
+
+    fn start_decoding(&mut self, last: bool) -> String {
+        let sniffed = sniff_encoding(&self.buffered, self.content_type.as_deref());
+        // The decoder strips a BOM, as decode_html does
+        self.decoder = Some(sniffed.encoding.new_decoder());
+        let buffered = std::mem::take(&mut self.buffered);
+        self.decode_chunk(&buffered, last)
+    }
+
+    fn decode_chunk(&mut self, bytes: &[u8], last: bool) -> String {
+        let decoder = self.decoder.as_mut().expect("decoder is created before decoding");
+        let capacity = decoder.max_utf8_buffer_length(bytes.len()).unwrap_or(bytes.len() * 3 + 16);
+        let mut text = String::with_capacity(capacity);
+        let _ = decoder.decode_to_string(bytes, &mut text, last);
+        text
+    }
+}
+
+/// The Content-Type header value from a response's headers
+pub fn content_type_header(headers: &[(String, String)]) -> Option<&str> {
+    headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Spec 2.5.4 Algorithm for extracting a character encoding from a meta element,
+/// also used for the charset parameter of a Content-Type value
+pub fn extract_charset(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut position = 0;
+
+    loop {
+        // Find "charset", ASCII case-insensitively
+        let found = (position..bytes.len().saturating_sub(6))
+            .find(|&i| bytes[i..i + 7].eq_ignore_ascii_case(b"charset"))?;
+        position = found + 7;
+
+        while position < bytes.len() && is_ascii_whitespace(bytes[position]) {
+            position += 1;
+        }
+        if bytes.get(position) != Some(&b'=') {
+            continue;
+        }
+        position += 1;
+        while position < bytes.len() && is_ascii_whitespace(bytes[position]) {
+            position += 1;
+        }
+
+        return match bytes.get(position)? {
+            &quote @ (b'"' | b'\'') => {
+                let rest = &value[position + 1..];
+                let end = rest.bytes().position(|b| b == quote)?;
+                Some(rest[..end].to_string())
+            }
+            _ => {
+                let rest = &value[position..];
+                let end = rest
+                    .bytes()
+                    .position(|b| is_ascii_whitespace(b) || b == b';')
+                    .unwrap_or(rest.len());
+                Some(rest[..end].to_string())
+            }
+        };
+    }
+}
+
+/// Spec 13.2.3.2 Prescan a byte stream to determine its encoding
+pub fn prescan(bytes: &[u8]) -> Option<&'static Encoding> {
+    let input = &bytes[..bytes.len().min(PRESCAN_LENGTH)];
+    let mut position = 0;
+
+    while position < input.len() {
+        let rest = &input[position..];
+
+        if rest.starts_with(b"<!--") {
+            // Skip to the end of a "-->" that follows the "<!"
+            position += 2;
+            loop {
+                if position + 3 > input.len() {
+                    return None;
+                }
+                if &input[position..position + 3] == b"-->" {
+                    position += 2;
+                    break;
+                }
+                position += 1;
+            }
+        } else if rest.len() >= 6
+            && rest[..5].eq_ignore_ascii_case(b"<meta")
+            && (is_ascii_whitespace(rest[5]) || rest[5] == b'/')
+        {
+            position += 6;
+            if let Some(encoding) = prescan_meta(input, &mut position) {
+                return Some(encoding);
+            }
+        } else if rest.len() >= 2
+            && (rest[1].is_ascii_alphabetic()
+                || (rest[1] == b'/' && rest.get(2).is_some_and(|b| b.is_ascii_alphabetic())))
+            && rest[0] == b'<'
+        {
+            // Skip a tag and its attributes
+            while position < input.len() && !is_ascii_whitespace(input[position]) && input[position] != b'>' {
+                position += 1;
+            }
+            while get_attribute(input, &mut position).is_some() {}
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            position += input[position..].iter().position(|&b| b == b'>')?;
+        }
+
+        position += 1;
+    }
+
+    None
+}
+
+/// Attribute steps of the prescan for a <meta> tag
+fn prescan_meta(input: &[u8], position: &mut usize) -> Option<&'static Encoding> {
+    let mut seen: Vec<String> = Vec::new();
+    let mut got_pragma = false;
+    let mut need_pragma: Option<bool> = None;
+    let mut charset: Option<&'static Encoding> = None;
+
+    while let Some((name, value)) = get_attribute(input, position) {
+        if seen.contains(&name) {
+            continue;
+        }
+        match name.as_str() {
+            "http-equiv" if value == "content-type" => got_pragma = true,
+            "content" if charset.is_none() => {
+                if let Some(encoding) = extract_charset(&value).and_then(|l| Encoding::for_label(l.as_bytes())) {
+                    charset = Some(encoding);
+                    need_pragma = Some(true);
+                }
+            }
+            "charset" => {
+                charset = Encoding::for_label(value.as_bytes());
+                need_pragma = Some(false);
+            }
+            _ => {}
+        }
+        seen.push(name);
+    }
+
+    match need_pragma {
+        None => return None,
+        Some(true) if !got_pragma => return None,
+        _ => {}
+    }
+    let encoding = charset?;
+    if encoding == UTF_16BE || encoding == UTF_16LE {
+        Some(UTF_8)
+    } else if encoding == X_USER_DEFINED {
+        Some(WINDOWS_1252)
+    } else {
+        Some(encoding)
+    }
+}
+
+/// Spec 13.2.3.2 "get an attribute" during the prescan
+fn get_attribute(input: &[u8], position: &mut usize) -> Option<(String, String)> {
+    let byte_at = |p: usize| input.get(p).copied();
+
+    while let Some(b) = byte_at(*position) {
+        if is_ascii_whitespace(b) || b == b'/' {
+            *position += 1;
+        } else {
+            break;
+        }
+    }
+    if byte_at(*position)? == b'>' {
+        return None;
+    }
+
+    let mut name = Vec::new();
+    let mut value = Vec::new();
+
+    // Attribute name
+    loop {
+        let b = byte_at(*position)?;
+        if b == b'=' && !name.is_empty() {
+            *position += 1;
+            break;
+        }
+        if is_ascii_whitespace(b) {
+            // Spaces, then maybe an '='
+            while byte_at(*position).is_some_and(is_ascii_whitespace) {
+                *position += 1;
+            }
+            if byte_at(*position)? != b'=' {
+                return Some((bytes_to_string(name), String::new()));
+            }
+            *position += 1;
+            break;
+        }
+        if b == b'/' || b == b'>' {
+            return Some((bytes_to_string(name), String::new()));
+        }
+        name.push(b.to_ascii_lowercase());
+        *position += 1;
+    }
+
+    // Attribute value
+    while byte_at(*position).is_some_and(is_ascii_whitespace) {
+        *position += 1;
+    }
+    match byte_at(*position)? {
+        quote @ (b'"' | b'\'') => {
+            *position += 1;
+            loop {
+                let b = byte_at(*position)?;
+                *position += 1;
+                if b == quote {
+                    return Some((bytes_to_string(name), bytes_to_string(value)));
+                }
+                value.push(b.to_ascii_lowercase());
+            }
+        }
+        b'>' => Some((bytes_to_string(name), String::new())),
+        _ => {
+            loop {
+                let b = byte_at(*position)?;
+                if is_ascii_whitespace(b) || b == b'>' {
+                    return Some((bytes_to_string(name), bytes_to_string(value)));
+                }
+                value.push(b.to_ascii_lowercase());
+                *position += 1;
+            }
+        }
+    }
+}
+
+fn bytes_to_string(bytes: Vec<u8>) -> String {
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn is_ascii_whitespace(b: u8) -> bool {
+    matches!(b, b'\t' | b'\n' | b'\x0C' | b'\r' | b' ')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{GBK, ISO_8859_2, SHIFT_JIS};
+
+    #[test]
+    fn test_bom_wins_over_everything() {
+        let bytes = b"\xEF\xBB\xBF<meta charset=shift_jis>";
+        let sniffed = sniff_encoding(bytes, Some("text/html; charset=iso-8859-2"));
+        assert_eq!(sniffed.encoding, UTF_8);
+        assert_eq!(sniffed.confidence, EncodingConfidence::Certain);
+
+        let (text, encoding) = decode_html(b"\xFF\xFEh\x00i\x00", None);
+        assert_eq!(encoding, UTF_16LE);
+        assert_eq!(text, "hi");
+    }
+
+    #[test]
+    fn test_content_type_charset() {
+        let sniffed = sniff_encoding(b"<meta charset=utf-8>", Some("text/html; charset=\"ISO-8859-2\""));
+        assert_eq!(sniffed.encoding, ISO_8859_2);
+        assert_eq!(sniffed.confidence, EncodingConfidence::Certain);
+
+        // Unknown labels fall through to the prescan
+        let sniffed = sniff_encoding(b"<meta charset=gbk>", Some("text/html; charset=bogus"));
+        assert_eq!(sniffed.encoding, GBK);
+    }
+
+    #[test]
+    fn test_meta_charset_prescan() {
+        assert_eq!(prescan(b"<!DOCTYPE html><head><meta charset=\"Shift_JIS\">"), Some(SHIFT_JIS));
+        assert_eq!(prescan(b"<META CHARSET='gbk'/>"), Some(GBK));
+        // UTF-16 labels in a meta can't be right, since the meta itself was ASCII
+        assert_eq!(prescan(b"<meta charset=utf-16le>"), Some(UTF_8));
+    }
+
+    #[test]
+    fn test_meta_http_equiv_prescan() {
+        let html = b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=windows-1251\">";
+        assert_eq!(prescan(html).map(|e| e.name()), Some("windows-1251"));
+        // content= without the pragma is ignored
+        assert_eq!(prescan(b"<meta content=\"text/html; charset=windows-1251\">"), None);
+    }
+
+    #[test]
+    fn test_prescan_skips_comments_and_other_tags() {
+        assert_eq!(prescan(b"<!-- <meta charset=gbk> --><meta charset=shift_jis>"), Some(SHIFT_JIS));
+        assert_eq!(prescan(b"<div title='<meta charset=gbk>'><meta charset=shift_jis>"), Some(SHIFT_JIS));
+        let mut late = vec![b' '; PRESCAN_LENGTH];
+        late.extend_from_slice(b"<meta charset=gbk>");
+        assert_eq!(prescan(&late), None);
+    }
+
+    #[test]
+    fn test_extract_charset() {
+        assert_eq!(extract_charset("text/html; charset=UTF-8").as_deref(), Some("UTF-8"));
+        assert_eq!(extract_charset("text/html;charset = 'koi8-r' ").as_deref(), Some("koi8-r"));
+        assert_eq!(extract_charset("text/html; charset=\"unterminated"), None);
+        assert_eq!(extract_charset("text/html"), None);
+    }
+
+    #[test]
+    fn test_default_and_autodetection() {
+        assert_eq!(sniff_encoding(b"<p>plain</p>", None).encoding, WINDOWS_1252);
+        assert_eq!(sniff_encoding("<p>caf\u{e9}</p>".as_bytes(), None).encoding, UTF_8);
+
+        let (text, _) = decode_html(b"<p>\x93quoted\x94 caf\xE9</p>", None);
+        assert_eq!(text, "<p>\u{201C}quoted\u{201D} caf\u{E9}</p>");
+    }
+
+    #[test]
+    fn test_chunked_decoding_matches_whole_input() {
+        let mut bytes = b"<meta charset=shift_jis>".to_vec();
+        bytes.resize(PRESCAN_LENGTH + 10, b' ');
+        bytes.extend_from_slice(b"\x93\xfa\x96\x7b");
+
+        let mut decoder = HtmlDecoder::new(None);
+        let mut text = String::new();
+        // Split inside the two-byte sequences, too
+        for chunk in bytes.chunks(3) {
+            text.push_str(&decoder.decode(chunk));
+        }
+        text.push_str(&decoder.finish());
+        assert_eq!(decoder.encoding(), Some(SHIFT_JIS));
+        assert_eq!(text, decode_html(&bytes, None).0);
+    }
+
+    #[test]
+    fn test_short_input_is_sniffed_at_finish() {
+        let mut decoder = HtmlDecoder::new(None);
+        assert_eq!(decoder.decode("<p>caf\u{e9}".as_bytes()), "");
+        assert_eq!(decoder.encoding(), None);
+        assert_eq!(decoder.finish(), "<p>caf\u{e9}");
+        assert_eq!(decoder.encoding(), Some(UTF_8));
+    }
+
+    #[test]
+    fn test_decode_legacy_encodings() {
+        let (text, encoding) = decode_html(b"<meta charset=shift_jis>\x93\xfa\x96\x7b", None);
+        assert_eq!(encoding, SHIFT_JIS);
+        assert!(text.ends_with("\u{65E5}\u{672C}"));
+
+        let (text, _) = decode_html(b"\xc4\xe3\xba\xc3", Some("text/html; charset=gbk"));
+        assert_eq!(text, "\u{4F60}\u{597D}");
+    }
+}
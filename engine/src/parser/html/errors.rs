@@ -0,0 +1,24 @@
+// Parse errors reported by the HTML tokenizer and tree builder
+// Spec Reference: https://html.spec.whatwg.org/multipage/parsing.html#parse-errors
+//
+// Tokenizer errors use the spec's error codes. Tree construction errors have
+// no standardized codes, so they use descriptive codes in the same style.
+
+use std::fmt;
+
+/// A recoverable error encountered while parsing HTML
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Error code, e.g. "unexpected-null-character"
+    pub code: &'static str,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column number, counted in characters
+    pub column: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.code)
+    }
+}
@@ -51,42 +51,22 @@ pub struct SrcsetDescriptor {
 
 /// Extract the <base href> value from the DOM, if present
 pub fn extract_base_href(dom: &Dom) -> Option<String> {
-    fn find_base(dom: &Dom, node_id: NodeId) -> Option<String> {
-        let node = &dom.nodes[node_id];
-        
-        if let NodeType::Element(el) = &node.node_type {
-            if el.tag_name.eq_ignore_ascii_case("base") {
-                for (key, value) in &el.attributes {
-                    if key.eq_ignore_ascii_case("href") && !value.is_empty() {
-                        return Some(value.clone());
-                    }
-                }
-            }
-        }
-        
-        for &child_id in &node.children {
-            if let Some(href) = find_base(dom, child_id) {
-                return Some(href);
-            }
-        }
-        
-        None
-    }
-    
-    find_base(dom, dom.root())
+    dom.elements_by_tag_name("base")
+        .find_map(|id| dom.get_attribute(id, "href").filter(|href| !href.is_empty()))
+        .map(str::to_string)
 }
 
 /// Extract all image references from the DOM
 pub fn extract_image_refs(dom: &Dom) -> Vec<ImageRef> {
     let mut refs = Vec::new();
-    extract_from_node(dom, dom.root(), &mut refs);
+    for node_id in dom.descendants(dom.root()) {
+        extract_from_node(dom, node_id, &mut refs);
+    }
     refs
 }
 
 fn extract_from_node(dom: &Dom, node_id: NodeId, refs: &mut Vec<ImageRef>) {
-    let node = &dom.nodes[node_id];
-    
-    if let NodeType::Element(el) = &node.node_type {
+    if let NodeType::Element(el) = &dom.nodes[node_id].node_type {
         let tag = el.tag_name.to_lowercase();
         
         match tag.as_str() {
@@ -107,11 +87,6 @@ fn extract_from_node(dom: &Dom, node_id: NodeId, refs: &mut Vec<ImageRef>) {
             extract_css_url_refs(&style, node_id, refs);
         }
     }
-    
-    // Recurse into children
-    for &child_id in &node.children {
-        extract_from_node(dom, child_id, refs);
-    }
 }
 
 fn get_attribute(el: &crate::dom::ElementData, name: &str) -> Option<String> {
@@ -378,34 +353,53 @@ fn find_property_name(before: &str, properties: &[&str]) -> Option<String> {
     None
 }
 
-/// Extract all CSS from <style> tags in the DOM
-pub fn extract_stylesheets(dom: &Dom) -> Vec<(NodeId, String)> {
-    let mut styles = Vec::new();
-    extract_styles_from_node(dom, dom.root(), &mut styles);
-    styles
+/// A stylesheet used by the document: a <style> element or a
+/// <link rel="stylesheet">
+#[derive(Debug, Clone, PartialEq)]
+pub struct StylesheetRef {
+    pub source: StylesheetSource,
+    /// The media attribute, if present
+    pub media: Option<String>,
+    /// The node ID of the <style> or <link> element
+    pub node_id: NodeId,
 }
 
-fn extract_styles_from_node(dom: &Dom, node_id: NodeId, styles: &mut Vec<(NodeId, String)>) {
-    let node = &dom.nodes[node_id];
-    
-    if let NodeType::Element(el) = &node.node_type {
-        if el.tag_name.eq_ignore_ascii_case("style") {
-            // Get text content
-            let mut css = String::new();
-            for &child_id in &node.children {
-                if let NodeType::Text(text) = &dom.nodes[child_id].node_type {
-                    css.push_str(text);
-                }
-            }
-            if !css.is_empty() {
-                styles.push((node_id, css));
-            }
-        }
-    }
-    
-    for &child_id in &node.children {
-        extract_styles_from_node(dom, child_id, styles);
-    }
+#[derive(Debug, Clone, PartialEq)]
+pub enum StylesheetSource {
+    /// The CSS text of a <style> element
+    Inline(String),
+    /// The href of a <link> (may be relative)
+    External(String),
+}
+
+/// Extract the stylesheets of the document in tree order, which is the order
+/// they cascade in. Alternate and disabled stylesheets and ones with a type
+/// other than text/css are skipped.
+pub fn extract_stylesheets(dom: &Dom) -> Vec<StylesheetRef> {
+    let is_css = |id: NodeId| {
+        dom.get_attribute(id, "type")
+            .is_none_or(|t| t.is_empty() || t.trim().eq_ignore_ascii_case("text/css"))
+    };
+    dom.descendants(dom.root())
+        .filter_map(|id| {
+            let el = dom.element(id)?;
+            let source = if el.tag_name.eq_ignore_ascii_case("style") {
+                let css = dom.text_content(id);
+                (!css.is_empty() && is_css(id)).then_some(StylesheetSource::Inline(css))?
+            } else if el.tag_name.eq_ignore_ascii_case("link") {
+                let rel = dom.get_attribute(id, "rel")?.to_ascii_lowercase();
+                let mut rel = rel.split_ascii_whitespace();
+                let is_stylesheet = rel.clone().any(|token| token == "stylesheet") && !rel.any(|token| token == "alternate");
+                let href = dom.get_attribute(id, "href").filter(|href| !href.trim().is_empty())?;
+                let enabled = dom.get_attribute(id, "disabled").is_none();
+                (is_stylesheet && enabled && is_css(id)).then(|| StylesheetSource::External(href.trim().to_string()))?
+            } else {
+                return None;
+            };
+            let media = dom.get_attribute(id, "media").map(str::to_string);
+            Some(StylesheetRef { source, media, node_id: id })
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -468,4 +462,25 @@ mod tests {
         assert_eq!(refs[0].url, "photo.jpg");
         assert!(matches!(refs[0].ref_type, ImageRefType::ImgSrc));
     }
+    
+    #[test]
+    fn test_extract_stylesheets() {
+        use crate::parser::html::tree_builder::HtmlParser;
+        let dom = HtmlParser::new(
+            r#"<link rel="stylesheet" href="a.css" media="print">
+            <style>p { color: red }</style>
+            <link rel="alternate stylesheet" href="alt.css">
+            <link rel="icon" href="favicon.ico">
+            <style type="text/less">p { }</style>
+            <link rel="Stylesheet" href=" b.css ">"#,
+        )
+        .parse();
+        let sheets = extract_stylesheets(&dom);
+        
+        assert_eq!(sheets.len(), 3);
+        assert_eq!(sheets[0].source, StylesheetSource::External("a.css".to_string()));
+        assert_eq!(sheets[0].media.as_deref(), Some("print"));
+        assert_eq!(sheets[1].source, StylesheetSource::Inline("p { color: red }".to_string()));
+        assert_eq!(sheets[2].source, StylesheetSource::External("b.css".to_string()));
+    }
 }
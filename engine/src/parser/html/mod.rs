@@ -1,9 +1,16 @@
+pub mod encoding;
 pub mod tokenizer;
+pub mod entities;
+pub mod errors;
 pub mod tree_builder;
 pub mod image_refs;
 
+pub use encoding::{decode_html, sniff_encoding, EncodingConfidence, SniffedEncoding};
+pub use errors::ParseError;
+
 pub use image_refs::{
     extract_image_refs, extract_base_href, extract_stylesheets,
+    StylesheetRef, StylesheetSource,
     parse_srcset_attribute, parse_css_urls,
     ImageRef, ImageRefType, SrcsetDescriptor, CssUrlRef,
 };
@@ -2,7 +2,9 @@
 // Spec Reference: https://html.spec.whatwg.org/multipage/parsing.html#tokenization
 //
 // IMPLEMENTATION STATUS:
-// ✅ Data state - basic
+// ✅ Data state
+// ✅ RCDATA, RAWTEXT, script data and PLAINTEXT states
+// ✅ Script data escaped and double escaped states
 // ✅ Tag open state
 // ✅ End tag open state
 // ✅ Tag name state
@@ -14,22 +16,21 @@
 // ✅ Attribute value (single-quoted) state
 // ✅ Attribute value (unquoted) state
 // ✅ After attribute value (quoted) state
-// ⚠️ Bogus comment state - partial
-// ✅ Markup declaration open state - basic
-// ⚠️ Comment start state - basic
-// ⚠️ Comment state - basic
-// ⚠️ Comment end state - basic
-// ⚠️ DOCTYPE state - basic
-// ❌ Character reference states - not implemented
-// ❌ RCDATA states - not implemented
-// ❌ RAWTEXT states - not implemented
-// ❌ Script data states - not implemented
+// ✅ Bogus comment state
+// ✅ Markup declaration open state
+// ✅ Comment states
+// ✅ DOCTYPE states, including public and system identifiers
+// ✅ CDATA section states
+// ✅ Character reference states
 //
-// TODO(spec 13.2.5.1): Implement preprocessing input stream
-// TODO(spec 13.2.5.2): Implement parse errors properly
+// ✅ Parse errors collected with line and column
+// ✅ Streaming input fed in chunks
 
 use std::collections::VecDeque;
 
+use super::entities::{lookup_entity, LONGEST_ENTITY_NAME};
+use super::errors::ParseError;
+
 /// Debug logging for tokenizer operations
 const DEBUG_TOKENIZER: bool = false;
 
@@ -93,6 +94,22 @@ pub enum TokenizerState {
     RawTextEndTagOpen,
     RawTextEndTagName,
     ScriptDataLessThan,
+    ScriptDataEndTagOpen,
+    ScriptDataEndTagName,
+    ScriptDataEscapeStart,
+    ScriptDataEscapeStartDash,
+    ScriptDataEscaped,
+    ScriptDataEscapedDash,
+    ScriptDataEscapedDashDash,
+    ScriptDataEscapedLessThan,
+    ScriptDataEscapedEndTagOpen,
+    ScriptDataEscapedEndTagName,
+    ScriptDataDoubleEscapeStart,
+    ScriptDataDoubleEscaped,
+    ScriptDataDoubleEscapedDash,
+    ScriptDataDoubleEscapedDashDash,
+    ScriptDataDoubleEscapedLessThan,
+    ScriptDataDoubleEscapeEnd,
     BeforeAttributeName,
     AttributeName,
     AfterAttributeName,
@@ -108,6 +125,9 @@ pub enum TokenizerState {
     CommentStartDash,
     Comment,
     CommentLessThan,
+    CommentLessThanBang,
+    CommentLessThanBangDash,
+    CommentLessThanBangDashDash,
     CommentEndDash,
     CommentEnd,
     CommentEndBang,
@@ -115,6 +135,30 @@ pub enum TokenizerState {
     BeforeDoctypeName,
     DoctypeName,
     AfterDoctypeName,
+    AfterDoctypePublicKeyword,
+    BeforeDoctypePublicIdentifier,
+    DoctypePublicIdentifierDoubleQuoted,
+    DoctypePublicIdentifierSingleQuoted,
+    AfterDoctypePublicIdentifier,
+    BetweenDoctypePublicAndSystemIdentifiers,
+    AfterDoctypeSystemKeyword,
+    BeforeDoctypeSystemIdentifier,
+    DoctypeSystemIdentifierDoubleQuoted,
+    DoctypeSystemIdentifierSingleQuoted,
+    AfterDoctypeSystemIdentifier,
+    BogusDoctype,
+    CdataSection,
+    CdataSectionBracket,
+    CdataSectionEnd,
+    CharacterReference,
+    NamedCharacterReference,
+    AmbiguousAmpersand,
+    NumericCharacterReference,
+    HexadecimalCharacterReferenceStart,
+    DecimalCharacterReferenceStart,
+    HexadecimalCharacterReference,
+    DecimalCharacterReference,
+    NumericCharacterReferenceEnd,
 }
 
 /// Void elements that cannot have content (spec 13.1.2)
@@ -134,23 +178,62 @@ pub const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
 /// Escapable raw text elements (spec 13.1.2.2)
 pub const ESCAPABLE_RAW_TEXT_ELEMENTS: &[&str] = &["textarea", "title"];
 
+/// Numeric character reference replacements for C1 controls (spec 13.2.5.80)
+const NUMERIC_REPLACEMENTS: &[(u32, char)] = &[
+    (0x80, '\u{20AC}'), (0x82, '\u{201A}'), (0x83, '\u{0192}'), (0x84, '\u{201E}'),
+    (0x85, '\u{2026}'), (0x86, '\u{2020}'), (0x87, '\u{2021}'), (0x88, '\u{02C6}'),
+    (0x89, '\u{2030}'), (0x8A, '\u{0160}'), (0x8B, '\u{2039}'), (0x8C, '\u{0152}'),
+    (0x8E, '\u{017D}'), (0x91, '\u{2018}'), (0x92, '\u{2019}'), (0x93, '\u{201C}'),
+    (0x94, '\u{201D}'), (0x95, '\u{2022}'), (0x96, '\u{2013}'), (0x97, '\u{2014}'),
+    (0x98, '\u{02DC}'), (0x99, '\u{2122}'), (0x9A, '\u{0161}'), (0x9B, '\u{203A}'),
+    (0x9C, '\u{0153}'), (0x9E, '\u{017E}'), (0x9F, '\u{0178}'),
+];
+
+/// Characters that must be buffered past the current position before a state
+/// runs on partial input; covers the longest lookahead (named character references)
+const STREAMING_LOOKAHEAD: usize = LONGEST_ENTITY_NAME + 2;
+
 /// HTML Tokenizer
 pub struct Tokenizer {
     input: Vec<char>,
     pos: usize,
     state: TokenizerState,
     current_token: Option<Token>,
-    token_queue: VecDeque<Token>,
+    /// Emitted tokens with the input index of their last character
+    token_queue: VecDeque<(Token, usize)>,
     temp_buffer: String,
     current_attribute: Option<Attribute>,
     last_start_tag_name: Option<String>,
     reconsume: bool,
+    /// State to go back to after a character reference
+    return_state: TokenizerState,
+    character_reference_code: u32,
+    /// Whether `<![CDATA[` opens a CDATA section (adjusted current node is foreign)
+    cdata_allowed: bool,
+    /// Input index where each line starts
+    line_starts: Vec<usize>,
+    /// Input index of the last character of the token most recently returned
+    last_token_end: usize,
+    errors: Vec<ParseError>,
+    /// False while more input may still be fed
+    input_complete: bool,
+    /// A CR at the end of the last chunk, which may pair with an LF in the next
+    pending_cr: bool,
 }
 
 impl Tokenizer {
     pub fn new(input: &str) -> Self {
+        let mut tokenizer = Self::new_streaming();
+        tokenizer.feed(input);
+        tokenizer.end_input();
+        tokenizer
+    }
+
+    /// Create a tokenizer whose input arrives in chunks through `feed`.
+    /// `next_token` returns None while it needs more input.
+    pub fn new_streaming() -> Self {
         Self {
-            input: input.chars().collect(),
+            input: Vec::new(),
             pos: 0,
             state: TokenizerState::Data,
             current_token: None,
@@ -159,9 +242,80 @@ impl Tokenizer {
             current_attribute: None,
             last_start_tag_name: None,
             reconsume: false,
+            return_state: TokenizerState::Data,
+            character_reference_code: 0,
+            cdata_allowed: false,
+            line_starts: vec![0],
+            last_token_end: 0,
+            errors: Vec::new(),
+            input_complete: false,
+            pending_cr: false,
+        }
+    }
+
+    /// Append a chunk of input, normalizing newlines (13.2.3.5)
+    pub fn feed(&mut self, chunk: &str) {
+        debug_assert!(!self.input_complete, "input fed after end_input");
+        self.input.reserve(chunk.len());
+        for c in chunk.chars() {
+            if self.pending_cr {
+                self.pending_cr = false;
+                self.push_input_char('\n');
+                if c == '\n' {
+                    continue;
+                }
+            }
+            if c == '\r' {
+                self.pending_cr = true;
+            } else {
+                self.push_input_char(c);
+            }
+        }
+    }
+
+    /// Mark the end of the input, so the remaining characters and EOF are tokenized
+    pub fn end_input(&mut self) {
+        if self.pending_cr {
+            self.pending_cr = false;
+            self.push_input_char('\n');
+        }
+        self.input_complete = true;
+    }
+
+    fn push_input_char(&mut self, c: char) {
+        self.input.push(c);
+        if c == '\n' {
+            self.line_starts.push(self.input.len());
         }
     }
 
+    /// 1-based (line, column) of an input index
+    fn location(&self, index: usize) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|&start| start <= index);
+        (line, index - self.line_starts[line - 1] + 1)
+    }
+
+    /// Record a parse error at the current input character
+    fn parse_error(&mut self, code: &'static str) {
+        let (line, column) = self.location(self.pos.saturating_sub(1));
+        tokenizer_log(&format!("Parse error: {} at {}:{}", code, line, column));
+        self.errors.push(ParseError { code, line, column });
+    }
+
+    /// Parse errors found so far
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// 1-based (line, column) of the end of the token most recently returned
+    pub fn last_token_location(&self) -> (usize, usize) {
+        self.location(self.last_token_end)
+    }
+
     pub fn state(&self) -> TokenizerState {
         self.state
     }
@@ -171,13 +325,24 @@ impl Tokenizer {
         self.state = state;
     }
 
+    /// Set the tag name end tags are checked against in RCDATA, RAWTEXT and script data
+    pub fn set_last_start_tag(&mut self, name: Option<&str>) {
+        self.last_start_tag_name = name.map(|n| n.to_ascii_lowercase());
+    }
+
+    /// Set by the tree builder when the adjusted current node is not an HTML element
+    pub fn set_cdata_allowed(&mut self, allowed: bool) {
+        self.cdata_allowed = allowed;
+    }
+
     fn consume_next(&mut self) -> Option<char> {
         if self.reconsume {
             self.reconsume = false;
             return self.current_input_char();
         }
         let c = self.input.get(self.pos).copied();
-        if c.is_some() {
+        // Step past EOF too, so reconsuming at EOF yields None again
+        if self.pos <= self.input.len() {
             self.pos += 1;
         }
         c
@@ -206,6 +371,10 @@ impl Tokenizer {
         true
     }
 
+    fn next_chars_are(&self, s: &str) -> bool {
+        s.chars().enumerate().all(|(i, c)| self.input.get(self.pos + i) == Some(&c))
+    }
+
     fn consume_chars(&mut self, count: usize) {
         for _ in 0..count {
             self.consume_next();
@@ -219,7 +388,8 @@ impl Tokenizer {
 
     fn emit_char(&mut self, c: char) {
         tokenizer_log(&format!("Emit character: {:?}", c));
-        self.token_queue.push_back(Token::Character(c));
+        let end = self.pos.saturating_sub(1);
+        self.token_queue.push_back((Token::Character(c), end));
     }
 
     fn emit_current_token(&mut self) {
@@ -228,13 +398,15 @@ impl Tokenizer {
             if let Token::StartTag { ref name, .. } = token {
                 self.last_start_tag_name = Some(name.clone());
             }
-            self.token_queue.push_back(token);
+            let end = self.pos.saturating_sub(1);
+            self.token_queue.push_back((token, end));
         }
     }
 
     fn emit_eof(&mut self) {
         tokenizer_log("Emit EOF");
-        self.token_queue.push_back(Token::Eof);
+        let end = self.pos.saturating_sub(1);
+        self.token_queue.push_back((Token::Eof, end));
     }
 
     fn create_start_tag(&mut self) {
@@ -301,10 +473,10 @@ impl Tokenizer {
     fn finalize_current_attribute(&mut self) {
         if let Some(attr) = self.current_attribute.take() {
             if let Some(Token::StartTag { attributes, .. }) = &mut self.current_token {
-                if !attributes.iter().any(|a| a.name == attr.name) {
-                    attributes.push(attr);
+                if attributes.iter().any(|a| a.name == attr.name) {
+                    self.parse_error("duplicate-attribute");
                 } else {
-                    tokenizer_log(&format!("Parse error: duplicate attribute '{}'", attr.name));
+                    attributes.push(attr);
                 }
             }
         }
@@ -333,6 +505,30 @@ impl Tokenizer {
         }
     }
 
+    fn start_doctype_public_id(&mut self) {
+        if let Some(Token::Doctype { public_id, .. }) = &mut self.current_token {
+            *public_id = Some(String::new());
+        }
+    }
+
+    fn append_to_doctype_public_id(&mut self, c: char) {
+        if let Some(Token::Doctype { public_id: Some(id), .. }) = &mut self.current_token {
+            id.push(c);
+        }
+    }
+
+    fn start_doctype_system_id(&mut self) {
+        if let Some(Token::Doctype { system_id, .. }) = &mut self.current_token {
+            *system_id = Some(String::new());
+        }
+    }
+
+    fn append_to_doctype_system_id(&mut self, c: char) {
+        if let Some(Token::Doctype { system_id: Some(id), .. }) = &mut self.current_token {
+            id.push(c);
+        }
+    }
+
     fn set_force_quirks(&mut self) {
         if let Some(Token::Doctype { force_quirks, .. }) = &mut self.current_token {
             *force_quirks = true;
@@ -350,16 +546,24 @@ impl Tokenizer {
 
     pub fn next_token(&mut self) -> Option<Token> {
         loop {
-            if let Some(token) = self.token_queue.pop_front() {
+            if let Some((token, end)) = self.token_queue.pop_front() {
+                self.last_token_end = end;
                 return Some(token);
             }
 
+            // On partial input, wait until every lookahead a state may do is buffered
+            if !self.input_complete && self.pos + STREAMING_LOOKAHEAD > self.input.len() {
+                return None;
+            }
+
             let c = self.consume_next();
             
             match self.state {
                 TokenizerState::Data => self.data_state(c),
                 TokenizerState::RcData => self.rcdata_state(c),
                 TokenizerState::RawText => self.rawtext_state(c),
+                TokenizerState::ScriptData => self.script_data_state(c),
+                TokenizerState::PlainText => self.plaintext_state(c),
                 TokenizerState::TagOpen => self.tag_open_state(c),
                 TokenizerState::EndTagOpen => self.end_tag_open_state(c),
                 TokenizerState::TagName => self.tag_name_state(c),
@@ -369,6 +573,27 @@ impl Tokenizer {
                 TokenizerState::RawTextLessThan => self.rawtext_less_than_state(c),
                 TokenizerState::RawTextEndTagOpen => self.rawtext_end_tag_open_state(c),
                 TokenizerState::RawTextEndTagName => self.rawtext_end_tag_name_state(c),
+                TokenizerState::ScriptDataLessThan => self.script_data_less_than_state(c),
+                TokenizerState::ScriptDataEndTagOpen => self.script_data_end_tag_open_state(c),
+                TokenizerState::ScriptDataEndTagName => self.script_data_end_tag_name_state(c),
+                TokenizerState::ScriptDataEscapeStart => self.script_data_escape_start_state(c),
+                TokenizerState::ScriptDataEscapeStartDash => self.script_data_escape_start_dash_state(c),
+                TokenizerState::ScriptDataEscaped => self.script_data_escaped_state(c),
+                TokenizerState::ScriptDataEscapedDash => self.script_data_escaped_dash_state(c),
+                TokenizerState::ScriptDataEscapedDashDash => self.script_data_escaped_dash_dash_state(c),
+                TokenizerState::ScriptDataEscapedLessThan => self.script_data_escaped_less_than_state(c),
+                TokenizerState::ScriptDataEscapedEndTagOpen => self.script_data_escaped_end_tag_open_state(c),
+                TokenizerState::ScriptDataEscapedEndTagName => self.script_data_escaped_end_tag_name_state(c),
+                TokenizerState::ScriptDataDoubleEscapeStart => self.script_data_double_escape_start_state(c),
+                TokenizerState::ScriptDataDoubleEscaped => self.script_data_double_escaped_state(c),
+                TokenizerState::ScriptDataDoubleEscapedDash => self.script_data_double_escaped_dash_state(c),
+                TokenizerState::ScriptDataDoubleEscapedDashDash => {
+                    self.script_data_double_escaped_dash_dash_state(c)
+                }
+                TokenizerState::ScriptDataDoubleEscapedLessThan => {
+                    self.script_data_double_escaped_less_than_state(c)
+                }
+                TokenizerState::ScriptDataDoubleEscapeEnd => self.script_data_double_escape_end_state(c),
                 TokenizerState::BeforeAttributeName => self.before_attribute_name_state(c),
                 TokenizerState::AttributeName => self.attribute_name_state(c),
                 TokenizerState::AfterAttributeName => self.after_attribute_name_state(c),
@@ -383,6 +608,10 @@ impl Tokenizer {
                 TokenizerState::CommentStart => self.comment_start_state(c),
                 TokenizerState::CommentStartDash => self.comment_start_dash_state(c),
                 TokenizerState::Comment => self.comment_state(c),
+                TokenizerState::CommentLessThan => self.comment_less_than_state(c),
+                TokenizerState::CommentLessThanBang => self.comment_less_than_bang_state(c),
+                TokenizerState::CommentLessThanBangDash => self.comment_less_than_bang_dash_state(c),
+                TokenizerState::CommentLessThanBangDashDash => self.comment_less_than_bang_dash_dash_state(c),
                 TokenizerState::CommentEndDash => self.comment_end_dash_state(c),
                 TokenizerState::CommentEnd => self.comment_end_state(c),
                 TokenizerState::CommentEndBang => self.comment_end_bang_state(c),
@@ -390,10 +619,48 @@ impl Tokenizer {
                 TokenizerState::BeforeDoctypeName => self.before_doctype_name_state(c),
                 TokenizerState::DoctypeName => self.doctype_name_state(c),
                 TokenizerState::AfterDoctypeName => self.after_doctype_name_state(c),
-                _ => {
-                    tokenizer_log(&format!("Unimplemented state: {:?}", self.state));
-                    self.state = TokenizerState::Data;
+                TokenizerState::AfterDoctypePublicKeyword => self.after_doctype_public_keyword_state(c),
+                TokenizerState::BeforeDoctypePublicIdentifier => {
+                    self.before_doctype_public_identifier_state(c)
+                }
+                TokenizerState::DoctypePublicIdentifierDoubleQuoted => {
+                    self.doctype_public_identifier_quoted_state(c, '"')
+                }
+                TokenizerState::DoctypePublicIdentifierSingleQuoted => {
+                    self.doctype_public_identifier_quoted_state(c, '\'')
+                }
+                TokenizerState::AfterDoctypePublicIdentifier => self.after_doctype_public_identifier_state(c),
+                TokenizerState::BetweenDoctypePublicAndSystemIdentifiers => {
+                    self.between_doctype_public_and_system_identifiers_state(c)
                 }
+                TokenizerState::AfterDoctypeSystemKeyword => self.after_doctype_system_keyword_state(c),
+                TokenizerState::BeforeDoctypeSystemIdentifier => {
+                    self.before_doctype_system_identifier_state(c)
+                }
+                TokenizerState::DoctypeSystemIdentifierDoubleQuoted => {
+                    self.doctype_system_identifier_quoted_state(c, '"')
+                }
+                TokenizerState::DoctypeSystemIdentifierSingleQuoted => {
+                    self.doctype_system_identifier_quoted_state(c, '\'')
+                }
+                TokenizerState::AfterDoctypeSystemIdentifier => self.after_doctype_system_identifier_state(c),
+                TokenizerState::BogusDoctype => self.bogus_doctype_state(c),
+                TokenizerState::CdataSection => self.cdata_section_state(c),
+                TokenizerState::CdataSectionBracket => self.cdata_section_bracket_state(c),
+                TokenizerState::CdataSectionEnd => self.cdata_section_end_state(c),
+                TokenizerState::CharacterReference => self.character_reference_state(c),
+                TokenizerState::NamedCharacterReference => self.named_character_reference_state(c),
+                TokenizerState::AmbiguousAmpersand => self.ambiguous_ampersand_state(c),
+                TokenizerState::NumericCharacterReference => self.numeric_character_reference_state(c),
+                TokenizerState::HexadecimalCharacterReferenceStart => {
+                    self.hexadecimal_character_reference_start_state(c)
+                }
+                TokenizerState::DecimalCharacterReferenceStart => {
+                    self.decimal_character_reference_start_state(c)
+                }
+                TokenizerState::HexadecimalCharacterReference => self.hexadecimal_character_reference_state(c),
+                TokenizerState::DecimalCharacterReference => self.decimal_character_reference_state(c),
+                TokenizerState::NumericCharacterReferenceEnd => self.numeric_character_reference_end_state(c),
             }
         }
     }
@@ -402,14 +669,14 @@ impl Tokenizer {
     fn data_state(&mut self, c: Option<char>) {
         match c {
             Some('&') => {
-                // TODO: character reference
-                self.emit_char('&');
+                self.return_state = TokenizerState::Data;
+                self.state = TokenizerState::CharacterReference;
             }
             Some('<') => {
                 self.state = TokenizerState::TagOpen;
             }
             Some('\0') => {
-                tokenizer_log("Parse error: unexpected-null-character");
+                self.parse_error("unexpected-null-character");
                 self.emit_char('\0');
             }
             None => {
@@ -425,13 +692,14 @@ impl Tokenizer {
     fn rcdata_state(&mut self, c: Option<char>) {
         match c {
             Some('&') => {
-                self.emit_char('&');
+                self.return_state = TokenizerState::RcData;
+                self.state = TokenizerState::CharacterReference;
             }
             Some('<') => {
                 self.state = TokenizerState::RcDataLessThan;
             }
             Some('\0') => {
-                tokenizer_log("Parse error: unexpected-null-character");
+                self.parse_error("unexpected-null-character");
                 self.emit_char('\u{FFFD}');
             }
             None => {
@@ -450,7 +718,42 @@ impl Tokenizer {
                 self.state = TokenizerState::RawTextLessThan;
             }
             Some('\0') => {
-                tokenizer_log("Parse error: unexpected-null-character");
+                self.parse_error("unexpected-null-character");
+                self.emit_char('\u{FFFD}');
+            }
+            None => {
+                self.emit_eof();
+            }
+            Some(c) => {
+                self.emit_char(c);
+            }
+        }
+    }
+
+    /// 13.2.5.4 Script data state
+    fn script_data_state(&mut self, c: Option<char>) {
+        match c {
+            Some('<') => {
+                self.state = TokenizerState::ScriptDataLessThan;
+            }
+            Some('\0') => {
+                self.parse_error("unexpected-null-character");
+                self.emit_char('\u{FFFD}');
+            }
+            None => {
+                self.emit_eof();
+            }
+            Some(c) => {
+                self.emit_char(c);
+            }
+        }
+    }
+
+    /// 13.2.5.5 PLAINTEXT state
+    fn plaintext_state(&mut self, c: Option<char>) {
+        match c {
+            Some('\0') => {
+                self.parse_error("unexpected-null-character");
                 self.emit_char('\u{FFFD}');
             }
             None => {
@@ -476,17 +779,17 @@ impl Tokenizer {
                 self.reconsume_in(TokenizerState::TagName);
             }
             Some('?') => {
-                tokenizer_log("Parse error: unexpected-question-mark-instead-of-tag-name");
+                self.parse_error("unexpected-question-mark-instead-of-tag-name");
                 self.create_comment("");
                 self.reconsume_in(TokenizerState::BogusComment);
             }
             None => {
-                tokenizer_log("Parse error: eof-before-tag-name");
+                self.parse_error("eof-before-tag-name");
                 self.emit_char('<');
                 self.emit_eof();
             }
             Some(_) => {
-                tokenizer_log("Parse error: invalid-first-character-of-tag-name");
+                self.parse_error("invalid-first-character-of-tag-name");
                 self.emit_char('<');
                 self.reconsume_in(TokenizerState::Data);
             }
@@ -501,17 +804,17 @@ impl Tokenizer {
                 self.reconsume_in(TokenizerState::TagName);
             }
             Some('>') => {
-                tokenizer_log("Parse error: missing-end-tag-name");
+                self.parse_error("missing-end-tag-name");
                 self.state = TokenizerState::Data;
             }
             None => {
-                tokenizer_log("Parse error: eof-before-tag-name");
+                self.parse_error("eof-before-tag-name");
                 self.emit_char('<');
                 self.emit_char('/');
                 self.emit_eof();
             }
             Some(_) => {
-                tokenizer_log("Parse error: invalid-first-character-of-tag-name");
+                self.parse_error("invalid-first-character-of-tag-name");
                 self.create_comment("");
                 self.reconsume_in(TokenizerState::BogusComment);
             }
@@ -532,11 +835,11 @@ impl Tokenizer {
                 self.emit_current_token();
             }
             Some('\0') => {
-                tokenizer_log("Parse error: unexpected-null-character");
+                self.parse_error("unexpected-null-character");
                 self.append_to_tag_name('\u{FFFD}');
             }
             None => {
-                tokenizer_log("Parse error: eof-in-tag");
+                self.parse_error("eof-in-tag");
                 self.emit_eof();
             }
             Some(c) => {
@@ -576,40 +879,7 @@ impl Tokenizer {
 
     /// 13.2.5.11 RCDATA end tag name state
     fn rcdata_end_tag_name_state(&mut self, c: Option<char>) {
-        match c {
-            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
-                if self.is_appropriate_end_tag() {
-                    self.state = TokenizerState::BeforeAttributeName;
-                    return;
-                }
-            }
-            Some('/') => {
-                if self.is_appropriate_end_tag() {
-                    self.state = TokenizerState::SelfClosingStartTag;
-                    return;
-                }
-            }
-            Some('>') => {
-                if self.is_appropriate_end_tag() {
-                    self.state = TokenizerState::Data;
-                    self.emit_current_token();
-                    return;
-                }
-            }
-            Some(c) if c.is_ascii_alphabetic() => {
-                self.append_to_tag_name(c);
-                self.temp_buffer.push(c);
-                return;
-            }
-            _ => {}
-        }
-        self.emit_char('<');
-        self.emit_char('/');
-        let temp_chars: Vec<char> = self.temp_buffer.chars().collect();
-        for c in temp_chars {
-            self.emit_char(c);
-        }
-        self.reconsume_in(TokenizerState::RcData);
+        self.end_tag_name_state(c, TokenizerState::RcData);
     }
 
     /// 13.2.5.12 RAWTEXT less-than sign state
@@ -643,6 +913,12 @@ impl Tokenizer {
 
     /// 13.2.5.14 RAWTEXT end tag name state
     fn rawtext_end_tag_name_state(&mut self, c: Option<char>) {
+        self.end_tag_name_state(c, TokenizerState::RawText);
+    }
+
+    /// Shared steps of the RCDATA, RAWTEXT and script data end tag name states:
+    /// anything but an appropriate end tag is emitted back as text in `text_state`
+    fn end_tag_name_state(&mut self, c: Option<char>, text_state: TokenizerState) {
         match c {
             Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
                 if self.is_appropriate_end_tag() {
@@ -670,200 +946,525 @@ impl Tokenizer {
             }
             _ => {}
         }
+        self.current_token = None;
         self.emit_char('<');
         self.emit_char('/');
         let temp_chars: Vec<char> = self.temp_buffer.chars().collect();
         for c in temp_chars {
             self.emit_char(c);
         }
-        self.reconsume_in(TokenizerState::RawText);
+        self.reconsume_in(text_state);
     }
 
-    /// 13.2.5.32 Before attribute name state
-    fn before_attribute_name_state(&mut self, c: Option<char>) {
+    /// 13.2.5.15 Script data less-than sign state
+    fn script_data_less_than_state(&mut self, c: Option<char>) {
         match c {
-            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
-                // Ignore whitespace
-            }
-            Some('/') | Some('>') | None => {
-                self.reconsume_in(TokenizerState::AfterAttributeName);
+            Some('/') => {
+                self.temp_buffer.clear();
+                self.state = TokenizerState::ScriptDataEndTagOpen;
             }
-            Some('=') => {
-                tokenizer_log("Parse error: unexpected-equals-sign-before-attribute-name");
-                self.start_new_attribute();
-                self.append_to_attribute_name('=');
-                self.state = TokenizerState::AttributeName;
+            Some('!') => {
+                self.state = TokenizerState::ScriptDataEscapeStart;
+                self.emit_char('<');
+                self.emit_char('!');
             }
-            Some(_) => {
-                self.start_new_attribute();
-                self.reconsume_in(TokenizerState::AttributeName);
+            _ => {
+                self.emit_char('<');
+                self.reconsume_in(TokenizerState::ScriptData);
             }
         }
     }
 
-    /// 13.2.5.33 Attribute name state
-    fn attribute_name_state(&mut self, c: Option<char>) {
+    /// 13.2.5.16 Script data end tag open state
+    fn script_data_end_tag_open_state(&mut self, c: Option<char>) {
         match c {
-            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') | Some('/') | Some('>') | None => {
-                self.reconsume_in(TokenizerState::AfterAttributeName);
-            }
-            Some('=') => {
-                self.state = TokenizerState::BeforeAttributeValue;
-            }
-            Some('\0') => {
-                tokenizer_log("Parse error: unexpected-null-character");
-                self.append_to_attribute_name('\u{FFFD}');
-            }
-            Some('"') | Some('\'') | Some('<') => {
-                tokenizer_log("Parse error: unexpected-character-in-attribute-name");
-                self.append_to_attribute_name(c.unwrap());
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.create_end_tag();
+                self.reconsume_in(TokenizerState::ScriptDataEndTagName);
             }
-            Some(c) => {
-                self.append_to_attribute_name(c);
+            _ => {
+                self.emit_char('<');
+                self.emit_char('/');
+                self.reconsume_in(TokenizerState::ScriptData);
             }
         }
     }
 
-    /// 13.2.5.34 After attribute name state
-    fn after_attribute_name_state(&mut self, c: Option<char>) {
+    /// 13.2.5.17 Script data end tag name state
+    fn script_data_end_tag_name_state(&mut self, c: Option<char>) {
+        self.end_tag_name_state(c, TokenizerState::ScriptData);
+    }
+
+    /// 13.2.5.18 Script data escape start state
+    fn script_data_escape_start_state(&mut self, c: Option<char>) {
         match c {
-            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
-                // Ignore whitespace
-            }
-            Some('/') => {
-                self.finalize_current_attribute();
-                self.state = TokenizerState::SelfClosingStartTag;
-            }
-            Some('=') => {
-                self.state = TokenizerState::BeforeAttributeValue;
-            }
-            Some('>') => {
-                self.finalize_current_attribute();
-                self.state = TokenizerState::Data;
-                self.emit_current_token();
-            }
-            None => {
-                tokenizer_log("Parse error: eof-in-tag");
-                self.emit_eof();
+            Some('-') => {
+                self.state = TokenizerState::ScriptDataEscapeStartDash;
+                self.emit_char('-');
             }
-            Some(_) => {
-                self.finalize_current_attribute();
-                self.start_new_attribute();
-                self.reconsume_in(TokenizerState::AttributeName);
+            _ => {
+                self.reconsume_in(TokenizerState::ScriptData);
             }
         }
     }
 
-    /// 13.2.5.35 Before attribute value state
-    fn before_attribute_value_state(&mut self, c: Option<char>) {
+    /// 13.2.5.19 Script data escape start dash state
+    fn script_data_escape_start_dash_state(&mut self, c: Option<char>) {
         match c {
-            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
-                // Ignore whitespace
-            }
-            Some('"') => {
-                self.state = TokenizerState::AttributeValueDoubleQuoted;
-            }
-            Some('\'') => {
-                self.state = TokenizerState::AttributeValueSingleQuoted;
-            }
-            Some('>') => {
-                tokenizer_log("Parse error: missing-attribute-value");
-                self.finalize_current_attribute();
-                self.state = TokenizerState::Data;
-                self.emit_current_token();
+            Some('-') => {
+                self.state = TokenizerState::ScriptDataEscapedDashDash;
+                self.emit_char('-');
             }
-            Some(_) | None => {
-                self.reconsume_in(TokenizerState::AttributeValueUnquoted);
+            _ => {
+                self.reconsume_in(TokenizerState::ScriptData);
             }
         }
     }
 
-    /// 13.2.5.36 Attribute value (double-quoted) state
-    fn attribute_value_double_quoted_state(&mut self, c: Option<char>) {
+    /// 13.2.5.20 Script data escaped state
+    fn script_data_escaped_state(&mut self, c: Option<char>) {
         match c {
-            Some('"') => {
-                self.state = TokenizerState::AfterAttributeValueQuoted;
+            Some('-') => {
+                self.state = TokenizerState::ScriptDataEscapedDash;
+                self.emit_char('-');
             }
-            Some('&') => {
-                self.append_to_attribute_value('&');
+            Some('<') => {
+                self.state = TokenizerState::ScriptDataEscapedLessThan;
             }
             Some('\0') => {
-                tokenizer_log("Parse error: unexpected-null-character");
-                self.append_to_attribute_value('\u{FFFD}');
+                self.parse_error("unexpected-null-character");
+                self.emit_char('\u{FFFD}');
             }
             None => {
-                tokenizer_log("Parse error: eof-in-tag");
+                self.parse_error("eof-in-script-html-comment-like-text");
                 self.emit_eof();
             }
             Some(c) => {
-                self.append_to_attribute_value(c);
+                self.emit_char(c);
             }
         }
     }
 
-    /// 13.2.5.37 Attribute value (single-quoted) state
-    fn attribute_value_single_quoted_state(&mut self, c: Option<char>) {
+    /// 13.2.5.21 Script data escaped dash state
+    fn script_data_escaped_dash_state(&mut self, c: Option<char>) {
         match c {
-            Some('\'') => {
-                self.state = TokenizerState::AfterAttributeValueQuoted;
+            Some('-') => {
+                self.state = TokenizerState::ScriptDataEscapedDashDash;
+                self.emit_char('-');
             }
-            Some('&') => {
-                self.append_to_attribute_value('&');
+            Some('<') => {
+                self.state = TokenizerState::ScriptDataEscapedLessThan;
             }
             Some('\0') => {
-                tokenizer_log("Parse error: unexpected-null-character");
-                self.append_to_attribute_value('\u{FFFD}');
+                self.parse_error("unexpected-null-character");
+                self.state = TokenizerState::ScriptDataEscaped;
+                self.emit_char('\u{FFFD}');
             }
             None => {
-                tokenizer_log("Parse error: eof-in-tag");
+                self.parse_error("eof-in-script-html-comment-like-text");
                 self.emit_eof();
             }
             Some(c) => {
-                self.append_to_attribute_value(c);
+                self.state = TokenizerState::ScriptDataEscaped;
+                self.emit_char(c);
             }
         }
     }
 
-    /// 13.2.5.38 Attribute value (unquoted) state
-    fn attribute_value_unquoted_state(&mut self, c: Option<char>) {
+    /// 13.2.5.22 Script data escaped dash dash state
+    fn script_data_escaped_dash_dash_state(&mut self, c: Option<char>) {
         match c {
-            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
-                self.finalize_current_attribute();
-                self.state = TokenizerState::BeforeAttributeName;
+            Some('-') => {
+                self.emit_char('-');
             }
-            Some('&') => {
-                self.append_to_attribute_value('&');
+            Some('<') => {
+                self.state = TokenizerState::ScriptDataEscapedLessThan;
             }
             Some('>') => {
-                self.finalize_current_attribute();
-                self.state = TokenizerState::Data;
-                self.emit_current_token();
+                self.state = TokenizerState::ScriptData;
+                self.emit_char('>');
             }
             Some('\0') => {
-                tokenizer_log("Parse error: unexpected-null-character");
-                self.append_to_attribute_value('\u{FFFD}');
-            }
-            Some('"') | Some('\'') | Some('<') | Some('=') | Some('`') => {
-                tokenizer_log("Parse error: unexpected-character-in-unquoted-attribute-value");
-                self.append_to_attribute_value(c.unwrap());
+                self.parse_error("unexpected-null-character");
+                self.state = TokenizerState::ScriptDataEscaped;
+                self.emit_char('\u{FFFD}');
             }
             None => {
-                tokenizer_log("Parse error: eof-in-tag");
+                self.parse_error("eof-in-script-html-comment-like-text");
                 self.emit_eof();
             }
             Some(c) => {
-                self.append_to_attribute_value(c);
+                self.state = TokenizerState::ScriptDataEscaped;
+                self.emit_char(c);
             }
         }
     }
 
-    /// 13.2.5.39 After attribute value (quoted) state
-    fn after_attribute_value_quoted_state(&mut self, c: Option<char>) {
-        self.finalize_current_attribute();
+    /// 13.2.5.23 Script data escaped less-than sign state
+    fn script_data_escaped_less_than_state(&mut self, c: Option<char>) {
         match c {
-            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
-                self.state = TokenizerState::BeforeAttributeName;
-            }
+            Some('/') => {
+                self.temp_buffer.clear();
+                self.state = TokenizerState::ScriptDataEscapedEndTagOpen;
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.temp_buffer.clear();
+                self.emit_char('<');
+                self.reconsume_in(TokenizerState::ScriptDataDoubleEscapeStart);
+            }
+            _ => {
+                self.emit_char('<');
+                self.reconsume_in(TokenizerState::ScriptDataEscaped);
+            }
+        }
+    }
+
+    /// 13.2.5.24 Script data escaped end tag open state
+    fn script_data_escaped_end_tag_open_state(&mut self, c: Option<char>) {
+        match c {
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.create_end_tag();
+                self.reconsume_in(TokenizerState::ScriptDataEscapedEndTagName);
+            }
+            _ => {
+                self.emit_char('<');
+                self.emit_char('/');
+                self.reconsume_in(TokenizerState::ScriptDataEscaped);
+            }
+        }
+    }
+
+    /// 13.2.5.25 Script data escaped end tag name state
+    fn script_data_escaped_end_tag_name_state(&mut self, c: Option<char>) {
+        self.end_tag_name_state(c, TokenizerState::ScriptDataEscaped);
+    }
+
+    /// 13.2.5.26 Script data double escape start state
+    fn script_data_double_escape_start_state(&mut self, c: Option<char>) {
+        match c {
+            Some(c @ ('\t' | '\n' | '\x0C' | ' ' | '/' | '>')) => {
+                self.state = if self.temp_buffer == "script" {
+                    TokenizerState::ScriptDataDoubleEscaped
+                } else {
+                    TokenizerState::ScriptDataEscaped
+                };
+                self.emit_char(c);
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.temp_buffer.push(c.to_ascii_lowercase());
+                self.emit_char(c);
+            }
+            _ => {
+                self.reconsume_in(TokenizerState::ScriptDataEscaped);
+            }
+        }
+    }
+
+    /// 13.2.5.27 Script data double escaped state
+    fn script_data_double_escaped_state(&mut self, c: Option<char>) {
+        match c {
+            Some('-') => {
+                self.state = TokenizerState::ScriptDataDoubleEscapedDash;
+                self.emit_char('-');
+            }
+            Some('<') => {
+                self.state = TokenizerState::ScriptDataDoubleEscapedLessThan;
+                self.emit_char('<');
+            }
+            Some('\0') => {
+                self.parse_error("unexpected-null-character");
+                self.emit_char('\u{FFFD}');
+            }
+            None => {
+                self.parse_error("eof-in-script-html-comment-like-text");
+                self.emit_eof();
+            }
+            Some(c) => {
+                self.emit_char(c);
+            }
+        }
+    }
+
+    /// 13.2.5.28 Script data double escaped dash state
+    fn script_data_double_escaped_dash_state(&mut self, c: Option<char>) {
+        match c {
+            Some('-') => {
+                self.state = TokenizerState::ScriptDataDoubleEscapedDashDash;
+                self.emit_char('-');
+            }
+            Some('<') => {
+                self.state = TokenizerState::ScriptDataDoubleEscapedLessThan;
+                self.emit_char('<');
+            }
+            Some('\0') => {
+                self.parse_error("unexpected-null-character");
+                self.state = TokenizerState::ScriptDataDoubleEscaped;
+                self.emit_char('\u{FFFD}');
+            }
+            None => {
+                self.parse_error("eof-in-script-html-comment-like-text");
+                self.emit_eof();
+            }
+            Some(c) => {
+                self.state = TokenizerState::ScriptDataDoubleEscaped;
+                self.emit_char(c);
+            }
+        }
+    }
+
+    /// 13.2.5.29 Script data double escaped dash dash state
+    fn script_data_double_escaped_dash_dash_state(&mut self, c: Option<char>) {
+        match c {
+            Some('-') => {
+                self.emit_char('-');
+            }
+            Some('<') => {
+                self.state = TokenizerState::ScriptDataDoubleEscapedLessThan;
+                self.emit_char('<');
+            }
+            Some('>') => {
+                self.state = TokenizerState::ScriptData;
+                self.emit_char('>');
+            }
+            Some('\0') => {
+                self.parse_error("unexpected-null-character");
+                self.state = TokenizerState::ScriptDataDoubleEscaped;
+                self.emit_char('\u{FFFD}');
+            }
+            None => {
+                self.parse_error("eof-in-script-html-comment-like-text");
+                self.emit_eof();
+            }
+            Some(c) => {
+                self.state = TokenizerState::ScriptDataDoubleEscaped;
+                self.emit_char(c);
+            }
+        }
+    }
+
+    /// 13.2.5.30 Script data double escaped less-than sign state
+    fn script_data_double_escaped_less_than_state(&mut self, c: Option<char>) {
+        match c {
+            Some('/') => {
+                self.temp_buffer.clear();
+                self.state = TokenizerState::ScriptDataDoubleEscapeEnd;
+                self.emit_char('/');
+            }
+            _ => {
+                self.reconsume_in(TokenizerState::ScriptDataDoubleEscaped);
+            }
+        }
+    }
+
+    /// 13.2.5.31 Script data double escape end state
+    fn script_data_double_escape_end_state(&mut self, c: Option<char>) {
+        match c {
+            Some(c @ ('\t' | '\n' | '\x0C' | ' ' | '/' | '>')) => {
+                self.state = if self.temp_buffer == "script" {
+                    TokenizerState::ScriptDataEscaped
+                } else {
+                    TokenizerState::ScriptDataDoubleEscaped
+                };
+                self.emit_char(c);
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.temp_buffer.push(c.to_ascii_lowercase());
+                self.emit_char(c);
+            }
+            _ => {
+                self.reconsume_in(TokenizerState::ScriptDataDoubleEscaped);
+            }
+        }
+    }
+
+    /// 13.2.5.32 Before attribute name state
+    fn before_attribute_name_state(&mut self, c: Option<char>) {
+        match c {
+            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
+                // Ignore whitespace
+            }
+            Some('/') | Some('>') | None => {
+                self.reconsume_in(TokenizerState::AfterAttributeName);
+            }
+            Some('=') => {
+                self.parse_error("unexpected-equals-sign-before-attribute-name");
+                self.start_new_attribute();
+                self.append_to_attribute_name('=');
+                self.state = TokenizerState::AttributeName;
+            }
+            Some(_) => {
+                self.start_new_attribute();
+                self.reconsume_in(TokenizerState::AttributeName);
+            }
+        }
+    }
+
+    /// 13.2.5.33 Attribute name state
+    fn attribute_name_state(&mut self, c: Option<char>) {
+        match c {
+            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') | Some('/') | Some('>') | None => {
+                self.reconsume_in(TokenizerState::AfterAttributeName);
+            }
+            Some('=') => {
+                self.state = TokenizerState::BeforeAttributeValue;
+            }
+            Some('\0') => {
+                self.parse_error("unexpected-null-character");
+                self.append_to_attribute_name('\u{FFFD}');
+            }
+            Some('"') | Some('\'') | Some('<') => {
+                self.parse_error("unexpected-character-in-attribute-name");
+                self.append_to_attribute_name(c.unwrap());
+            }
+            Some(c) => {
+                self.append_to_attribute_name(c);
+            }
+        }
+    }
+
+    /// 13.2.5.34 After attribute name state
+    fn after_attribute_name_state(&mut self, c: Option<char>) {
+        match c {
+            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
+                // Ignore whitespace
+            }
+            Some('/') => {
+                self.finalize_current_attribute();
+                self.state = TokenizerState::SelfClosingStartTag;
+            }
+            Some('=') => {
+                self.state = TokenizerState::BeforeAttributeValue;
+            }
+            Some('>') => {
+                self.finalize_current_attribute();
+                self.state = TokenizerState::Data;
+                self.emit_current_token();
+            }
+            None => {
+                self.parse_error("eof-in-tag");
+                self.emit_eof();
+            }
+            Some(_) => {
+                self.finalize_current_attribute();
+                self.start_new_attribute();
+                self.reconsume_in(TokenizerState::AttributeName);
+            }
+        }
+    }
+
+    /// 13.2.5.35 Before attribute value state
+    fn before_attribute_value_state(&mut self, c: Option<char>) {
+        match c {
+            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
+                // Ignore whitespace
+            }
+            Some('"') => {
+                self.state = TokenizerState::AttributeValueDoubleQuoted;
+            }
+            Some('\'') => {
+                self.state = TokenizerState::AttributeValueSingleQuoted;
+            }
+            Some('>') => {
+                self.parse_error("missing-attribute-value");
+                self.finalize_current_attribute();
+                self.state = TokenizerState::Data;
+                self.emit_current_token();
+            }
+            Some(_) | None => {
+                self.reconsume_in(TokenizerState::AttributeValueUnquoted);
+            }
+        }
+    }
+
+    /// 13.2.5.36 Attribute value (double-quoted) state
+    fn attribute_value_double_quoted_state(&mut self, c: Option<char>) {
+        match c {
+            Some('"') => {
+                self.state = TokenizerState::AfterAttributeValueQuoted;
+            }
+            Some('&') => {
+                self.return_state = TokenizerState::AttributeValueDoubleQuoted;
+                self.state = TokenizerState::CharacterReference;
+            }
+            Some('\0') => {
+                self.parse_error("unexpected-null-character");
+                self.append_to_attribute_value('\u{FFFD}');
+            }
+            None => {
+                self.parse_error("eof-in-tag");
+                self.emit_eof();
+            }
+            Some(c) => {
+                self.append_to_attribute_value(c);
+            }
+        }
+    }
+
+    /// 13.2.5.37 Attribute value (single-quoted) state
+    fn attribute_value_single_quoted_state(&mut self, c: Option<char>) {
+        match c {
+            Some('\'') => {
+                self.state = TokenizerState::AfterAttributeValueQuoted;
+            }
+            Some('&') => {
+                self.return_state = TokenizerState::AttributeValueSingleQuoted;
+                self.state = TokenizerState::CharacterReference;
+            }
+            Some('\0') => {
+                self.parse_error("unexpected-null-character");
+                self.append_to_attribute_value('\u{FFFD}');
+            }
+            None => {
+                self.parse_error("eof-in-tag");
+                self.emit_eof();
+            }
+            Some(c) => {
+                self.append_to_attribute_value(c);
+            }
+        }
+    }
+
+    /// 13.2.5.38 Attribute value (unquoted) state
+    fn attribute_value_unquoted_state(&mut self, c: Option<char>) {
+        match c {
+            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
+                self.finalize_current_attribute();
+                self.state = TokenizerState::BeforeAttributeName;
+            }
+            Some('&') => {
+                self.return_state = TokenizerState::AttributeValueUnquoted;
+                self.state = TokenizerState::CharacterReference;
+            }
+            Some('>') => {
+                self.finalize_current_attribute();
+                self.state = TokenizerState::Data;
+                self.emit_current_token();
+            }
+            Some('\0') => {
+                self.parse_error("unexpected-null-character");
+                self.append_to_attribute_value('\u{FFFD}');
+            }
+            Some('"') | Some('\'') | Some('<') | Some('=') | Some('`') => {
+                self.parse_error("unexpected-character-in-unquoted-attribute-value");
+                self.append_to_attribute_value(c.unwrap());
+            }
+            None => {
+                self.parse_error("eof-in-tag");
+                self.emit_eof();
+            }
+            Some(c) => {
+                self.append_to_attribute_value(c);
+            }
+        }
+    }
+
+    /// 13.2.5.39 After attribute value (quoted) state
+    fn after_attribute_value_quoted_state(&mut self, c: Option<char>) {
+        self.finalize_current_attribute();
+        match c {
+            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
+                self.state = TokenizerState::BeforeAttributeName;
+            }
             Some('/') => {
                 self.state = TokenizerState::SelfClosingStartTag;
             }
@@ -872,321 +1473,920 @@ impl Tokenizer {
                 self.emit_current_token();
             }
             None => {
-                tokenizer_log("Parse error: eof-in-tag");
+                self.parse_error("eof-in-tag");
+                self.emit_eof();
+            }
+            Some(_) => {
+                self.parse_error("missing-whitespace-between-attributes");
+                self.reconsume_in(TokenizerState::BeforeAttributeName);
+            }
+        }
+    }
+
+    /// 13.2.5.40 Self-closing start tag state
+    fn self_closing_start_tag_state(&mut self, c: Option<char>) {
+        match c {
+            Some('>') => {
+                self.set_self_closing();
+                self.state = TokenizerState::Data;
+                self.emit_current_token();
+            }
+            None => {
+                self.parse_error("eof-in-tag");
+                self.emit_eof();
+            }
+            Some(_) => {
+                self.parse_error("unexpected-solidus-in-tag");
+                self.reconsume_in(TokenizerState::BeforeAttributeName);
+            }
+        }
+    }
+
+    /// 13.2.5.41 Bogus comment state
+    fn bogus_comment_state(&mut self, c: Option<char>) {
+        match c {
+            Some('>') => {
+                self.state = TokenizerState::Data;
+                self.emit_current_token();
+            }
+            None => {
+                self.emit_current_token();
+                self.emit_eof();
+            }
+            Some('\0') => {
+                self.append_to_comment('\u{FFFD}');
+            }
+            Some(c) => {
+                self.append_to_comment(c);
+            }
+        }
+    }
+
+    /// 13.2.5.42 Markup declaration open state
+    fn markup_declaration_open_state(&mut self, _c: Option<char>) {
+        self.pos = self.pos.saturating_sub(1);
+        
+        if self.next_chars_are_case_insensitive("--") {
+            self.consume_chars(2);
+            self.create_comment("");
+            self.state = TokenizerState::CommentStart;
+        } else if self.next_chars_are_case_insensitive("DOCTYPE") {
+            self.consume_chars(7);
+            self.state = TokenizerState::Doctype;
+        } else if self.next_chars_are("[CDATA[") {
+            self.consume_chars(7);
+            if self.cdata_allowed {
+                self.state = TokenizerState::CdataSection;
+            } else {
+                self.parse_error("cdata-in-html-content");
+                self.create_comment("[CDATA[");
+                self.state = TokenizerState::BogusComment;
+            }
+        } else {
+            self.parse_error("incorrectly-opened-comment");
+            self.create_comment("");
+            self.state = TokenizerState::BogusComment;
+        }
+    }
+
+    /// 13.2.5.43 Comment start state
+    fn comment_start_state(&mut self, c: Option<char>) {
+        match c {
+            Some('-') => {
+                self.state = TokenizerState::CommentStartDash;
+            }
+            Some('>') => {
+                self.parse_error("abrupt-closing-of-empty-comment");
+                self.state = TokenizerState::Data;
+                self.emit_current_token();
+            }
+            Some(_) | None => {
+                self.reconsume_in(TokenizerState::Comment);
+            }
+        }
+    }
+
+    /// 13.2.5.44 Comment start dash state
+    fn comment_start_dash_state(&mut self, c: Option<char>) {
+        match c {
+            Some('-') => {
+                self.state = TokenizerState::CommentEnd;
+            }
+            Some('>') => {
+                self.parse_error("abrupt-closing-of-empty-comment");
+                self.state = TokenizerState::Data;
+                self.emit_current_token();
+            }
+            None => {
+                self.parse_error("eof-in-comment");
+                self.emit_current_token();
+                self.emit_eof();
+            }
+            Some(_) => {
+                self.append_to_comment('-');
+                self.reconsume_in(TokenizerState::Comment);
+            }
+        }
+    }
+
+    /// 13.2.5.45 Comment state
+    fn comment_state(&mut self, c: Option<char>) {
+        match c {
+            Some('<') => {
+                self.append_to_comment('<');
+                self.state = TokenizerState::CommentLessThan;
+            }
+            Some('-') => {
+                self.state = TokenizerState::CommentEndDash;
+            }
+            Some('\0') => {
+                self.parse_error("unexpected-null-character");
+                self.append_to_comment('\u{FFFD}');
+            }
+            None => {
+                self.parse_error("eof-in-comment");
+                self.emit_current_token();
+                self.emit_eof();
+            }
+            Some(c) => {
+                self.append_to_comment(c);
+            }
+        }
+    }
+
+    /// 13.2.5.46 Comment less-than sign state
+    fn comment_less_than_state(&mut self, c: Option<char>) {
+        match c {
+            Some('!') => {
+                self.append_to_comment('!');
+                self.state = TokenizerState::CommentLessThanBang;
+            }
+            Some('<') => {
+                self.append_to_comment('<');
+            }
+            _ => {
+                self.reconsume_in(TokenizerState::Comment);
+            }
+        }
+    }
+
+    /// 13.2.5.47 Comment less-than sign bang state
+    fn comment_less_than_bang_state(&mut self, c: Option<char>) {
+        match c {
+            Some('-') => {
+                self.state = TokenizerState::CommentLessThanBangDash;
+            }
+            _ => {
+                self.reconsume_in(TokenizerState::Comment);
+            }
+        }
+    }
+
+    /// 13.2.5.48 Comment less-than sign bang dash state
+    fn comment_less_than_bang_dash_state(&mut self, c: Option<char>) {
+        match c {
+            Some('-') => {
+                self.state = TokenizerState::CommentLessThanBangDashDash;
+            }
+            _ => {
+                self.reconsume_in(TokenizerState::CommentEndDash);
+            }
+        }
+    }
+
+    /// 13.2.5.49 Comment less-than sign bang dash dash state
+    fn comment_less_than_bang_dash_dash_state(&mut self, c: Option<char>) {
+        match c {
+            Some('>') | None => {
+                self.reconsume_in(TokenizerState::CommentEnd);
+            }
+            Some(_) => {
+                self.parse_error("nested-comment");
+                self.reconsume_in(TokenizerState::CommentEnd);
+            }
+        }
+    }
+
+    /// 13.2.5.50 Comment end dash state
+    fn comment_end_dash_state(&mut self, c: Option<char>) {
+        match c {
+            Some('-') => {
+                self.state = TokenizerState::CommentEnd;
+            }
+            None => {
+                self.parse_error("eof-in-comment");
+                self.emit_current_token();
+                self.emit_eof();
+            }
+            Some(_) => {
+                self.append_to_comment('-');
+                self.reconsume_in(TokenizerState::Comment);
+            }
+        }
+    }
+
+    /// 13.2.5.51 Comment end state
+    fn comment_end_state(&mut self, c: Option<char>) {
+        match c {
+            Some('>') => {
+                self.state = TokenizerState::Data;
+                self.emit_current_token();
+            }
+            Some('!') => {
+                self.state = TokenizerState::CommentEndBang;
+            }
+            Some('-') => {
+                self.append_to_comment('-');
+            }
+            None => {
+                self.parse_error("eof-in-comment");
+                self.emit_current_token();
                 self.emit_eof();
             }
             Some(_) => {
-                tokenizer_log("Parse error: missing-whitespace-between-attributes");
-                self.reconsume_in(TokenizerState::BeforeAttributeName);
+                self.append_to_comment('-');
+                self.append_to_comment('-');
+                self.reconsume_in(TokenizerState::Comment);
             }
         }
     }
 
-    /// 13.2.5.40 Self-closing start tag state
-    fn self_closing_start_tag_state(&mut self, c: Option<char>) {
+    /// 13.2.5.52 Comment end bang state
+    fn comment_end_bang_state(&mut self, c: Option<char>) {
         match c {
+            Some('-') => {
+                self.append_to_comment('-');
+                self.append_to_comment('-');
+                self.append_to_comment('!');
+                self.state = TokenizerState::CommentEndDash;
+            }
             Some('>') => {
-                self.set_self_closing();
+                self.parse_error("incorrectly-closed-comment");
                 self.state = TokenizerState::Data;
                 self.emit_current_token();
             }
             None => {
-                tokenizer_log("Parse error: eof-in-tag");
+                self.parse_error("eof-in-comment");
+                self.emit_current_token();
                 self.emit_eof();
             }
             Some(_) => {
-                tokenizer_log("Parse error: unexpected-solidus-in-tag");
-                self.reconsume_in(TokenizerState::BeforeAttributeName);
+                self.append_to_comment('-');
+                self.append_to_comment('-');
+                self.append_to_comment('!');
+                self.reconsume_in(TokenizerState::Comment);
             }
         }
     }
 
-    /// 13.2.5.41 Bogus comment state
-    fn bogus_comment_state(&mut self, c: Option<char>) {
+    /// 13.2.5.53 DOCTYPE state
+    fn doctype_state(&mut self, c: Option<char>) {
+        match c {
+            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
+                self.state = TokenizerState::BeforeDoctypeName;
+            }
+            Some('>') => {
+                self.reconsume_in(TokenizerState::BeforeDoctypeName);
+            }
+            None => {
+                self.parse_error("eof-in-doctype");
+                self.create_doctype();
+                self.set_force_quirks();
+                self.emit_current_token();
+                self.emit_eof();
+            }
+            Some(_) => {
+                self.parse_error("missing-whitespace-before-doctype-name");
+                self.reconsume_in(TokenizerState::BeforeDoctypeName);
+            }
+        }
+    }
+
+    /// 13.2.5.54 Before DOCTYPE name state
+    fn before_doctype_name_state(&mut self, c: Option<char>) {
         match c {
+            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
+                // Ignore whitespace
+            }
+            Some('\0') => {
+                self.parse_error("unexpected-null-character");
+                self.create_doctype();
+                self.append_to_doctype_name('\u{FFFD}');
+                self.state = TokenizerState::DoctypeName;
+            }
             Some('>') => {
+                self.parse_error("missing-doctype-name");
+                self.create_doctype();
+                self.set_force_quirks();
                 self.state = TokenizerState::Data;
                 self.emit_current_token();
             }
             None => {
+                self.parse_error("eof-in-doctype");
+                self.create_doctype();
+                self.set_force_quirks();
                 self.emit_current_token();
                 self.emit_eof();
             }
+            Some(c) => {
+                self.create_doctype();
+                self.append_to_doctype_name(c);
+                self.state = TokenizerState::DoctypeName;
+            }
+        }
+    }
+
+    /// 13.2.5.55 DOCTYPE name state
+    fn doctype_name_state(&mut self, c: Option<char>) {
+        match c {
+            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
+                self.state = TokenizerState::AfterDoctypeName;
+            }
+            Some('>') => {
+                self.state = TokenizerState::Data;
+                self.emit_current_token();
+            }
             Some('\0') => {
-                self.append_to_comment('\u{FFFD}');
+                self.parse_error("unexpected-null-character");
+                self.append_to_doctype_name('\u{FFFD}');
+            }
+            None => {
+                self.parse_error("eof-in-doctype");
+                self.set_force_quirks();
+                self.emit_current_token();
+                self.emit_eof();
             }
             Some(c) => {
-                self.append_to_comment(c);
+                self.append_to_doctype_name(c);
             }
         }
     }
 
-    /// 13.2.5.42 Markup declaration open state
-    fn markup_declaration_open_state(&mut self, _c: Option<char>) {
-        self.pos = self.pos.saturating_sub(1);
-        
-        if self.next_chars_are_case_insensitive("--") {
-            self.consume_chars(2);
-            self.create_comment("");
-            self.state = TokenizerState::CommentStart;
-        } else if self.next_chars_are_case_insensitive("DOCTYPE") {
-            self.consume_chars(7);
-            self.state = TokenizerState::Doctype;
-        } else if self.next_chars_are_case_insensitive("[CDATA[") {
-            tokenizer_log("Parse error: cdata-in-html-content");
-            self.create_comment("[CDATA[");
-            self.state = TokenizerState::BogusComment;
-        } else {
-            tokenizer_log("Parse error: incorrectly-opened-comment");
-            self.create_comment("");
-            self.state = TokenizerState::BogusComment;
+    /// 13.2.5.56 After DOCTYPE name state
+    fn after_doctype_name_state(&mut self, c: Option<char>) {
+        match c {
+            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
+                // Ignore whitespace
+            }
+            Some('>') => {
+                self.state = TokenizerState::Data;
+                self.emit_current_token();
+            }
+            None => {
+                self.parse_error("eof-in-doctype");
+                self.set_force_quirks();
+                self.emit_current_token();
+                self.emit_eof();
+            }
+            Some(_) => {
+                // The current character starts the keyword
+                self.pos -= 1;
+                if self.next_chars_are_case_insensitive("PUBLIC") {
+                    self.consume_chars(6);
+                    self.state = TokenizerState::AfterDoctypePublicKeyword;
+                } else if self.next_chars_are_case_insensitive("SYSTEM") {
+                    self.consume_chars(6);
+                    self.state = TokenizerState::AfterDoctypeSystemKeyword;
+                } else {
+                    self.parse_error("invalid-character-sequence-after-doctype-name");
+                    self.set_force_quirks();
+                    self.pos += 1;
+                    self.reconsume_in(TokenizerState::BogusDoctype);
+                }
+            }
         }
     }
 
-    /// 13.2.5.43 Comment start state
-    fn comment_start_state(&mut self, c: Option<char>) {
+    /// 13.2.5.57 After DOCTYPE public keyword state
+    fn after_doctype_public_keyword_state(&mut self, c: Option<char>) {
         match c {
-            Some('-') => {
-                self.state = TokenizerState::CommentStartDash;
+            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
+                self.state = TokenizerState::BeforeDoctypePublicIdentifier;
+            }
+            Some('"') => {
+                self.parse_error("missing-whitespace-after-doctype-public-keyword");
+                self.start_doctype_public_id();
+                self.state = TokenizerState::DoctypePublicIdentifierDoubleQuoted;
+            }
+            Some('\'') => {
+                self.parse_error("missing-whitespace-after-doctype-public-keyword");
+                self.start_doctype_public_id();
+                self.state = TokenizerState::DoctypePublicIdentifierSingleQuoted;
+            }
+            _ => self.doctype_missing_public_identifier(c),
+        }
+    }
+
+    /// 13.2.5.58 Before DOCTYPE public identifier state
+    fn before_doctype_public_identifier_state(&mut self, c: Option<char>) {
+        match c {
+            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
+                // Ignore whitespace
+            }
+            Some('"') => {
+                self.start_doctype_public_id();
+                self.state = TokenizerState::DoctypePublicIdentifierDoubleQuoted;
+            }
+            Some('\'') => {
+                self.start_doctype_public_id();
+                self.state = TokenizerState::DoctypePublicIdentifierSingleQuoted;
             }
+            _ => self.doctype_missing_public_identifier(c),
+        }
+    }
+
+    /// Shared "anything else" steps when a public identifier was expected
+    fn doctype_missing_public_identifier(&mut self, c: Option<char>) {
+        match c {
             Some('>') => {
-                tokenizer_log("Parse error: abrupt-closing-of-empty-comment");
+                self.parse_error("missing-doctype-public-identifier");
+                self.set_force_quirks();
                 self.state = TokenizerState::Data;
                 self.emit_current_token();
             }
-            Some(_) | None => {
-                self.reconsume_in(TokenizerState::Comment);
+            None => self.eof_in_doctype(),
+            Some(_) => {
+                self.parse_error("missing-quote-before-doctype-public-identifier");
+                self.set_force_quirks();
+                self.reconsume_in(TokenizerState::BogusDoctype);
             }
         }
     }
 
-    /// 13.2.5.44 Comment start dash state
-    fn comment_start_dash_state(&mut self, c: Option<char>) {
+    /// 13.2.5.59 DOCTYPE public identifier (double-quoted) state
+    /// 13.2.5.60 DOCTYPE public identifier (single-quoted) state
+    fn doctype_public_identifier_quoted_state(&mut self, c: Option<char>, quote: char) {
         match c {
-            Some('-') => {
-                self.state = TokenizerState::CommentEnd;
+            Some(c) if c == quote => {
+                self.state = TokenizerState::AfterDoctypePublicIdentifier;
+            }
+            Some('\0') => {
+                self.parse_error("unexpected-null-character");
+                self.append_to_doctype_public_id('\u{FFFD}');
             }
             Some('>') => {
-                tokenizer_log("Parse error: abrupt-closing-of-empty-comment");
+                self.parse_error("abrupt-doctype-public-identifier");
+                self.set_force_quirks();
                 self.state = TokenizerState::Data;
                 self.emit_current_token();
             }
-            None => {
-                tokenizer_log("Parse error: eof-in-comment");
+            None => self.eof_in_doctype(),
+            Some(c) => {
+                self.append_to_doctype_public_id(c);
+            }
+        }
+    }
+
+    /// 13.2.5.61 After DOCTYPE public identifier state
+    fn after_doctype_public_identifier_state(&mut self, c: Option<char>) {
+        match c {
+            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
+                self.state = TokenizerState::BetweenDoctypePublicAndSystemIdentifiers;
+            }
+            Some('>') => {
+                self.state = TokenizerState::Data;
                 self.emit_current_token();
-                self.emit_eof();
             }
-            Some(_) => {
-                self.append_to_comment('-');
-                self.reconsume_in(TokenizerState::Comment);
+            Some('"') => {
+                self.parse_error("missing-whitespace-between-doctype-public-and-system-identifiers");
+                self.start_doctype_system_id();
+                self.state = TokenizerState::DoctypeSystemIdentifierDoubleQuoted;
+            }
+            Some('\'') => {
+                self.parse_error("missing-whitespace-between-doctype-public-and-system-identifiers");
+                self.start_doctype_system_id();
+                self.state = TokenizerState::DoctypeSystemIdentifierSingleQuoted;
             }
+            _ => self.doctype_missing_system_identifier(c),
         }
     }
 
-    /// 13.2.5.45 Comment state
-    fn comment_state(&mut self, c: Option<char>) {
+    /// 13.2.5.62 Between DOCTYPE public and system identifiers state
+    fn between_doctype_public_and_system_identifiers_state(&mut self, c: Option<char>) {
         match c {
-            Some('<') => {
-                self.append_to_comment('<');
-                self.state = TokenizerState::CommentLessThan;
+            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
+                // Ignore whitespace
             }
-            Some('-') => {
-                self.state = TokenizerState::CommentEndDash;
+            Some('>') => {
+                self.state = TokenizerState::Data;
+                self.emit_current_token();
+            }
+            Some('"') => {
+                self.start_doctype_system_id();
+                self.state = TokenizerState::DoctypeSystemIdentifierDoubleQuoted;
+            }
+            Some('\'') => {
+                self.start_doctype_system_id();
+                self.state = TokenizerState::DoctypeSystemIdentifierSingleQuoted;
+            }
+            _ => self.doctype_missing_system_identifier(c),
+        }
+    }
+
+    /// 13.2.5.63 After DOCTYPE system keyword state
+    fn after_doctype_system_keyword_state(&mut self, c: Option<char>) {
+        match c {
+            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
+                self.state = TokenizerState::BeforeDoctypeSystemIdentifier;
+            }
+            Some('"') => {
+                self.parse_error("missing-whitespace-after-doctype-system-keyword");
+                self.start_doctype_system_id();
+                self.state = TokenizerState::DoctypeSystemIdentifierDoubleQuoted;
+            }
+            Some('\'') => {
+                self.parse_error("missing-whitespace-after-doctype-system-keyword");
+                self.start_doctype_system_id();
+                self.state = TokenizerState::DoctypeSystemIdentifierSingleQuoted;
+            }
+            Some('>') => {
+                self.parse_error("missing-doctype-system-identifier");
+                self.set_force_quirks();
+                self.state = TokenizerState::Data;
+                self.emit_current_token();
+            }
+            _ => self.doctype_missing_system_identifier(c),
+        }
+    }
+
+    /// 13.2.5.64 Before DOCTYPE system identifier state
+    fn before_doctype_system_identifier_state(&mut self, c: Option<char>) {
+        match c {
+            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
+                // Ignore whitespace
+            }
+            Some('"') => {
+                self.start_doctype_system_id();
+                self.state = TokenizerState::DoctypeSystemIdentifierDoubleQuoted;
+            }
+            Some('\'') => {
+                self.start_doctype_system_id();
+                self.state = TokenizerState::DoctypeSystemIdentifierSingleQuoted;
+            }
+            Some('>') => {
+                self.parse_error("missing-doctype-system-identifier");
+                self.set_force_quirks();
+                self.state = TokenizerState::Data;
+                self.emit_current_token();
+            }
+            _ => self.doctype_missing_system_identifier(c),
+        }
+    }
+
+    /// Shared "anything else" steps when a system identifier was expected
+    fn doctype_missing_system_identifier(&mut self, c: Option<char>) {
+        match c {
+            None => self.eof_in_doctype(),
+            _ => {
+                self.parse_error("missing-quote-before-doctype-system-identifier");
+                self.set_force_quirks();
+                self.reconsume_in(TokenizerState::BogusDoctype);
+            }
+        }
+    }
+
+    /// 13.2.5.65 DOCTYPE system identifier (double-quoted) state
+    /// 13.2.5.66 DOCTYPE system identifier (single-quoted) state
+    fn doctype_system_identifier_quoted_state(&mut self, c: Option<char>, quote: char) {
+        match c {
+            Some(c) if c == quote => {
+                self.state = TokenizerState::AfterDoctypeSystemIdentifier;
             }
             Some('\0') => {
-                tokenizer_log("Parse error: unexpected-null-character");
-                self.append_to_comment('\u{FFFD}');
+                self.parse_error("unexpected-null-character");
+                self.append_to_doctype_system_id('\u{FFFD}');
             }
-            None => {
-                tokenizer_log("Parse error: eof-in-comment");
+            Some('>') => {
+                self.parse_error("abrupt-doctype-system-identifier");
+                self.set_force_quirks();
+                self.state = TokenizerState::Data;
                 self.emit_current_token();
-                self.emit_eof();
             }
+            None => self.eof_in_doctype(),
             Some(c) => {
-                self.append_to_comment(c);
+                self.append_to_doctype_system_id(c);
             }
         }
     }
-
-    /// 13.2.5.50 Comment end dash state
-    fn comment_end_dash_state(&mut self, c: Option<char>) {
+
+    /// 13.2.5.67 After DOCTYPE system identifier state
+    fn after_doctype_system_identifier_state(&mut self, c: Option<char>) {
         match c {
-            Some('-') => {
-                self.state = TokenizerState::CommentEnd;
+            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
+                // Ignore whitespace
             }
-            None => {
-                tokenizer_log("Parse error: eof-in-comment");
+            Some('>') => {
+                self.state = TokenizerState::Data;
                 self.emit_current_token();
-                self.emit_eof();
             }
+            None => self.eof_in_doctype(),
             Some(_) => {
-                self.append_to_comment('-');
-                self.reconsume_in(TokenizerState::Comment);
+                self.parse_error("unexpected-character-after-doctype-system-identifier");
+                self.reconsume_in(TokenizerState::BogusDoctype);
             }
         }
     }
 
-    /// 13.2.5.51 Comment end state
-    fn comment_end_state(&mut self, c: Option<char>) {
+    /// 13.2.5.68 Bogus DOCTYPE state
+    fn bogus_doctype_state(&mut self, c: Option<char>) {
         match c {
             Some('>') => {
                 self.state = TokenizerState::Data;
                 self.emit_current_token();
             }
-            Some('!') => {
-                self.state = TokenizerState::CommentEndBang;
-            }
-            Some('-') => {
-                self.append_to_comment('-');
+            Some('\0') => {
+                self.parse_error("unexpected-null-character");
             }
             None => {
-                tokenizer_log("Parse error: eof-in-comment");
                 self.emit_current_token();
                 self.emit_eof();
             }
             Some(_) => {
-                self.append_to_comment('-');
-                self.append_to_comment('-');
-                self.reconsume_in(TokenizerState::Comment);
+                // Ignore the character
             }
         }
     }
 
-    /// 13.2.5.52 Comment end bang state
-    fn comment_end_bang_state(&mut self, c: Option<char>) {
+    fn eof_in_doctype(&mut self) {
+        self.parse_error("eof-in-doctype");
+        self.set_force_quirks();
+        self.emit_current_token();
+        self.emit_eof();
+    }
+
+    /// 13.2.5.69 CDATA section state
+    fn cdata_section_state(&mut self, c: Option<char>) {
         match c {
-            Some('-') => {
-                self.append_to_comment('-');
-                self.append_to_comment('-');
-                self.append_to_comment('!');
-                self.state = TokenizerState::CommentEndDash;
-            }
-            Some('>') => {
-                tokenizer_log("Parse error: incorrectly-closed-comment");
-                self.state = TokenizerState::Data;
-                self.emit_current_token();
+            Some(']') => {
+                self.state = TokenizerState::CdataSectionBracket;
             }
             None => {
-                tokenizer_log("Parse error: eof-in-comment");
-                self.emit_current_token();
+                self.parse_error("eof-in-cdata");
                 self.emit_eof();
             }
-            Some(_) => {
-                self.append_to_comment('-');
-                self.append_to_comment('-');
-                self.append_to_comment('!');
-                self.reconsume_in(TokenizerState::Comment);
+            Some(c) => {
+                self.emit_char(c);
             }
         }
     }
 
-    /// 13.2.5.53 DOCTYPE state
-    fn doctype_state(&mut self, c: Option<char>) {
+    /// 13.2.5.70 CDATA section bracket state
+    fn cdata_section_bracket_state(&mut self, c: Option<char>) {
         match c {
-            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
-                self.state = TokenizerState::BeforeDoctypeName;
+            Some(']') => {
+                self.state = TokenizerState::CdataSectionEnd;
+            }
+            _ => {
+                self.emit_char(']');
+                self.reconsume_in(TokenizerState::CdataSection);
+            }
+        }
+    }
+
+    /// 13.2.5.71 CDATA section end state
+    fn cdata_section_end_state(&mut self, c: Option<char>) {
+        match c {
+            Some(']') => {
+                self.emit_char(']');
             }
             Some('>') => {
-                self.reconsume_in(TokenizerState::BeforeDoctypeName);
+                self.state = TokenizerState::Data;
             }
-            None => {
-                tokenizer_log("Parse error: eof-in-doctype");
-                self.create_doctype();
-                self.set_force_quirks();
-                self.emit_current_token();
-                self.emit_eof();
+            _ => {
+                self.emit_char(']');
+                self.emit_char(']');
+                self.reconsume_in(TokenizerState::CdataSection);
             }
-            Some(_) => {
-                tokenizer_log("Parse error: missing-whitespace-before-doctype-name");
-                self.reconsume_in(TokenizerState::BeforeDoctypeName);
+        }
+    }
+
+    /// Whether the pending character reference belongs to an attribute value
+    fn char_ref_in_attribute(&self) -> bool {
+        matches!(
+            self.return_state,
+            TokenizerState::AttributeValueDoubleQuoted
+                | TokenizerState::AttributeValueSingleQuoted
+                | TokenizerState::AttributeValueUnquoted
+        )
+    }
+
+    /// Spec 13.2.5 "flush code points consumed as a character reference"
+    fn flush_character_reference(&mut self) {
+        let buffer = std::mem::take(&mut self.temp_buffer);
+        for c in buffer.chars() {
+            if self.char_ref_in_attribute() {
+                self.append_to_attribute_value(c);
+            } else {
+                self.emit_char(c);
             }
         }
     }
 
-    /// 13.2.5.54 Before DOCTYPE name state
-    fn before_doctype_name_state(&mut self, c: Option<char>) {
+    /// 13.2.5.72 Character reference state
+    fn character_reference_state(&mut self, c: Option<char>) {
+        self.temp_buffer.clear();
+        self.temp_buffer.push('&');
         match c {
-            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
-                // Ignore whitespace
+            Some(c) if c.is_ascii_alphanumeric() => {
+                self.reconsume_in(TokenizerState::NamedCharacterReference);
             }
-            Some('\0') => {
-                tokenizer_log("Parse error: unexpected-null-character");
-                self.create_doctype();
-                self.append_to_doctype_name('\u{FFFD}');
-                self.state = TokenizerState::DoctypeName;
+            Some('#') => {
+                self.temp_buffer.push('#');
+                self.state = TokenizerState::NumericCharacterReference;
             }
-            Some('>') => {
-                tokenizer_log("Parse error: missing-doctype-name");
-                self.create_doctype();
-                self.set_force_quirks();
-                self.state = TokenizerState::Data;
-                self.emit_current_token();
+            _ => {
+                self.flush_character_reference();
+                self.reconsume_in(self.return_state);
+            }
+        }
+    }
+
+    /// 13.2.5.73 Named character reference state
+    fn named_character_reference_state(&mut self, _c: Option<char>) {
+        // The reconsumed character starts the candidate name
+        let start = self.pos - 1;
+        let mut candidate: Vec<char> = self.input[start..]
+            .iter()
+            .take(LONGEST_ENTITY_NAME)
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .copied()
+            .collect();
+        if candidate.len() < LONGEST_ENTITY_NAME && self.input.get(start + candidate.len()) == Some(&';') {
+            candidate.push(';');
+        }
+
+        // Longest prefix of the candidate that names an entity
+        let found = (1..=candidate.len()).rev().find_map(|len| {
+            let name: String = candidate[..len].iter().collect();
+            lookup_entity(&name).map(|value| (len, value))
+        });
+
+        match found {
+            Some((len, value)) => {
+                self.pos = start + len;
+                let last = candidate[len - 1];
+                let next = self.input.get(self.pos).copied();
+                let legacy_text = self.char_ref_in_attribute()
+                    && last != ';'
+                    && matches!(next, Some(n) if n == '=' || n.is_ascii_alphanumeric());
+                if legacy_text {
+                    // Historical: "&copy=..." in attributes stays as written
+                    self.temp_buffer.extend(&candidate[..len]);
+                } else {
+                    if last != ';' {
+                        self.parse_error("missing-semicolon-after-character-reference");
+                    }
+                    self.temp_buffer.clear();
+                    self.temp_buffer.push_str(value);
+                }
+                self.flush_character_reference();
+                self.state = self.return_state;
             }
             None => {
-                tokenizer_log("Parse error: eof-in-doctype");
-                self.create_doctype();
-                self.set_force_quirks();
-                self.emit_current_token();
-                self.emit_eof();
+                self.flush_character_reference();
+                self.reconsume_in(TokenizerState::AmbiguousAmpersand);
             }
-            Some(c) => {
-                self.create_doctype();
-                self.append_to_doctype_name(c);
-                self.state = TokenizerState::DoctypeName;
+        }
+    }
+
+    /// 13.2.5.74 Ambiguous ampersand state
+    fn ambiguous_ampersand_state(&mut self, c: Option<char>) {
+        match c {
+            Some(c) if c.is_ascii_alphanumeric() => {
+                if self.char_ref_in_attribute() {
+                    self.append_to_attribute_value(c);
+                } else {
+                    self.emit_char(c);
+                }
+            }
+            Some(';') => {
+                self.parse_error("unknown-named-character-reference");
+                self.reconsume_in(self.return_state);
+            }
+            _ => {
+                self.reconsume_in(self.return_state);
             }
         }
     }
 
-    /// 13.2.5.55 DOCTYPE name state
-    fn doctype_name_state(&mut self, c: Option<char>) {
+    /// 13.2.5.75 Numeric character reference state
+    fn numeric_character_reference_state(&mut self, c: Option<char>) {
+        self.character_reference_code = 0;
         match c {
-            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
-                self.state = TokenizerState::AfterDoctypeName;
+            Some(c @ ('x' | 'X')) => {
+                self.temp_buffer.push(c);
+                self.state = TokenizerState::HexadecimalCharacterReferenceStart;
             }
-            Some('>') => {
-                self.state = TokenizerState::Data;
-                self.emit_current_token();
+            _ => {
+                self.reconsume_in(TokenizerState::DecimalCharacterReferenceStart);
             }
-            Some('\0') => {
-                tokenizer_log("Parse error: unexpected-null-character");
-                self.append_to_doctype_name('\u{FFFD}');
+        }
+    }
+
+    /// 13.2.5.76 Hexadecimal character reference start state
+    fn hexadecimal_character_reference_start_state(&mut self, c: Option<char>) {
+        match c {
+            Some(c) if c.is_ascii_hexdigit() => {
+                self.reconsume_in(TokenizerState::HexadecimalCharacterReference);
             }
-            None => {
-                tokenizer_log("Parse error: eof-in-doctype");
-                self.set_force_quirks();
-                self.emit_current_token();
-                self.emit_eof();
+            _ => {
+                self.parse_error("absence-of-digits-in-numeric-character-reference");
+                self.flush_character_reference();
+                self.reconsume_in(self.return_state);
             }
-            Some(c) => {
-                self.append_to_doctype_name(c);
+        }
+    }
+
+    /// 13.2.5.77 Decimal character reference start state
+    fn decimal_character_reference_start_state(&mut self, c: Option<char>) {
+        match c {
+            Some(c) if c.is_ascii_digit() => {
+                self.reconsume_in(TokenizerState::DecimalCharacterReference);
+            }
+            _ => {
+                self.parse_error("absence-of-digits-in-numeric-character-reference");
+                self.flush_character_reference();
+                self.reconsume_in(self.return_state);
             }
         }
     }
 
-    /// 13.2.5.56 After DOCTYPE name state
-    fn after_doctype_name_state(&mut self, c: Option<char>) {
+    /// 13.2.5.78 Hexadecimal character reference state
+    fn hexadecimal_character_reference_state(&mut self, c: Option<char>) {
         match c {
-            Some('\t') | Some('\n') | Some('\x0C') | Some(' ') => {
-                // Ignore whitespace
+            Some(c) if c.is_ascii_hexdigit() => {
+                let digit = c.to_digit(16).unwrap();
+                self.character_reference_code =
+                    self.character_reference_code.saturating_mul(16).saturating_add(digit);
             }
-            Some('>') => {
-                self.state = TokenizerState::Data;
-                self.emit_current_token();
+            Some(';') => {
+                self.state = TokenizerState::NumericCharacterReferenceEnd;
             }
-            None => {
-                tokenizer_log("Parse error: eof-in-doctype");
-                self.set_force_quirks();
-                self.emit_current_token();
-                self.emit_eof();
+            _ => {
+                self.parse_error("missing-semicolon-after-character-reference");
+                self.reconsume_in(TokenizerState::NumericCharacterReferenceEnd);
             }
-            Some(_) => {
-                // TODO: Handle PUBLIC and SYSTEM identifiers
-                self.set_force_quirks();
-                self.state = TokenizerState::BogusComment;
+        }
+    }
+
+    /// 13.2.5.79 Decimal character reference state
+    fn decimal_character_reference_state(&mut self, c: Option<char>) {
+        match c {
+            Some(c) if c.is_ascii_digit() => {
+                let digit = c.to_digit(10).unwrap();
+                self.character_reference_code =
+                    self.character_reference_code.saturating_mul(10).saturating_add(digit);
+            }
+            Some(';') => {
+                self.state = TokenizerState::NumericCharacterReferenceEnd;
+            }
+            _ => {
+                self.parse_error("missing-semicolon-after-character-reference");
+                self.reconsume_in(TokenizerState::NumericCharacterReferenceEnd);
             }
         }
     }
 
+    /// 13.2.5.80 Numeric character reference end state
+    /// Consumes nothing: the current character is handed back to the return state
+    fn numeric_character_reference_end_state(&mut self, _c: Option<char>) {
+        let code = self.character_reference_code;
+        let replacement = match code {
+            0 => {
+                self.parse_error("null-character-reference");
+                '\u{FFFD}'
+            }
+            _ if code > 0x10FFFF => {
+                self.parse_error("character-reference-outside-unicode-range");
+                '\u{FFFD}'
+            }
+            0xD800..=0xDFFF => {
+                self.parse_error("surrogate-character-reference");
+                '\u{FFFD}'
+            }
+            _ => {
+                if (0xFDD0..=0xFDEF).contains(&code) || (code & 0xFFFE) == 0xFFFE {
+                    self.parse_error("noncharacter-character-reference");
+                }
+                let is_control = code == 0x0D
+                    || ((code <= 0x1F || (0x7F..=0x9F).contains(&code))
+                        && !matches!(code, 0x09 | 0x0A | 0x0C | 0x20));
+                if is_control {
+                    self.parse_error("control-character-reference");
+                }
+                NUMERIC_REPLACEMENTS
+                    .iter()
+                    .find(|(from, _)| *from == code)
+                    .map(|(_, to)| *to)
+                    .unwrap_or_else(|| char::from_u32(code).unwrap_or('\u{FFFD}'))
+            }
+        };
+        self.temp_buffer.clear();
+        self.temp_buffer.push(replacement);
+        self.flush_character_reference();
+        self.reconsume_in(self.return_state);
+    }
+
     /// Tokenize entire input (compatibility method)
     pub fn tokenize(&mut self) -> Vec<Token> {
         let mut tokens = Vec::new();
@@ -1305,4 +2505,207 @@ mod tests {
         // Last token should be end tag
         assert!(matches!(&tokens[tokens.len()-1], Token::EndTag { name } if name == "a"));
     }
+
+    fn text_of(input: &str) -> String {
+        Tokenizer::new(input).tokenize().iter()
+            .filter_map(|t| if let Token::Character(c) = t { Some(*c) } else { None })
+            .collect()
+    }
+
+    fn attribute_value(input: &str) -> String {
+        let tokens = Tokenizer::new(input).tokenize();
+        match &tokens[0] {
+            Token::StartTag { attributes, .. } => attributes[0].value.clone(),
+            other => panic!("Expected StartTag, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_named_character_references() {
+        assert_eq!(text_of("&amp;&lt;&gt;&quot;"), "&<>\"");
+        assert_eq!(text_of("caf&eacute;&nbsp;bar"), "caf\u{E9}\u{A0}bar");
+        assert_eq!(text_of("&NotNestedGreaterGreater;"), "\u{2AA2}\u{338}");
+    }
+
+    #[test]
+    fn test_legacy_reference_without_semicolon() {
+        assert_eq!(text_of("&amp &copy2024"), "& \u{A9}2024");
+        // Longest match wins, the rest stays text
+        assert_eq!(text_of("&notit;"), "\u{AC}it;");
+        assert_eq!(text_of("a &amp"), "a &");
+    }
+
+    #[test]
+    fn test_unknown_references_stay_literal() {
+        assert_eq!(text_of("&foo; & &#; &#x;"), "&foo; & &#; &#x;");
+        assert_eq!(text_of("AT&T"), "AT&T");
+    }
+
+    #[test]
+    fn test_numeric_character_references() {
+        assert_eq!(text_of("&#8212;&#x41;&#X6a;"), "\u{2014}Aj");
+        assert_eq!(text_of("&#65"), "A");
+        assert_eq!(text_of("&#128;&#x9F;"), "\u{20AC}\u{178}");
+        assert_eq!(text_of("&#0;&#xD800;&#x110000;&#99999999999;"), "\u{FFFD}\u{FFFD}\u{FFFD}\u{FFFD}");
+    }
+
+    #[test]
+    fn test_character_references_in_attributes() {
+        assert_eq!(attribute_value("<a title=\"Tom &amp; Jerry\">"), "Tom & Jerry");
+        assert_eq!(attribute_value("<a title='&#169; 2024'>"), "\u{A9} 2024");
+        assert_eq!(attribute_value("<a title=x&lt;y>"), "x<y");
+        // Legacy references followed by '=' or an alphanumeric are left alone
+        assert_eq!(
+            attribute_value("<a href=\"?a=1&copy=2&amp;b&notx\">"),
+            "?a=1&copy=2&b&notx"
+        );
+        assert_eq!(attribute_value("<a href=\"?x&copy;=1\">"), "?x\u{A9}=1");
+    }
+
+    #[test]
+    fn test_character_references_in_rcdata() {
+        let mut tokenizer = Tokenizer::new("a &lt;b&gt; &amp c</title>");
+        tokenizer.set_state(TokenizerState::RcData);
+        tokenizer.last_start_tag_name = Some("title".to_string());
+        let tokens = tokenizer.tokenize();
+        let text: String = tokens.iter()
+            .filter_map(|t| if let Token::Character(c) = t { Some(*c) } else { None })
+            .collect();
+        assert_eq!(text, "a <b> & c");
+        assert!(matches!(tokens.last(), Some(Token::EndTag { name }) if name == "title"));
+    }
+
+    #[test]
+    fn test_raw_text_ignores_character_references() {
+        let mut tokenizer = Tokenizer::new("a &amp; b");
+        tokenizer.set_state(TokenizerState::RawText);
+        let text: String = tokenizer.tokenize().iter()
+            .filter_map(|t| if let Token::Character(c) = t { Some(*c) } else { None })
+            .collect();
+        assert_eq!(text, "a &amp; b");
+    }
+
+    #[test]
+    fn test_script_data_double_escape() {
+        let mut tokenizer = Tokenizer::new("a<!--<script></script>-->b</script>");
+        tokenizer.set_state(TokenizerState::ScriptData);
+        tokenizer.set_last_start_tag(Some("script"));
+        let tokens = tokenizer.tokenize();
+        let text: String = tokens.iter()
+            .filter_map(|t| if let Token::Character(c) = t { Some(*c) } else { None })
+            .collect();
+        assert_eq!(text, "a<!--<script></script>-->b");
+        assert!(matches!(tokens.last(), Some(Token::EndTag { name }) if name == "script"));
+    }
+
+    #[test]
+    fn test_script_data_escaped_end_tag() {
+        // Inside <!-- but not double escaped, </script> still ends the script
+        let mut tokenizer = Tokenizer::new("<!-- x </script>after");
+        tokenizer.set_state(TokenizerState::ScriptData);
+        tokenizer.set_last_start_tag(Some("script"));
+        let tokens = tokenizer.tokenize();
+        let end = tokens.iter().position(|t| matches!(t, Token::EndTag { .. })).unwrap();
+        let text: String = tokens[..end].iter()
+            .filter_map(|t| if let Token::Character(c) = t { Some(*c) } else { None })
+            .collect();
+        assert_eq!(text, "<!-- x ");
+    }
+
+    #[test]
+    fn test_doctype_identifiers() {
+        let tokens = Tokenizer::new(
+            r#"<!DOCTYPE html PUBLIC "-//W3C//DTD HTML 4.01//EN" 'http://www.w3.org/TR/html4/strict.dtd'>"#,
+        ).tokenize();
+        assert_eq!(tokens[0], Token::Doctype {
+            name: Some("html".to_string()),
+            public_id: Some("-//W3C//DTD HTML 4.01//EN".to_string()),
+            system_id: Some("http://www.w3.org/TR/html4/strict.dtd".to_string()),
+            force_quirks: false,
+        });
+
+        let tokens = Tokenizer::new("<!DOCTYPE html SYSTEM>").tokenize();
+        assert!(matches!(&tokens[0], Token::Doctype { system_id: None, force_quirks: true, .. }));
+    }
+
+    #[test]
+    fn test_cdata_section() {
+        let mut tokenizer = Tokenizer::new("<![CDATA[a<b]]]>");
+        tokenizer.set_cdata_allowed(true);
+        let text: String = tokenizer.tokenize().iter()
+            .filter_map(|t| if let Token::Character(c) = t { Some(*c) } else { None })
+            .collect();
+        assert_eq!(text, "a<b]");
+
+        // In HTML content it becomes a bogus comment
+        let tokens = Tokenizer::new("<![CDATA[x]]>").tokenize();
+        assert_eq!(tokens[0], Token::Comment("[CDATA[x]]".to_string()));
+    }
+
+    #[test]
+    fn test_nested_comment_markers() {
+        let tokens = Tokenizer::new("<!-- a <!-- b -->").tokenize();
+        assert_eq!(tokens[0], Token::Comment(" a <!-- b ".to_string()));
+    }
+
+    #[test]
+    fn test_newlines_are_normalized() {
+        assert_eq!(text_of("a\r\nb\rc"), "a\nb\nc");
+    }
+
+    /// Tokenize by feeding `chunk_size` characters at a time
+    fn tokenize_in_chunks(input: &str, chunk_size: usize) -> Vec<Token> {
+        let mut tokenizer = Tokenizer::new_streaming();
+        let mut tokens = Vec::new();
+        let chars: Vec<char> = input.chars().collect();
+        for chunk in chars.chunks(chunk_size) {
+            tokenizer.feed(&chunk.iter().collect::<String>());
+            while let Some(token) = tokenizer.next_token() {
+                tokens.push(token);
+            }
+        }
+        tokenizer.end_input();
+        tokens.extend(tokenizer.tokenize());
+        tokens
+    }
+
+    #[test]
+    fn test_streaming_matches_whole_input() {
+        let input = "<!DOCTYPE html>\r\n<p class=\"a&amp;b\">caf&eacute; &notin &#x41;</p>\r<!-- c -->\
+                     <script>if (a<b) {}</script><svg><![CDATA[x]]></svg>";
+        let expected = Tokenizer::new(input).tokenize();
+        for chunk_size in [1, 2, 3, 7, 64] {
+            assert_eq!(tokenize_in_chunks(input, chunk_size), expected, "chunk size {}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn test_streaming_waits_for_more_input() {
+        let mut tokenizer = Tokenizer::new_streaming();
+        tokenizer.feed("<p>&amp");
+        assert_eq!(tokenizer.next_token(), None);
+        tokenizer.feed(";x</p>");
+        tokenizer.end_input();
+        let tokens = tokenizer.tokenize();
+        assert_eq!(tokens[1], Token::Character('&'));
+        assert_eq!(tokens[2], Token::Character('x'));
+    }
+
+    #[test]
+    fn test_parse_errors_have_codes_and_positions() {
+        let mut tokenizer = Tokenizer::new("<p>\n<a href=>x\0</a>");
+        tokenizer.tokenize();
+        let errors = tokenizer.errors();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0], ParseError { code: "missing-attribute-value", line: 2, column: 9 });
+        assert_eq!(errors[1], ParseError { code: "unexpected-null-character", line: 2, column: 11 });
+    }
+
+    #[test]
+    fn test_duplicate_attribute_error() {
+        let mut tokenizer = Tokenizer::new("<a id=1 id=2>");
+        let tokens = tokenizer.tokenize();
+        assert_eq!(tokens[0].get_attributes(), vec![("id".to_string(), "1".to_string())]);
+        assert_eq!(tokenizer.errors()[0].code, "duplicate-attribute");
+    }
 }
@@ -3,26 +3,36 @@
 //
 // IMPLEMENTATION STATUS:
 // ✅ Initial mode - basic
-// ✅ BeforeHtml mode - basic
-// ✅ BeforeHead mode - basic
-// ✅ InHead mode - partial (meta, link, title, style, base)
-// ✅ AfterHead mode - basic
-// ✅ InBody mode - basic element creation
-// ⚠️ Text mode - partial
-// ❌ InTable mode - not implemented
-// ❌ InSelect mode - not implemented
-// ❌ InForeignContent mode - not implemented
-// ⚠️ AfterBody mode - partial
+// ✅ BeforeHtml mode
+// ✅ BeforeHead mode
+// ✅ InHead mode
+// ✅ InHeadNoscript mode
+// ✅ AfterHead mode
+// ✅ InBody mode - formatting elements via adoption agency algorithm
+// ✅ Text mode
+// ✅ InTable mode
+// ✅ InTableText mode
+// ✅ InCaption mode
+// ✅ InColumnGroup mode
+// ✅ InTableBody mode
+// ✅ InRow mode
+// ✅ InCell mode
+// ✅ InSelect mode
+// ✅ InSelectInTable mode
+// ✅ InTemplate mode - contents are children of the template element
+// ✅ InForeignContent mode - SVG and MathML, attribute names kept qualified
+// ✅ AfterBody mode
 // ❌ InFrameset mode - not implemented
 // ❌ AfterFrameset mode - not implemented
-// ❌ AfterAfterBody mode - not implemented
-//
-// TODO(spec 13.2.6): Implement full adoption agency algorithm
-// TODO(spec 13.2.6): Implement foster parenting
-// TODO(spec 13.2.6): Implement AAA (adoption agency algorithm)
+// ✅ AfterAfterBody mode
+
+use encoding_rs::{Encoding, UTF_8};
 
-use crate::dom::{Dom, NodeId};
-use super::tokenizer::{Token, Tokenizer, VOID_ELEMENTS};
+use crate::dom::{Dom, ElementData, Namespace, NodeId, NodeType, QuirksMode};
+use crate::net::FetchedResource;
+use super::encoding::{content_type_header, decode_html, HtmlDecoder};
+use super::errors::ParseError;
+use super::tokenizer::{Attribute, Token, Tokenizer, TokenizerState, VOID_ELEMENTS};
 
 /// Debug logging for tree construction
 const DEBUG_TREE_BUILDER: bool = false;
@@ -62,375 +72,3098 @@ enum InsertionMode {
     AfterAfterFrameset,
 }
 
+/// Entry in the list of active formatting elements (spec 13.2.4.3)
+#[derive(Debug, Clone, PartialEq)]
+enum FormattingEntry {
+    Marker,
+    /// The element plus the token it was created from, for reconstruction
+    Element {
+        node: NodeId,
+        tag: String,
+        attributes: Vec<Attribute>,
+    },
+}
+
+impl FormattingEntry {
+    fn node(&self) -> Option<NodeId> {
+        match self {
+            FormattingEntry::Element { node, .. } => Some(*node),
+            FormattingEntry::Marker => None,
+        }
+    }
+}
+
+/// Where a new node goes: appended to `parent`, or inserted before `before`
+/// (spec 13.2.6.1 "appropriate place for inserting a node")
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct InsertionLocation {
+    parent: NodeId,
+    before: Option<NodeId>,
+}
+
 pub struct HtmlParser {
     tokenizer: Tokenizer,
+    dom: Dom,
+    /// The synthetic document root
+    document: NodeId,
+    /// Stack of open elements (spec 13.2.4.3), bottom first
+    open_elements: Vec<NodeId>,
+    mode: InsertionMode,
+    /// Mode to return to after Text and InTableText
+    original_mode: InsertionMode,
+    /// Stack of template insertion modes (spec 13.2.4.1)
+    template_modes: Vec<InsertionMode>,
+    head_element: Option<NodeId>,
+    form_element: Option<NodeId>,
+    frameset_ok: bool,
+    /// List of active formatting elements (spec 13.2.4.3)
+    active_formatting: Vec<FormattingEntry>,
+    /// Set while "anything else" in table modes is processed with InBody rules
+    foster_parenting: bool,
+    /// Drop a newline directly after <pre>, <listing> and <textarea>
+    ignore_next_lf: bool,
     /// Buffer for accumulating character tokens into text nodes
     pending_text: String,
+    /// Where the buffered text will be inserted
+    pending_text_location: Option<InsertionLocation>,
+    /// Character tokens collected in the InTableText mode
+    pending_table_text: String,
+    /// Tree construction errors
+    errors: Vec<ParseError>,
+    /// Character encoding the input was decoded from
+    encoding: &'static Encoding,
+    /// Detached copy of the context element when parsing a fragment
+    context_element: Option<NodeId>,
+    /// Byte decoder for streaming input
+    decoder: Option<HtmlDecoder>,
 }
 
-// Auto-closing tags that force parent closure
-const AUTO_CLOSING_TAGS: &[&str] = &[
-    "p", "li", "dd", "dt", "option", "optgroup", "tr", "td", "th", 
+// Elements popped by "generate implied end tags" (spec 13.2.6.3)
+const IMPLIED_END_TAGS: &[&str] = &[
+    "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc",
 ];
 
-// Tags that can be implicitly closed (not currently used, but may be useful for future HTML5 spec compliance)
-#[allow(dead_code)]
+// Boundaries for "has an element in scope" (spec 13.2.4.2)
+const DEFAULT_SCOPE: &[&str] = &[
+    "applet", "caption", "html", "table", "td", "th", "marquee", "object", "template",
+];
+
+// Special category elements (spec 13.2.4.2)
+const SPECIAL_ELEMENTS: &[&str] = &[
+    "address", "applet", "area", "article", "aside", "base", "basefont", "bgsound", "blockquote",
+    "body", "br", "button", "caption", "center", "col", "colgroup", "dd", "details", "dir", "div",
+    "dl", "dt", "embed", "fieldset", "figcaption", "figure", "footer", "form", "frame", "frameset",
+    "h1", "h2", "h3", "h4", "h5", "h6", "head", "header", "hgroup", "hr", "html", "iframe", "img",
+    "input", "keygen", "li", "link", "listing", "main", "marquee", "menu", "meta", "nav",
+    "noembed", "noframes", "noscript", "object", "ol", "p", "param", "plaintext", "pre", "script",
+    "search", "section", "select", "source", "style", "summary", "table", "tbody", "td",
+    "template", "textarea", "tfoot", "th", "thead", "title", "tr", "track", "ul", "wbr", "xmp",
+];
+
+// MathML text integration points (spec 13.2.6), also scope boundaries and special
+const MATHML_TEXT_INTEGRATION_POINTS: &[&str] = &["mi", "mo", "mn", "ms", "mtext"];
+
+// SVG HTML integration points (spec 13.2.6), also scope boundaries and special
+const SVG_HTML_INTEGRATION_POINTS: &[&str] = &["foreignObject", "desc", "title"];
+
+// Start tags that break out of foreign content (spec 13.2.6.5)
+const FOREIGN_BREAKOUT_TAGS: &[&str] = &[
+    "b", "big", "blockquote", "body", "br", "center", "code", "dd", "div", "dl", "dt", "em",
+    "embed", "h1", "h2", "h3", "h4", "h5", "h6", "head", "hr", "i", "img", "li", "listing", "menu",
+    "meta", "nobr", "ol", "p", "pre", "ruby", "s", "small", "span", "strong", "strike", "sub",
+    "sup", "table", "tt", "u", "ul", "var",
+];
+
+// Lowercased SVG tag names and their camelCase forms (spec 13.2.6.5)
+const SVG_TAG_ADJUSTMENTS: &[(&str, &str)] = &[
+    ("altglyph", "altGlyph"), ("altglyphdef", "altGlyphDef"), ("altglyphitem", "altGlyphItem"),
+    ("animatecolor", "animateColor"), ("animatemotion", "animateMotion"),
+    ("animatetransform", "animateTransform"), ("clippath", "clipPath"), ("feblend", "feBlend"),
+    ("fecolormatrix", "feColorMatrix"), ("fecomponenttransfer", "feComponentTransfer"),
+    ("fecomposite", "feComposite"), ("feconvolvematrix", "feConvolveMatrix"),
+    ("fediffuselighting", "feDiffuseLighting"), ("fedisplacementmap", "feDisplacementMap"),
+    ("fedistantlight", "feDistantLight"), ("fedropshadow", "feDropShadow"),
+    ("feflood", "feFlood"), ("fefunca", "feFuncA"), ("fefuncb", "feFuncB"),
+    ("fefuncg", "feFuncG"), ("fefuncr", "feFuncR"), ("fegaussianblur", "feGaussianBlur"),
+    ("feimage", "feImage"), ("femerge", "feMerge"), ("femergenode", "feMergeNode"),
+    ("femorphology", "feMorphology"), ("feoffset", "feOffset"), ("fepointlight", "fePointLight"),
+    ("fespecularlighting", "feSpecularLighting"), ("fespotlight", "feSpotLight"),
+    ("fetile", "feTile"), ("feturbulence", "feTurbulence"), ("foreignobject", "foreignObject"),
+    ("glyphref", "glyphRef"), ("lineargradient", "linearGradient"),
+    ("radialgradient", "radialGradient"), ("textpath", "textPath"),
+];
+
+// Lowercased SVG attribute names and their camelCase forms (spec 13.2.6.1)
+const SVG_ATTRIBUTE_ADJUSTMENTS: &[(&str, &str)] = &[
+    ("attributename", "attributeName"), ("attributetype", "attributeType"),
+    ("basefrequency", "baseFrequency"), ("baseprofile", "baseProfile"), ("calcmode", "calcMode"),
+    ("clippathunits", "clipPathUnits"), ("diffuseconstant", "diffuseConstant"),
+    ("edgemode", "edgeMode"), ("filterunits", "filterUnits"), ("glyphref", "glyphRef"),
+    ("gradienttransform", "gradientTransform"), ("gradientunits", "gradientUnits"),
+    ("kernelmatrix", "kernelMatrix"), ("kernelunitlength", "kernelUnitLength"),
+    ("keypoints", "keyPoints"), ("keysplines", "keySplines"), ("keytimes", "keyTimes"),
+    ("lengthadjust", "lengthAdjust"), ("limitingconeangle", "limitingConeAngle"),
+    ("markerheight", "markerHeight"), ("markerunits", "markerUnits"),
+    ("markerwidth", "markerWidth"), ("maskcontentunits", "maskContentUnits"),
+    ("maskunits", "maskUnits"), ("numoctaves", "numOctaves"), ("pathlength", "pathLength"),
+    ("patterncontentunits", "patternContentUnits"), ("patterntransform", "patternTransform"),
+    ("patternunits", "patternUnits"), ("pointsatx", "pointsAtX"), ("pointsaty", "pointsAtY"),
+    ("pointsatz", "pointsAtZ"), ("preservealpha", "preserveAlpha"),
+    ("preserveaspectratio", "preserveAspectRatio"), ("primitiveunits", "primitiveUnits"),
+    ("refx", "refX"), ("refy", "refY"), ("repeatcount", "repeatCount"),
+    ("repeatdur", "repeatDur"), ("requiredextensions", "requiredExtensions"),
+    ("requiredfeatures", "requiredFeatures"), ("specularconstant", "specularConstant"),
+    ("specularexponent", "specularExponent"), ("spreadmethod", "spreadMethod"),
+    ("startoffset", "startOffset"), ("stddeviation", "stdDeviation"),
+    ("stitchtiles", "stitchTiles"), ("surfacescale", "surfaceScale"),
+    ("systemlanguage", "systemLanguage"), ("tablevalues", "tableValues"),
+    ("targetx", "targetX"), ("targety", "targetY"), ("textlength", "textLength"),
+    ("viewbox", "viewBox"), ("viewtarget", "viewTarget"),
+    ("xchannelselector", "xChannelSelector"), ("ychannelselector", "yChannelSelector"),
+    ("zoomandpan", "zoomAndPan"),
+];
+
+// Public identifiers that select quirks mode when matched exactly (spec 13.2.6.4.1)
+const QUIRKY_PUBLIC_IDS: &[&str] = &[
+    "-//W3O//DTD W3 HTML Strict 3.0//EN//", "-/W3C/DTD HTML 4.0 Transitional/EN", "HTML",
+];
+
+// Public identifier prefixes that select quirks mode (spec 13.2.6.4.1)
+const QUIRKY_PUBLIC_ID_PREFIXES: &[&str] = &[
+    "+//Silmaril//dtd html Pro v0r11 19970101//",
+    "-//AS//DTD HTML 3.0 asWedit + extensions//",
+    "-//AdvaSoft Ltd//DTD HTML 3.0 asWedit + extensions//",
+    "-//IETF//DTD HTML 2.0 Level 1//",
+    "-//IETF//DTD HTML 2.0 Level 2//",
+    "-//IETF//DTD HTML 2.0 Strict Level 1//",
+    "-//IETF//DTD HTML 2.0 Strict Level 2//",
+    "-//IETF//DTD HTML 2.0 Strict//",
+    "-//IETF//DTD HTML 2.0//",
+    "-//IETF//DTD HTML 2.1E//",
+    "-//IETF//DTD HTML 3.0//",
+    "-//IETF//DTD HTML 3.2 Final//",
+    "-//IETF//DTD HTML 3.2//",
+    "-//IETF//DTD HTML 3//",
+    "-//IETF//DTD HTML Level 0//",
+    "-//IETF//DTD HTML Level 1//",
+    "-//IETF//DTD HTML Level 2//",
+    "-//IETF//DTD HTML Level 3//",
+    "-//IETF//DTD HTML Strict Level 0//",
+    "-//IETF//DTD HTML Strict Level 1//",
+    "-//IETF//DTD HTML Strict Level 2//",
+    "-//IETF//DTD HTML Strict Level 3//",
+    "-//IETF//DTD HTML Strict//",
+    "-//IETF//DTD HTML//",
+    "-//Metrius//DTD Metrius Presentational//",
+    "-//Microsoft//DTD Internet Explorer 2.0 HTML Strict//",
+    "-//Microsoft//DTD Internet Explorer 2.0 HTML//",
+    "-//Microsoft//DTD Internet Explorer 2.0 Tables//",
+    "-//Microsoft//DTD Internet Explorer 3.0 HTML Strict//",
+    "-//Microsoft//DTD Internet Explorer 3.0 HTML//",
+    "-//Microsoft//DTD Internet Explorer 3.0 Tables//",
+    "-//Netscape Comm. Corp.//DTD HTML//",
+    "-//Netscape Comm. Corp.//DTD Strict HTML//",
+    "-//O'Reilly and Associates//DTD HTML 2.0//",
+    "-//O'Reilly and Associates//DTD HTML Extended 1.0//",
+    "-//O'Reilly and Associates//DTD HTML Extended Relaxed 1.0//",
+    "-//SQ//DTD HTML 2.0 HoTMetaL + extensions//",
+    "-//SoftQuad Software//DTD HoTMetaL PRO 6.0::19990601::extensions to HTML 4.0//",
+    "-//SoftQuad//DTD HoTMetaL PRO 4.0::19970916::extensions to HTML 4.0//",
+    "-//Spyglass//DTD HTML 2.0 Extended//",
+    "-//Sun Microsystems Corp.//DTD HotJava HTML//",
+    "-//Sun Microsystems Corp.//DTD HotJava Strict HTML//",
+    "-//W3C//DTD HTML 3 1995-03-24//",
+    "-//W3C//DTD HTML 3.2 Draft//",
+    "-//W3C//DTD HTML 3.2 Final//",
+    "-//W3C//DTD HTML 3.2//",
+    "-//W3C//DTD HTML 3.2S Draft//",
+    "-//W3C//DTD HTML 4.0 Frameset//",
+    "-//W3C//DTD HTML 4.0 Transitional//",
+    "-//W3C//DTD HTML Experimental 19960712//",
+    "-//W3C//DTD HTML Experimental 970421//",
+    "-//W3C//DTD W3 HTML//",
+    "-//W3O//DTD W3 HTML 3.0//",
+    "-//WebTechs//DTD Mozilla HTML 2.0//",
+    "-//WebTechs//DTD Mozilla HTML//",
+];
+
+// Formatting elements tracked in the list of active formatting elements
 const FORMATTING_TAGS: &[&str] = &[
     "a", "b", "big", "code", "em", "font", "i", "nobr", "s", "small", "strike", "strong", "tt", "u",
 ];
 
+const HEADING_TAGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+
+// Start tags that close an open <p> and otherwise insert a plain block element
+const BLOCK_START_TAGS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "center", "details", "dialog", "dir", "div",
+    "dl", "fieldset", "figcaption", "figure", "footer", "header", "hgroup", "main", "menu", "nav",
+    "ol", "p", "search", "section", "summary", "ul",
+];
+
+// End tags that close the matching block element
+const BLOCK_END_TAGS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "button", "center", "details", "dialog", "dir",
+    "div", "dl", "fieldset", "figcaption", "figure", "footer", "header", "hgroup", "listing",
+    "main", "menu", "nav", "ol", "pre", "search", "section", "summary", "ul",
+];
+
+// Start tags that end a table section or row and are reprocessed by the table modes
+const TABLE_STRUCTURE_TAGS: &[&str] = &[
+    "caption", "col", "colgroup", "tbody", "td", "tfoot", "th", "thead", "tr",
+];
+
+// Elements whose presence as current node sends character tokens to InTableText
+const TABLE_TEXT_CONTEXT: &[&str] = &["table", "tbody", "template", "tfoot", "thead", "tr"];
+
+// Tags that may be foster parented out of a table
+const FOSTER_TARGETS: &[&str] = &["table", "tbody", "tfoot", "thead", "tr"];
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0C' | '\r' | ' ')
+}
+
+/// Deep-copy a node from the parser's Dom into `dom` as the last child of `parent`
+fn import_node(source: &Dom, id: NodeId, dom: &mut Dom, parent: NodeId) -> NodeId {
+    let copy = match &source.nodes[id].node_type {
+        NodeType::Element(el) => {
+            dom.create_element_ns(&el.tag_name, el.attributes.clone(), el.namespace, Some(parent))
+        }
+        NodeType::Text(text) => dom.create_text(text, Some(parent)),
+        NodeType::Comment(data) => dom.create_comment(data, Some(parent)),
+        NodeType::Doctype { name, public_id, system_id } => dom.create_doctype(name, public_id, system_id, Some(parent)),
+        // Fragment parsing only produces elements, text and comments
+        NodeType::Document | NodeType::DocumentFragment => unreachable!("not a fragment child"),
+    };
+    for &child in &source.nodes[id].children {
+        import_node(source, child, dom, copy);
+    }
+    copy
+}
+
+/// Spec 13.2.6.4.1 quirks mode from a DOCTYPE token. Identifiers compare
+/// ASCII case-insensitively; a missing system identifier differs from an empty one.
+fn quirks_mode_for_doctype(
+    name: Option<&str>,
+    public_id: Option<&str>,
+    system_id: Option<&str>,
+    force_quirks: bool,
+) -> QuirksMode {
+    let public = public_id.unwrap_or_default().to_ascii_lowercase();
+    let system = system_id.map(|id| id.to_ascii_lowercase());
+    let public_starts_with = |prefixes: &[&str]| prefixes.iter().any(|p| public.starts_with(&p.to_ascii_lowercase()));
+
+    let html401 = ["-//W3C//DTD HTML 4.01 Frameset//", "-//W3C//DTD HTML 4.01 Transitional//"];
+    let quirks = force_quirks
+        || name != Some("html")
+        || QUIRKY_PUBLIC_IDS.iter().any(|id| public == id.to_ascii_lowercase())
+        || system.as_deref() == Some("http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd")
+        || public_starts_with(QUIRKY_PUBLIC_ID_PREFIXES)
+        || (system.is_none() && public_starts_with(&html401));
+    if quirks {
+        return QuirksMode::Quirks;
+    }
+
+    let xhtml10 = ["-//W3C//DTD XHTML 1.0 Frameset//", "-//W3C//DTD XHTML 1.0 Transitional//"];
+    if public_starts_with(&xhtml10) || (system.is_some() && public_starts_with(&html401)) {
+        QuirksMode::LimitedQuirks
+    } else {
+        QuirksMode::NoQuirks
+    }
+}
+
+/// Start tags that pop foreign content back to HTML (spec 13.2.6.5)
+fn breaks_out_of_foreign_content(name: &str, attributes: &[Attribute]) -> bool {
+    FOREIGN_BREAKOUT_TAGS.contains(&name)
+        || (name == "font" && attributes.iter().any(|a| matches!(a.name.as_str(), "color" | "face" | "size")))
+}
+
+/// Restore the case of SVG tag and attribute names and MathML's definitionURL
+/// (spec 13.2.6.1). Foreign attributes such as xlink:href keep their prefixed name.
+fn adjust_foreign_names(tag: &str, attributes: &[Attribute], namespace: Namespace) -> (String, Vec<(String, String)>) {
+    let lookup = |table: &[(&str, &'static str)], name: &str| {
+        table.iter().find(|(lower, _)| *lower == name).map(|(_, adjusted)| adjusted.to_string())
+    };
+    let tag = match namespace {
+        Namespace::Svg => lookup(SVG_TAG_ADJUSTMENTS, tag).unwrap_or_else(|| tag.to_string()),
+        _ => tag.to_string(),
+    };
+    let attributes = attributes
+        .iter()
+        .map(|attr| {
+            let name = match namespace {
+                Namespace::Svg => lookup(SVG_ATTRIBUTE_ADJUSTMENTS, &attr.name),
+                Namespace::MathMl if attr.name == "definitionurl" => Some("definitionURL".to_string()),
+                _ => None,
+            };
+            (name.unwrap_or_else(|| attr.name.clone()), attr.value.clone())
+        })
+        .collect();
+    (tag, attributes)
+}
+
 impl HtmlParser {
     pub fn new(input: &str) -> Self {
+        let dom = Dom::new();
+        let document = dom.root();
         Self {
             tokenizer: Tokenizer::new(input),
+            dom,
+            document,
+            open_elements: Vec::new(),
+            active_formatting: Vec::new(),
+            mode: InsertionMode::Initial,
+            original_mode: InsertionMode::Initial,
+            template_modes: Vec::new(),
+            head_element: None,
+            form_element: None,
+            frameset_ok: true,
+            foster_parenting: false,
+            ignore_next_lf: false,
             pending_text: String::new(),
+            pending_text_location: None,
+            pending_table_text: String::new(),
+            errors: Vec::new(),
+            encoding: UTF_8,
+            context_element: None,
+            decoder: None,
         }
     }
 
-    /// Flush any pending text to the DOM
-    /// Only creates a text node if there's meaningful content (not just whitespace)
-    fn flush_pending_text(&mut self, dom: &mut Dom, parent: NodeId) {
-        if !self.pending_text.is_empty() {
-            // Only create text node if it has non-whitespace content
-            // OR if it's meaningful whitespace (single space between inline elements)
-            let trimmed = self.pending_text.trim();
-            if !trimmed.is_empty() {
-                tree_builder_log(&format!("Flushing text: {:?}", self.pending_text));
-                dom.create_text(&self.pending_text, Some(parent));
-            } else {
-                tree_builder_log(&format!("Skipping whitespace-only text: {:?}", self.pending_text));
+    /// Create a parser for a document whose bytes arrive in chunks through
+    /// `feed`. The encoding is sniffed as in `from_bytes`.
+    pub fn new_streaming(content_type: Option<&str>) -> Self {
+        let mut parser = Self::new("");
+        parser.tokenizer = Tokenizer::new_streaming();
+        parser.decoder = Some(HtmlDecoder::new(content_type));
+        parser
+    }
+
+    /// Parse the next chunk of a streamed document as far as possible.
+    /// The partially built tree is available from `dom()` afterwards.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        let decoder = self.decoder.as_mut().expect("feed requires HtmlParser::new_streaming");
+        let text = decoder.decode(bytes);
+        if let Some(encoding) = decoder.encoding() {
+            self.encoding = encoding;
+        }
+        self.tokenizer.feed(&text);
+        self.process_available_tokens();
+        // Show buffered text now; whitespace waits, since it may be dropped
+        if !self.pending_text.trim().is_empty() {
+            self.flush_pending_text();
+        }
+    }
+
+    /// End a streamed document and return the finished tree
+    pub fn finish(self) -> Dom {
+        self.finish_with_errors().0
+    }
+
+    /// End a streamed document, also returning parse errors in document order
+    pub fn finish_with_errors(mut self) -> (Dom, Vec<ParseError>) {
+        if let Some(decoder) = self.decoder.as_mut() {
+            let text = decoder.finish();
+            if let Some(encoding) = decoder.encoding() {
+                self.encoding = encoding;
             }
-            self.pending_text.clear();
+            self.tokenizer.feed(&text);
         }
+        self.tokenizer.end_input();
+        let errors = self.run();
+        (self.dom, errors)
     }
 
-    /// Convert attribute list from tokenizer format to DOM format
-    fn convert_attributes(attributes: &[super::tokenizer::Attribute]) -> Vec<(String, String)> {
-        attributes.iter().map(|a| (a.name.clone(), a.value.clone())).collect()
+    /// The tree built so far
+    pub fn dom(&self) -> &Dom {
+        &self.dom
     }
 
-    pub fn parse(mut self) -> Dom {
-        let mut dom = Dom::new();
-        let document = dom.create_element("document", vec![], None);
-        let mut stack: Vec<NodeId> = vec![document];
-        let mut mode = InsertionMode::Initial;
-        let _fragment_context: Option<String> = None;
+    /// Create a parser for raw document bytes, sniffing the character encoding
+    /// from the BOM, the Content-Type header value and a <meta> prescan
+    pub fn from_bytes(bytes: &[u8], content_type: Option<&str>) -> Self {
+        let (input, encoding) = decode_html(bytes, content_type);
+        let mut parser = Self::new(&input);
+        parser.encoding = encoding;
+        parser
+    }
 
-        while let Some(token) = self.tokenizer.next_token() {
-            tree_builder_log(&format!("Mode: {:?}, Token: {:?}", mode, token));
-            
-            match &token {
-                Token::Eof => {
-                    // Flush any remaining text
-                    if let Some(&parent) = stack.last() {
-                        self.flush_pending_text(&mut dom, parent);
-                    }
+    /// Create a parser for a fetched document, using its Content-Type charset
+    pub fn from_resource(resource: &FetchedResource) -> Self {
+        // Headers are empty for cached resources, but content_type is kept
+        let content_type = content_type_header(&resource.headers).unwrap_or(&resource.content_type);
+        Self::from_bytes(&resource.data, Some(content_type))
+    }
+
+    /// The character encoding the input was decoded from
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
+    pub fn parse(self) -> Dom {
+        self.parse_with_errors().0
+    }
+
+    /// Parse, also returning tokenizer and tree construction errors in document order
+    pub fn parse_with_errors(mut self) -> (Dom, Vec<ParseError>) {
+        let errors = self.run();
+        (self.dom, errors)
+    }
+
+    /// Spec 13.4 HTML fragment parsing algorithm
+    ///
+    /// Parses `html` as if it were the contents of `context`, an element in `dom`,
+    /// and appends the resulting nodes to `context`. Returns the appended nodes.
+    pub fn parse_fragment(dom: &mut Dom, context: NodeId, html: &str) -> Vec<NodeId> {
+        let mut parser = Self::new(html);
+        parser.dom.quirks_mode = dom.quirks_mode;
+        let context_data = match &dom.nodes[context].node_type {
+            NodeType::Element(el) => el.clone(),
+            _ => ElementData { tag_name: String::new(), attributes: vec![], namespace: Namespace::Html },
+        };
+
+        if context_data.is_html() {
+            let state = match context_data.tag_name.as_str() {
+                "title" | "textarea" => TokenizerState::RcData,
+                "style" | "xmp" | "iframe" | "noembed" | "noframes" => TokenizerState::RawText,
+                "script" => TokenizerState::ScriptData,
+                "plaintext" => TokenizerState::PlainText,
+                // Scripting is disabled, so <noscript> is tokenized as markup
+                _ => TokenizerState::Data,
+            };
+            parser.tokenizer.set_state(state);
+        }
+
+        let context_copy = parser.dom.create_element_ns(
+            &context_data.tag_name,
+            context_data.attributes,
+            context_data.namespace,
+            None,
+        );
+        parser.context_element = Some(context_copy);
+
+        let root = parser.dom.create_element("html", vec![], Some(parser.document));
+        parser.open_elements.push(root);
+        parser.reset_insertion_mode();
+
+        // The form element pointer only needs to be set, never attached
+        let mut ancestor = Some(context);
+        while let Some(id) = ancestor {
+            if let NodeType::Element(el) = &dom.nodes[id].node_type {
+                if el.is_html() && el.tag_name == "form" {
+                    parser.form_element = Some(parser.dom.create_element("form", vec![], None));
                     break;
                 }
-                Token::Comment(_) => {
-                    // Flush text before comment
-                    if let Some(&parent) = stack.last() {
-                        self.flush_pending_text(&mut dom, parent);
-                    }
-                    // Append comment to current node (optional for now)
-                    continue;
-                }
-                Token::Doctype { .. } => {
-                    // Doctype only relevant in initial mode
-                    if mode == InsertionMode::Initial {
-                        mode = InsertionMode::BeforeHtml;
-                    }
-                }
-                Token::Character(c) => {
-                    // Accumulate characters into pending_text
-                    self.pending_text.push(*c);
-                    continue;
-                }
-                Token::StartTag { name, attributes, self_closing } => {
-                    // Flush pending text before processing tag
-                    if let Some(&parent) = stack.last() {
-                        self.flush_pending_text(&mut dom, parent);
-                    }
-                    
-                    let tag = name.to_lowercase();
-                    let attrs = Self::convert_attributes(attributes);
-
-                    // -------- INITIAL MODE --------
-                    if mode == InsertionMode::Initial {
-                        // Move directly to BeforeHtml without creating an element yet
-                        mode = InsertionMode::BeforeHtml;
-                    }
+            }
+            ancestor = dom.nodes[id].parent;
+        }
 
-                    // -------- BEFORE HTML --------
-                    if mode == InsertionMode::BeforeHtml {
-                        if tag == "html" {
-                            let html = dom.create_element("html", attrs.clone(), Some(document));
-                            stack.push(html);
-                            mode = InsertionMode::BeforeHead;
-                            continue;
-                        } else {
-                            // Auto-insert html element
-                            let html = dom.create_element("html", vec![], Some(document));
-                            stack.push(html);
-                            mode = InsertionMode::BeforeHead;
-                            // Fall through to process this tag in BeforeHead mode
-                        }
-                    }
+        parser.run();
 
-                    // -------- BEFORE HEAD / IN HEAD --------
-                    if mode == InsertionMode::BeforeHead || mode == InsertionMode::InHead {
-                        if tag == "head" && mode == InsertionMode::BeforeHead {
-                            if let Some(&parent) = stack.last() {
-                                let head = dom.create_element("head", attrs.clone(), Some(parent));
-                                stack.push(head);
-                                mode = InsertionMode::InHead;
-                            }
-                            continue;
-                        }
-                        
-                        if matches!(tag.as_str(), "meta" | "link" | "title" | "style" | "base") {
-                            if mode == InsertionMode::BeforeHead {
-                                if let Some(&parent) = stack.last() {
-                                    let head = dom.create_element("head", vec![], Some(parent));
-                                    stack.push(head);
-                                    mode = InsertionMode::InHead;
-                                }
-                            }
-                            if let Some(&parent) = stack.last() {
-                                let elem_id = dom.create_element(&tag, attrs.clone(), Some(parent));
-                                // Push non-self-closing elements to stack
-                                if !*self_closing && !matches!(tag.as_str(), "meta" | "link") {
-                                    stack.push(elem_id);
-                                }
-                            }
-                            if *self_closing || matches!(tag.as_str(), "meta" | "link") {
-                                // self-closing, don't push to stack
-                            }
-                            continue;
-                        }
+        parser.dom.nodes[root]
+            .children
+            .clone()
+            .into_iter()
+            .map(|child| import_node(&parser.dom, child, dom, context))
+            .collect()
+    }
 
-                        if mode == InsertionMode::InHead && tag != "head" {
-                            stack.pop();
-                            mode = InsertionMode::AfterHead;
-                        }
-                    }
+    /// Run the tokenizer and tree builder to the end of the input, returning
+    /// tokenizer and tree construction errors in document order
+    fn run(&mut self) -> Vec<ParseError> {
+        self.process_available_tokens();
+        self.flush_pending_text();
 
-                    // -------- AFTER HEAD / IN BODY --------
-                    if mode == InsertionMode::AfterHead || mode == InsertionMode::InBody {
-                        if mode == InsertionMode::AfterHead {
-                            if let Some(&parent) = stack.last() {
-                                let body = dom.create_element("body", vec![], Some(parent));
-                                stack.push(body);
-                                mode = InsertionMode::InBody;
-                            }
-                            // After transitioning to InBody, if this tag is body, skip creating another one
-                            if tag == "body" {
-                                continue;
-                            }
-                        }
+        let mut errors = self.tokenizer.take_errors();
+        errors.append(&mut self.errors);
+        errors.sort_by_key(|e| (e.line, e.column));
+        errors
+    }
 
-                        // Handle auto-closing tags (like <p>, <li>, etc.)
-                        if AUTO_CLOSING_TAGS.contains(&tag.as_str()) {
-                            // Close any open tags of the same type by popping them
-                            while let Some(&last) = stack.last() {
-                                if last == document {
-                                    break;
-                                }
-                                if let crate::dom::NodeType::Element(el) = &dom.nodes[last].node_type {
-                                    if el.tag_name.to_lowercase() == tag {
-                                        // Found open tag of same type, close it and stop
-                                        stack.pop();
-                                        break;
-                                    }
-                                    // Don't pop past body, html, document
-                                    if matches!(el.tag_name.as_str(), "body" | "html" | "document") {
-                                        break;
-                                    }
-                                    // Don't pop past list containers when handling li
-                                    if tag == "li" && matches!(el.tag_name.as_str(), "ul" | "ol") {
-                                        break;
-                                    }
-                                    // Don't pop past definition lists when handling dt/dd
-                                    if matches!(tag.as_str(), "dt" | "dd") && el.tag_name == "dl" {
-                                        break;
-                                    }
-                                }
-                                stack.pop();
-                            }
-                        }
+    /// Tree-build every token the tokenizer can produce from the input so far
+    fn process_available_tokens(&mut self) {
+        while let Some(token) = self.tokenizer.next_token() {
+            let is_eof = token == Token::Eof;
+            self.process_token(token);
+            if is_eof {
+                break;
+            }
+            // CDATA sections are only recognized in foreign content
+            let foreign = !self.open_elements.is_empty() && !self.is_html_element(self.adjusted_current_node());
+            self.tokenizer.set_cdata_allowed(foreign);
+        }
+    }
 
-                        if let Some(&parent) = stack.last() {
-                            let id = dom.create_element(&tag, attrs.clone(), Some(parent));
-                            
-                            // Check if it's a void element that shouldn't be pushed to stack
-                            let is_void = VOID_ELEMENTS.contains(&tag.as_str());
-                            
-                            if !*self_closing && !is_void {
-                                stack.push(id);
-                            }
-                        }
-                    }
-                }
+    /// Record a tree construction error at the current token
+    fn parse_error(&mut self, code: &'static str) {
+        let (line, column) = self.tokenizer.last_token_location();
+        tree_builder_log(&format!("Parse error: {} at {}:{}", code, line, column));
+        self.errors.push(ParseError { code, line, column });
+    }
 
-                Token::EndTag { name } => {
-                    // Flush pending text before processing end tag
-                    if let Some(&parent) = stack.last() {
-                        self.flush_pending_text(&mut dom, parent);
-                    }
-                    
-                    let tag = name.to_lowercase();
-
-                    // Special handling for head-related elements
-                    if mode == InsertionMode::InHead {
-                        if tag == "head" {
-                            stack.pop(); // Pop head element
-                            mode = InsertionMode::AfterHead;
-                            continue;
-                        } else if matches!(tag.as_str(), "title" | "meta" | "link" | "style" | "base") {
-                            // Pop the element if it matches
-                            if let Some(&last) = stack.last() {
-                                if let crate::dom::NodeType::Element(el) = &dom.nodes[last].node_type {
-                                    if el.tag_name.to_lowercase() == tag {
-                                        stack.pop();
-                                    }
-                                }
-                            }
-                            continue;
-                        } else {
-                            // Unrecognized tag in head mode - exit head mode
-                            stack.pop(); // Pop head
-                            mode = InsertionMode::AfterHead;
-                            // Reprocess this end tag in AfterHead mode
-                        }
-                    }
+    // ------------------------------------------------------------------
+    // Token dispatch
+    // ------------------------------------------------------------------
 
-                    // Before closing an element, auto-close any open auto-closing tags (like <p>)
-                    if mode == InsertionMode::InBody && !matches!(tag.as_str(), "p" | "li" | "dd" | "dt" | "option" | "optgroup" | "tr" | "td" | "th") {
-                        // We're closing a non-auto-closing tag, so close any open auto-closing tags first
-                        while let Some(&last) = stack.last() {
-                            if last == document {
-                                break;
-                            }
-                            if let crate::dom::NodeType::Element(el) = &dom.nodes[last].node_type {
-                                if matches!(el.tag_name.as_str(), "p" | "li" | "dd" | "dt" | "option" | "optgroup" | "tr" | "td" | "th") {
-                                    // Found an auto-closing tag - check if we should close it
-                                    if el.tag_name.to_lowercase() == tag {
-                                        // This is the tag we're trying to close, stop here
-                                        break;
-                                    }
-                                    // This is an auto-closing tag that's in the way - close it
-                                    stack.pop();
-                                } else {
-                                    // Not an auto-closing tag
-                                    break;
-                                }
-                            } else {
-                                break;
-                            }
-                        }
-                    }
+    fn process_token(&mut self, token: Token) {
+        tree_builder_log(&format!("Mode: {:?}, Token: {:?}", self.mode, token));
 
-                    // Close elements until we find matching opening tag
-                    while let Some(&last) = stack.last() {
-                        if last == document {
-                            break;
-                        }
+        if self.ignore_next_lf {
+            self.ignore_next_lf = false;
+            if token == Token::Character('\n') {
+                return;
+            }
+        }
 
-                        if let crate::dom::NodeType::Element(el) = &dom.nodes[last].node_type {
-                            if el.tag_name.to_lowercase() == tag {
-                                stack.pop();
-                                break;
-                            }
-
-                            // Mismatch: don't pop scope-limiting elements
-                            if matches!(el.tag_name.as_str(), "body" | "html" | "document") {
-                                // Can't close past body/html/document
-                                break;
-                            }
-
-                            // For other mismatches, pop the element
-                            stack.pop();
-                        } else {
-                            stack.pop();
-                        }
-                    }
+        let foreign = self.in_foreign_content(&token);
 
-                    // Determine next mode based on current element
-                    if let Some(&current) = stack.last() {
-                        if let crate::dom::NodeType::Element(el) = &dom.nodes[current].node_type {
-                            if el.tag_name == "body" {
-                                mode = InsertionMode::InBody;
-                            } else if el.tag_name == "head" {
-                                mode = InsertionMode::InHead;
-                            } else if el.tag_name == "html" {
-                                mode = InsertionMode::AfterHead;
-                            }
-                        }
-                    }
-                }
+        if let Token::StartTag { name, self_closing: true, .. } = &token {
+            // Foreign elements and <svg>/<math> acknowledge the self-closing flag
+            if !foreign && !VOID_ELEMENTS.contains(&name.as_str()) && !matches!(name.as_str(), "svg" | "math") {
+                self.parse_error("non-void-html-element-start-tag-with-trailing-solidus");
             }
         }
 
-        dom
+        // Text only accumulates while character tokens keep arriving
+        if !matches!(token, Token::Character(_)) {
+            self.flush_pending_text();
+        }
+
+        if foreign {
+            self.handle_foreign_content(token);
+        } else {
+            self.process_using_mode_rules(token);
+        }
     }
-}
 
-#[cfg(test)]
-mod debug_tests {
-    use super::*;
+    /// Process a token with the rules of the current insertion mode
+    fn process_using_mode_rules(&mut self, token: Token) {
+        match self.mode {
+            InsertionMode::Initial => self.handle_initial(token),
+            InsertionMode::BeforeHtml => self.handle_before_html(token),
+            InsertionMode::BeforeHead => self.handle_before_head(token),
+            InsertionMode::InHead => self.handle_in_head(token),
+            InsertionMode::InHeadNoscript => self.handle_in_head_noscript(token),
+            InsertionMode::AfterHead => self.handle_after_head(token),
+            InsertionMode::InBody => self.handle_in_body(token),
+            InsertionMode::Text => self.handle_text(token),
+            InsertionMode::InTable => self.handle_in_table(token),
+            InsertionMode::InTableText => self.handle_in_table_text(token),
+            InsertionMode::InCaption => self.handle_in_caption(token),
+            InsertionMode::InColumnGroup => self.handle_in_column_group(token),
+            InsertionMode::InTableBody => self.handle_in_table_body(token),
+            InsertionMode::InRow => self.handle_in_row(token),
+            InsertionMode::InCell => self.handle_in_cell(token),
+            InsertionMode::InSelect => self.handle_in_select(token),
+            InsertionMode::InSelectInTable => self.handle_in_select_in_table(token),
+            InsertionMode::InTemplate => self.handle_in_template(token),
+            InsertionMode::AfterBody => self.handle_after_body(token),
+            InsertionMode::AfterAfterBody => self.handle_after_after_body(token),
+            _ => {
+                tree_builder_log(&format!("Unimplemented mode {:?}, using InBody rules", self.mode));
+                self.handle_in_body(token);
+            }
+        }
+    }
 
-    fn print_dom_tree(dom: &Dom, node_id: NodeId, indent: usize) {
-        let node = &dom.nodes[node_id];
-        let prefix = "  ".repeat(indent);
-        
-        match &node.node_type {
-            crate::dom::NodeType::Element(el) => {
-                let attrs: Vec<String> = el.attributes.iter()
-                    .map(|(k, v)| format!("{}={}", k, v))
-                    .collect();
-                if attrs.is_empty() {
-                    eprintln!("{}<{}>", prefix, el.tag_name);
-                } else {
-                    eprintln!("{}<{} {}>", prefix, el.tag_name, attrs.join(" "));
-                }
+    /// Spec 13.2.6 tree construction dispatcher: whether the token is
+    /// processed with the rules for foreign content
+    fn in_foreign_content(&self, token: &Token) -> bool {
+        if self.open_elements.is_empty() {
+            return false;
+        }
+        let node = self.adjusted_current_node();
+        if self.is_html_element(node) {
+            return false;
+        }
+        let text_integration_point = self.is_mathml_text_integration_point(node);
+        let html_integration_point = self.is_html_integration_point(node);
+        match token {
+            Token::Eof => false,
+            Token::Character(_) => !(text_integration_point || html_integration_point),
+            Token::StartTag { name, .. } => {
+                let in_text_integration_point = text_integration_point && !matches!(name.as_str(), "mglyph" | "malignmark");
+                let svg_in_annotation = name == "svg"
+                    && matches!(self.element_data(node), Some(el) if el.namespace == Namespace::MathMl && el.tag_name == "annotation-xml");
+                !(in_text_integration_point || svg_in_annotation || html_integration_point)
             }
-            crate::dom::NodeType::Text(text) => {
-                let text = text.trim();
-                if !text.is_empty() {
-                    let display = if text.len() > 40 {
-                        format!("{}...", &text[..40])
-                    } else {
-                        text.to_string()
-                    };
-                    eprintln!("{}TEXT: {:?}", prefix, display);
-                }
+            _ => true,
+        }
+    }
+
+    /// Switch modes and hand the token to the new mode
+    fn reprocess_in(&mut self, mode: InsertionMode, token: Token) {
+        self.mode = mode;
+        self.process_token(token);
+    }
+
+    // ------------------------------------------------------------------
+    // Stack of open elements helpers
+    // ------------------------------------------------------------------
+
+    fn element_data(&self, id: NodeId) -> Option<&ElementData> {
+        match &self.dom.nodes[id].node_type {
+            NodeType::Element(el) => Some(el),
+            _ => None,
+        }
+    }
+
+    /// Tag name of an HTML element; "" for foreign elements and other nodes,
+    /// so tag checks in the insertion modes only ever match HTML elements
+    fn tag_of(&self, id: NodeId) -> &str {
+        match self.element_data(id) {
+            Some(el) if el.is_html() => el.tag_name.as_str(),
+            _ => "",
+        }
+    }
+
+    fn is_html_element(&self, id: NodeId) -> bool {
+        self.element_data(id).is_some_and(|el| el.is_html())
+    }
+
+    fn is_mathml_text_integration_point(&self, id: NodeId) -> bool {
+        matches!(self.element_data(id), Some(el)
+            if el.namespace == Namespace::MathMl && MATHML_TEXT_INTEGRATION_POINTS.contains(&el.tag_name.as_str()))
+    }
+
+    fn is_html_integration_point(&self, id: NodeId) -> bool {
+        match self.element_data(id) {
+            Some(el) if el.namespace == Namespace::Svg => SVG_HTML_INTEGRATION_POINTS.contains(&el.tag_name.as_str()),
+            Some(el) if el.namespace == Namespace::MathMl && el.tag_name == "annotation-xml" => {
+                el.attributes.iter().any(|(name, value)| {
+                    name == "encoding"
+                        && (value.eq_ignore_ascii_case("text/html") || value.eq_ignore_ascii_case("application/xhtml+xml"))
+                })
             }
+            _ => false,
         }
-        
-        for &child_id in &node.children {
-            print_dom_tree(dom, child_id, indent + 1);
+    }
+
+    /// Foreign elements in the special category, which also bound every scope
+    /// except table and select scope
+    fn is_special_foreign(&self, id: NodeId) -> bool {
+        match self.element_data(id) {
+            Some(el) if el.namespace == Namespace::MathMl => {
+                MATHML_TEXT_INTEGRATION_POINTS.contains(&el.tag_name.as_str()) || el.tag_name == "annotation-xml"
+            }
+            Some(el) if el.namespace == Namespace::Svg => SVG_HTML_INTEGRATION_POINTS.contains(&el.tag_name.as_str()),
+            _ => false,
         }
     }
 
-    #[test]
-    fn test_parse_old_cern_html() {
-        let html = r#"<TITLE>What is Hypertext?</TITLE>
-<NEXTID 20>
-<H1>What is HyperText</H1>Hypertext is text which is not constrained to be linear.<P>
-Hypertext is text which contains <A NAME=0 HREF=Terms.html#link>links</A> to other texts.<P>
-See also:
-<UL>
-<LI><A NAME=2 HREF=Terms.html>A list of terms</A> used in hypertext litterature.
-<LI><A NAME=19 HREF=../Conferences/Overview.html>Conferences</A>
-</UL>"#;
+    fn is_special(&self, id: NodeId) -> bool {
+        SPECIAL_ELEMENTS.contains(&self.tag_of(id)) || self.is_special_foreign(id)
+    }
 
-        eprintln!("\n=== PARSING OLD CERN HTML ===");
-        let parser = HtmlParser::new(html);
-        let dom = parser.parse();
-        
-        eprintln!("\n=== ACTUAL DOM STRUCTURE ===");
-        print_dom_tree(&dom, 0, 0);
-        
-        eprintln!("\n=== NODE COUNT: {} ===", dom.nodes.len());
+    fn current_node(&self) -> NodeId {
+        self.open_elements.last().copied().unwrap_or(self.document)
+    }
+
+    /// Spec 13.2.4.3 adjusted current node: the context element while only
+    /// the fragment root is open, otherwise the current node
+    fn adjusted_current_node(&self) -> NodeId {
+        match self.context_element {
+            Some(context) if self.open_elements.len() == 1 => context,
+            _ => self.current_node(),
+        }
+    }
+
+    fn current_tag(&self) -> &str {
+        match self.open_elements.last() {
+            Some(&id) => self.tag_of(id),
+            None => "",
+        }
+    }
+
+    fn is_open(&self, id: NodeId) -> bool {
+        self.open_elements.contains(&id)
+    }
+
+    fn has_in_scope_with(&self, targets: &[&str], boundary: &dyn Fn(NodeId) -> bool) -> bool {
+        for &id in self.open_elements.iter().rev() {
+            if targets.contains(&self.tag_of(id)) {
+                return true;
+            }
+            if boundary(id) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn is_default_scope_boundary(&self, id: NodeId) -> bool {
+        DEFAULT_SCOPE.contains(&self.tag_of(id)) || self.is_special_foreign(id)
+    }
+
+    fn has_in_scope(&self, targets: &[&str]) -> bool {
+        self.has_in_scope_with(targets, &|id| self.is_default_scope_boundary(id))
+    }
+
+    fn has_in_list_item_scope(&self, tag: &str) -> bool {
+        self.has_in_scope_with(&[tag], &|id| {
+            self.is_default_scope_boundary(id) || matches!(self.tag_of(id), "ol" | "ul")
+        })
+    }
+
+    fn has_in_button_scope(&self, tag: &str) -> bool {
+        self.has_in_scope_with(&[tag], &|id| self.is_default_scope_boundary(id) || self.tag_of(id) == "button")
+    }
+
+    fn has_in_table_scope(&self, targets: &[&str]) -> bool {
+        self.has_in_scope_with(targets, &|id| matches!(self.tag_of(id), "html" | "table" | "template"))
+    }
+
+    fn has_in_select_scope(&self, tag: &str) -> bool {
+        self.has_in_scope_with(&[tag], &|id| !matches!(self.tag_of(id), "optgroup" | "option"))
+    }
+
+    fn pop(&mut self) -> Option<NodeId> {
+        self.open_elements.pop()
+    }
+
+    /// Pop elements until one of `tags` has been popped
+    fn pop_until_one_of(&mut self, tags: &[&str]) {
+        while let Some(id) = self.pop() {
+            if tags.contains(&self.tag_of(id)) {
+                break;
+            }
+        }
+    }
+
+    fn pop_until(&mut self, tag: &str) {
+        self.pop_until_one_of(&[tag]);
+    }
+
+    /// Pop elements until `node` has been popped
+    fn pop_until_node(&mut self, node: NodeId) {
+        while let Some(id) = self.pop() {
+            if id == node {
+                break;
+            }
+        }
+    }
+
+    /// Spec 13.2.6.3 Generate implied end tags
+    fn generate_implied_end_tags(&mut self, except: Option<&str>) {
+        loop {
+            let tag = self.current_tag();
+            if Some(tag) == except || !IMPLIED_END_TAGS.contains(&tag) {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    /// Spec 13.2.6.4.7 "close a p element"
+    fn close_p_element(&mut self) {
+        self.generate_implied_end_tags(Some("p"));
+        if self.current_tag() != "p" {
+            self.parse_error("end-tag-too-early");
+        }
+        self.pop_until("p");
+    }
+
+    fn close_p_if_in_button_scope(&mut self) {
+        if self.has_in_button_scope("p") {
+            self.close_p_element();
+        }
+    }
+
+    /// Pop until the current node is one of `tags` (or html)
+    fn clear_stack_back_to(&mut self, tags: &[&str]) {
+        while !self.open_elements.is_empty() {
+            let tag = self.current_tag();
+            if tags.contains(&tag) || tag == "html" {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    fn clear_stack_back_to_table_context(&mut self) {
+        self.clear_stack_back_to(&["table", "template"]);
+    }
+
+    fn clear_stack_back_to_table_body_context(&mut self) {
+        self.clear_stack_back_to(&["tbody", "tfoot", "thead", "template"]);
+    }
+
+    fn clear_stack_back_to_table_row_context(&mut self) {
+        self.clear_stack_back_to(&["tr", "template"]);
+    }
+
+    /// Spec 13.2.4.1 Reset the insertion mode appropriately
+    fn reset_insertion_mode(&mut self) {
+        for (index, &id) in self.open_elements.iter().enumerate().rev() {
+            let last = index == 0;
+            let id = if last { self.context_element.unwrap_or(id) } else { id };
+            let mode = match self.tag_of(id) {
+                "select" => {
+                    let in_table = self.open_elements[..index]
+                        .iter()
+                        .rev()
+                        .take_while(|&&ancestor| self.tag_of(ancestor) != "template")
+                        .any(|&ancestor| self.tag_of(ancestor) == "table");
+                    if in_table {
+                        InsertionMode::InSelectInTable
+                    } else {
+                        InsertionMode::InSelect
+                    }
+                }
+                "template" => match self.template_modes.last() {
+                    Some(&mode) => mode,
+                    None => continue,
+                },
+                "td" | "th" if !last => InsertionMode::InCell,
+                "tr" => InsertionMode::InRow,
+                "tbody" | "thead" | "tfoot" => InsertionMode::InTableBody,
+                "caption" => InsertionMode::InCaption,
+                "colgroup" => InsertionMode::InColumnGroup,
+                "table" => InsertionMode::InTable,
+                "head" if !last => InsertionMode::InHead,
+                "body" => InsertionMode::InBody,
+                "html" => {
+                    if self.head_element.is_none() {
+                        InsertionMode::BeforeHead
+                    } else {
+                        InsertionMode::AfterHead
+                    }
+                }
+                _ if last => InsertionMode::InBody,
+                _ => continue,
+            };
+            self.mode = mode;
+            return;
+        }
+        self.mode = InsertionMode::InBody;
+    }
+
+    // ------------------------------------------------------------------
+    // Node insertion
+    // ------------------------------------------------------------------
+
+    /// Spec 13.2.6.1 Appropriate place for inserting a node, with foster parenting
+    fn appropriate_insertion_place(&self) -> InsertionLocation {
+        self.appropriate_insertion_place_for(self.current_node())
+    }
+
+    /// Appropriate place for inserting a node with an override target
+    fn appropriate_insertion_place_for(&self, target: NodeId) -> InsertionLocation {
+        if self.foster_parenting && FOSTER_TARGETS.contains(&self.tag_of(target)) {
+            let last_table = self
+                .open_elements
+                .iter()
+                .rposition(|&id| self.tag_of(id) == "table");
+
+            return match last_table {
+                None => InsertionLocation { parent: self.open_elements[0], before: None },
+                Some(index) => {
+                    let table = self.open_elements[index];
+                    match self.dom.nodes[table].parent {
+                        Some(parent) => InsertionLocation { parent, before: Some(table) },
+                        None => InsertionLocation {
+                            parent: self.open_elements[index - 1],
+                            before: None,
+                        },
+                    }
+                }
+            };
+        }
+
+        InsertionLocation { parent: target, before: None }
+    }
+
+    /// Attach a parentless node at the given location
+    fn insert_node_at(&mut self, location: InsertionLocation, id: NodeId) {
+        self.dom
+            .insert_before(location.parent, id, location.before)
+            .expect("insertion location is always a valid parent");
+    }
+
+    /// Remove a node from its parent's children
+    fn detach_node(&mut self, id: NodeId) {
+        if let Some(parent) = self.dom.nodes[id].parent {
+            let _ = self.dom.remove_child(parent, id);
+        }
+    }
+
+    /// Move a node (with its subtree) to the end of `parent`'s children
+    fn reparent_node(&mut self, id: NodeId, parent: NodeId) {
+        self.insert_node_at(InsertionLocation { parent, before: None }, id);
+    }
+
+    fn create_element_for(&mut self, tag: &str, attributes: &[Attribute]) -> NodeId {
+        self.dom.create_element(tag, Self::convert_attributes(attributes), None)
+    }
+
+    /// Insert an HTML element at the appropriate place and push it onto the stack
+    fn insert_html_element(&mut self, tag: &str, attributes: &[Attribute]) -> NodeId {
+        let location = self.appropriate_insertion_place();
+        let id = self.create_element_for(tag, attributes);
+        self.insert_node_at(location, id);
+        self.open_elements.push(id);
+        id
+    }
+
+    /// Spec 13.2.6.1 Insert a foreign element, adjusting SVG/MathML names
+    fn insert_foreign_element(&mut self, tag: &str, attributes: &[Attribute], namespace: Namespace) -> NodeId {
+        let location = self.appropriate_insertion_place();
+        let (tag, attributes) = adjust_foreign_names(tag, attributes, namespace);
+        let id = self.dom.create_element_ns(&tag, attributes, namespace, None);
+        self.insert_node_at(location, id);
+        self.open_elements.push(id);
+        id
+    }
+
+    /// Insert a comment at the appropriate place, or as the last child of `parent`
+    fn insert_comment(&mut self, data: &str, parent: Option<NodeId>) {
+        let location = match parent {
+            Some(parent) => InsertionLocation { parent, before: None },
+            None => self.appropriate_insertion_place(),
+        };
+        let id = self.dom.create_comment(data, None);
+        self.insert_node_at(location, id);
+    }
+
+    /// Insert an element that never has children (void elements and friends)
+    fn insert_void_element(&mut self, tag: &str, attributes: &[Attribute]) -> NodeId {
+        let id = self.insert_html_element(tag, attributes);
+        self.pop();
+        id
+    }
+
+    // ------------------------------------------------------------------
+    // List of active formatting elements
+    // ------------------------------------------------------------------
+
+    fn insert_formatting_marker(&mut self) {
+        self.active_formatting.push(FormattingEntry::Marker);
+    }
+
+    /// Push an element, enforcing the Noah's Ark clause (at most three
+    /// identical elements after the last marker)
+    fn push_active_formatting(&mut self, node: NodeId, tag: &str, attributes: &[Attribute]) {
+        let same_attributes = |other: &[Attribute]| {
+            other.len() == attributes.len()
+                && attributes.iter().all(|a| other.iter().any(|b| b.name == a.name && b.value == a.value))
+        };
+        let mut matching = Vec::new();
+        for (index, entry) in self.active_formatting.iter().enumerate().rev() {
+            match entry {
+                FormattingEntry::Marker => break,
+                FormattingEntry::Element { tag: t, attributes: a, .. } => {
+                    if t == tag && same_attributes(a) {
+                        matching.push(index);
+                    }
+                }
+            }
+        }
+        if matching.len() >= 3 {
+            // `matching` runs from last to first, so the earliest is at the end
+            self.active_formatting.remove(matching[matching.len() - 1]);
+        }
+        self.active_formatting.push(FormattingEntry::Element {
+            node,
+            tag: tag.to_string(),
+            attributes: attributes.to_vec(),
+        });
+    }
+
+    fn formatting_position(&self, node: NodeId) -> Option<usize> {
+        self.active_formatting.iter().position(|e| e.node() == Some(node))
+    }
+
+    fn remove_from_active_formatting(&mut self, node: NodeId) {
+        if let Some(index) = self.formatting_position(node) {
+            self.active_formatting.remove(index);
+        }
+    }
+
+    /// The last element with the given tag after the last marker
+    fn formatting_element_after_marker(&self, tag: &str) -> Option<NodeId> {
+        for entry in self.active_formatting.iter().rev() {
+            match entry {
+                FormattingEntry::Marker => return None,
+                FormattingEntry::Element { node, tag: t, .. } if t == tag => return Some(*node),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Spec 13.2.4.3 Reconstruct the active formatting elements
+    fn reconstruct_active_formatting(&mut self) {
+        let is_open_or_marker = |parser: &Self, entry: &FormattingEntry| match entry {
+            FormattingEntry::Marker => true,
+            FormattingEntry::Element { node, .. } => parser.is_open(*node),
+        };
+
+        let last = match self.active_formatting.last() {
+            Some(entry) => entry,
+            None => return,
+        };
+        if is_open_or_marker(self, last) {
+            return;
+        }
+
+        // Rewind to the entry after the last marker or open element
+        let mut index = self.active_formatting.len() - 1;
+        while index > 0 && !is_open_or_marker(self, &self.active_formatting[index - 1]) {
+            index -= 1;
+        }
+
+        // Advance, recreating each entry
+        for i in index..self.active_formatting.len() {
+            if let FormattingEntry::Element { tag, attributes, .. } = self.active_formatting[i].clone() {
+                let node = self.insert_html_element(&tag, &attributes);
+                self.active_formatting[i] = FormattingEntry::Element { node, tag, attributes };
+            }
+        }
+    }
+
+    /// Spec 13.2.4.3 Clear the list of active formatting elements up to the last marker
+    fn clear_active_formatting_to_marker(&mut self) {
+        while let Some(entry) = self.active_formatting.pop() {
+            if entry == FormattingEntry::Marker {
+                break;
+            }
+        }
+    }
+
+    /// Spec 13.2.6.4.7 Adoption agency algorithm
+    fn adoption_agency(&mut self, subject: &str) {
+        // A current node that matches and is not a formatting element is simply popped
+        let current = self.current_node();
+        if self.current_tag() == subject && self.formatting_position(current).is_none() {
+            self.pop();
+            return;
+        }
+
+        for _ in 0..8 {
+            let formatting_element = match self.formatting_element_after_marker(subject) {
+                Some(node) => node,
+                None => {
+                    self.any_other_end_tag(subject);
+                    return;
+                }
+            };
+
+            let formatting_index = match self.open_elements.iter().position(|&id| id == formatting_element) {
+                Some(index) => index,
+                None => {
+                    self.parse_error("unexpected-end-tag");
+                    self.remove_from_active_formatting(formatting_element);
+                    return;
+                }
+            };
+
+            if !self.has_in_scope(&[subject]) {
+                self.parse_error("unexpected-end-tag");
+                return;
+            }
+            if formatting_element != self.current_node() {
+                self.parse_error("misnested-end-tag");
+            }
+
+            let furthest_block_index = (formatting_index + 1..self.open_elements.len())
+                .find(|&i| self.is_special(self.open_elements[i]));
+            let furthest_block_index = match furthest_block_index {
+                Some(index) => index,
+                None => {
+                    self.pop_until_node(formatting_element);
+                    self.remove_from_active_formatting(formatting_element);
+                    return;
+                }
+            };
+            let furthest_block = self.open_elements[furthest_block_index];
+            let common_ancestor = self.open_elements[formatting_index - 1];
+            let mut bookmark = self.formatting_position(formatting_element).unwrap_or(0);
+
+            let mut node_index = furthest_block_index;
+            let mut last_node = furthest_block;
+            let mut inner_loop_counter = 0;
+            loop {
+                inner_loop_counter += 1;
+                node_index -= 1;
+                let node = self.open_elements[node_index];
+                if node == formatting_element {
+                    break;
+                }
+
+                let mut formatting_pos = self.formatting_position(node);
+                if inner_loop_counter > 3 {
+                    if let Some(pos) = formatting_pos.take() {
+                        self.active_formatting.remove(pos);
+                        if pos < bookmark {
+                            bookmark -= 1;
+                        }
+                    }
+                }
+                let pos = match formatting_pos {
+                    Some(pos) => pos,
+                    None => {
+                        self.open_elements.remove(node_index);
+                        continue;
+                    }
+                };
+
+                // Replace the node with a fresh copy in both lists
+                let (tag, attributes) = match &self.active_formatting[pos] {
+                    FormattingEntry::Element { tag, attributes, .. } => (tag.clone(), attributes.clone()),
+                    FormattingEntry::Marker => unreachable!("markers have no node"),
+                };
+                let new_node = self.create_element_for(&tag, &attributes);
+                self.active_formatting[pos] = FormattingEntry::Element { node: new_node, tag, attributes };
+                self.open_elements[node_index] = new_node;
+
+                if last_node == furthest_block {
+                    bookmark = pos + 1;
+                }
+                self.reparent_node(last_node, new_node);
+                last_node = new_node;
+            }
+
+            // Put whatever the inner loop ended on under the common ancestor
+            self.detach_node(last_node);
+            let location = self.appropriate_insertion_place_for(common_ancestor);
+            self.insert_node_at(location, last_node);
+
+            // Move the furthest block's children into a copy of the formatting element
+            let (tag, attributes) = match &self.active_formatting[self.formatting_position(formatting_element).unwrap()] {
+                FormattingEntry::Element { tag, attributes, .. } => (tag.clone(), attributes.clone()),
+                FormattingEntry::Marker => unreachable!("markers have no node"),
+            };
+            let new_element = self.create_element_for(&tag, &attributes);
+            for child in self.dom.nodes[furthest_block].children.clone() {
+                self.reparent_node(child, new_element);
+            }
+            self.insert_node_at(InsertionLocation { parent: furthest_block, before: None }, new_element);
+
+            let old_pos = self.formatting_position(formatting_element).unwrap();
+            self.active_formatting.remove(old_pos);
+            if old_pos < bookmark {
+                bookmark -= 1;
+            }
+            let bookmark = bookmark.min(self.active_formatting.len());
+            self.active_formatting.insert(
+                bookmark,
+                FormattingEntry::Element { node: new_element, tag, attributes },
+            );
+
+            self.open_elements.retain(|&id| id != formatting_element);
+            let furthest_block_index = self.open_elements.iter().position(|&id| id == furthest_block).unwrap();
+            self.open_elements.insert(furthest_block_index + 1, new_element);
+        }
+    }
+
+    /// Buffer a character for insertion at the appropriate place
+    fn insert_character(&mut self, c: char) {
+        let location = self.appropriate_insertion_place();
+        if self.pending_text_location != Some(location) {
+            self.flush_pending_text();
+            self.pending_text_location = Some(location);
+        }
+        self.pending_text.push(c);
+    }
+
+    /// Flush any pending text to the DOM
+    /// Only creates a text node if there's meaningful content (not just whitespace)
+    fn flush_pending_text(&mut self) {
+        let location = self.pending_text_location.take();
+        if self.pending_text.is_empty() {
+            return;
+        }
+        let text = std::mem::take(&mut self.pending_text);
+        let location = match location {
+            Some(location) => location,
+            None => return,
+        };
+
+        tree_builder_log(&format!("Flushing text: {:?}", text));
+
+        // Adjacent text is merged into the preceding text node
+        let siblings = &self.dom.nodes[location.parent].children;
+        let previous = match location.before {
+            Some(before) => siblings
+                .iter()
+                .position(|&c| c == before)
+                .and_then(|index| index.checked_sub(1))
+                .map(|index| siblings[index]),
+            None => siblings.last().copied(),
+        };
+        if let Some(previous) = previous {
+            if let NodeType::Text(existing) = &mut self.dom.nodes[previous].node_type {
+                existing.push_str(&text);
+                return;
+            }
+        }
+
+        // Whitespace-only text only survives as part of a neighbouring text node
+        if text.trim().is_empty() {
+            tree_builder_log(&format!("Skipping whitespace-only text: {:?}", text));
+            return;
+        }
+
+        let id = self.dom.create_text(&text, None);
+        self.insert_node_at(location, id);
+    }
+
+    /// Convert attribute list from tokenizer format to DOM format
+    fn convert_attributes(attributes: &[Attribute]) -> Vec<(String, String)> {
+        attributes.iter().map(|a| (a.name.clone(), a.value.clone())).collect()
+    }
+
+    /// Copy attributes onto an existing element unless already present
+    fn merge_attributes(&mut self, id: NodeId, attributes: &[Attribute]) {
+        for attr in attributes {
+            if self.dom.get_attribute(id, &attr.name).is_none() {
+                let _ = self.dom.set_attribute(id, &attr.name, &attr.value);
+            }
+        }
+    }
+
+    /// Spec 13.2.6.2 Generic raw text and RCDATA element parsing algorithms
+    fn parse_generic_text_element(&mut self, tag: &str, attributes: &[Attribute], state: TokenizerState) {
+        self.insert_html_element(tag, attributes);
+        self.tokenizer.set_state(state);
+        self.original_mode = self.mode;
+        self.mode = InsertionMode::Text;
+    }
+
+    // ------------------------------------------------------------------
+    // Insertion modes
+    // ------------------------------------------------------------------
+
+    /// 13.2.6.4.1 The "initial" insertion mode
+    fn handle_initial(&mut self, token: Token) {
+        match token {
+            Token::Character(c) if is_whitespace(c) => {}
+            Token::Comment(data) => self.insert_comment(&data, Some(self.document)),
+            Token::Doctype { name, public_id, system_id, force_quirks } => {
+                let conforming = name.as_deref() == Some("html")
+                    && public_id.is_none()
+                    && matches!(system_id.as_deref(), None | Some("about:legacy-compat"));
+                if !conforming {
+                    self.parse_error("non-conforming-doctype");
+                }
+                self.dom.quirks_mode = quirks_mode_for_doctype(
+                    name.as_deref(),
+                    public_id.as_deref(),
+                    system_id.as_deref(),
+                    force_quirks,
+                );
+                self.dom.create_doctype(
+                    name.as_deref().unwrap_or_default(),
+                    public_id.as_deref().unwrap_or_default(),
+                    system_id.as_deref().unwrap_or_default(),
+                    Some(self.document),
+                );
+                self.mode = InsertionMode::BeforeHtml;
+            }
+            _ => {
+                self.parse_error("missing-doctype");
+                self.dom.quirks_mode = QuirksMode::Quirks;
+                self.reprocess_in(InsertionMode::BeforeHtml, token);
+            }
+        }
+    }
+
+    /// 13.2.6.4.2 The "before html" insertion mode
+    fn handle_before_html(&mut self, token: Token) {
+        match &token {
+            Token::Comment(data) => self.insert_comment(data, Some(self.document)),
+            Token::Doctype { .. } => self.parse_error("unexpected-doctype"),
+            Token::Character(c) if is_whitespace(*c) => {}
+            Token::StartTag { name, attributes, .. } if name == "html" => {
+                let html = self.create_element_for("html", attributes);
+                self.insert_node_at(InsertionLocation { parent: self.document, before: None }, html);
+                self.open_elements.push(html);
+                self.mode = InsertionMode::BeforeHead;
+            }
+            Token::EndTag { name } if !matches!(name.as_str(), "head" | "body" | "html" | "br") => {
+                self.parse_error("unexpected-end-tag");
+            }
+            _ => {
+                // Auto-insert html element
+                let html = self.dom.create_element("html", vec![], None);
+                self.insert_node_at(InsertionLocation { parent: self.document, before: None }, html);
+                self.open_elements.push(html);
+                self.reprocess_in(InsertionMode::BeforeHead, token);
+            }
+        }
+    }
+
+    /// 13.2.6.4.3 The "before head" insertion mode
+    fn handle_before_head(&mut self, token: Token) {
+        match &token {
+            Token::Character(c) if is_whitespace(*c) => {}
+            Token::Comment(data) => self.insert_comment(data, None),
+            Token::Doctype { .. } => self.parse_error("unexpected-doctype"),
+            Token::StartTag { name, .. } if name == "html" => self.handle_in_body(token),
+            Token::StartTag { name, attributes, .. } if name == "head" => {
+                let head = self.insert_html_element("head", attributes);
+                self.head_element = Some(head);
+                self.mode = InsertionMode::InHead;
+            }
+            Token::EndTag { name } if !matches!(name.as_str(), "head" | "body" | "html" | "br") => {
+                self.parse_error("unexpected-end-tag");
+            }
+            _ => {
+                let head = self.insert_html_element("head", &[]);
+                self.head_element = Some(head);
+                self.reprocess_in(InsertionMode::InHead, token);
+            }
+        }
+    }
+
+    /// 13.2.6.4.4 The "in head" insertion mode
+    fn handle_in_head(&mut self, token: Token) {
+        match &token {
+            Token::Character(c) if is_whitespace(*c) => self.insert_character(*c),
+            Token::Comment(data) => self.insert_comment(data, None),
+            Token::Doctype { .. } => self.parse_error("unexpected-doctype"),
+            Token::StartTag { name, attributes, .. } => match name.as_str() {
+                "html" => self.handle_in_body(token),
+                "base" | "basefont" | "bgsound" | "link" | "meta" => {
+                    self.insert_void_element(name, attributes);
+                }
+                "title" => self.parse_generic_text_element(name, attributes, TokenizerState::RcData),
+                "noscript" => {
+                    // Scripting is disabled, so <noscript> content is parsed as markup
+                    self.insert_html_element(name, attributes);
+                    self.mode = InsertionMode::InHeadNoscript;
+                }
+                "noframes" | "style" => {
+                    self.parse_generic_text_element(name, attributes, TokenizerState::RawText)
+                }
+                "script" => self.parse_generic_text_element(name, attributes, TokenizerState::ScriptData),
+                "template" => {
+                    self.insert_html_element(name, attributes);
+                    self.active_formatting.push(FormattingEntry::Marker);
+                    self.frameset_ok = false;
+                    self.mode = InsertionMode::InTemplate;
+                    self.template_modes.push(InsertionMode::InTemplate);
+                }
+                "head" => self.parse_error("unexpected-start-tag"),
+                _ => self.in_head_anything_else(token),
+            },
+            Token::EndTag { name } => match name.as_str() {
+                "head" => {
+                    self.pop();
+                    self.mode = InsertionMode::AfterHead;
+                }
+                "body" | "html" | "br" => self.in_head_anything_else(token),
+                "template" => self.close_template_element(),
+                _ => self.parse_error("unexpected-end-tag"),
+            },
+            _ => self.in_head_anything_else(token),
+        }
+    }
+
+    /// Spec 13.2.6.4.4 An end tag whose tag name is "template"
+    fn close_template_element(&mut self) {
+        if !self.open_elements.iter().any(|&id| self.tag_of(id) == "template") {
+            self.parse_error("unexpected-end-tag");
+            return;
+        }
+        self.generate_implied_end_tags(None);
+        if self.current_tag() != "template" {
+            self.parse_error("end-tag-too-early");
+        }
+        self.pop_until("template");
+        self.clear_active_formatting_to_marker();
+        self.template_modes.pop();
+        self.reset_insertion_mode();
+    }
+
+    fn in_head_anything_else(&mut self, token: Token) {
+        // Unrecognized content in head mode - exit head mode
+        self.pop();
+        self.reprocess_in(InsertionMode::AfterHead, token);
+    }
+
+    /// 13.2.6.4.5 The "in head noscript" insertion mode
+    fn handle_in_head_noscript(&mut self, token: Token) {
+        match &token {
+            Token::Doctype { .. } => self.parse_error("unexpected-doctype"),
+            Token::StartTag { name, .. } if name == "html" => self.handle_in_body(token),
+            Token::EndTag { name } if name == "noscript" => {
+                self.pop();
+                self.mode = InsertionMode::InHead;
+            }
+            Token::Character(c) if is_whitespace(*c) => self.handle_in_head(token),
+            Token::Comment(_) => self.handle_in_head(token),
+            Token::StartTag { name, .. }
+                if matches!(name.as_str(), "basefont" | "bgsound" | "link" | "meta" | "noframes" | "style") =>
+            {
+                self.handle_in_head(token)
+            }
+            Token::StartTag { name, .. } if matches!(name.as_str(), "head" | "noscript") => {}
+            Token::EndTag { name } if name != "br" => {}
+            _ => {
+                self.parse_error("unexpected-token-in-noscript");
+                self.pop();
+                self.reprocess_in(InsertionMode::InHead, token);
+            }
+        }
+    }
+
+    /// 13.2.6.4.6 The "after head" insertion mode
+    fn handle_after_head(&mut self, token: Token) {
+        match &token {
+            Token::Character(c) if is_whitespace(*c) => self.insert_character(*c),
+            Token::Comment(data) => self.insert_comment(data, None),
+            Token::Doctype { .. } => self.parse_error("unexpected-doctype"),
+            Token::StartTag { name, attributes, .. } => match name.as_str() {
+                "html" => self.handle_in_body(token),
+                "body" => {
+                    self.insert_html_element("body", attributes);
+                    self.frameset_ok = false;
+                    self.mode = InsertionMode::InBody;
+                }
+                "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script"
+                | "style" | "template" | "title" => {
+                    self.parse_error("unexpected-start-tag");
+                    if let Some(head) = self.head_element {
+                        self.open_elements.push(head);
+                        self.handle_in_head(token);
+                        if let Some(index) = self.open_elements.iter().rposition(|&id| id == head) {
+                            self.open_elements.remove(index);
+                        }
+                    }
+                }
+                "head" => self.parse_error("unexpected-start-tag"),
+                _ => self.after_head_anything_else(token),
+            },
+            Token::EndTag { name } if matches!(name.as_str(), "body" | "html" | "br") => {
+                self.after_head_anything_else(token)
+            }
+            Token::EndTag { .. } => self.parse_error("unexpected-end-tag"),
+            _ => self.after_head_anything_else(token),
+        }
+    }
+
+    fn after_head_anything_else(&mut self, token: Token) {
+        self.insert_html_element("body", &[]);
+        self.reprocess_in(InsertionMode::InBody, token);
+    }
+
+    /// 13.2.6.4.7 The "in body" insertion mode
+    fn handle_in_body(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => self.parse_error("unexpected-null-character"),
+            Token::Character(c) => {
+                self.reconstruct_active_formatting();
+                if !is_whitespace(c) {
+                    self.frameset_ok = false;
+                }
+                self.insert_character(c);
+            }
+            Token::Comment(data) => self.insert_comment(&data, None),
+            Token::Doctype { .. } => self.parse_error("unexpected-doctype"),
+            Token::StartTag { name, attributes, self_closing } => {
+                self.in_body_start_tag(&name, &attributes, self_closing)
+            }
+            Token::EndTag { name } => self.in_body_end_tag(&name),
+            Token::Eof if !self.template_modes.is_empty() => self.handle_in_template(Token::Eof),
+            Token::Eof => {
+                let unclosed = self.open_elements.iter().any(|&id| {
+                    let tag = self.tag_of(id);
+                    !IMPLIED_END_TAGS.contains(&tag)
+                        && !matches!(tag, "tbody" | "td" | "tfoot" | "th" | "thead" | "tr" | "body" | "html")
+                });
+                if unclosed {
+                    self.parse_error("eof-in-element");
+                }
+            }
+        }
+    }
+
+    fn in_body_start_tag(&mut self, tag: &str, attributes: &[Attribute], self_closing: bool) {
+        match tag {
+            "html" => {
+                self.parse_error("unexpected-start-tag");
+                if let Some(&html) = self.open_elements.first() {
+                    self.merge_attributes(html, attributes);
+                }
+            }
+            "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style"
+            | "template" | "title" => {
+                self.handle_in_head(Token::StartTag {
+                    name: tag.to_string(),
+                    attributes: attributes.to_vec(),
+                    self_closing,
+                });
+            }
+            "body" => {
+                self.parse_error("unexpected-start-tag");
+                if self.open_elements.len() > 1 && self.tag_of(self.open_elements[1]) == "body" {
+                    self.frameset_ok = false;
+                    let body = self.open_elements[1];
+                    self.merge_attributes(body, attributes);
+                }
+            }
+            "frameset" => self.parse_error("unexpected-start-tag"),
+            _ if BLOCK_START_TAGS.contains(&tag) => {
+                self.close_p_if_in_button_scope();
+                self.insert_html_element(tag, attributes);
+            }
+            _ if HEADING_TAGS.contains(&tag) => {
+                self.close_p_if_in_button_scope();
+                if HEADING_TAGS.contains(&self.current_tag()) {
+                    self.parse_error("unexpected-start-tag");
+                    self.pop();
+                }
+                self.insert_html_element(tag, attributes);
+            }
+            "pre" | "listing" => {
+                self.close_p_if_in_button_scope();
+                self.insert_html_element(tag, attributes);
+                self.ignore_next_lf = true;
+                self.frameset_ok = false;
+            }
+            "form" => {
+                if self.form_element.is_some() {
+                    self.parse_error("unexpected-start-tag");
+                    return;
+                }
+                self.close_p_if_in_button_scope();
+                let form = self.insert_html_element(tag, attributes);
+                self.form_element = Some(form);
+            }
+            "li" => {
+                self.frameset_ok = false;
+                self.close_list_item(&["li"]);
+                self.close_p_if_in_button_scope();
+                self.insert_html_element(tag, attributes);
+            }
+            "dd" | "dt" => {
+                self.frameset_ok = false;
+                self.close_list_item(&["dd", "dt"]);
+                self.close_p_if_in_button_scope();
+                self.insert_html_element(tag, attributes);
+            }
+            "plaintext" => {
+                self.close_p_if_in_button_scope();
+                self.insert_html_element(tag, attributes);
+                self.tokenizer.set_state(TokenizerState::PlainText);
+            }
+            "button" => {
+                if self.has_in_scope(&["button"]) {
+                    self.parse_error("unexpected-start-tag");
+                    self.generate_implied_end_tags(None);
+                    self.pop_until("button");
+                }
+                self.reconstruct_active_formatting();
+                self.insert_html_element(tag, attributes);
+                self.frameset_ok = false;
+            }
+            "a" => {
+                if let Some(a) = self.formatting_element_after_marker("a") {
+                    self.parse_error("unexpected-start-tag");
+                    self.adoption_agency("a");
+                    self.remove_from_active_formatting(a);
+                    self.open_elements.retain(|&id| id != a);
+                }
+                self.reconstruct_active_formatting();
+                let node = self.insert_html_element(tag, attributes);
+                self.push_active_formatting(node, tag, attributes);
+            }
+            "nobr" => {
+                self.reconstruct_active_formatting();
+                if self.has_in_scope(&["nobr"]) {
+                    self.parse_error("unexpected-start-tag");
+                    self.adoption_agency("nobr");
+                    self.reconstruct_active_formatting();
+                }
+                let node = self.insert_html_element(tag, attributes);
+                self.push_active_formatting(node, tag, attributes);
+            }
+            _ if FORMATTING_TAGS.contains(&tag) => {
+                self.reconstruct_active_formatting();
+                let node = self.insert_html_element(tag, attributes);
+                self.push_active_formatting(node, tag, attributes);
+            }
+            "applet" | "marquee" | "object" => {
+                self.reconstruct_active_formatting();
+                self.insert_html_element(tag, attributes);
+                self.insert_formatting_marker();
+                self.frameset_ok = false;
+            }
+            "table" => {
+                // Quirks mode tables may sit inside a paragraph
+                if !self.dom.is_quirks_mode() {
+                    self.close_p_if_in_button_scope();
+                }
+                self.insert_html_element(tag, attributes);
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InTable;
+            }
+            "area" | "br" | "embed" | "img" | "keygen" | "wbr" => {
+                self.reconstruct_active_formatting();
+                self.insert_void_element(tag, attributes);
+                self.frameset_ok = false;
+            }
+            "input" => {
+                self.reconstruct_active_formatting();
+                self.insert_void_element(tag, attributes);
+                let hidden = attributes
+                    .iter()
+                    .any(|a| a.name == "type" && a.value.eq_ignore_ascii_case("hidden"));
+                if !hidden {
+                    self.frameset_ok = false;
+                }
+            }
+            "param" | "source" | "track" => {
+                self.insert_void_element(tag, attributes);
+            }
+            "hr" => {
+                self.close_p_if_in_button_scope();
+                self.insert_void_element(tag, attributes);
+                self.frameset_ok = false;
+            }
+            "image" => {
+                self.parse_error("unexpected-start-tag");
+                self.in_body_start_tag("img", attributes, self_closing);
+            }
+            "textarea" => {
+                self.insert_html_element(tag, attributes);
+                self.ignore_next_lf = true;
+                self.tokenizer.set_state(TokenizerState::RcData);
+                self.original_mode = self.mode;
+                self.frameset_ok = false;
+                self.mode = InsertionMode::Text;
+            }
+            "xmp" => {
+                self.close_p_if_in_button_scope();
+                self.reconstruct_active_formatting();
+                self.frameset_ok = false;
+                self.parse_generic_text_element(tag, attributes, TokenizerState::RawText);
+            }
+            "iframe" => {
+                self.frameset_ok = false;
+                self.parse_generic_text_element(tag, attributes, TokenizerState::RawText);
+            }
+            "noembed" => {
+                self.parse_generic_text_element(tag, attributes, TokenizerState::RawText);
+            }
+            "select" => {
+                self.reconstruct_active_formatting();
+                self.insert_html_element(tag, attributes);
+                self.frameset_ok = false;
+                self.mode = match self.mode {
+                    InsertionMode::InTable
+                    | InsertionMode::InCaption
+                    | InsertionMode::InTableBody
+                    | InsertionMode::InRow
+                    | InsertionMode::InCell => InsertionMode::InSelectInTable,
+                    _ => InsertionMode::InSelect,
+                };
+            }
+            "optgroup" | "option" => {
+                if self.current_tag() == "option" {
+                    self.pop();
+                }
+                self.reconstruct_active_formatting();
+                self.insert_html_element(tag, attributes);
+            }
+            "rb" | "rtc" => {
+                if self.has_in_scope(&["ruby"]) {
+                    self.generate_implied_end_tags(None);
+                }
+                self.insert_html_element(tag, attributes);
+            }
+            "rp" | "rt" => {
+                if self.has_in_scope(&["ruby"]) {
+                    self.generate_implied_end_tags(Some("rtc"));
+                }
+                self.insert_html_element(tag, attributes);
+            }
+            "math" | "svg" => {
+                self.reconstruct_active_formatting();
+                let namespace = if tag == "svg" { Namespace::Svg } else { Namespace::MathMl };
+                self.insert_foreign_element(tag, attributes, namespace);
+                if self_closing {
+                    self.pop();
+                }
+            }
+            "caption" | "col" | "colgroup" | "frame" | "head" | "tbody" | "td" | "tfoot" | "th"
+            | "thead" | "tr" => {
+                self.parse_error("unexpected-start-tag");
+            }
+            _ => {
+                self.reconstruct_active_formatting();
+                self.insert_html_element(tag, attributes);
+                // Obsolete void elements never take children
+                if VOID_ELEMENTS.contains(&tag) {
+                    self.pop();
+                }
+            }
+        }
+    }
+
+    /// Shared loop for <li>, <dd> and <dt> start tags
+    fn close_list_item(&mut self, tags: &[&str]) {
+        for index in (0..self.open_elements.len()).rev() {
+            let node = self.open_elements[index];
+            let tag = self.tag_of(node).to_string();
+            if tags.contains(&tag.as_str()) {
+                self.generate_implied_end_tags(Some(&tag));
+                if self.current_tag() != tag {
+                    self.parse_error("end-tag-too-early");
+                }
+                self.pop_until(&tag);
+                return;
+            }
+            if self.is_special(node) && !matches!(tag.as_str(), "address" | "div" | "p") {
+                return;
+            }
+        }
+    }
+
+    fn in_body_end_tag(&mut self, tag: &str) {
+        match tag {
+            "body" => {
+                if !self.has_in_scope(&["body"]) {
+                    self.parse_error("unexpected-end-tag");
+                    return;
+                }
+                self.mode = InsertionMode::AfterBody;
+            }
+            "html" => {
+                if !self.has_in_scope(&["body"]) {
+                    self.parse_error("unexpected-end-tag");
+                    return;
+                }
+                self.reprocess_in(InsertionMode::AfterBody, Token::EndTag { name: tag.to_string() });
+            }
+            "template" => self.close_template_element(),
+            _ if BLOCK_END_TAGS.contains(&tag) => {
+                if !self.has_in_scope(&[tag]) {
+                    self.parse_error("unexpected-end-tag");
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                self.pop_until(tag);
+            }
+            "form" => {
+                let form = self.form_element.take();
+                match form {
+                    Some(form) if self.has_in_scope(&["form"]) && self.is_open(form) => {
+                        self.generate_implied_end_tags(None);
+                        if let Some(index) = self.open_elements.iter().rposition(|&id| id == form) {
+                            self.open_elements.remove(index);
+                        }
+                    }
+                    _ => self.parse_error("unexpected-end-tag"),
+                }
+            }
+            "p" => {
+                if !self.has_in_button_scope("p") {
+                    self.parse_error("unexpected-end-tag");
+                    self.insert_html_element("p", &[]);
+                }
+                self.close_p_element();
+            }
+            "li" => {
+                if !self.has_in_list_item_scope("li") {
+                    self.parse_error("unexpected-end-tag");
+                    return;
+                }
+                self.generate_implied_end_tags(Some("li"));
+                self.pop_until("li");
+            }
+            "dd" | "dt" => {
+                if !self.has_in_scope(&[tag]) {
+                    self.parse_error("unexpected-end-tag");
+                    return;
+                }
+                self.generate_implied_end_tags(Some(tag));
+                self.pop_until(tag);
+            }
+            _ if HEADING_TAGS.contains(&tag) => {
+                if !self.has_in_scope(HEADING_TAGS) {
+                    self.parse_error("unexpected-end-tag");
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                self.pop_until_one_of(HEADING_TAGS);
+            }
+            "applet" | "marquee" | "object" => {
+                if !self.has_in_scope(&[tag]) {
+                    self.parse_error("unexpected-end-tag");
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                self.pop_until(tag);
+                self.clear_active_formatting_to_marker();
+            }
+            _ if FORMATTING_TAGS.contains(&tag) => self.adoption_agency(tag),
+            "br" => {
+                self.parse_error("unexpected-end-tag");
+                self.in_body_start_tag("br", &[], false);
+            }
+            _ => self.any_other_end_tag(tag),
+        }
+    }
+
+    /// "Any other end tag" steps of the in body insertion mode
+    fn any_other_end_tag(&mut self, tag: &str) {
+        for index in (0..self.open_elements.len()).rev() {
+            let node = self.open_elements[index];
+            if self.tag_of(node) == tag {
+                self.generate_implied_end_tags(Some(tag));
+                if node != self.current_node() {
+                    self.parse_error("end-tag-too-early");
+                }
+                self.pop_until_node(node);
+                return;
+            }
+            if self.is_special(node) {
+                self.parse_error("unexpected-end-tag");
+                return;
+            }
+        }
+    }
+
+    /// 13.2.6.5 The rules for parsing tokens in foreign content
+    fn handle_foreign_content(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => {
+                self.parse_error("unexpected-null-character");
+                self.insert_character('\u{FFFD}');
+            }
+            Token::Character(c) => {
+                if !is_whitespace(c) {
+                    self.frameset_ok = false;
+                }
+                self.insert_character(c);
+            }
+            Token::Comment(data) => self.insert_comment(&data, None),
+            Token::Doctype { .. } => self.parse_error("unexpected-doctype"),
+            Token::StartTag { ref name, ref attributes, .. } if breaks_out_of_foreign_content(name, attributes) => {
+                self.parse_error("unexpected-html-element-in-foreign-content");
+                self.pop_until_html_context();
+                self.process_using_mode_rules(token);
+            }
+            Token::EndTag { ref name } if name == "br" || name == "p" => {
+                self.parse_error("unexpected-html-element-in-foreign-content");
+                self.pop_until_html_context();
+                self.process_using_mode_rules(token);
+            }
+            Token::StartTag { name, attributes, self_closing } => {
+                let namespace = self
+                    .element_data(self.adjusted_current_node())
+                    .map_or(Namespace::Html, |el| el.namespace);
+                self.insert_foreign_element(&name, &attributes, namespace);
+                if self_closing {
+                    self.pop();
+                }
+            }
+            Token::EndTag { name } => {
+                let mut index = self.open_elements.len() - 1;
+                if !self.foreign_tag_matches(self.open_elements[index], &name) {
+                    self.parse_error("unexpected-end-tag");
+                }
+                while index > 0 {
+                    let node = self.open_elements[index];
+                    if self.foreign_tag_matches(node, &name) {
+                        self.pop_until_node(node);
+                        return;
+                    }
+                    index -= 1;
+                    if self.is_html_element(self.open_elements[index]) {
+                        self.process_using_mode_rules(Token::EndTag { name });
+                        return;
+                    }
+                }
+            }
+            Token::Eof => self.process_using_mode_rules(token),
+        }
+    }
+
+    /// Compare an element's tag name with an end tag, ASCII case-insensitively
+    fn foreign_tag_matches(&self, id: NodeId, name: &str) -> bool {
+        self.element_data(id).is_some_and(|el| el.tag_name.eq_ignore_ascii_case(name))
+    }
+
+    /// Pop until the current node is an HTML element or an integration point
+    fn pop_until_html_context(&mut self) {
+        while let Some(&id) = self.open_elements.last() {
+            if self.is_html_element(id) || self.is_mathml_text_integration_point(id) || self.is_html_integration_point(id) {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    /// 13.2.6.4.8 The "text" insertion mode
+    fn handle_text(&mut self, token: Token) {
+        match token {
+            Token::Character(c) => self.insert_character(c),
+            Token::Eof => {
+                self.parse_error("eof-in-text");
+                self.pop();
+                let mode = self.original_mode;
+                self.reprocess_in(mode, token);
+            }
+            Token::EndTag { .. } => {
+                self.pop();
+                self.mode = self.original_mode;
+            }
+            _ => {}
+        }
+    }
+
+    /// 13.2.6.4.9 The "in table" insertion mode
+    fn handle_in_table(&mut self, token: Token) {
+        match &token {
+            Token::Character(_) if TABLE_TEXT_CONTEXT.contains(&self.current_tag()) => {
+                self.pending_table_text.clear();
+                self.original_mode = self.mode;
+                self.reprocess_in(InsertionMode::InTableText, token);
+            }
+            Token::Comment(data) => self.insert_comment(data, None),
+            Token::Doctype { .. } => self.parse_error("unexpected-doctype"),
+            Token::StartTag { name, attributes, .. } => match name.as_str() {
+                "caption" => {
+                    self.clear_stack_back_to_table_context();
+                    self.insert_html_element(name, attributes);
+                    self.insert_formatting_marker();
+                    self.mode = InsertionMode::InCaption;
+                }
+                "colgroup" => {
+                    self.clear_stack_back_to_table_context();
+                    self.insert_html_element(name, attributes);
+                    self.mode = InsertionMode::InColumnGroup;
+                }
+                "col" => {
+                    self.clear_stack_back_to_table_context();
+                    self.insert_html_element("colgroup", &[]);
+                    self.reprocess_in(InsertionMode::InColumnGroup, token);
+                }
+                "tbody" | "tfoot" | "thead" => {
+                    self.clear_stack_back_to_table_context();
+                    self.insert_html_element(name, attributes);
+                    self.mode = InsertionMode::InTableBody;
+                }
+                "td" | "th" | "tr" => {
+                    // Implied <tbody>
+                    self.clear_stack_back_to_table_context();
+                    self.insert_html_element("tbody", &[]);
+                    self.reprocess_in(InsertionMode::InTableBody, token);
+                }
+                "table" => {
+                    self.parse_error("unexpected-start-tag");
+                    if !self.has_in_table_scope(&["table"]) {
+                        return;
+                    }
+                    self.pop_until("table");
+                    self.reset_insertion_mode();
+                    self.process_token(token);
+                }
+                "style" | "script" | "template" => self.handle_in_head(token),
+                "input" => {
+                    let hidden = attributes
+                        .iter()
+                        .any(|a| a.name == "type" && a.value.eq_ignore_ascii_case("hidden"));
+                    if !hidden {
+                        self.in_table_anything_else(token);
+                        return;
+                    }
+                    self.parse_error("unexpected-start-tag");
+                    self.insert_void_element(name, attributes);
+                }
+                "form" => {
+                    self.parse_error("unexpected-start-tag");
+                    if self.form_element.is_some() {
+                        return;
+                    }
+                    let form = self.insert_void_element(name, attributes);
+                    self.form_element = Some(form);
+                }
+                _ => self.in_table_anything_else(token),
+            },
+            Token::EndTag { name } => match name.as_str() {
+                "table" => {
+                    if !self.has_in_table_scope(&["table"]) {
+                        self.parse_error("unexpected-end-tag");
+                        return;
+                    }
+                    self.pop_until("table");
+                    self.reset_insertion_mode();
+                }
+                "body" | "caption" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot" | "th"
+                | "thead" | "tr" => {
+                    self.parse_error("unexpected-end-tag");
+                }
+                "template" => self.handle_in_head(token),
+                _ => self.in_table_anything_else(token),
+            },
+            Token::Eof => self.handle_in_body(token),
+            _ => self.in_table_anything_else(token),
+        }
+    }
+
+    /// Misplaced content in a table is processed with foster parenting enabled
+    fn in_table_anything_else(&mut self, token: Token) {
+        self.parse_error("foster-parented-content");
+        self.foster_parenting = true;
+        self.handle_in_body(token);
+        self.foster_parenting = false;
+    }
+
+    /// 13.2.6.4.10 The "in table text" insertion mode
+    fn handle_in_table_text(&mut self, token: Token) {
+        match token {
+            Token::Character('\0') => self.parse_error("unexpected-null-character"),
+            Token::Character(c) => self.pending_table_text.push(c),
+            _ => {
+                let text = std::mem::take(&mut self.pending_table_text);
+                if text.chars().any(|c| !is_whitespace(c)) {
+                    // Non-whitespace text is moved in front of the table
+                    self.foster_parenting = true;
+                    for c in text.chars() {
+                        self.handle_in_body(Token::Character(c));
+                    }
+                    self.flush_pending_text();
+                    self.foster_parenting = false;
+                } else {
+                    for c in text.chars() {
+                        self.insert_character(c);
+                    }
+                    self.flush_pending_text();
+                }
+                let mode = self.original_mode;
+                self.reprocess_in(mode, token);
+            }
+        }
+    }
+
+    /// 13.2.6.4.11 The "in caption" insertion mode
+    fn handle_in_caption(&mut self, token: Token) {
+        match &token {
+            Token::EndTag { name } if name == "caption" => {
+                self.close_caption();
+            }
+            Token::StartTag { name, .. } if TABLE_STRUCTURE_TAGS.contains(&name.as_str()) => {
+                if self.close_caption() {
+                    self.process_token(token);
+                }
+            }
+            Token::EndTag { name } if name == "table" => {
+                if self.close_caption() {
+                    self.process_token(token);
+                }
+            }
+            Token::EndTag { name }
+                if matches!(
+                    name.as_str(),
+                    "body" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr"
+                ) =>
+            {
+                self.parse_error("unexpected-end-tag");
+            }
+            _ => self.handle_in_body(token),
+        }
+    }
+
+    /// Close an open caption, returning false if there was none to close
+    fn close_caption(&mut self) -> bool {
+        if !self.has_in_table_scope(&["caption"]) {
+            self.parse_error("element-not-in-table-scope");
+            return false;
+        }
+        self.generate_implied_end_tags(None);
+        if self.current_tag() != "caption" {
+            self.parse_error("end-tag-too-early");
+        }
+        self.pop_until("caption");
+        self.clear_active_formatting_to_marker();
+        self.mode = InsertionMode::InTable;
+        true
+    }
+
+    /// 13.2.6.4.12 The "in column group" insertion mode
+    fn handle_in_column_group(&mut self, token: Token) {
+        match &token {
+            Token::Character(c) if is_whitespace(*c) => self.insert_character(*c),
+            Token::Comment(data) => self.insert_comment(data, None),
+            Token::Doctype { .. } => self.parse_error("unexpected-doctype"),
+            Token::StartTag { name, .. } if name == "html" => self.handle_in_body(token),
+            Token::StartTag { name, attributes, .. } if name == "col" => {
+                self.insert_void_element(name, attributes);
+            }
+            Token::EndTag { name } if name == "colgroup" => {
+                if self.current_tag() != "colgroup" {
+                    self.parse_error("unexpected-end-tag");
+                    return;
+                }
+                self.pop();
+                self.mode = InsertionMode::InTable;
+            }
+            Token::EndTag { name } if name == "col" => {
+                self.parse_error("unexpected-end-tag");
+            }
+            Token::StartTag { name, .. } | Token::EndTag { name } if name == "template" => {
+                self.handle_in_head(token)
+            }
+            Token::Eof => self.handle_in_body(token),
+            _ => {
+                if self.current_tag() != "colgroup" {
+                    self.parse_error("unexpected-token-in-column-group");
+                    return;
+                }
+                self.pop();
+                self.reprocess_in(InsertionMode::InTable, token);
+            }
+        }
+    }
+
+    /// 13.2.6.4.13 The "in table body" insertion mode
+    fn handle_in_table_body(&mut self, token: Token) {
+        match &token {
+            Token::StartTag { name, attributes, .. } if name == "tr" => {
+                self.clear_stack_back_to_table_body_context();
+                self.insert_html_element(name, attributes);
+                self.mode = InsertionMode::InRow;
+            }
+            Token::StartTag { name, .. } if matches!(name.as_str(), "th" | "td") => {
+                // Implied <tr>
+                self.parse_error("unexpected-start-tag");
+                self.clear_stack_back_to_table_body_context();
+                self.insert_html_element("tr", &[]);
+                self.reprocess_in(InsertionMode::InRow, token);
+            }
+            Token::EndTag { name } if matches!(name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if !self.has_in_table_scope(&[name.as_str()]) {
+                    self.parse_error("unexpected-end-tag");
+                    return;
+                }
+                self.clear_stack_back_to_table_body_context();
+                self.pop();
+                self.mode = InsertionMode::InTable;
+            }
+            Token::StartTag { name, .. }
+                if matches!(name.as_str(), "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead") =>
+            {
+                self.end_table_body_and_reprocess(token);
+            }
+            Token::EndTag { name } if name == "table" => self.end_table_body_and_reprocess(token),
+            Token::EndTag { name }
+                if matches!(
+                    name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th" | "tr"
+                ) =>
+            {
+                self.parse_error("unexpected-end-tag");
+            }
+            _ => self.handle_in_table(token),
+        }
+    }
+
+    fn end_table_body_and_reprocess(&mut self, token: Token) {
+        if !self.has_in_table_scope(&["tbody", "thead", "tfoot"]) {
+            self.parse_error("element-not-in-table-scope");
+            return;
+        }
+        self.clear_stack_back_to_table_body_context();
+        self.pop();
+        self.reprocess_in(InsertionMode::InTable, token);
+    }
+
+    /// 13.2.6.4.14 The "in row" insertion mode
+    fn handle_in_row(&mut self, token: Token) {
+        match &token {
+            Token::StartTag { name, attributes, .. } if matches!(name.as_str(), "th" | "td") => {
+                self.clear_stack_back_to_table_row_context();
+                self.insert_html_element(name, attributes);
+                self.insert_formatting_marker();
+                self.mode = InsertionMode::InCell;
+            }
+            Token::EndTag { name } if name == "tr" => {
+                self.close_row();
+            }
+            Token::StartTag { name, .. }
+                if matches!(
+                    name.as_str(),
+                    "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead" | "tr"
+                ) =>
+            {
+                if self.close_row() {
+                    self.process_token(token);
+                }
+            }
+            Token::EndTag { name } if name == "table" => {
+                if self.close_row() {
+                    self.process_token(token);
+                }
+            }
+            Token::EndTag { name } if matches!(name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if !self.has_in_table_scope(&[name.as_str()]) {
+                    self.parse_error("unexpected-end-tag");
+                    return;
+                }
+                if self.close_row() {
+                    self.process_token(token);
+                }
+            }
+            Token::EndTag { name }
+                if matches!(name.as_str(), "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th") =>
+            {
+                self.parse_error("unexpected-end-tag");
+            }
+            _ => self.handle_in_table(token),
+        }
+    }
+
+    /// Close an open table row, returning false if there was none to close
+    fn close_row(&mut self) -> bool {
+        if !self.has_in_table_scope(&["tr"]) {
+            self.parse_error("element-not-in-table-scope");
+            return false;
+        }
+        self.clear_stack_back_to_table_row_context();
+        self.pop();
+        self.mode = InsertionMode::InTableBody;
+        true
+    }
+
+    /// 13.2.6.4.15 The "in cell" insertion mode
+    fn handle_in_cell(&mut self, token: Token) {
+        match &token {
+            Token::EndTag { name } if matches!(name.as_str(), "td" | "th") => {
+                if !self.has_in_table_scope(&[name.as_str()]) {
+                    self.parse_error("unexpected-end-tag");
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                if self.current_tag() != name {
+                    self.parse_error("end-tag-too-early");
+                }
+                self.pop_until(name);
+                self.clear_active_formatting_to_marker();
+                self.mode = InsertionMode::InRow;
+            }
+            Token::StartTag { name, .. } if TABLE_STRUCTURE_TAGS.contains(&name.as_str()) => {
+                if !self.has_in_table_scope(&["td", "th"]) {
+                    self.parse_error("unexpected-start-tag");
+                    return;
+                }
+                self.close_cell();
+                self.process_token(token);
+            }
+            Token::EndTag { name } if matches!(name.as_str(), "body" | "caption" | "col" | "colgroup" | "html") => {
+                self.parse_error("unexpected-end-tag");
+            }
+            Token::EndTag { name } if matches!(name.as_str(), "table" | "tbody" | "tfoot" | "thead" | "tr") => {
+                if !self.has_in_table_scope(&[name.as_str()]) {
+                    self.parse_error("unexpected-end-tag");
+                    return;
+                }
+                self.close_cell();
+                self.process_token(token);
+            }
+            _ => self.handle_in_body(token),
+        }
+    }
+
+    /// Spec 13.2.6.4.15 "close the cell"
+    fn close_cell(&mut self) {
+        self.generate_implied_end_tags(None);
+        if !matches!(self.current_tag(), "td" | "th") {
+            self.parse_error("end-tag-too-early");
+        }
+        self.pop_until_one_of(&["td", "th"]);
+        self.clear_active_formatting_to_marker();
+        self.mode = InsertionMode::InRow;
+    }
+
+    /// 13.2.6.4.16 The "in select" insertion mode
+    fn handle_in_select(&mut self, token: Token) {
+        match &token {
+            Token::Character('\0') => self.parse_error("unexpected-null-character"),
+            Token::Character(c) => self.insert_character(*c),
+            Token::Comment(data) => self.insert_comment(data, None),
+            Token::Doctype { .. } => self.parse_error("unexpected-doctype"),
+            Token::StartTag { name, attributes, .. } => match name.as_str() {
+                "html" => self.handle_in_body(token),
+                "option" => {
+                    if self.current_tag() == "option" {
+                        self.pop();
+                    }
+                    self.insert_html_element(name, attributes);
+                }
+                "optgroup" => {
+                    if self.current_tag() == "option" {
+                        self.pop();
+                    }
+                    if self.current_tag() == "optgroup" {
+                        self.pop();
+                    }
+                    self.insert_html_element(name, attributes);
+                }
+                "hr" => {
+                    if self.current_tag() == "option" {
+                        self.pop();
+                    }
+                    if self.current_tag() == "optgroup" {
+                        self.pop();
+                    }
+                    self.insert_void_element(name, attributes);
+                }
+                "select" => {
+                    self.parse_error("unexpected-start-tag");
+                    if self.has_in_select_scope("select") {
+                        self.pop_until("select");
+                        self.reset_insertion_mode();
+                    }
+                }
+                "input" | "keygen" | "textarea" => {
+                    self.parse_error("unexpected-start-tag");
+                    if !self.has_in_select_scope("select") {
+                        return;
+                    }
+                    self.pop_until("select");
+                    self.reset_insertion_mode();
+                    self.process_token(token);
+                }
+                "script" | "template" => self.handle_in_head(token),
+                _ => self.parse_error("unexpected-start-tag"),
+            },
+            Token::EndTag { name } => match name.as_str() {
+                "optgroup" => {
+                    let len = self.open_elements.len();
+                    if self.current_tag() == "option"
+                        && len >= 2
+                        && self.tag_of(self.open_elements[len - 2]) == "optgroup"
+                    {
+                        self.pop();
+                    }
+                    if self.current_tag() == "optgroup" {
+                        self.pop();
+                    }
+                }
+                "option" => {
+                    if self.current_tag() == "option" {
+                        self.pop();
+                    }
+                }
+                "select" => {
+                    if !self.has_in_select_scope("select") {
+                        self.parse_error("unexpected-end-tag");
+                        return;
+                    }
+                    self.pop_until("select");
+                    self.reset_insertion_mode();
+                }
+                "template" => self.handle_in_head(token),
+                _ => self.parse_error("unexpected-end-tag"),
+            },
+            Token::Eof => self.handle_in_body(token),
+        }
+    }
+
+    /// 13.2.6.4.17 The "in select in table" insertion mode
+    fn handle_in_select_in_table(&mut self, token: Token) {
+        const TABLE_TAGS: &[&str] = &["caption", "table", "tbody", "tfoot", "thead", "tr", "td", "th"];
+        match &token {
+            Token::StartTag { name, .. } if TABLE_TAGS.contains(&name.as_str()) => {
+                self.parse_error("unexpected-start-tag");
+                self.pop_until("select");
+                self.reset_insertion_mode();
+                self.process_token(token);
+            }
+            Token::EndTag { name } if TABLE_TAGS.contains(&name.as_str()) => {
+                self.parse_error("unexpected-end-tag");
+                if !self.has_in_table_scope(&[name.as_str()]) {
+                    return;
+                }
+                self.pop_until("select");
+                self.reset_insertion_mode();
+                self.process_token(token);
+            }
+            _ => self.handle_in_select(token),
+        }
+    }
+
+    /// 13.2.6.4.18 The "in template" insertion mode
+    fn handle_in_template(&mut self, token: Token) {
+        let mode = match &token {
+            Token::Character(_) | Token::Comment(_) | Token::Doctype { .. } => {
+                self.handle_in_body(token);
+                return;
+            }
+            Token::StartTag { name, .. } => match name.as_str() {
+                "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style"
+                | "template" | "title" => {
+                    self.handle_in_head(token);
+                    return;
+                }
+                "caption" | "colgroup" | "tbody" | "tfoot" | "thead" => InsertionMode::InTable,
+                "col" => InsertionMode::InColumnGroup,
+                "tr" => InsertionMode::InTableBody,
+                "td" | "th" => InsertionMode::InRow,
+                _ => InsertionMode::InBody,
+            },
+            Token::EndTag { name } if name == "template" => {
+                self.handle_in_head(token);
+                return;
+            }
+            Token::EndTag { .. } => {
+                self.parse_error("unexpected-end-tag");
+                return;
+            }
+            Token::Eof => {
+                if !self.open_elements.iter().any(|&id| self.tag_of(id) == "template") {
+                    return;
+                }
+                self.parse_error("eof-in-template");
+                self.pop_until("template");
+                self.clear_active_formatting_to_marker();
+                self.template_modes.pop();
+                self.reset_insertion_mode();
+                self.process_token(token);
+                return;
+            }
+        };
+        if let Some(top) = self.template_modes.last_mut() {
+            *top = mode;
+        }
+        self.reprocess_in(mode, token);
+    }
+
+    /// 13.2.6.4.19 The "after body" insertion mode
+    fn handle_after_body(&mut self, token: Token) {
+        match &token {
+            Token::Character(c) if is_whitespace(*c) => self.handle_in_body(token),
+            Token::Comment(data) => {
+                let html = self.open_elements[0];
+                self.insert_comment(data, Some(html));
+            }
+            Token::Doctype { .. } => self.parse_error("unexpected-doctype"),
+            Token::StartTag { name, .. } if name == "html" => self.handle_in_body(token),
+            Token::EndTag { name } if name == "html" => {
+                self.mode = InsertionMode::AfterAfterBody;
+            }
+            Token::Eof => {}
+            _ => {
+                self.parse_error("unexpected-content-after-body");
+                self.reprocess_in(InsertionMode::InBody, token);
+            }
+        }
+    }
+
+    /// 13.2.6.4.22 The "after after body" insertion mode
+    fn handle_after_after_body(&mut self, token: Token) {
+        match &token {
+            Token::Comment(data) => self.insert_comment(data, Some(self.document)),
+            Token::Doctype { .. } => self.parse_error("unexpected-doctype"),
+            Token::Character(c) if is_whitespace(*c) => self.handle_in_body(token),
+            Token::StartTag { name, .. } if name == "html" => self.handle_in_body(token),
+            Token::Eof => {}
+            _ => {
+                self.parse_error("unexpected-content-after-html");
+                self.reprocess_in(InsertionMode::InBody, token);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod debug_tests {
+    use super::*;
+
+    fn print_dom_tree(dom: &Dom, node_id: NodeId, indent: usize) {
+        let node = &dom.nodes[node_id];
+        let prefix = "  ".repeat(indent);
+
+        match &node.node_type {
+            crate::dom::NodeType::Element(el) => {
+                let attrs: Vec<String> = el.attributes.iter()
+                    .map(|(k, v)| format!("{}={}", k, v))
+                    .collect();
+                if attrs.is_empty() {
+                    eprintln!("{}<{}>", prefix, el.tag_name);
+                } else {
+                    eprintln!("{}<{} {}>", prefix, el.tag_name, attrs.join(" "));
+                }
+            }
+            crate::dom::NodeType::Text(text) => {
+                let text = text.trim();
+                if !text.is_empty() {
+                    let display = if text.len() > 40 {
+                        format!("{}...", &text[..40])
+                    } else {
+                        text.to_string()
+                    };
+                    eprintln!("{}TEXT: {:?}", prefix, display);
+                }
+            }
+            other => eprintln!("{}{:?}", prefix, other),
+        }
+
+        for &child_id in &node.children {
+            print_dom_tree(dom, child_id, indent + 1);
+        }
+    }
+
+    #[test]
+    fn test_parse_old_cern_html() {
+        let html = r#"<TITLE>What is Hypertext?</TITLE>
+<NEXTID 20>
+<H1>What is HyperText</H1>Hypertext is text which is not constrained to be linear.<P>
+Hypertext is text which contains <A NAME=0 HREF=Terms.html#link>links</A> to other texts.<P>
+See also:
+<UL>
+<LI><A NAME=2 HREF=Terms.html>A list of terms</A> used in hypertext litterature.
+<LI><A NAME=19 HREF=../Conferences/Overview.html>Conferences</A>
+</UL>"#;
+
+        eprintln!("\n=== PARSING OLD CERN HTML ===");
+        let parser = HtmlParser::new(html);
+        let dom = parser.parse();
+
+        eprintln!("\n=== ACTUAL DOM STRUCTURE ===");
+        print_dom_tree(&dom, dom.root(), 0);
+
+        eprintln!("\n=== NODE COUNT: {} ===", dom.nodes.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serialize the <body> subtree into compact markup for assertions
+    fn body_markup(html: &str) -> String {
+        let dom = HtmlParser::new(html).parse();
+        let body = find_tag(&dom, dom.root(), "body").expect("document has a body");
+        let mut out = String::new();
+        for &child in &dom.nodes[body].children {
+            write_node(&dom, child, &mut out);
+        }
+        out
+    }
+
+    fn find_tag(dom: &Dom, id: NodeId, tag: &str) -> Option<NodeId> {
+        if let NodeType::Element(el) = &dom.nodes[id].node_type {
+            if el.tag_name == tag {
+                return Some(id);
+            }
+        }
+        dom.nodes[id].children.iter().find_map(|&child| find_tag(dom, child, tag))
+    }
+
+    fn write_node(dom: &Dom, id: NodeId, out: &mut String) {
+        match &dom.nodes[id].node_type {
+            NodeType::Element(el) => {
+                out.push_str(&format!("<{}>", el.tag_name));
+                for &child in &dom.nodes[id].children {
+                    write_node(dom, child, out);
+                }
+                out.push_str(&format!("</{}>", el.tag_name));
+            }
+            NodeType::Text(text) => out.push_str(text),
+            NodeType::Comment(data) => out.push_str(&format!("<!--{}-->", data)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_implied_tbody() {
+        assert_eq!(
+            body_markup("<table><tr><td>a</td></tr></table>"),
+            "<table><tbody><tr><td>a</td></tr></tbody></table>"
+        );
+    }
+
+    #[test]
+    fn test_cells_close_each_other() {
+        assert_eq!(
+            body_markup("<table><td>x<td>y</table>"),
+            "<table><tbody><tr><td>x</td><td>y</td></tr></tbody></table>"
+        );
+    }
+
+    #[test]
+    fn test_rows_close_each_other() {
+        assert_eq!(
+            body_markup("<table><tr><td>1<tr><td>2</table>"),
+            "<table><tbody><tr><td>1</td></tr><tr><td>2</td></tr></tbody></table>"
+        );
+    }
+
+    #[test]
+    fn test_stray_text_is_foster_parented() {
+        assert_eq!(
+            body_markup("<table>foo<tr><td>bar</td></tr></table>"),
+            "foo<table><tbody><tr><td>bar</td></tr></tbody></table>"
+        );
+    }
+
+    #[test]
+    fn test_stray_element_is_foster_parented() {
+        assert_eq!(
+            body_markup("<table><tr><div>moved</div><td>cell</td></tr></table>"),
+            "<div>moved</div><table><tbody><tr><td>cell</td></tr></tbody></table>"
+        );
+    }
+
+    #[test]
+    fn test_whitespace_in_table_stays_put() {
+        assert_eq!(
+            body_markup("<table>\n  <tr>\n    <td>a</td>\n  </tr>\n</table>"),
+            "<table><tbody><tr><td>a</td></tr></tbody></table>"
+        );
+    }
+
+    #[test]
+    fn test_end_table_closes_open_cell_contents() {
+        assert_eq!(
+            body_markup("<table><tr><td>a<p>b</table>c"),
+            "<table><tbody><tr><td>a<p>b</p></td></tr></tbody></table>c"
+        );
+    }
+
+    #[test]
+    fn test_table_closes_paragraph() {
+        assert_eq!(
+            body_markup("<!DOCTYPE html><p>para<table><tr><td>x</table>"),
+            "<p>para</p><table><tbody><tr><td>x</td></tr></tbody></table>"
+        );
+        // Quirks mode keeps the table inside the paragraph
+        assert_eq!(
+            body_markup("<p>para<table><tr><td>x</table>"),
+            "<p>para<table><tbody><tr><td>x</td></tr></tbody></table></p>"
+        );
+    }
+
+    #[test]
+    fn test_caption_closed_by_cell() {
+        assert_eq!(
+            body_markup("<table><caption>cap<td>cell</table>"),
+            "<table><caption>cap</caption><tbody><tr><td>cell</td></tr></tbody></table>"
+        );
+    }
+
+    #[test]
+    fn test_implied_colgroup() {
+        assert_eq!(
+            body_markup("<table><col><tr><td>1</table>"),
+            "<table><colgroup><col></col></colgroup><tbody><tr><td>1</td></tr></tbody></table>"
+        );
+    }
+
+    #[test]
+    fn test_nested_table_in_cell() {
+        assert_eq!(
+            body_markup("<table><tr><td><table><tr><td>inner</table>outer</td></tr></table>"),
+            "<table><tbody><tr><td><table><tbody><tr><td>inner</td></tr></tbody></table>outer</td></tr></tbody></table>"
+        );
+    }
+
+    #[test]
+    fn test_table_start_tag_closes_open_table() {
+        assert_eq!(
+            body_markup("<table><tr><td>1</td></tr><table><tr><td>2</td></tr></table>"),
+            "<table><tbody><tr><td>1</td></tr></tbody></table><table><tbody><tr><td>2</td></tr></tbody></table>"
+        );
+    }
+
+    #[test]
+    fn test_stray_cell_tags_outside_table_are_ignored() {
+        assert_eq!(body_markup("<div>a</td><tr>b</div>"), "<div>ab</div>");
+    }
+
+    #[test]
+    fn test_select_inside_table_cell() {
+        assert_eq!(
+            body_markup("<table><tr><td><select><option>x<td>y</table>"),
+            "<table><tbody><tr><td><select><option>x</option></select></td><td>y</td></tr></tbody></table>"
+        );
+    }
+
+    #[test]
+    fn test_script_with_escaped_markup() {
+        let html = r#"<script>var s = "<!--<script>x</script>-->";</script><p>after</p>"#;
+        assert_eq!(
+            body_markup(&format!("<body>{}", html)),
+            r#"<script>var s = "<!--<script>x</script>-->";</script><p>after</p>"#
+        );
+    }
+
+    #[test]
+    fn test_plaintext_swallows_markup() {
+        assert_eq!(body_markup("<plaintext><b>x</b>"), "<plaintext><b>x</b></plaintext>");
+    }
+
+    #[test]
+    fn test_misnested_formatting_elements() {
+        assert_eq!(body_markup("<b><i>x</b>y</i>"), "<b><i>x</i></b><i>y</i>");
+    }
+
+    #[test]
+    fn test_formatting_element_around_block() {
+        assert_eq!(body_markup("<b>1<p>2</b>3</p>"), "<b>1</b><p><b>2</b>3</p>");
+    }
+
+    #[test]
+    fn test_anchor_split_by_paragraph() {
+        assert_eq!(body_markup("<a><p>x</a>y"), "<a></a><p><a>x</a>y</p>");
+    }
+
+    #[test]
+    fn test_nested_anchor_closes_previous() {
+        assert_eq!(body_markup("<a href=1>a<a href=2>b</a>"), "<a>a</a><a>b</a>");
+    }
+
+    #[test]
+    fn test_formatting_reconstructed_in_next_paragraph() {
+        assert_eq!(
+            body_markup("<p><b>x</p><p>y</p>"),
+            "<p><b>x</b></p><p><b>y</b></p>"
+        );
+    }
+
+    #[test]
+    fn test_noahs_ark_limits_reconstruction() {
+        assert_eq!(
+            body_markup("<p><b><b><b><b>x</p><p>y</p>"),
+            "<p><b><b><b><b>x</b></b></b></b></p><p><b><b><b>y</b></b></b></p>"
+        );
+    }
+
+    #[test]
+    fn test_table_cell_is_formatting_boundary() {
+        assert_eq!(
+            body_markup("<b><table><td>x</td></table>y"),
+            "<b><table><tbody><tr><td>x</td></tr></tbody></table>y</b>"
+        );
+    }
+
+    #[test]
+    fn test_unclosed_font_spans_text() {
+        assert_eq!(
+            body_markup("<font>a<div>b</font>c</div>"),
+            "<font>a</font><div><font>b</font>c</div>"
+        );
+    }
+
+    fn element<'a>(dom: &'a Dom, tag: &str) -> &'a ElementData {
+        let id = find_tag(dom, dom.root(), tag).unwrap_or_else(|| panic!("no <{}>", tag));
+        match &dom.nodes[id].node_type {
+            NodeType::Element(el) => el,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_inline_svg_keeps_case_and_namespace() {
+        let html = "<svg viewbox=\"0 0 10 10\"><lineargradient gradientunits=\"userSpaceOnUse\"/><path d=\"M0\"/></svg>";
+        let dom = HtmlParser::new(html).parse();
+        let svg = element(&dom, "svg");
+        assert_eq!(svg.namespace, Namespace::Svg);
+        assert_eq!(svg.attributes, vec![("viewBox".to_string(), "0 0 10 10".to_string())]);
+        let gradient = element(&dom, "linearGradient");
+        assert_eq!(gradient.namespace, Namespace::Svg);
+        assert_eq!(gradient.attributes[0].0, "gradientUnits");
+        assert_eq!(body_markup(html), "<svg><linearGradient></linearGradient><path></path></svg>");
+    }
+
+    #[test]
+    fn test_foreign_object_is_html_integration_point() {
+        let html = "<svg><foreignObject><p>hi<b>there</b></p></foreignObject><title>t</title></svg>";
+        assert_eq!(
+            body_markup(html),
+            "<svg><foreignObject><p>hi<b>there</b></p></foreignObject><title>t</title></svg>"
+        );
+        let dom = HtmlParser::new(html).parse();
+        assert_eq!(element(&dom, "p").namespace, Namespace::Html);
+        assert_eq!(element(&dom, "title").namespace, Namespace::Svg);
+    }
+
+    #[test]
+    fn test_html_start_tag_breaks_out_of_svg() {
+        assert_eq!(
+            body_markup("<svg><g><circle/><p>after</p>"),
+            "<svg><g><circle></circle></g></svg><p>after</p>"
+        );
+        // <font> only breaks out with presentational attributes
+        assert_eq!(
+            body_markup("<svg><font>x</font><font color=red>y"),
+            "<svg><font>x</font></svg><font>y</font>"
+        );
+    }
+
+    #[test]
+    fn test_mathml_integration_points() {
+        let html = "<math definitionurl=u><mi>x<b>bold</b></mi>\
+                    <annotation-xml encoding=\"text/html\"><div>d</div></annotation-xml></math>";
+        let dom = HtmlParser::new(html).parse();
+        let math = element(&dom, "math");
+        assert_eq!(math.namespace, Namespace::MathMl);
+        assert_eq!(math.attributes[0].0, "definitionURL");
+        assert_eq!(element(&dom, "b").namespace, Namespace::Html);
+        assert_eq!(element(&dom, "div").namespace, Namespace::Html);
+        assert_eq!(element(&dom, "annotation-xml").namespace, Namespace::MathMl);
+    }
+
+    #[test]
+    fn test_cdata_only_in_foreign_content() {
+        assert_eq!(body_markup("<svg><![CDATA[a<b]]></svg>"), "<svg>a<b</svg>");
+        // In HTML content CDATA is a bogus comment
+        assert_eq!(body_markup("<div><![CDATA[x]]></div>"), "<div><!--[CDATA[x]]--></div>");
+    }
+
+    #[test]
+    fn test_svg_end_tag_case_and_self_closing() {
+        let (dom, errors) = HtmlParser::new("<!DOCTYPE html><svg><clipPath/></svg><math/><p>x</p>").parse_with_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(element(&dom, "clipPath").namespace, Namespace::Svg);
+        assert_eq!(body_markup("<svg><foreignObject></FOREIGNOBJECT><g></svg>text"), "<svg><foreignObject></foreignObject><g></g></svg>text");
+    }
+
+    fn fragment_markup(context_tag: &str, html: &str) -> String {
+        let mut dom = Dom::new();
+        let context = dom.create_element(context_tag, vec![], None);
+        let inserted = HtmlParser::parse_fragment(&mut dom, context, html);
+        assert_eq!(inserted, dom.nodes[context].children);
+        let mut out = String::new();
+        for &child in &inserted {
+            write_node(&dom, child, &mut out);
+        }
+        out
+    }
+
+    #[test]
+    fn test_fragment_in_body_context() {
+        assert_eq!(fragment_markup("div", "<p>a<b>b</p>c"), "<p>a<b>b</b></p><b>c</b>");
+        // Document-level tags are dropped
+        assert_eq!(fragment_markup("div", "<html><body>x"), "x");
+    }
+
+    #[test]
+    fn test_fragment_appends_to_existing_children() {
+        let mut dom = HtmlParser::new("<ul><li>one</li></ul>").parse();
+        let ul = find_tag(&dom, dom.root(), "ul").unwrap();
+        let inserted = HtmlParser::parse_fragment(&mut dom, ul, "<li>two<li>three");
+        assert_eq!(inserted.len(), 2);
+        assert_eq!(dom.nodes[ul].children.len(), 3);
+        assert!(inserted.iter().all(|&id| dom.nodes[id].parent == Some(ul)));
+    }
+
+    #[test]
+    fn test_fragment_table_contexts() {
+        assert_eq!(
+            fragment_markup("table", "<tr><td>x</td></tr>"),
+            "<tbody><tr><td>x</td></tr></tbody>"
+        );
+        assert_eq!(fragment_markup("tr", "<td>x<td>y"), "<td>x</td><td>y</td>");
+        assert_eq!(fragment_markup("select", "<option>a<option>b"), "<option>a</option><option>b</option>");
+    }
+
+    #[test]
+    fn test_fragment_tokenizer_state_from_context() {
+        assert_eq!(fragment_markup("title", "a</title><b>"), "a</title><b>");
+        assert_eq!(fragment_markup("script", "if (a<b) {}"), "if (a<b) {}");
+        assert_eq!(fragment_markup("textarea", "&lt;b&gt;"), "<b>");
+    }
+
+    #[test]
+    fn test_fragment_in_svg_context() {
+        let mut dom = HtmlParser::new("<svg></svg>").parse();
+        let svg = find_tag(&dom, dom.root(), "svg").unwrap();
+        let inserted = HtmlParser::parse_fragment(&mut dom, svg, "<lineargradient/><foreignObject><p>x</p></foreignObject>");
+        assert_eq!(inserted.len(), 2);
+        assert_eq!(element(&dom, "linearGradient").namespace, Namespace::Svg);
+        assert_eq!(element(&dom, "p").namespace, Namespace::Html);
+    }
+
+    #[test]
+    fn test_fragment_inside_form_ignores_nested_form() {
+        let mut dom = HtmlParser::new("<form><div></div></form>").parse();
+        let div = find_tag(&dom, dom.root(), "div").unwrap();
+        let inserted = HtmlParser::parse_fragment(&mut dom, div, "<form><input></form>");
+        let mut out = String::new();
+        for &child in &inserted {
+            write_node(&dom, child, &mut out);
+        }
+        assert_eq!(out, "<input></input>");
+    }
+
+    #[test]
+    fn test_streaming_matches_whole_document() {
+        let html = "<!DOCTYPE html><title>t</title><p>one <b>two</b> three\r\n<table><tr><td>cell</table>\
+                    <ul><li>a<li>b</ul><svg viewbox=\"0 0 1 1\"><path/></svg>caf\u{e9}";
+        let expected = body_markup(html);
+        for chunk_size in [1, 5, 100] {
+            let mut parser = HtmlParser::new_streaming(Some("text/html; charset=utf-8"));
+            for chunk in html.as_bytes().chunks(chunk_size) {
+                parser.feed(chunk);
+            }
+            let dom = parser.finish();
+            let body = find_tag(&dom, dom.root(), "body").unwrap();
+            let mut out = String::new();
+            for &child in &dom.nodes[body].children {
+                write_node(&dom, child, &mut out);
+            }
+            assert_eq!(out, expected, "chunk size {}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn test_partial_dom_is_observable() {
+        let mut parser = HtmlParser::new_streaming(None);
+        let first = format!("<!DOCTYPE html><body><h1>Title</h1><p>{}", "x".repeat(2000));
+        parser.feed(first.as_bytes());
+        let dom = parser.dom();
+        let h1 = find_tag(dom, dom.root(), "h1").expect("h1 is built before the document ends");
+        let mut out = String::new();
+        write_node(dom, h1, &mut out);
+        assert_eq!(out, "<h1>Title</h1>");
+        assert!(find_tag(dom, dom.root(), "footer").is_none());
+
+        parser.feed(b"</p><footer>end</footer>");
+        let dom = parser.finish();
+        assert!(find_tag(&dom, dom.root(), "footer").is_some());
+    }
+
+    #[test]
+    fn test_document_node_holds_doctype_and_comments() {
+        let dom = HtmlParser::new("<!-- top --><!DOCTYPE html><html><body>a<!-- in body -->b</body></html><!-- end -->").parse();
+        let document = dom.root();
+        assert!(matches!(dom.nodes[document].node_type, NodeType::Document));
+        let kinds: Vec<String> = dom.nodes[document]
+            .children
+            .iter()
+            .map(|&id| match &dom.nodes[id].node_type {
+                NodeType::Comment(data) => format!("comment:{}", data),
+                NodeType::Doctype { name, .. } => format!("doctype:{}", name),
+                NodeType::Element(el) => el.tag_name.clone(),
+                other => format!("{:?}", other),
+            })
+            .collect();
+        assert_eq!(kinds, vec!["comment: top ", "doctype:html", "html", "comment: end "]);
+        assert_eq!(body_markup("a<!-- in body -->b"), "a<!-- in body -->b");
+    }
+
+    #[test]
+    fn test_comment_after_body_goes_to_html_element() {
+        let dom = HtmlParser::new("<!DOCTYPE html><body></body><!-- x -->").parse();
+        let html = find_tag(&dom, dom.root(), "html").unwrap();
+        let last = *dom.nodes[html].children.last().unwrap();
+        assert!(matches!(&dom.nodes[last].node_type, NodeType::Comment(data) if data == " x "));
+    }
+
+    #[test]
+    fn test_quirks_mode_from_doctype() {
+        let mode = |html: &str| HtmlParser::new(html).parse().quirks_mode;
+        assert_eq!(mode("<!DOCTYPE html>"), QuirksMode::NoQuirks);
+        assert_eq!(mode("<p>no doctype"), QuirksMode::Quirks);
+        assert_eq!(mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.0 Transitional//EN\">"), QuirksMode::Quirks);
+        assert_eq!(mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\">"), QuirksMode::Quirks);
+        assert_eq!(
+            mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\" \"http://www.w3.org/TR/html4/loose.dtd\">"),
+            QuirksMode::LimitedQuirks
+        );
+        assert_eq!(
+            mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Transitional//EN\" \"x\">"),
+            QuirksMode::LimitedQuirks
+        );
+        assert_eq!(mode("<!DOCTYPE svg>"), QuirksMode::Quirks);
+        assert_eq!(mode("<!DOCTYPE>"), QuirksMode::Quirks);
+    }
+
+    #[test]
+    fn test_parse_with_errors() {
+        let (dom, errors) = HtmlParser::new("<!DOCTYPE html><p>a</b>\n<div/>&amp\n</span>").parse_with_errors();
+        assert!(find_tag(&dom, dom.root(), "div").is_some());
+        let found: Vec<(&str, usize, usize)> = errors.iter().map(|e| (e.code, e.line, e.column)).collect();
+        assert_eq!(
+            found,
+            vec![
+                ("unexpected-end-tag", 1, 23),
+                ("non-void-html-element-start-tag-with-trailing-solidus", 2, 6),
+                ("missing-semicolon-after-character-reference", 2, 10),
+                ("unexpected-end-tag", 3, 7),
+                ("eof-in-element", 3, 8),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_well_formed_document_has_no_errors() {
+        let html = "<!DOCTYPE html><html><head><title>t</title></head><body><p>x</p></body></html>";
+        let (_, errors) = HtmlParser::new(html).parse_with_errors();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    #[test]
+    fn test_from_bytes_decodes_before_tokenizing() {
+        let parser = HtmlParser::from_bytes(b"<meta charset=windows-1252><p>caf\xE9 \x80</p>", None);
+        assert_eq!(parser.encoding(), encoding_rs::WINDOWS_1252);
+        let dom = parser.parse();
+        let p = find_tag(&dom, dom.root(), "p").unwrap();
+        let mut out = String::new();
+        write_node(&dom, p, &mut out);
+        assert_eq!(out, "<p>caf\u{e9} \u{20ac}</p>");
+    }
+
+    #[test]
+    fn test_from_resource_uses_content_type() {
+        let resource = FetchedResource {
+            url: "http://example.com/".to_string(),
+            data: b"\x82\xa0".to_vec(),
+            content_type: "text/html; charset=Shift_JIS".to_string(),
+            headers: Vec::new(),
+            from_cache: true,
+        };
+        let parser = HtmlParser::from_resource(&resource);
+        assert_eq!(parser.encoding(), encoding_rs::SHIFT_JIS);
+        let dom = parser.parse();
+        let body = find_tag(&dom, dom.root(), "body").unwrap();
+        let mut out = String::new();
+        write_node(&dom, body, &mut out);
+        assert_eq!(out, "<body>\u{3042}</body>");
+    }
+
+    #[test]
+    fn test_template_in_head() {
+        let dom = HtmlParser::new("<head><template><p>a</template><title>t</title></head><body>b").parse();
+        let head = find_tag(&dom, dom.root(), "head").unwrap();
+        let mut out = String::new();
+        write_node(&dom, head, &mut out);
+        assert_eq!(out, "<head><template><p>a</p></template><title>t</title></head>");
+        assert_eq!(body_markup("<head><template><p>a</template></head><body>b"), "b");
+    }
+
+    #[test]
+    fn test_template_in_body() {
+        assert_eq!(
+            body_markup("<div><template>x<b>y</template>z</div>"),
+            "<div><template>x<b>y</b></template>z</div>"
+        );
+        assert_eq!(body_markup("<body><template><template>a</template>b"), "<template><template>a</template>b</template>");
+    }
+
+    #[test]
+    fn test_template_in_table() {
+        assert_eq!(
+            body_markup("<table><template><tr><td>a</td></tr></template></table>"),
+            "<table><template><tr><td>a</td></tr></template></table>"
+        );
+        assert_eq!(
+            body_markup("<table><tr><template><td>a</template><td>b</table>"),
+            "<table><tbody><tr><template><td>a</td></template><td>b</td></tr></tbody></table>"
+        );
+    }
+
+    #[test]
+    fn test_template_in_select() {
+        assert_eq!(
+            body_markup("<select><template><option>a</template><option>b</select>"),
+            "<select><template><option>a</option></template><option>b</option></select>"
+        );
     }
 }
@@ -0,0 +1,379 @@
+// Math functions: calc(), min(), max() and clamp()
+// Spec Reference: https://www.w3.org/TR/css-values-4/#math
+//
+// Expressions are parsed into a tree when the declaration is parsed and
+// evaluated when a length is needed, since percentages, font-relative and
+// viewport units are only known then.
+//
+// Status:
+// ✅ calc() with + - * / and parentheses, nested math functions
+// ✅ min(), max() and clamp()
+// ✅ Lengths, percentages and numbers, with type checking (spec 10.9)
+// ❌ Other math functions (round(), mod(), abs(), trigonometry...) and
+//    constants (pi, e, infinity)
+// ❌ Angles, times and other non-length types
+
+use std::fmt;
+
+use super::values::{Length, LengthContext, LengthUnit};
+use crate::parser::css::{ComponentValue, CssToken};
+
+/// A math function's expression tree, in the spec's internal representation
+/// (spec 10.8): subtraction is a negated sum term, division an inverted factor
+#[derive(Debug, Clone, PartialEq)]
+pub enum MathExpr {
+    Number(f32),
+    Length(Length),
+    Percentage(f32),
+    Sum(Vec<MathExpr>),
+    Negate(Box<MathExpr>),
+    Product(Vec<MathExpr>),
+    Invert(Box<MathExpr>),
+    Min(Vec<MathExpr>),
+    Max(Vec<MathExpr>),
+    Clamp(Box<MathExpr>, Box<MathExpr>, Box<MathExpr>),
+}
+
+/// The type of an evaluated expression; percentages resolve to lengths
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Number,
+    Length,
+}
+
+impl MathExpr {
+    /// Parse a math function from its name and arguments; None if it isn't
+    /// one or its syntax is invalid
+    pub fn parse_function(name: &str, arguments: &[ComponentValue]) -> Option<MathExpr> {
+        let name = name.to_ascii_lowercase();
+        if !matches!(name.as_str(), "calc" | "min" | "max" | "clamp") {
+            return None;
+        }
+        let items: Vec<&ComponentValue> = arguments.iter().collect();
+        let arguments: Vec<MathExpr> = items
+            .split(|v| matches!(v, ComponentValue::Token(CssToken::Comma)))
+            .map(parse_calc_sum)
+            .collect::<Option<_>>()?;
+        match (name.as_str(), arguments.len()) {
+            ("calc", 1) => arguments.into_iter().next(),
+            ("min", 1..) => Some(MathExpr::Min(arguments)),
+            ("max", 1..) => Some(MathExpr::Max(arguments)),
+            ("clamp", 3) => {
+                let [min, value, max] = <[MathExpr; 3]>::try_from(arguments).ok()?;
+                Some(MathExpr::Clamp(Box::new(min), Box::new(value), Box::new(max)))
+            }
+            _ => None,
+        }
+    }
+
+    /// The value in pixels, with percentages taken of `percent_basis`. Plain
+    /// numbers count as pixels, like unitless lengths elsewhere. None if the
+    /// expression mixes incompatible types.
+    pub fn to_px(&self, context: &LengthContext, percent_basis: f32) -> Option<f32> {
+        self.evaluate(context, percent_basis).map(|(value, _)| value)
+    }
+
+    fn evaluate(&self, context: &LengthContext, percent_basis: f32) -> Option<(f32, Kind)> {
+        let evaluate_all = |terms: &[MathExpr]| -> Option<Vec<(f32, Kind)>> {
+            terms.iter().map(|term| term.evaluate(context, percent_basis)).collect()
+        };
+        // Sums and comparisons need every argument to have the same type
+        let same_kind = |values: &[(f32, Kind)]| -> Option<Kind> {
+            let kind = values.first()?.1;
+            values.iter().all(|(_, k)| *k == kind).then_some(kind)
+        };
+        match self {
+            MathExpr::Number(n) => Some((*n, Kind::Number)),
+            MathExpr::Length(length) => Some((length.to_px(context), Kind::Length)),
+            MathExpr::Percentage(p) => Some((percent_basis * p / 100.0, Kind::Length)),
+            MathExpr::Negate(inner) => inner.evaluate(context, percent_basis).map(|(value, kind)| (-value, kind)),
+            MathExpr::Invert(inner) => match inner.evaluate(context, percent_basis)? {
+                (value, Kind::Number) => Some((1.0 / value, Kind::Number)),
+                _ => None,
+            },
+            MathExpr::Sum(terms) => {
+                let values = evaluate_all(terms)?;
+                Some((values.iter().map(|(value, _)| value).sum(), same_kind(&values)?))
+            }
+            MathExpr::Product(factors) => {
+                let values = evaluate_all(factors)?;
+                // At most one factor may be a length
+                let kind = match values.iter().filter(|(_, kind)| *kind == Kind::Length).count() {
+                    0 => Kind::Number,
+                    1 => Kind::Length,
+                    _ => return None,
+                };
+                Some((values.iter().map(|(value, _)| value).product(), kind))
+            }
+            MathExpr::Min(arguments) => {
+                let values = evaluate_all(arguments)?;
+                Some((values.iter().map(|(value, _)| *value).fold(f32::INFINITY, f32::min), same_kind(&values)?))
+            }
+            MathExpr::Max(arguments) => {
+                let values = evaluate_all(arguments)?;
+                Some((values.iter().map(|(value, _)| *value).fold(f32::NEG_INFINITY, f32::max), same_kind(&values)?))
+            }
+            MathExpr::Clamp(min, value, max) => {
+                let values: Vec<(f32, Kind)> = [min, value, max]
+                    .iter()
+                    .map(|expr| expr.evaluate(context, percent_basis))
+                    .collect::<Option<_>>()?;
+                let kind = same_kind(&values)?;
+                let [(min, _), (value, _), (max, _)] = values[..] else {
+                    return None;
+                };
+                // The minimum wins if it is larger than the maximum (spec 10.3)
+                Some((value.min(max).max(min), kind))
+            }
+        }
+    }
+}
+
+/// `<calc-sum>`: products joined by `+` and `-`
+fn parse_calc_sum(items: &[&ComponentValue]) -> Option<MathExpr> {
+    // `+` and `-` need whitespace on both sides, so `1px+ 2px` is invalid
+    let is_whitespace = |i: usize| items.get(i).is_some_and(|v| v.is_whitespace());
+    let unspaced_operator = items.iter().enumerate().any(|(i, item)| {
+        matches!(item, ComponentValue::Token(CssToken::Delim('+' | '-')))
+            && !(i > 0 && is_whitespace(i - 1) && is_whitespace(i + 1))
+    });
+    if unspaced_operator {
+        return None;
+    }
+    let mut terms = Vec::new();
+    let mut negate = false;
+    for (i, part) in split_keeping(items, |c| c == '+' || c == '-').into_iter().enumerate() {
+        match part {
+            Part::Operand(operand) => {
+                let term = parse_calc_product(operand)?;
+                terms.push(if negate { MathExpr::Negate(Box::new(term)) } else { term });
+            }
+            Part::Operator(operator) if i % 2 == 1 => negate = operator == '-',
+            Part::Operator(_) => return None,
+        }
+    }
+    single_or(terms, MathExpr::Sum)
+}
+
+/// `<calc-product>`: values joined by `*` and `/`
+fn parse_calc_product(items: &[&ComponentValue]) -> Option<MathExpr> {
+    let items: Vec<&ComponentValue> = items.iter().copied().filter(|v| !v.is_whitespace()).collect();
+    let mut factors = Vec::new();
+    let mut invert = false;
+    for (i, part) in split_keeping(&items, |c| c == '*' || c == '/').into_iter().enumerate() {
+        match part {
+            Part::Operand([value]) => {
+                let factor = parse_calc_value(value)?;
+                factors.push(if invert { MathExpr::Invert(Box::new(factor)) } else { factor });
+            }
+            Part::Operator(operator) if i % 2 == 1 => invert = operator == '/',
+            _ => return None,
+        }
+    }
+    single_or(factors, MathExpr::Product)
+}
+
+/// `<calc-value>`: a number, dimension, percentage, parenthesized sum or
+/// nested math function
+fn parse_calc_value(value: &ComponentValue) -> Option<MathExpr> {
+    match value {
+        ComponentValue::Token(CssToken::Number(n)) => Some(MathExpr::Number(n.value)),
+        ComponentValue::Token(CssToken::Percentage(p)) => Some(MathExpr::Percentage(p.value)),
+        ComponentValue::Token(CssToken::Dimension { value, unit }) => {
+            LengthUnit::from_unit(unit).map(|unit| MathExpr::Length(Length { value: value.value, unit }))
+        }
+        ComponentValue::Block { open: '(', value } => parse_calc_sum(&value.iter().collect::<Vec<_>>()),
+        ComponentValue::Function { name, value } => MathExpr::parse_function(name, value),
+        _ => None,
+    }
+}
+
+enum Part<'a, 'b> {
+    Operand(&'b [&'a ComponentValue]),
+    Operator(char),
+}
+
+/// Split on operator delimiters, keeping them; the result alternates between
+/// operands and operators if the expression is well formed
+fn split_keeping<'a, 'b>(items: &'b [&'a ComponentValue], is_operator: impl Fn(char) -> bool) -> Vec<Part<'a, 'b>> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    for (i, item) in items.iter().enumerate() {
+        if let ComponentValue::Token(CssToken::Delim(c)) = item {
+            if is_operator(*c) {
+                parts.push(Part::Operand(&items[start..i]));
+                parts.push(Part::Operator(*c));
+                start = i + 1;
+            }
+        }
+    }
+    parts.push(Part::Operand(&items[start..]));
+    parts
+}
+
+fn single_or(mut items: Vec<MathExpr>, combine: fn(Vec<MathExpr>) -> MathExpr) -> Option<MathExpr> {
+    match items.len() {
+        0 => None,
+        1 => items.pop(),
+        _ => Some(combine(items)),
+    }
+}
+
+impl fmt::Display for MathExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MathExpr::Min(_) | MathExpr::Max(_) | MathExpr::Clamp(..) => write_expr(f, self),
+            _ => {
+                write!(f, "calc(")?;
+                match self {
+                    MathExpr::Sum(terms) => write_sum(f, terms)?,
+                    expr => write_expr(f, expr)?,
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+fn write_expr(f: &mut fmt::Formatter<'_>, expr: &MathExpr) -> fmt::Result {
+    let write_list = |f: &mut fmt::Formatter<'_>, name: &str, arguments: &[&MathExpr]| {
+        write!(f, "{}(", name)?;
+        for (i, argument) in arguments.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match argument {
+                MathExpr::Sum(terms) => write_sum(f, terms)?,
+                argument => write_expr(f, argument)?,
+            }
+        }
+        write!(f, ")")
+    };
+    match expr {
+        MathExpr::Number(n) => write!(f, "{}", n),
+        MathExpr::Length(length) => write!(f, "{}{}", length.value, length.unit.as_str()),
+        MathExpr::Percentage(p) => write!(f, "{}%", p),
+        MathExpr::Sum(terms) => {
+            write!(f, "(")?;
+            write_sum(f, terms)?;
+            write!(f, ")")
+        }
+        MathExpr::Negate(inner) => {
+            write!(f, "-1 * ")?;
+            write_expr(f, inner)
+        }
+        MathExpr::Product(factors) => {
+            for (i, factor) in factors.iter().enumerate() {
+                match (i, factor) {
+                    (0, MathExpr::Invert(inner)) => {
+                        write!(f, "1 / ")?;
+                        write_expr(f, inner)?;
+                    }
+                    (_, MathExpr::Invert(inner)) => {
+                        write!(f, " / ")?;
+                        write_expr(f, inner)?;
+                    }
+                    (0, factor) => write_expr(f, factor)?,
+                    (_, factor) => {
+                        write!(f, " * ")?;
+                        write_expr(f, factor)?;
+                    }
+                }
+            }
+            Ok(())
+        }
+        MathExpr::Invert(inner) => {
+            write!(f, "1 / ")?;
+            write_expr(f, inner)
+        }
+        MathExpr::Min(arguments) => write_list(f, "min", &arguments.iter().collect::<Vec<_>>()),
+        MathExpr::Max(arguments) => write_list(f, "max", &arguments.iter().collect::<Vec<_>>()),
+        MathExpr::Clamp(min, value, max) => write_list(f, "clamp", &[min, value, max]),
+    }
+}
+
+/// Terms of a sum, written with `+` and `-` between them
+fn write_sum(f: &mut fmt::Formatter<'_>, terms: &[MathExpr]) -> fmt::Result {
+    for (i, term) in terms.iter().enumerate() {
+        match (i, term) {
+            (0, term) => write_expr(f, term)?,
+            (_, MathExpr::Negate(inner)) => {
+                write!(f, " - ")?;
+                write_expr(f, inner)?;
+            }
+            (_, term) => {
+                write!(f, " + ")?;
+                write_expr(f, term)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::style::{CssValue, Viewport};
+
+    fn px(text: &str, percent_basis: f32) -> Option<f32> {
+        let context = LengthContext { font_size: 20.0, root_font_size: 16.0, viewport: Viewport::new(1000.0, 500.0) };
+        CssValue::parse_str(text).to_px(&context, percent_basis)
+    }
+
+    #[test]
+    fn test_calc_arithmetic() {
+        assert_eq!(px("calc(100% - 2em)", 500.0), Some(460.0));
+        assert_eq!(px("calc(10px + 2 * (1rem - 6px) / 4)", 0.0), Some(15.0));
+        assert_eq!(px("calc(50vw - 10vh * 2)", 0.0), Some(400.0));
+        assert_eq!(px("calc(calc(1in) / 2)", 0.0), Some(48.0));
+        assert_eq!(px("calc(-1 * 3px - -2px)", 0.0), Some(-1.0));
+    }
+
+    #[test]
+    fn test_comparison_functions() {
+        assert_eq!(px("min(100%, 600px)", 800.0), Some(600.0));
+        assert_eq!(px("max(100%, 600px)", 800.0), Some(800.0));
+        assert_eq!(px("clamp(1rem, 2.5vw, 1.2em)", 0.0), Some(24.0));
+        assert_eq!(px("clamp(10px, 2px, 20px)", 0.0), Some(10.0));
+        assert_eq!(px("clamp(30px, 25px, 20px)", 0.0), Some(30.0));
+        assert_eq!(px("min(max(1px, 5px), calc(3px + 1px))", 0.0), Some(4.0));
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        // Type errors
+        assert_eq!(px("calc(1px + 2)", 0.0), None);
+        assert_eq!(px("calc(2px * 3px)", 0.0), None);
+        assert_eq!(px("calc(2 / 1px)", 0.0), None);
+        assert_eq!(px("min(1px, 2)", 0.0), None);
+        // Syntax errors keep the value as an ordinary function
+        assert!(matches!(CssValue::parse_str("calc(1px 2px)"), CssValue::Function { .. }));
+        assert!(matches!(CssValue::parse_str("calc(1px +)"), CssValue::Function { .. }));
+        assert!(matches!(CssValue::parse_str("calc(1px+ 2px)"), CssValue::Function { .. }));
+        assert!(matches!(CssValue::parse_str("calc(1px -2px)"), CssValue::Function { .. }));
+        assert!(matches!(CssValue::parse_str("calc((1px -2px) * 2)"), CssValue::Function { .. }));
+        assert!(matches!(CssValue::parse_str("clamp(1px, 2px)"), CssValue::Function { .. }));
+        assert!(matches!(CssValue::parse_str("calc(1deg + 2px)"), CssValue::Function { .. }));
+    }
+
+    #[test]
+    fn test_lengths_in_style() {
+        let mut style = crate::style::Style::new();
+        style.set_str("font-size", "20px");
+        style.set_str("width", "calc(100% - 2em)");
+        style.set_str("margin", "0 min(10%, 30px)");
+        style.set_str("padding-left", "clamp(4px, 1vw, 8px)");
+        let viewport = Viewport::new(600.0, 400.0);
+        assert_eq!(style.get_width_px(&viewport, 500.0), Some(460.0));
+        assert_eq!(style.get_margin(&viewport, 500.0), (0.0, 30.0, 0.0, 30.0));
+        assert_eq!(style.get_margin(&viewport, 200.0), (0.0, 20.0, 0.0, 20.0));
+        assert_eq!(style.get_padding(&viewport, 500.0).3, 6.0);
+    }
+
+    #[test]
+    fn test_serialization() {
+        assert_eq!(CssValue::parse_str("calc( 100% - 2em )").to_string(), "calc(100% - 2em)");
+        assert_eq!(CssValue::parse_str("calc(2 * (1px + 3em) / 4)").to_string(), "calc(2 * (1px + 3em) / 4)");
+        assert_eq!(CssValue::parse_str("clamp(1rem, 1rem + 2vw, 3rem)").to_string(), "clamp(1rem, 1rem + 2vw, 3rem)");
+    }
+}
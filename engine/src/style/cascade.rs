@@ -0,0 +1,289 @@
+// The cascade: choosing the winning declaration for each property
+// Spec Reference: https://www.w3.org/TR/css-cascade-4/#cascade-sort
+//
+// Declarations that apply to an element are sorted by origin and importance,
+// then whether they come from the element's style attribute, then selector
+// specificity, then source order; the last one for each property wins.
+// Within a rule, later declarations beat earlier ones.
+//
+// Winning custom properties are computed first, so that var() in the other
+// winning declarations can be substituted (see `variables`).
+//
+// Shorthands are expanded into their longhands when declarations are parsed,
+// so a later shorthand overrides an earlier longhand and vice versa. A
+// shorthand using var() can only be split once it is substituted.
+
+use std::collections::HashMap;
+
+use super::values::CssValue;
+use super::variables::{self, contains_var, is_custom_property};
+use super::Style;
+use crate::parser::css::{ComponentValue, Declaration, Specificity};
+
+/// Where a style rule came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// The browser's default styles
+    UserAgent,
+    /// The reader's own styles
+    User,
+    /// The page's stylesheets
+    Author,
+}
+
+impl Origin {
+    /// Rank in the cascade, lowest first (spec 6.2). Important declarations
+    /// reverse the order of origins and beat every normal declaration.
+    pub fn precedence(self, important: bool) -> u8 {
+        match (self, important) {
+            (Origin::UserAgent, false) => 0,
+            (Origin::User, false) => 1,
+            (Origin::Author, false) => 2,
+            (Origin::Author, true) => 3,
+            (Origin::User, true) => 4,
+            (Origin::UserAgent, true) => 5,
+        }
+    }
+}
+
+/// A declaration with its value parsed, as stored in style rules
+#[derive(Debug, Clone)]
+pub struct StyleDeclaration {
+    pub property: String,
+    pub value: CssValue,
+    pub important: bool,
+    /// The unparsed value of a custom property or of a value using var(),
+    /// which is only known once custom properties are computed
+    pub unparsed: Option<Vec<ComponentValue>>,
+    /// The shorthand an unparsed longhand declaration was expanded from
+    pub shorthand: Option<&'static str>,
+}
+
+impl StyleDeclaration {
+    /// Parse the values of declarations from the CSS parser, expanding
+    /// shorthands into their longhands
+    pub fn from_declarations(declarations: &[Declaration]) -> Vec<StyleDeclaration> {
+        let mut result = Vec::with_capacity(declarations.len());
+        for declaration in declarations {
+            let property = declaration.property.as_str();
+            let important = declaration.important;
+            let value = CssValue::parse(&declaration.components);
+            if is_custom_property(property) || contains_var(&declaration.components) {
+                let unparsed = Some(declaration.components.clone());
+                match shorthand_longhands(property) {
+                    Some((shorthand, longhands)) => result.extend(longhands.iter().map(|longhand| StyleDeclaration {
+                        property: longhand.to_string(),
+                        value: value.clone(),
+                        important,
+                        unparsed: unparsed.clone(),
+                        shorthand: Some(shorthand),
+                    })),
+                    None => result.push(StyleDeclaration {
+                        property: property.to_string(),
+                        value,
+                        important,
+                        unparsed,
+                        shorthand: None,
+                    }),
+                }
+                continue;
+            }
+            match expand_shorthand(property, &value) {
+                Some(longhands) => result.extend(longhands.into_iter().map(|(longhand, value)| StyleDeclaration {
+                    property: longhand.to_string(),
+                    value,
+                    important,
+                    unparsed: None,
+                    shorthand: None,
+                })),
+                None => result.push(StyleDeclaration {
+                    property: property.to_string(),
+                    value,
+                    important,
+                    unparsed: None,
+                    shorthand: None,
+                }),
+            }
+        }
+        result
+    }
+}
+
+/// The shorthand's canonical name and its longhands; box shorthands list
+/// them in top, right, bottom, left order
+fn shorthand_longhands(property: &str) -> Option<(&'static str, [&'static str; 4])> {
+    match property {
+        "margin" => Some(("margin", ["margin-top", "margin-right", "margin-bottom", "margin-left"])),
+        "padding" => Some(("padding", ["padding-top", "padding-right", "padding-bottom", "padding-left"])),
+        _ => None,
+    }
+}
+
+/// Expand a shorthand's value into its longhands. None if the property is not
+/// a shorthand; an empty list if the value is invalid, which drops it.
+pub(crate) fn expand_shorthand(property: &str, value: &CssValue) -> Option<Vec<(&'static str, CssValue)>> {
+    let (_, longhands) = shorthand_longhands(property)?;
+    // One to four values, for the top, right, bottom and left sides
+    let items = value.items();
+    let indexes = match items.len() {
+        1 => [0, 0, 0, 0],
+        2 => [0, 1, 0, 1],
+        3 => [0, 1, 2, 1],
+        4 => [0, 1, 2, 3],
+        _ => return Some(Vec::new()),
+    };
+    Some(longhands.into_iter().zip(indexes).map(|(longhand, i)| (longhand, items[i].clone())).collect())
+}
+
+/// A declaration that applies to an element, with what it sorts by
+pub(crate) struct Candidate<'a> {
+    pub origin: Origin,
+    /// From the element's style attribute, which beats any selector (spec 6.4)
+    pub element_attached: bool,
+    pub specificity: Specificity,
+    /// Source order of the rule the declaration came from
+    pub order: usize,
+    pub declaration: &'a StyleDeclaration,
+}
+
+/// Apply the declarations to `style` in cascade order, so that the winner for
+/// each property is the one left in place. Returns the properties whose
+/// winning declaration is invalid at computed-value time; the caller resets
+/// them as if they were `unset`.
+pub(crate) fn apply(style: &mut Style, mut candidates: Vec<Candidate<'_>>) -> Vec<String> {
+    // The sort is stable, so declarations from the same rule keep their order
+    candidates.sort_by_key(|c| (c.origin.precedence(c.declaration.important), c.element_attached, c.specificity, c.order));
+    let mut winners: HashMap<&str, &StyleDeclaration> = HashMap::new();
+    for candidate in candidates {
+        winners.insert(&candidate.declaration.property, candidate.declaration);
+    }
+
+    let custom: HashMap<&str, &[ComponentValue]> = winners
+        .iter()
+        .filter(|(property, _)| is_custom_property(property))
+        .filter_map(|(&property, declaration)| Some((property, declaration.unparsed.as_deref()?)))
+        .collect();
+    variables::resolve_custom_properties(&mut style.custom_properties, &custom);
+
+    let mut invalid = Vec::new();
+    for (property, declaration) in winners {
+        if is_custom_property(property) {
+            continue;
+        }
+        match &declaration.unparsed {
+            None => style.set(property, declaration.value.clone()),
+            Some(unparsed) => {
+                let value = variables::substitute(unparsed, &style.custom_properties).map(|values| CssValue::parse(&values));
+                let value = match declaration.shorthand {
+                    Some(shorthand) => value.and_then(|value| {
+                        let longhands = expand_shorthand(shorthand, &value)?;
+                        longhands.into_iter().find(|&(longhand, _)| longhand == property).map(|(_, value)| value)
+                    }),
+                    None => value,
+                };
+                match value {
+                    Some(value) => style.set(property, value),
+                    None => invalid.push(property.to_string()),
+                }
+            }
+        }
+    }
+    invalid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::css::{CssItem, CssParser, CssTokenizer};
+    use crate::parser::html::tree_builder::HtmlParser;
+    use crate::style::{CssRule, Stylesheet, Viewport};
+
+    fn stylesheet(css: &str, origin: Origin) -> Stylesheet {
+        let mut stylesheet = Stylesheet::new();
+        for item in CssParser::new(CssTokenizer::new(css).tokenize()).parse() {
+            if let CssItem::Rule(rule) = item {
+                stylesheet.add_rule(CssRule::new(rule.selector, &rule.declarations).with_origin(origin));
+            }
+        }
+        stylesheet
+    }
+
+    fn color_of_p(stylesheet: &Stylesheet) -> String {
+        let dom = HtmlParser::new("<div id=main><p>x</p></div>").parse();
+        let p = dom.query_selector("p").unwrap();
+        stylesheet.compute_style(&dom, p).get("color").unwrap().to_string()
+    }
+
+    #[test]
+    fn test_specificity_beats_source_order() {
+        let sheet = stylesheet("#main p { color: red } p { color: blue } span { color: green }", Origin::Author);
+        assert_eq!(color_of_p(&sheet), "red");
+        let sheet = stylesheet("p { color: red } p { color: green; color: navy }", Origin::Author);
+        assert_eq!(color_of_p(&sheet), "navy");
+    }
+
+    #[test]
+    fn test_important_and_origins() {
+        let sheet = stylesheet("p { color: red !important } #main p { color: blue }", Origin::Author);
+        assert_eq!(color_of_p(&sheet), "red");
+
+        // Important user-agent declarations beat important author ones, but
+        // normal author declarations beat normal user-agent ones
+        let mut sheet = stylesheet("p { color: red !important } div p { color: teal }", Origin::UserAgent);
+        sheet.rules.extend(stylesheet("#main p { color: blue !important }", Origin::Author).rules);
+        assert_eq!(color_of_p(&sheet), "red");
+
+        let mut sheet = stylesheet("div p { color: teal }", Origin::UserAgent);
+        sheet.rules.extend(stylesheet("p { color: blue }", Origin::Author).rules);
+        assert_eq!(color_of_p(&sheet), "blue");
+    }
+
+    #[test]
+    fn test_shorthands_override_longhands() {
+        let viewport = Viewport::default();
+        let style_of = |css: &str, html: &str, tag: &str| {
+            let dom = HtmlParser::new(html).parse();
+            let node = dom.query_selector(tag).unwrap();
+            stylesheet(css, Origin::Author).compute_style(&dom, node)
+        };
+        // Author shorthands beat user agent longhands
+        let ul = style_of("ul { padding: 0 }", "<ul><li>x</li></ul>", "ul");
+        assert_eq!(ul.get_padding(&viewport, 800.0), (0.0, 0.0, 0.0, 0.0));
+        let dd = style_of("dd { margin: 0 }", "<dl><dd>x</dd></dl>", "dd");
+        assert_eq!(dd.get_margin(&viewport, 800.0), (0.0, 0.0, 0.0, 0.0));
+
+        // Within the same origin, the later declaration wins either way
+        let p = style_of("p { margin-left: 5px } p { margin: 0 }", "<p>x</p>", "p");
+        assert_eq!(p.get_margin(&viewport, 800.0), (0.0, 0.0, 0.0, 0.0));
+        let p = style_of("p { margin: 0 } p { margin-left: 5px }", "<p>x</p>", "p");
+        assert_eq!(p.get_margin(&viewport, 800.0), (0.0, 0.0, 0.0, 5.0));
+        let p = style_of("p { padding: 1px 2px 3px; padding: var(--missing, 4px 5px) }", "<p>x</p>", "p");
+        assert_eq!(p.get_padding(&viewport, 800.0), (4.0, 5.0, 4.0, 5.0));
+    }
+
+    #[test]
+    fn test_style_attribute() {
+        let color = |css: &str, style: &str| {
+            let html = format!("<div id=main><p id=p style='{}'>x</p></div>", style);
+            let dom = HtmlParser::new(&html).parse();
+            let p = dom.query_selector("p").unwrap();
+            stylesheet(css, Origin::Author).compute_style(&dom, p).get("color").unwrap().to_string()
+        };
+        assert_eq!(color("#main #p { color: red }", "color: blue"), "blue");
+        assert_eq!(color("p { color: red !important }", "color: blue"), "red");
+        assert_eq!(color("p { color: red !important }", "color: blue !important"), "blue");
+        assert_eq!(color("p { color: red }", "color: blue; color: green; bogus"), "green");
+        assert_eq!(color("p { color: red }", ""), "red");
+    }
+
+    #[test]
+    fn test_style_attribute_cache() {
+        let mut dom = HtmlParser::new("<p style='color: blue'>x</p>").parse();
+        let p = dom.query_selector("p").unwrap();
+        let sheet = Stylesheet::new();
+        assert_eq!(sheet.compute_style(&dom, p).get("color").unwrap().to_string(), "blue");
+        assert_eq!(sheet.compute_style(&dom, p).get("color").unwrap().to_string(), "blue");
+        dom.set_attribute(p, "style", "color: red").unwrap();
+        assert_eq!(sheet.compute_style(&dom, p).get("color").unwrap().to_string(), "red");
+    }
+}
@@ -0,0 +1,218 @@
+// Stylesheet loading: <style>, <link rel="stylesheet"> and @import
+// Spec Reference: https://html.spec.whatwg.org/multipage/semantics.html#link-type-stylesheet
+// Spec Reference: https://www.w3.org/TR/css-cascade-4/#at-import
+//
+// Status:
+// ✅ <style> and <link rel="stylesheet"> in tree order, with their media attribute
+// ✅ @import with url() or a string, relative to the importing sheet
+// ✅ @import media queries, nested inside the importing sheet's media
+// ✅ Import cycles and overly deep import chains are cut off
+// ✅ @import after other rules is ignored
+// ⚠️ supports() conditions on @import are not evaluated; the sheet is always used
+// ❌ layer() on @import (cascade layers are not implemented)
+// ❌ @charset and Content-Type charsets; sheets are decoded as UTF-8
+
+use super::media::MediaQueryList;
+use super::Stylesheet;
+use crate::dom::Dom;
+use crate::net::{resolve_url, resolve_url_with_base, NetworkManager};
+use crate::parser::css::parser::serialize;
+use crate::parser::css::{ComponentValue, CssItem, CssParser, CssToken, CssTokenizer};
+use crate::parser::html::{extract_base_href, extract_stylesheets, StylesheetSource};
+
+/// How deep @import chains may go before the rest is ignored
+const MAX_IMPORT_DEPTH: usize = 16;
+
+/// Fetches the text of external stylesheets
+pub trait StylesheetFetcher {
+    /// Fetch the stylesheet at an absolute URL, returning its URL after
+    /// redirects (the base for its own relative URLs) and its text
+    fn fetch_stylesheet(&self, url: &str) -> Option<(String, String)>;
+}
+
+impl StylesheetFetcher for NetworkManager {
+    fn fetch_stylesheet(&self, url: &str) -> Option<(String, String)> {
+        let resource = self.fetch_resource(url)?;
+        let data = resource.data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&resource.data);
+        Some((resource.url, String::from_utf8_lossy(data).into_owned()))
+    }
+}
+
+/// Load every stylesheet of the document into one author stylesheet, in
+/// cascade order. Relative URLs resolve against `document_url` and the
+/// document's <base href>.
+pub fn load_stylesheets(dom: &Dom, document_url: &str, fetcher: &impl StylesheetFetcher) -> Stylesheet {
+    let base_href = extract_base_href(dom);
+    let mut loader = Loader { fetcher, stylesheet: Stylesheet::new(), importing: Vec::new() };
+    let document_base = resolve_url_with_base(document_url, base_href.as_deref(), "");
+
+    for sheet in extract_stylesheets(dom) {
+        let queries: Vec<MediaQueryList> = sheet.media.as_deref().map(MediaQueryList::parse).into_iter().collect();
+        match sheet.source {
+            StylesheetSource::Inline(css) => loader.add_sheet(&css, &document_base, &queries),
+            StylesheetSource::External(href) => {
+                loader.load(&resolve_url(&document_base, &href), &queries);
+            }
+        }
+    }
+    loader.stylesheet
+}
+
+struct Loader<'a, F> {
+    fetcher: &'a F,
+    stylesheet: Stylesheet,
+    /// URLs of the sheets being loaded, outermost first, to detect cycles
+    importing: Vec<String>,
+}
+
+impl<F: StylesheetFetcher> Loader<'_, F> {
+    /// Fetch an external sheet and add it under the given media
+    fn load(&mut self, url: &str, queries: &[MediaQueryList]) {
+        if self.importing.iter().any(|importing| importing == url) {
+            eprintln!("Ignoring cyclic @import of {}", url);
+            return;
+        }
+        if self.importing.len() >= MAX_IMPORT_DEPTH {
+            eprintln!("Ignoring @import of {}: imports nested too deeply", url);
+            return;
+        }
+        let Some((final_url, css)) = self.fetcher.fetch_stylesheet(url) else {
+            eprintln!("Failed to load stylesheet {}", url);
+            return;
+        };
+        self.importing.push(url.to_string());
+        self.add_sheet(&css, &final_url, queries);
+        self.importing.pop();
+    }
+
+    /// Add a sheet's imports, then its own rules
+    fn add_sheet(&mut self, css: &str, base_url: &str, queries: &[MediaQueryList]) {
+        let mut items = CssParser::new(CssTokenizer::new(css).tokenize()).parse().into_iter().peekable();
+        // @import is only valid before every other rule except @charset and @layer
+        while let Some(CssItem::AtRule { name, prelude, .. }) = items.peek() {
+            let name = name.to_ascii_lowercase();
+            if name == "import" {
+                if let Some((href, media)) = parse_import(prelude) {
+                    let mut nested = queries.to_vec();
+                    nested.extend(media);
+                    self.load(&resolve_url(base_url, &href), &nested);
+                }
+            } else if name != "charset" && name != "layer" {
+                break;
+            }
+            items.next();
+        }
+        self.stylesheet.add_items_in_media(items.collect(), queries);
+    }
+}
+
+/// The URL and media query list of an @import prelude:
+/// `url("a.css") layer(x) supports(display: grid) screen`
+fn parse_import(prelude: &str) -> Option<(String, Option<MediaQueryList>)> {
+    let values = CssParser::new(CssTokenizer::new(prelude).tokenize()).component_values();
+    let mut values = values.iter().filter(|v| !v.is_whitespace()).peekable();
+    let href = match values.next()? {
+        ComponentValue::Token(CssToken::Url(url) | CssToken::String(url)) => url.clone(),
+        ComponentValue::Function { name, value } if name.eq_ignore_ascii_case("url") => {
+            match value.iter().find(|v| !v.is_whitespace())? {
+                ComponentValue::Token(CssToken::String(url)) => url.clone(),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    // Skip the layer and supports conditions, which are not supported
+    while let Some(value) = values.peek() {
+        let skip = match value {
+            ComponentValue::Token(CssToken::Ident(name)) => name.eq_ignore_ascii_case("layer"),
+            ComponentValue::Function { name, .. } => {
+                name.eq_ignore_ascii_case("layer") || name.eq_ignore_ascii_case("supports")
+            }
+            _ => false,
+        };
+        if !skip {
+            break;
+        }
+        values.next();
+    }
+    let media: Vec<ComponentValue> = values.cloned().collect();
+    let media = (!media.is_empty()).then(|| MediaQueryList::parse(&serialize(&media)));
+    Some((href, media))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::html::tree_builder::HtmlParser;
+    use crate::style::{DeviceFeatures, Viewport};
+    use std::collections::HashMap;
+
+    impl StylesheetFetcher for HashMap<&str, &str> {
+        fn fetch_stylesheet(&self, url: &str) -> Option<(String, String)> {
+            self.get(url).map(|css| (url.to_string(), css.to_string()))
+        }
+    }
+
+    fn color_of_p(stylesheet: &Stylesheet, width: f32) -> String {
+        let dom = HtmlParser::new("<p>x</p>").parse();
+        let p = dom.query_selector("p").unwrap();
+        let style = stylesheet.compute_style_with_viewport(&dom, p, &Viewport::new(width, 600.0));
+        style.get("color").map(|c| c.to_string()).unwrap_or_default()
+    }
+
+    #[test]
+    fn test_link_and_style_order() {
+        let html = r#"<link rel=stylesheet href="css/a.css"><style>p { color: green }</style>
+            <link rel=stylesheet href="/b.css" media="(max-width: 500px)">"#;
+        let dom = HtmlParser::new(html).parse();
+        let fetcher = HashMap::from([
+            ("https://example.com/site/css/a.css", "p { color: red }"),
+            ("https://example.com/b.css", "p { color: blue }"),
+        ]);
+        let stylesheet = load_stylesheets(&dom, "https://example.com/site/index.html", &fetcher);
+        assert_eq!(color_of_p(&stylesheet, 800.0), "green");
+        assert_eq!(color_of_p(&stylesheet, 400.0), "blue");
+    }
+
+    #[test]
+    fn test_imports() {
+        let dom = HtmlParser::new(r#"<link rel=stylesheet href="main.css">"#).parse();
+        let fetcher = HashMap::from([
+            (
+                "https://example.com/main.css",
+                "@charset \"utf-8\"; @import url(theme/base.css); @import 'print.css' print; p { color: red }",
+            ),
+            // Imported sheets come before the importing sheet's rules
+            ("https://example.com/theme/base.css", "@import \"../main.css\"; @import 'more.css' (max-width: 500px); p { color: blue; background-color: blue }"),
+            ("https://example.com/theme/more.css", "body p { background-color: navy } @import 'ignored.css';"),
+            ("https://example.com/theme/ignored.css", "p { background-color: red }"),
+            ("https://example.com/print.css", "p { background-color: black }"),
+        ]);
+        let stylesheet = load_stylesheets(&dom, "https://example.com/", &fetcher);
+        assert_eq!(color_of_p(&stylesheet, 800.0), "red");
+        assert_eq!(stylesheet.rules.len(), 2);
+        assert_eq!(stylesheet.media_rules.len(), 2);
+
+        let background = |width: f32, device: DeviceFeatures| {
+            let mut stylesheet = load_stylesheets(&dom, "https://example.com/", &fetcher);
+            stylesheet.set_device(device);
+            let p_dom = HtmlParser::new("<p>x</p>").parse();
+            let p = p_dom.query_selector("p").unwrap();
+            let style = stylesheet.compute_style_with_viewport(&p_dom, p, &Viewport::new(width, 600.0));
+            style.get("background-color").unwrap().to_string()
+        };
+        assert_eq!(background(800.0, DeviceFeatures::default()), "blue");
+        assert_eq!(background(400.0, DeviceFeatures::default()), "navy");
+        let print = DeviceFeatures { media_type: crate::style::media::MediaType::Print, ..DeviceFeatures::default() };
+        assert_eq!(background(800.0, print), "black");
+    }
+
+    #[test]
+    fn test_parse_import() {
+        assert_eq!(parse_import("url(a.css)"), Some(("a.css".to_string(), None)));
+        let (href, media) = parse_import("url( \"a.css\" ) layer(base) supports(display: grid) screen and (color)").unwrap();
+        assert_eq!(href, "a.css");
+        assert_eq!(media.unwrap().queries.len(), 1);
+        assert_eq!(parse_import("a.css"), None);
+    }
+}
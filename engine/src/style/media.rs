@@ -0,0 +1,571 @@
+// Media queries
+// Spec Reference: https://www.w3.org/TR/mediaqueries-4/
+//
+// Status:
+// ✅ Media types with `not` and `only`
+// ✅ Conditions with `and`, `or`, `not` and nesting
+// ✅ Plain (`min-width: 600px`), boolean (`(color)`) and range
+//    (`400px <= width < 800px`) features
+// ✅ width, height, aspect-ratio, orientation, resolution, color,
+//    prefers-color-scheme, prefers-reduced-motion, hover and pointer
+// ✅ Unknown features and syntax evaluate to "unknown" (spec 3.2), so they
+//    never make a query match
+// ❌ Media features from Level 5 other than the prefers-* ones
+
+use super::values::{Length, LengthContext, LengthUnit};
+use super::Viewport;
+use crate::parser::css::{ComponentValue, CssParser, CssToken, CssTokenizer};
+
+/// The media type of the output device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaType {
+    Screen,
+    Print,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pointer {
+    None,
+    Coarse,
+    Fine,
+}
+
+/// What the output device supports, beyond the viewport size
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceFeatures {
+    pub media_type: MediaType,
+    /// Device pixels per CSS pixel
+    pub resolution: f32,
+    /// Bits per color component; 0 for a monochrome device
+    pub color_bits: u32,
+    pub color_scheme: ColorScheme,
+    pub reduced_motion: bool,
+    pub hover: bool,
+    pub pointer: Pointer,
+}
+
+impl Default for DeviceFeatures {
+    fn default() -> Self {
+        Self {
+            media_type: MediaType::Screen,
+            resolution: 1.0,
+            color_bits: 8,
+            color_scheme: ColorScheme::Light,
+            reduced_motion: false,
+            hover: true,
+            pointer: Pointer::Fine,
+        }
+    }
+}
+
+/// A comma-separated list of media queries; it matches if any query does.
+/// An empty list matches everything.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MediaQueryList {
+    pub queries: Vec<MediaQuery>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaQuery {
+    /// `not screen and (color)` negates the whole query
+    pub negated: bool,
+    /// The media type; None for `all` or when only a condition is given.
+    /// Unknown types are kept so the query never matches.
+    pub media_type: Option<String>,
+    pub condition: Option<MediaCondition>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaCondition {
+    Feature(MediaFeature),
+    Not(Box<MediaCondition>),
+    And(Vec<MediaCondition>),
+    Or(Vec<MediaCondition>),
+    /// Syntax this parser does not understand (spec <general-enclosed>)
+    Unknown,
+}
+
+/// A media feature test; `min-`/`max-` prefixes and range syntax are
+/// normalized to comparisons of the feature against a value
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaFeature {
+    pub name: String,
+    /// None for a boolean test like `(color)`
+    pub comparison: Option<(Comparison, MediaValue)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaValue {
+    Length(Length),
+    Number(f32),
+    /// `16/9`
+    Ratio(f32, f32),
+    /// In dots per CSS pixel (dppx)
+    Resolution(f32),
+    Keyword(String),
+}
+
+impl MediaQueryList {
+    /// Parse a media query list, like the prelude of an `@media` rule or a
+    /// `media` attribute. Queries that fail to parse become `not all`.
+    pub fn parse(text: &str) -> Self {
+        let values = CssParser::new(CssTokenizer::new(text).tokenize()).component_values();
+        if values.iter().all(ComponentValue::is_whitespace) {
+            return Self::default();
+        }
+        let queries = values
+            .split(|v| matches!(v, ComponentValue::Token(CssToken::Comma)))
+            .map(|query| {
+                let items: Vec<&ComponentValue> = query.iter().filter(|v| !v.is_whitespace()).collect();
+                parse_query(&items).unwrap_or_else(MediaQuery::never)
+            })
+            .collect();
+        Self { queries }
+    }
+
+    pub fn matches(&self, viewport: &Viewport, device: &DeviceFeatures) -> bool {
+        self.queries.is_empty() || self.queries.iter().any(|query| query.matches(viewport, device))
+    }
+}
+
+impl MediaQuery {
+    /// `not all`, which never matches
+    fn never() -> Self {
+        Self { negated: true, media_type: None, condition: None }
+    }
+
+    pub fn matches(&self, viewport: &Viewport, device: &DeviceFeatures) -> bool {
+        let type_matches = match self.media_type.as_deref() {
+            None => true,
+            Some(name) => matches!((name, device.media_type), ("screen", MediaType::Screen) | ("print", MediaType::Print)),
+        };
+        let condition = match &self.condition {
+            Some(condition) => condition.evaluate(viewport, device),
+            None => Some(true),
+        };
+        // An unknown result makes the whole query false, even when negated
+        match condition {
+            Some(condition) => (type_matches && condition) != self.negated,
+            None => false,
+        }
+    }
+}
+
+impl MediaCondition {
+    /// Evaluate with three-valued logic; None means unknown (spec 3.2)
+    pub fn evaluate(&self, viewport: &Viewport, device: &DeviceFeatures) -> Option<bool> {
+        match self {
+            MediaCondition::Feature(feature) => feature.evaluate(viewport, device),
+            MediaCondition::Not(inner) => inner.evaluate(viewport, device).map(|b| !b),
+            MediaCondition::And(parts) => {
+                let results: Vec<Option<bool>> = parts.iter().map(|p| p.evaluate(viewport, device)).collect();
+                if results.contains(&Some(false)) {
+                    Some(false)
+                } else if results.contains(&None) {
+                    None
+                } else {
+                    Some(true)
+                }
+            }
+            MediaCondition::Or(parts) => {
+                let results: Vec<Option<bool>> = parts.iter().map(|p| p.evaluate(viewport, device)).collect();
+                if results.contains(&Some(true)) {
+                    Some(true)
+                } else if results.contains(&None) {
+                    None
+                } else {
+                    Some(false)
+                }
+            }
+            MediaCondition::Unknown => None,
+        }
+    }
+}
+
+impl MediaFeature {
+    pub fn evaluate(&self, viewport: &Viewport, device: &DeviceFeatures) -> Option<bool> {
+        let context = LengthContext { viewport: *viewport, ..LengthContext::default() };
+        let actual = match self.name.as_str() {
+            "width" | "device-width" => FeatureValue::Number(viewport.width),
+            "height" | "device-height" => FeatureValue::Number(viewport.height),
+            "aspect-ratio" | "device-aspect-ratio" => FeatureValue::Number(viewport.width / viewport.height),
+            "resolution" => FeatureValue::Number(device.resolution),
+            "color" => FeatureValue::Number(device.color_bits as f32),
+            "monochrome" => FeatureValue::Number(if device.color_bits == 0 { 1.0 } else { 0.0 }),
+            "color-index" | "grid" => FeatureValue::Number(0.0),
+            "orientation" => FeatureValue::Keyword(if viewport.height >= viewport.width { "portrait" } else { "landscape" }),
+            "prefers-color-scheme" => FeatureValue::Keyword(match device.color_scheme {
+                ColorScheme::Light => "light",
+                ColorScheme::Dark => "dark",
+            }),
+            "prefers-reduced-motion" => FeatureValue::Keyword(if device.reduced_motion { "reduce" } else { "no-preference" }),
+            "hover" | "any-hover" => FeatureValue::Keyword(if device.hover { "hover" } else { "none" }),
+            "pointer" | "any-pointer" => FeatureValue::Keyword(match device.pointer {
+                Pointer::None => "none",
+                Pointer::Coarse => "coarse",
+                Pointer::Fine => "fine",
+            }),
+            "scripting" => FeatureValue::Keyword("none"),
+            _ => return None,
+        };
+
+        let Some((comparison, value)) = &self.comparison else {
+            // Boolean context: true unless the value would be zero or `none`
+            // (or `no-preference` for the prefers-* features)
+            return Some(match actual {
+                FeatureValue::Number(n) => n != 0.0,
+                FeatureValue::Keyword(k) => k != "none" && k != "no-preference",
+            });
+        };
+        match actual {
+            FeatureValue::Number(actual) => {
+                let is_length = matches!(self.name.as_str(), "width" | "height" | "device-width" | "device-height");
+                let expected = match (self.name.as_str(), value) {
+                    (_, MediaValue::Length(length)) if is_length => length.to_px(&context),
+                    // Zero is allowed without a unit for lengths
+                    (_, MediaValue::Number(n)) if is_length && *n == 0.0 => 0.0,
+                    ("aspect-ratio" | "device-aspect-ratio", MediaValue::Ratio(w, h)) => w / h,
+                    ("aspect-ratio" | "device-aspect-ratio", MediaValue::Number(n)) => *n,
+                    ("resolution", MediaValue::Resolution(dppx)) => *dppx,
+                    ("color" | "monochrome" | "color-index" | "grid", MediaValue::Number(n)) => *n,
+                    _ => return None,
+                };
+                Some(match comparison {
+                    Comparison::Equal => (actual - expected).abs() < 0.001,
+                    Comparison::Less => actual < expected,
+                    Comparison::LessOrEqual => actual <= expected,
+                    Comparison::Greater => actual > expected,
+                    Comparison::GreaterOrEqual => actual >= expected,
+                })
+            }
+            FeatureValue::Keyword(actual) => match (comparison, value) {
+                (Comparison::Equal, MediaValue::Keyword(expected)) => Some(expected.eq_ignore_ascii_case(actual)),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// The device's value for a feature
+enum FeatureValue {
+    Number(f32),
+    Keyword(&'static str),
+}
+
+fn ident(value: &ComponentValue) -> Option<String> {
+    match value {
+        ComponentValue::Token(CssToken::Ident(name)) => Some(name.to_ascii_lowercase()),
+        _ => None,
+    }
+}
+
+fn is_paren_block(value: &ComponentValue) -> bool {
+    matches!(value, ComponentValue::Block { open: '(', .. })
+}
+
+/// `<media-query>`: a condition, or a media type with an optional `and` condition
+fn parse_query(items: &[&ComponentValue]) -> Option<MediaQuery> {
+    let first = items.first()?;
+    let starts_condition =
+        is_paren_block(first) || (ident(first).as_deref() == Some("not") && items.get(1).is_some_and(|v| is_paren_block(v)));
+    if starts_condition {
+        let condition = parse_condition(items, true)?;
+        return Some(MediaQuery { negated: false, media_type: None, condition: Some(condition) });
+    }
+
+    let mut pos = 0;
+    let negated = match ident(items[0]).as_deref() {
+        Some("not") => {
+            pos += 1;
+            true
+        }
+        Some("only") => {
+            pos += 1;
+            false
+        }
+        _ => false,
+    };
+    let media_type = ident(items.get(pos)?)?;
+    if matches!(media_type.as_str(), "not" | "only" | "and" | "or" | "layer") {
+        return None;
+    }
+    pos += 1;
+    let condition = match items.get(pos) {
+        None => None,
+        Some(value) if ident(value).as_deref() == Some("and") => Some(parse_condition(&items[pos + 1..], false)?),
+        Some(_) => return None,
+    };
+    let media_type = (media_type != "all").then_some(media_type);
+    Some(MediaQuery { negated, media_type, condition })
+}
+
+/// `<media-condition>`, or `<media-condition-without-or>` when `allow_or` is false
+fn parse_condition(items: &[&ComponentValue], allow_or: bool) -> Option<MediaCondition> {
+    if ident(items.first()?).as_deref() == Some("not") {
+        return match items {
+            [_, inner] => Some(MediaCondition::Not(Box::new(parse_in_parens(inner)?))),
+            _ => None,
+        };
+    }
+    let mut parts = vec![parse_in_parens(items[0])?];
+    let mut connector: Option<String> = None;
+    for pair in items[1..].chunks(2) {
+        let [keyword, operand] = pair else {
+            return None;
+        };
+        let keyword = ident(keyword)?;
+        let allowed = keyword == "and" || (keyword == "or" && allow_or);
+        if !allowed || connector.as_ref().is_some_and(|c| *c != keyword) {
+            return None;
+        }
+        connector = Some(keyword);
+        parts.push(parse_in_parens(operand)?);
+    }
+    Some(match connector.as_deref() {
+        None => parts.pop()?,
+        Some("and") => MediaCondition::And(parts),
+        _ => MediaCondition::Or(parts),
+    })
+}
+
+/// `<media-in-parens>`: a nested condition, a feature, or anything else in
+/// parentheses or a function, which is unknown rather than invalid
+fn parse_in_parens(value: &ComponentValue) -> Option<MediaCondition> {
+    let inner = match value {
+        ComponentValue::Block { open: '(', value } => value,
+        ComponentValue::Function { .. } => return Some(MediaCondition::Unknown),
+        _ => return None,
+    };
+    let items: Vec<&ComponentValue> = inner.iter().filter(|v| !v.is_whitespace()).collect();
+    let first = items.first()?;
+    let nested = is_paren_block(first) || (ident(first).as_deref() == Some("not") && items.len() == 2);
+    if nested {
+        return Some(parse_condition(&items, true).unwrap_or(MediaCondition::Unknown));
+    }
+    Some(parse_feature(&items).unwrap_or(MediaCondition::Unknown))
+}
+
+/// `<media-feature>` in its plain, boolean or range form
+fn parse_feature(items: &[&ComponentValue]) -> Option<MediaCondition> {
+    let feature = |name: String, comparison: Comparison, value: MediaValue| {
+        MediaCondition::Feature(MediaFeature { name, comparison: Some((comparison, value)) })
+    };
+
+    // Boolean: (color)
+    if let [name] = items {
+        return Some(MediaCondition::Feature(MediaFeature { name: ident(name)?, comparison: None }));
+    }
+
+    // Plain: (min-width: 600px)
+    if let [name, ComponentValue::Token(CssToken::Colon), value @ ..] = items {
+        let name = ident(name)?;
+        let value = parse_value(value)?;
+        let (comparison, name) = if let Some(name) = name.strip_prefix("min-") {
+            (Comparison::GreaterOrEqual, name.to_string())
+        } else if let Some(name) = name.strip_prefix("max-") {
+            (Comparison::LessOrEqual, name.to_string())
+        } else {
+            (Comparison::Equal, name)
+        };
+        return Some(feature(name, comparison, value));
+    }
+
+    // Range: (width >= 600px), (600px <= width), (400px < width <= 800px)
+    let (operators, operands) = split_range(items)?;
+    match (operands.as_slice(), operators.as_slice()) {
+        ([left, right], [op]) => {
+            if let Some(name) = single_ident(left) {
+                Some(feature(name, *op, parse_value(right)?))
+            } else {
+                Some(feature(single_ident(right)?, flip(*op), parse_value(left)?))
+            }
+        }
+        ([low, name, high], [op1, op2]) => {
+            let name = single_ident(name)?;
+            let ascending = matches!(op1, Comparison::Less | Comparison::LessOrEqual)
+                && matches!(op2, Comparison::Less | Comparison::LessOrEqual);
+            let descending = matches!(op1, Comparison::Greater | Comparison::GreaterOrEqual)
+                && matches!(op2, Comparison::Greater | Comparison::GreaterOrEqual);
+            if !ascending && !descending {
+                return None;
+            }
+            Some(MediaCondition::And(vec![
+                feature(name.clone(), flip(*op1), parse_value(low)?),
+                feature(name, *op2, parse_value(high)?),
+            ]))
+        }
+        _ => None,
+    }
+}
+
+type RangeParts<'a, 'b> = (Vec<Comparison>, Vec<&'b [&'a ComponentValue]>);
+
+/// Split range syntax into the operands and the comparison operators between them
+fn split_range<'a, 'b>(items: &'b [&'a ComponentValue]) -> Option<RangeParts<'a, 'b>> {
+    let mut operators = Vec::new();
+    let mut operands = Vec::new();
+    let mut start = 0;
+    let mut pos = 0;
+    while pos < items.len() {
+        let delim = |i: usize| match items.get(i) {
+            Some(ComponentValue::Token(CssToken::Delim(c))) => Some(*c),
+            _ => None,
+        };
+        let operator = match (delim(pos), delim(pos + 1)) {
+            (Some('<'), Some('=')) => Some((Comparison::LessOrEqual, 2)),
+            (Some('>'), Some('=')) => Some((Comparison::GreaterOrEqual, 2)),
+            (Some('<'), _) => Some((Comparison::Less, 1)),
+            (Some('>'), _) => Some((Comparison::Greater, 1)),
+            (Some('='), _) => Some((Comparison::Equal, 1)),
+            _ => None,
+        };
+        match operator {
+            Some((operator, length)) => {
+                operands.push(&items[start..pos]);
+                operators.push(operator);
+                pos += length;
+                start = pos;
+            }
+            None => pos += 1,
+        }
+    }
+    operands.push(&items[start..]);
+    if operators.is_empty() || operands.iter().any(|operand| operand.is_empty()) {
+        return None;
+    }
+    Some((operators, operands))
+}
+
+fn single_ident(items: &[&ComponentValue]) -> Option<String> {
+    match items {
+        [value] => ident(value),
+        _ => None,
+    }
+}
+
+/// The comparison with its operands swapped: `a < b` is `b > a`
+fn flip(comparison: Comparison) -> Comparison {
+    match comparison {
+        Comparison::Equal => Comparison::Equal,
+        Comparison::Less => Comparison::Greater,
+        Comparison::LessOrEqual => Comparison::GreaterOrEqual,
+        Comparison::Greater => Comparison::Less,
+        Comparison::GreaterOrEqual => Comparison::LessOrEqual,
+    }
+}
+
+fn parse_value(items: &[&ComponentValue]) -> Option<MediaValue> {
+    match items {
+        [ComponentValue::Token(CssToken::Number(n))] => Some(MediaValue::Number(n.value)),
+        [ComponentValue::Token(CssToken::Number(w)), ComponentValue::Token(CssToken::Delim('/')), ComponentValue::Token(CssToken::Number(h))] => {
+            Some(MediaValue::Ratio(w.value, h.value))
+        }
+        [ComponentValue::Token(CssToken::Dimension { value, unit })] => {
+            if let Some(unit) = LengthUnit::from_unit(unit) {
+                return Some(MediaValue::Length(Length { value: value.value, unit }));
+            }
+            let dppx = match unit.to_ascii_lowercase().as_str() {
+                "dppx" | "x" => 1.0,
+                "dpi" => 1.0 / 96.0,
+                "dpcm" => 2.54 / 96.0,
+                _ => return None,
+            };
+            Some(MediaValue::Resolution(value.value * dppx))
+        }
+        [value] => ident(value).map(MediaValue::Keyword),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(query: &str, width: f32, height: f32) -> bool {
+        MediaQueryList::parse(query).matches(&Viewport::new(width, height), &DeviceFeatures::default())
+    }
+
+    #[test]
+    fn test_media_types_and_plain_features() {
+        assert!(matches("", 800.0, 600.0));
+        assert!(matches("screen", 800.0, 600.0));
+        assert!(matches("only screen and (max-width: 800px)", 800.0, 600.0));
+        assert!(!matches("print", 800.0, 600.0));
+        assert!(matches("print, (min-width: 50em)", 800.0, 600.0));
+        assert!(!matches("screen and (min-width: 801px)", 800.0, 600.0));
+        assert!(matches("not print", 800.0, 600.0));
+        assert!(!matches("not screen and (color)", 800.0, 600.0));
+        assert!(!matches("tv", 800.0, 600.0));
+    }
+
+    #[test]
+    fn test_range_syntax_and_logic() {
+        assert!(matches("(width >= 600px)", 800.0, 600.0));
+        assert!(matches("(1000px > width)", 800.0, 600.0));
+        assert!(matches("(400px < width <= 800px)", 800.0, 600.0));
+        assert!(!matches("(400px < width < 800px)", 800.0, 600.0));
+        assert!(matches("(width < 500px) or (orientation: landscape)", 800.0, 600.0));
+        assert!(!matches("(orientation: landscape) and (not (hover))", 800.0, 600.0));
+        assert!(matches("not ((width < 500px) or (height < 500px))", 800.0, 600.0));
+        assert!(matches("(min-aspect-ratio: 4/3) and (min-resolution: 96dpi)", 800.0, 600.0));
+        assert!(matches("(prefers-color-scheme: light) and (pointer: fine)", 800.0, 600.0));
+    }
+
+    #[test]
+    fn test_unknown_and_invalid_queries_never_match() {
+        assert!(!matches("(unknown-feature)", 800.0, 600.0));
+        assert!(!matches("not (unknown-feature)", 800.0, 600.0));
+        assert!(matches("(unknown-feature) or (color)", 800.0, 600.0));
+        assert!(!matches("screen and (color) or (hover)", 800.0, 600.0));
+        assert!(!matches("screen and", 800.0, 600.0));
+        assert!(matches("screen and, print, screen", 800.0, 600.0));
+        assert!(!matches("(width: )", 800.0, 600.0));
+    }
+
+    #[test]
+    fn test_device_features() {
+        let list = MediaQueryList::parse("(prefers-color-scheme: dark), print");
+        let dark = DeviceFeatures { color_scheme: ColorScheme::Dark, ..DeviceFeatures::default() };
+        let viewport = Viewport::default();
+        assert!(list.matches(&viewport, &dark));
+        assert!(!list.matches(&viewport, &DeviceFeatures::default()));
+        let print = DeviceFeatures { media_type: MediaType::Print, ..DeviceFeatures::default() };
+        assert!(list.matches(&viewport, &print));
+    }
+
+    #[test]
+    fn test_media_rules_cascade() {
+        use crate::parser::css::{CssParser, CssTokenizer};
+        use crate::parser::html::tree_builder::HtmlParser;
+        use crate::style::Stylesheet;
+
+        let css = "p { color: red }
+            @media (min-width: 600px) { p { color: green } @media print { p { color: blue } } }
+            @media (max-width: 599px) { p { color: navy } }";
+        let mut stylesheet = Stylesheet::new();
+        stylesheet.add_items(CssParser::new(CssTokenizer::new(css).tokenize()).parse());
+        let dom = HtmlParser::new("<p>x</p>").parse();
+        let p = dom.query_selector("p").unwrap();
+        let color = |width: f32| {
+            let style = stylesheet.compute_style_with_viewport(&dom, p, &Viewport::new(width, 600.0));
+            style.get("color").unwrap().to_string()
+        };
+        assert_eq!(color(800.0), "green");
+        assert_eq!(color(400.0), "navy");
+    }
+}
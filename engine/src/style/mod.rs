@@ -1,6 +1,22 @@
+pub mod calc;
+pub mod cascade;
+pub mod loader;
+pub mod media;
+pub mod values;
+pub mod variables;
+
 use crate::dom::NodeId;
 use crate::dom::{Dom, NodeType};
+use crate::parser::css::{ComponentValue, CssItem, CssParser, CssTokenizer, Declaration, Selector, Specificity};
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
+
+pub use calc::MathExpr;
+pub use cascade::{Origin, StyleDeclaration};
+pub use loader::{load_stylesheets, StylesheetFetcher};
+pub use media::{DeviceFeatures, MediaCondition, MediaQueryList};
+pub use values::{Color, CssValue, Length, LengthContext, LengthUnit, DEFAULT_FONT_SIZE};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Breakpoint {
@@ -45,352 +61,226 @@ impl Default for Viewport {
 
 #[derive(Debug, Clone)]
 pub struct Style {
-    pub properties: HashMap<String, String>,
+    pub properties: HashMap<String, CssValue>,
+    /// Computed custom properties (`--name`), with var() already substituted
+    pub custom_properties: HashMap<String, variables::CustomValue>,
 }
 
 impl Style {
     pub fn new() -> Self {
         Self {
             properties: HashMap::new(),
+            custom_properties: HashMap::new(),
         }
     }
 
-    pub fn get(&self, key: &str) -> Option<&str> {
-        self.properties.get(key).map(|s| s.as_str())
+    /// The computed value of a custom property, like `get_custom_property("--gap")`
+    pub fn get_custom_property(&self, name: &str) -> Option<&[ComponentValue]> {
+        self.custom_properties.get(name).map(|value| value.as_slice())
     }
 
-    pub fn get_font_family(&self) -> &str {
-        self.get("font-family").unwrap_or("Times New Roman")
+    /// Build a style from parsed declarations, parsing each value once
+    pub fn from_declarations(declarations: &[Declaration]) -> Self {
+        let mut style = Self::new();
+        for declaration in declarations {
+            style.set(&declaration.property, CssValue::parse(&declaration.components));
+        }
+        style
     }
 
-    pub fn get_font_size(&self) -> f32 {
-        self.get("font-size")
-            .and_then(|s| {
-                let s = s.trim();
-                if s.ends_with("px") {
-                    s.trim_end_matches("px").parse().ok()
-                } else if s.ends_with("em") {
-                    s.trim_end_matches("em").parse::<f32>().ok().map(|e| e * 16.0)
-                } else if s.ends_with("rem") {
-                    s.trim_end_matches("rem").parse::<f32>().ok().map(|e| e * 16.0)
-                } else {
-                    s.parse().ok()
+    pub fn get(&self, key: &str) -> Option<&CssValue> {
+        self.properties.get(key)
+    }
+
+    /// Set a property; shorthands like `margin` set their longhands instead
+    pub fn set(&mut self, property: &str, value: CssValue) {
+        match cascade::expand_shorthand(property, &value) {
+            Some(longhands) => {
+                for (longhand, value) in longhands {
+                    self.properties.insert(longhand.to_string(), value);
                 }
-            })
-            .unwrap_or(16.0)
+            }
+            None => {
+                self.properties.insert(property.to_string(), value);
+            }
+        }
     }
 
-    pub fn get_font_weight(&self) -> &str {
-        self.get("font-weight").unwrap_or("normal")
+    /// Set a property from CSS text, like `set_str("margin", "0 auto")`
+    pub fn set_str(&mut self, property: &str, value: &str) {
+        self.set(property, CssValue::parse_str(value));
+    }
+
+    /// The font-family list as a comma-separated string of family names
+    pub fn get_font_family(&self) -> String {
+        let Some(value) = self.get("font-family") else {
+            return "Times New Roman".to_string();
+        };
+        // Unquoted names with spaces arrive as lists of keywords
+        let family = |value: &CssValue| match value {
+            CssValue::String(name) | CssValue::Keyword(name) => name.clone(),
+            CssValue::List(words) => words.iter().map(|w| w.to_string()).collect::<Vec<_>>().join(" "),
+            other => other.to_string(),
+        };
+        match value {
+            CssValue::CommaList(families) => families.iter().map(family).collect::<Vec<_>>().join(", "),
+            value => family(value),
+        }
+    }
+
+    /// The font size in pixels. Relative sizes resolve against the default
+    /// font size, since styles do not know their parent's.
+    pub fn get_font_size(&self) -> f32 {
+        let Some(value) = self.get("font-size") else {
+            return DEFAULT_FONT_SIZE;
+        };
+        let keyword_size = value.as_keyword().and_then(|keyword| {
+            let scale = match keyword.to_ascii_lowercase().as_str() {
+                "xx-small" => 0.5625,
+                "x-small" => 0.625,
+                "small" | "smaller" => 0.8125,
+                "medium" => 1.0,
+                "large" | "larger" => 1.125,
+                "x-large" => 1.5,
+                "xx-large" => 2.0,
+                "xxx-large" => 3.0,
+                _ => return None,
+            };
+            Some(DEFAULT_FONT_SIZE * scale)
+        });
+        keyword_size
+            .or_else(|| value.to_px(&LengthContext::default(), DEFAULT_FONT_SIZE))
+            .unwrap_or(DEFAULT_FONT_SIZE)
+    }
+
+    /// The numeric font weight, 400 for normal and 700 for bold
+    pub fn get_font_weight(&self) -> u16 {
+        match self.get("font-weight") {
+            Some(CssValue::Number(weight)) => weight.clamp(1.0, 1000.0) as u16,
+            Some(value) if value.is_keyword("bold") || value.is_keyword("bolder") => 700,
+            Some(value) if value.is_keyword("lighter") => 300,
+            _ => 400,
+        }
     }
 
     pub fn is_bold(&self) -> bool {
-        matches!(self.get_font_weight(), "bold" | "700" | "800" | "900")
+        self.get_font_weight() >= 600
     }
 
     pub fn get_font_style(&self) -> &str {
-        self.get("font-style").unwrap_or("normal")
+        self.get("font-style").and_then(CssValue::as_keyword).unwrap_or("normal")
     }
 
     pub fn is_italic(&self) -> bool {
-        self.get_font_style() == "italic"
+        let style = self.get_font_style();
+        style.eq_ignore_ascii_case("italic") || style.eq_ignore_ascii_case("oblique")
     }
 
     pub fn get_color(&self) -> (u8, u8, u8) {
-        if let Some(color_str) = self.get("color") {
-            parse_color(color_str)
-        } else {
-            (0, 0, 0)
-        }
+        let color = self.get("color").and_then(CssValue::as_color).unwrap_or(Color::BLACK);
+        (color.r, color.g, color.b)
     }
 
+    /// The background color from `background-color` or the color in the
+    /// `background` shorthand; None when there is none or it is transparent
     pub fn get_background_color(&self) -> Option<(u8, u8, u8)> {
-        self.get("background").or_else(|| self.get("background-color")).map(|c| parse_color(c))
+        let color = match self.get("background-color") {
+            Some(value) => value.as_color(),
+            None => self.get("background")?.items().iter().find_map(CssValue::as_color),
+        }?;
+        (!color.is_transparent()).then_some((color.r, color.g, color.b))
+    }
+
+    /// The first url() in `background-image` or the `background` shorthand
+    pub fn get_background_image(&self) -> Option<&str> {
+        let value = self.get("background-image").or_else(|| self.get("background"))?;
+        value.items().iter().find_map(|item| match item {
+            CssValue::Url(url) => Some(url.as_str()),
+            _ => None,
+        })
     }
 
     pub fn get_opacity(&self) -> f32 {
-        self.get("opacity")
-            .and_then(|s| s.trim().parse().ok())
-            .unwrap_or(1.0)
+        match self.get("opacity") {
+            Some(CssValue::Number(n)) => n.clamp(0.0, 1.0),
+            Some(CssValue::Percentage(p)) => (p / 100.0).clamp(0.0, 1.0),
+            _ => 1.0,
+        }
     }
 
-    pub fn get_text_decoration(&self) -> Option<&str> {
+    pub fn get_text_decoration(&self) -> Option<&CssValue> {
         self.get("text-decoration")
     }
 
     pub fn has_text_decoration(&self, decoration: &str) -> bool {
         self.get_text_decoration()
-            .map(|d| d.contains(decoration))
-            .unwrap_or(false)
+            .is_some_and(|value| value.items().iter().any(|item| item.is_keyword(decoration)))
     }
-    
+
+    /// The width as a fraction of the viewport, for `%` and `vw` widths
     pub fn get_width_percentage(&self) -> Option<f32> {
-        self.get("width")
-            .and_then(|s| {
-                let s = s.trim();
-                if s.ends_with("vw") {
-                    // Viewport width percentage
-                    s.trim_end_matches("vw").parse::<f32>().ok().map(|v| v / 100.0)
-                } else if s.ends_with("%") {
-                    // Percentage
-                    s.trim_end_matches("%").parse::<f32>().ok().map(|v| v / 100.0)
-                } else {
-                    None
-                }
-            })
-    }
-
-    /// Parse a spacing value (padding or margin) that can be in px, em, or vh
-    /// Note: vh values just return the raw number, must be multiplied by viewport height / 100 by caller
-    fn parse_spacing_value(value: &str) -> f32 {
-        let s = value.trim();
-        if s.ends_with("px") {
-            s.trim_end_matches("px").parse::<f32>().unwrap_or(0.0)
-        } else if s.ends_with("em") {
-            s.trim_end_matches("em").parse::<f32>().unwrap_or(0.0) * 16.0 // 1em = 16px
-        } else if s.ends_with("vh") {
-            // Return negative to signal it's a vh value, caller will handle
-            s.trim_end_matches("vh").parse::<f32>().unwrap_or(0.0)
-        } else {
-            s.parse::<f32>().unwrap_or(0.0)
+        match self.get("width")? {
+            CssValue::Percentage(p) => Some(p / 100.0),
+            CssValue::Length(Length { value, unit: LengthUnit::Vw }) => Some(value / 100.0),
+            _ => None,
         }
     }
 
-    /// Parse a spacing value with viewport awareness
-    fn parse_spacing_value_with_viewport(value: &str, viewport_height: f32) -> f32 {
-        let s = value.trim();
-        if s.ends_with("px") {
-            s.trim_end_matches("px").parse::<f32>().unwrap_or(0.0)
-        } else if s.ends_with("em") {
-            s.trim_end_matches("em").parse::<f32>().unwrap_or(0.0) * 16.0 // 1em = 16px
-        } else if s.ends_with("vh") {
-            // vh = viewport height percentage
-            let vh_value = s.trim_end_matches("vh").parse::<f32>().unwrap_or(0.0);
-            viewport_height * vh_value / 100.0
-        } else if s.ends_with("vw") {
-            // vw = viewport width percentage - caller must handle this separately
-            // For now just parse the number
-            s.trim_end_matches("vw").parse::<f32>().unwrap_or(0.0)
-        } else {
-            s.parse::<f32>().unwrap_or(0.0)
+    /// What this element's relative lengths resolve against
+    pub fn length_context(&self, viewport: &Viewport) -> LengthContext {
+        LengthContext {
+            font_size: self.get_font_size(),
+            root_font_size: DEFAULT_FONT_SIZE,
+            viewport: *viewport,
         }
     }
 
-    /// Get padding value (top, right, bottom, left) - returns all four values
-    pub fn get_padding(&self) -> (f32, f32, f32, f32) {
-        if let Some(padding_str) = self.get("padding") {
-            let parts: Vec<&str> = padding_str.split_whitespace().collect();
-            match parts.len() {
-                1 => {
-                    let val = Self::parse_spacing_value(parts[0]);
-                    (val, val, val, val) // all sides same
-                }
-                2 => {
-                    let v1 = Self::parse_spacing_value(parts[0]);
-                    let v2 = Self::parse_spacing_value(parts[1]);
-                    (v1, v2, v1, v2) // top/bottom, left/right
-                }
-                3 => {
-                    let top = Self::parse_spacing_value(parts[0]);
-                    let h = Self::parse_spacing_value(parts[1]);
-                    let bottom = Self::parse_spacing_value(parts[2]);
-                    (top, h, bottom, h)
-                }
-                _ => {
-                    let top = Self::parse_spacing_value(parts[0]);
-                    let right = Self::parse_spacing_value(parts[1]);
-                    let bottom = Self::parse_spacing_value(parts[2]);
-                    let left = Self::parse_spacing_value(parts[3]);
-                    (top, right, bottom, left)
-                }
-            }
-        } else {
-            (0.0, 0.0, 0.0, 0.0)
-        }
+    /// A length in pixels, including calc() and the other math functions.
+    /// Percentages resolve against the containing block's width, as they do
+    /// for widths, margins and padding; `auto` and unknown values are 0.
+    fn resolve_length(&self, value: Option<&CssValue>, viewport: &Viewport, containing_width: f32) -> f32 {
+        value
+            .and_then(|v| v.to_px(&self.length_context(viewport), containing_width))
+            .unwrap_or(0.0)
     }
 
-    /// Get margin value (top, right, bottom, left) - returns all four values
-    /// Supports both shorthand `margin` and individual `margin-top`, `margin-right`, etc.
-    pub fn get_margin(&self) -> (f32, f32, f32, f32) {
-        let mut top = 0.0_f32;
-        let mut right = 0.0_f32;
-        let mut bottom = 0.0_f32;
-        let mut left = 0.0_f32;
-
-        // First check shorthand margin
-        if let Some(margin_str) = self.get("margin") {
-            let parts: Vec<&str> = margin_str.split_whitespace().collect();
-            match parts.len() {
-                1 => {
-                    let val = Self::parse_margin_value(parts[0]);
-                    top = val; right = val; bottom = val; left = val;
-                }
-                2 => {
-                    let v1 = Self::parse_margin_value(parts[0]);
-                    let v2 = Self::parse_margin_value(parts[1]);
-                    top = v1; bottom = v1; right = v2; left = v2;
-                }
-                3 => {
-                    top = Self::parse_margin_value(parts[0]);
-                    right = Self::parse_margin_value(parts[1]);
-                    left = right;
-                    bottom = Self::parse_margin_value(parts[2]);
-                }
-                _ => {
-                    top = Self::parse_margin_value(parts[0]);
-                    right = Self::parse_margin_value(parts[1]);
-                    bottom = Self::parse_margin_value(parts[2]);
-                    left = Self::parse_margin_value(parts[3]);
-                }
-            }
-        }
-
-        // Override with individual properties if present
-        if let Some(mt) = self.get("margin-top") {
-            top = Self::parse_margin_value(mt);
-        }
-        if let Some(mr) = self.get("margin-right") {
-            right = Self::parse_margin_value(mr);
-        }
-        if let Some(mb) = self.get("margin-bottom") {
-            bottom = Self::parse_margin_value(mb);
-        }
-        if let Some(ml) = self.get("margin-left") {
-            left = Self::parse_margin_value(ml);
-        }
-
-        (top, right, bottom, left)
+    /// The top, right, bottom and left longhands of a box property like
+    /// `margin`; the shorthand itself is expanded when it is declared
+    fn box_sides(&self, property: &str) -> [Option<&CssValue>; 4] {
+        ["top", "right", "bottom", "left"].map(|side| self.get(&format!("{}-{}", property, side)))
     }
 
-    /// Get margin value (top, right, bottom, left) with viewport unit support
-    /// vh values are converted to pixels using viewport_height
-    pub fn get_margin_with_viewport(&self, viewport_height: f32) -> (f32, f32, f32, f32) {
-        let mut top = 0.0_f32;
-        let mut right = 0.0_f32;
-        let mut bottom = 0.0_f32;
-        let mut left = 0.0_f32;
-
-        // First check shorthand margin
-        if let Some(margin_str) = self.get("margin") {
-            let parts: Vec<&str> = margin_str.split_whitespace().collect();
-            match parts.len() {
-                1 => {
-                    let val = Self::parse_margin_value_with_viewport(parts[0], viewport_height);
-                    top = val; right = val; bottom = val; left = val;
-                }
-                2 => {
-                    let v1 = Self::parse_margin_value_with_viewport(parts[0], viewport_height);
-                    let v2 = Self::parse_margin_value_with_viewport(parts[1], viewport_height);
-                    top = v1; bottom = v1; right = v2; left = v2;
-                }
-                3 => {
-                    top = Self::parse_margin_value_with_viewport(parts[0], viewport_height);
-                    right = Self::parse_margin_value_with_viewport(parts[1], viewport_height);
-                    left = right;
-                    bottom = Self::parse_margin_value_with_viewport(parts[2], viewport_height);
-                }
-                _ => {
-                    top = Self::parse_margin_value_with_viewport(parts[0], viewport_height);
-                    right = Self::parse_margin_value_with_viewport(parts[1], viewport_height);
-                    bottom = Self::parse_margin_value_with_viewport(parts[2], viewport_height);
-                    left = Self::parse_margin_value_with_viewport(parts[3], viewport_height);
-                }
-            }
-        }
-
-        // Override with individual properties if present
-        if let Some(mt) = self.get("margin-top") {
-            top = Self::parse_margin_value_with_viewport(mt, viewport_height);
-        }
-        if let Some(mr) = self.get("margin-right") {
-            right = Self::parse_margin_value_with_viewport(mr, viewport_height);
-        }
-        if let Some(mb) = self.get("margin-bottom") {
-            bottom = Self::parse_margin_value_with_viewport(mb, viewport_height);
-        }
-        if let Some(ml) = self.get("margin-left") {
-            left = Self::parse_margin_value_with_viewport(ml, viewport_height);
-        }
-
+    fn box_sides_px(&self, property: &str, viewport: &Viewport, containing_width: f32) -> (f32, f32, f32, f32) {
+        let [top, right, bottom, left] =
+            self.box_sides(property).map(|side| self.resolve_length(side, viewport, containing_width));
         (top, right, bottom, left)
     }
 
-    /// Check if element has auto horizontal margin (for centering)
-    pub fn has_auto_horizontal_margin(&self) -> bool {
-        // Check individual properties first
-        let left_auto = self.get("margin-left").map(|s| s.trim() == "auto").unwrap_or(false);
-        let right_auto = self.get("margin-right").map(|s| s.trim() == "auto").unwrap_or(false);
-        
-        if left_auto && right_auto {
-            return true;
-        }
-
-        // Check shorthand margin
-        if let Some(margin_str) = self.get("margin") {
-            let parts: Vec<&str> = margin_str.split_whitespace().collect();
-            match parts.len() {
-                2 => {
-                    // margin: vertical horizontal
-                    return parts[1].trim() == "auto";
-                }
-                4 => {
-                    // margin: top right bottom left
-                    return parts[1].trim() == "auto" && parts[3].trim() == "auto";
-                }
-                _ => {}
-            }
-        }
-        false
+    /// Padding (top, right, bottom, left) in pixels, from the `padding-*` longhands
+    pub fn get_padding(&self, viewport: &Viewport, containing_width: f32) -> (f32, f32, f32, f32) {
+        self.box_sides_px("padding", viewport, containing_width)
     }
 
-    /// Parse margin value, returning 0 for "auto" (to be handled by layout engine)
-    fn parse_margin_value(value: &str) -> f32 {
-        let s = value.trim();
-        if s == "auto" {
-            0.0 // Layout engine will handle auto margin
-        } else {
-            Self::parse_spacing_value(s)
-        }
+    /// Margin (top, right, bottom, left) in pixels, from the `margin-*`
+    /// longhands. Auto margins are 0 here and handled by the layout engine.
+    pub fn get_margin(&self, viewport: &Viewport, containing_width: f32) -> (f32, f32, f32, f32) {
+        self.box_sides_px("margin", viewport, containing_width)
     }
 
-    /// Parse margin value with viewport unit support, returning 0 for "auto"
-    fn parse_margin_value_with_viewport(value: &str, viewport_height: f32) -> f32 {
-        let s = value.trim();
-        if s == "auto" {
-            0.0 // Layout engine will handle auto margin
-        } else {
-            Self::parse_spacing_value_with_viewport(s, viewport_height)
-        }
+    /// Check if element has auto horizontal margin (for centering)
+    pub fn has_auto_horizontal_margin(&self) -> bool {
+        let [_, right, _, left] = self.box_sides("margin");
+        let is_auto = |side: Option<&CssValue>| side.is_some_and(|v| v.is_keyword("auto"));
+        is_auto(left) && is_auto(right)
     }
 
-    pub fn get_width_px(&self, viewport_width: f32) -> Option<f32> {
-        self.get("width").and_then(|s| {
-            let s = s.trim();
-            if s.ends_with("vw") {
-                s.trim_end_matches("vw").parse::<f32>().ok().map(|v| viewport_width * v / 100.0)
-            } else if s.ends_with("%") {
-                s.trim_end_matches("%").parse::<f32>().ok().map(|v| viewport_width * v / 100.0)
-            } else if s.ends_with("px") {
-                s.trim_end_matches("px").parse::<f32>().ok()
-            } else {
-                s.parse::<f32>().ok()
-            }
-        })
+    /// The width in pixels; None for `auto` and unknown values
+    pub fn get_width_px(&self, viewport: &Viewport, containing_width: f32) -> Option<f32> {
+        self.get("width")?.to_px(&self.length_context(viewport), containing_width)
     }
 
-    pub fn get_max_width_px(&self, viewport_width: f32) -> Option<f32> {
-        self.get("max-width").and_then(|s| {
-            let s = s.trim();
-            if s.ends_with("vw") {
-                s.trim_end_matches("vw").parse::<f32>().ok().map(|v| viewport_width * v / 100.0)
-            } else if s.ends_with("%") {
-                s.trim_end_matches("%").parse::<f32>().ok().map(|v| viewport_width * v / 100.0)
-            } else if s.ends_with("px") {
-                s.trim_end_matches("px").parse::<f32>().ok()
-            } else {
-                s.parse::<f32>().ok()
-            }
-        })
+    pub fn get_max_width_px(&self, viewport: &Viewport, containing_width: f32) -> Option<f32> {
+        self.get("max-width")?.to_px(&self.length_context(viewport), containing_width)
     }
 }
 
@@ -406,70 +296,65 @@ impl Default for Style {
     }
 }
 
-fn parse_color(color: &str) -> (u8, u8, u8) {
-    let color = color.trim();
-    if color.starts_with('#') {
-        let hex = &color[1..];
-        if hex.len() == 3 {
-            let r = u8::from_str_radix(&format!("{}{}", &hex[0..1], &hex[0..1]), 16).unwrap_or(0);
-            let g = u8::from_str_radix(&format!("{}{}", &hex[1..2], &hex[1..2]), 16).unwrap_or(0);
-            let b = u8::from_str_radix(&format!("{}{}", &hex[2..3], &hex[2..3]), 16).unwrap_or(0);
-            (r, g, b)
-        } else if hex.len() >= 6 {
-            let r = u8::from_str_radix(&hex[0..2], 16).unwrap_or(0);
-            let g = u8::from_str_radix(&hex[2..4], 16).unwrap_or(0);
-            let b = u8::from_str_radix(&hex[4..6], 16).unwrap_or(0);
-            (r, g, b)
-        } else {
-            (0, 0, 0)
-        }
-    } else {
-        (0, 0, 0)
-    }
-}
-
 #[derive(Debug, Clone)]
 pub struct CssRule {
     pub selector: Selector,
-    pub declarations: Style,
+    pub specificity: Specificity,
+    pub origin: Origin,
+    /// Position among the stylesheet's rules, assigned when the rule is added
+    pub order: usize,
+    pub declarations: Vec<StyleDeclaration>,
 }
 
-#[derive(Debug, Clone)]
-pub struct MediaRule {
-    pub condition: MediaCondition,
-    pub rules: Vec<CssRule>,
+impl CssRule {
+    /// An author rule from parsed declarations
+    pub fn new(selector: Selector, declarations: &[Declaration]) -> Self {
+        Self {
+            specificity: selector.specificity(),
+            selector,
+            origin: Origin::Author,
+            order: 0,
+            declarations: StyleDeclaration::from_declarations(declarations),
+        }
+    }
+
+    pub fn with_origin(mut self, origin: Origin) -> Self {
+        self.origin = origin;
+        self
+    }
 }
 
+/// Rules inside `@media`, which apply while every enclosing query list
+/// matches (one list per level of nesting)
 #[derive(Debug, Clone)]
-pub enum MediaCondition {
-    MinWidth(f32),
-    MaxWidth(f32),
-    Breakpoint(Breakpoint),
+pub struct MediaRule {
+    pub queries: Vec<MediaQueryList>,
+    pub rules: Vec<CssRule>,
 }
 
-impl MediaCondition {
-    pub fn matches(&self, viewport: &Viewport) -> bool {
-        match self {
-            MediaCondition::MinWidth(min) => viewport.width >= *min,
-            MediaCondition::MaxWidth(max) => viewport.width <= *max,
-            MediaCondition::Breakpoint(bp) => viewport.breakpoint() == *bp,
-        }
+impl MediaRule {
+    pub fn matches(&self, viewport: &Viewport, device: &DeviceFeatures) -> bool {
+        self.queries.iter().all(|list| list.matches(viewport, device))
     }
 }
 
-#[derive(Debug, Clone)]
-pub enum Selector {
-    Tag(String),
-    Class(String),
-    Id(String),
-    TagWithPseudo(String, String),
-    Any,
-}
-
 pub struct Stylesheet {
     pub rules: Vec<CssRule>,
     pub media_rules: Vec<MediaRule>,
     viewport: Viewport,
+    device: DeviceFeatures,
+    /// Source order for the next rule added
+    next_order: usize,
+    /// Parsed style attributes, reparsed when the attribute text changes
+    inline_styles: RefCell<HashMap<NodeId, InlineStyle>>,
+    /// Parsed user agent declarations, by tag name
+    default_declarations: RefCell<HashMap<String, Rc<Vec<StyleDeclaration>>>>,
+}
+
+/// The declarations of an element's style attribute
+struct InlineStyle {
+    source: String,
+    declarations: Rc<Vec<StyleDeclaration>>,
 }
 
 impl Stylesheet {
@@ -478,6 +363,10 @@ impl Stylesheet {
             rules: vec![], 
             media_rules: vec![],
             viewport: Viewport::default(),
+            device: DeviceFeatures::default(),
+            next_order: 0,
+            inline_styles: RefCell::new(HashMap::new()),
+            default_declarations: RefCell::new(HashMap::new()),
         } 
     }
 
@@ -489,12 +378,61 @@ impl Stylesheet {
         self.viewport
     }
 
-    pub fn add_rule(&mut self, selector: Selector, declarations: Style) {
-        self.rules.push(CssRule { selector, declarations });
+    pub fn set_device(&mut self, device: DeviceFeatures) {
+        self.device = device;
     }
 
-    pub fn add_media_rule(&mut self, condition: MediaCondition, rules: Vec<CssRule>) {
-        self.media_rules.push(MediaRule { condition, rules });
+    /// Add a rule after every rule added so far
+    pub fn add_rule(&mut self, mut rule: CssRule) {
+        rule.order = self.take_order();
+        self.rules.push(rule);
+    }
+
+    /// Add rules that apply while every query list matches, after every rule added so far
+    pub fn add_media_rule(&mut self, queries: Vec<MediaQueryList>, mut rules: Vec<CssRule>) {
+        for rule in &mut rules {
+            rule.order = self.take_order();
+        }
+        self.media_rules.push(MediaRule { queries, rules });
+    }
+
+    /// Add the style rules and `@media` blocks of a parsed stylesheet as
+    /// author rules, in source order. Other at-rules are ignored.
+    pub fn add_items(&mut self, items: Vec<CssItem>) {
+        self.add_items_in_media(items, &[]);
+    }
+
+    pub(crate) fn add_items_in_media(&mut self, items: Vec<CssItem>, queries: &[MediaQueryList]) {
+        let mut rules = Vec::new();
+        for item in items {
+            match item {
+                CssItem::Rule(rule) => rules.push(CssRule::new(rule.selector, &rule.declarations)),
+                CssItem::AtRule { name, prelude, content } if name.eq_ignore_ascii_case("media") => {
+                    // Flush what came before so source order is kept
+                    self.add_rules_in_media(std::mem::take(&mut rules), queries);
+                    let mut nested = queries.to_vec();
+                    nested.push(MediaQueryList::parse(&prelude));
+                    self.add_items_in_media(content, &nested);
+                }
+                CssItem::AtRule { .. } => {}
+            }
+        }
+        self.add_rules_in_media(rules, queries);
+    }
+
+    fn add_rules_in_media(&mut self, rules: Vec<CssRule>, queries: &[MediaQueryList]) {
+        if queries.is_empty() {
+            for rule in rules {
+                self.add_rule(rule);
+            }
+        } else if !rules.is_empty() {
+            self.add_media_rule(queries.to_vec(), rules);
+        }
+    }
+
+    fn take_order(&mut self) -> usize {
+        self.next_order += 1;
+        self.next_order - 1
     }
 
     pub fn compute_style(&self, dom: &Dom, node_id: NodeId) -> Style {
@@ -503,64 +441,85 @@ impl Stylesheet {
 
     pub fn compute_style_with_viewport(&self, dom: &Dom, node_id: NodeId, viewport: &Viewport) -> Style {
         let node = &dom.nodes[node_id];
-        let mut result = Style { properties: HashMap::new() };
+        let mut result = Style::new();
 
         if let NodeType::Element(el) = &node.node_type {
             // Step 0: Inherit inheritable properties from parent element
-            if let Some(parent_id) = node.parent {
-                let parent_style = self.compute_style_with_viewport(dom, parent_id, viewport);
+            let parent_style = node.parent.map(|parent_id| self.compute_style_with_viewport(dom, parent_id, viewport));
+            if let Some(parent_style) = &parent_style {
                 // Inherit inheritable CSS properties
                 for (key, value) in &parent_style.properties {
                     if self.is_inheritable_property(key) {
                         result.properties.insert(key.clone(), value.clone());
                     }
                 }
-            }
-
-            // Step 1: Apply default user agent styles for this element type
-            self.apply_default_styles(&mut result, &el.tag_name);
-
-            // Step 2: Iterate through custom CSS rules and apply matching ones
-            // If a property exists in the custom rule, it overrides the default
-            // If a property doesn't exist in the custom rule, the default is kept
-            for rule in &self.rules {
-                let matches = match &rule.selector {
-                    Selector::Tag(tag) if tag == "*" => true,
-                    Selector::Tag(tag) if tag == &el.tag_name => true,
-                    Selector::Id(id) => el.attributes.iter().any(|(k, v)| k == "id" && v == id),
-                    Selector::Class(class) => el.attributes.iter().any(|(k, v)| k == "class" && v == class),
-                    Selector::TagWithPseudo(tag, _pseudo) => tag == &el.tag_name,
-                    Selector::Any => true,
-                    _ => false,
-                };
-
-                if matches {
-                    // Apply custom rule properties - these override defaults
-                    for (key, value) in &rule.declarations.properties {
-                        result.properties.insert(key.clone(), value.clone());
-                    }
+                // Custom properties are always inherited
+                result.custom_properties = parent_style.custom_properties.clone();
+            }
+
+            // Step 1: Default user agent styles for this element type.
+            // These are normal user-agent declarations, the lowest cascade level.
+            let defaults = self.default_declarations(&el.tag_name);
+            let mut candidates: Vec<_> = defaults
+                .iter()
+                .map(|declaration| cascade::Candidate {
+                    origin: Origin::UserAgent,
+                    element_attached: false,
+                    specificity: Specificity::default(),
+                    order: 0,
+                    declaration,
+                })
+                .collect();
+
+            // Step 2: Cascade the declarations of every matching rule,
+            // including media rules whose queries match the viewport and device
+            let media_rules = self
+                .media_rules
+                .iter()
+                .filter(|media_rule| media_rule.matches(viewport, &self.device))
+                .flat_map(|media_rule| &media_rule.rules);
+            candidates.extend(
+                self.rules
+                    .iter()
+                    .chain(media_rules)
+                    .filter(|rule| dom.matches(node_id, &rule.selector))
+                    .flat_map(|rule| {
+                        rule.declarations.iter().map(move |declaration| cascade::Candidate {
+                            origin: rule.origin,
+                            element_attached: false,
+                            specificity: rule.specificity,
+                            order: rule.order,
+                            declaration,
+                        })
+                    }),
+            );
+
+            // Step 3: The style attribute, above every author rule of the
+            // same importance
+            let inline = self.inline_declarations(dom, node_id);
+            candidates.extend(inline.iter().flat_map(|declarations| declarations.iter()).map(|declaration| {
+                cascade::Candidate {
+                    origin: Origin::Author,
+                    element_attached: true,
+                    specificity: Specificity::default(),
+                    order: 0,
+                    declaration,
                 }
-            }
-
-            // Step 3: Apply matching media query rules based on viewport
-            for media_rule in &self.media_rules {
-                if media_rule.condition.matches(viewport) {
-                    for rule in &media_rule.rules {
-                        let media_matches = match &rule.selector {
-                            Selector::Tag(tag) if tag == "*" => true,
-                            Selector::Tag(tag) if tag == &el.tag_name => true,
-                            Selector::Id(id) => el.attributes.iter().any(|(k, v)| k == "id" && v == id),
-                            Selector::Class(class) => el.attributes.iter().any(|(k, v)| k == "class" && v == class),
-                            Selector::TagWithPseudo(tag, _pseudo) => tag == &el.tag_name,
-                            Selector::Any => true,
-                            _ => false,
-                        };
-
-                        if media_matches {
-                            for (key, value) in &rule.declarations.properties {
-                                result.properties.insert(key.clone(), value.clone());
-                            }
-                        }
+            }));
+            let invalid = cascade::apply(&mut result, candidates);
+
+            // Step 4: Declarations that failed var() substitution act as
+            // `unset`: inherited properties take the parent's value and the
+            // rest their initial value
+            for property in invalid {
+                let inherited = parent_style
+                    .as_ref()
+                    .filter(|_| self.is_inheritable_property(&property))
+                    .and_then(|parent_style| parent_style.get(&property));
+                match inherited {
+                    Some(value) => result.set(&property, value.clone()),
+                    None => {
+                        result.properties.remove(&property);
                     }
                 }
             }
@@ -574,6 +533,33 @@ impl Stylesheet {
         result
     }
 
+    /// The parsed declarations of the element's style attribute, cached
+    /// until the attribute changes
+    fn inline_declarations(&self, dom: &Dom, node_id: NodeId) -> Option<Rc<Vec<StyleDeclaration>>> {
+        let source = dom.get_attribute(node_id, "style")?;
+        let mut cache = self.inline_styles.borrow_mut();
+        if let Some(cached) = cache.get(&node_id).filter(|cached| cached.source == source) {
+            return Some(cached.declarations.clone());
+        }
+        let declarations = CssParser::new(CssTokenizer::new(source).tokenize()).parse_declaration_list();
+        let declarations = Rc::new(StyleDeclaration::from_declarations(&declarations));
+        cache.insert(node_id, InlineStyle { source: source.to_string(), declarations: declarations.clone() });
+        Some(declarations)
+    }
+
+    /// The parsed user agent declarations for an element type
+    fn default_declarations(&self, tag_name: &str) -> Rc<Vec<StyleDeclaration>> {
+        let mut cache = self.default_declarations.borrow_mut();
+        if let Some(cached) = cache.get(tag_name) {
+            return cached.clone();
+        }
+        let css = Self::default_styles(tag_name);
+        let declarations = CssParser::new(CssTokenizer::new(css).tokenize()).parse_declaration_list();
+        let declarations = Rc::new(StyleDeclaration::from_declarations(&declarations));
+        cache.insert(tag_name.to_string(), declarations.clone());
+        declarations
+    }
+
     /// Check if a CSS property is inheritable
     fn is_inheritable_property(&self, property: &str) -> bool {
         match property {
@@ -584,137 +570,55 @@ impl Stylesheet {
         }
     }
 
-    /// Apply default user agent styles for each element type
-    fn apply_default_styles(&self, style: &mut Style, tag_name: &str) {
+    /// Default user agent declarations for each element type
+    fn default_styles(tag_name: &str) -> &'static str {
         match tag_name {
             // Hyperlink
-            "a" => {
-                style.properties.insert("color".to_string(), "#0000ff".to_string());
-                style.properties.insert("text-decoration".to_string(), "underline".to_string());
-            }
+            "a" => "color: #0000ff; text-decoration: underline",
             // Text formatting
-            "b" | "strong" => {
-                style.properties.insert("font-weight".to_string(), "bold".to_string());
-            }
-            "i" | "em" => {
-                style.properties.insert("font-style".to_string(), "italic".to_string());
-            }
-            "u" => {
-                style.properties.insert("text-decoration".to_string(), "underline".to_string());
-            }
-            "s" | "del" => {
-                style.properties.insert("text-decoration".to_string(), "line-through".to_string());
-            }
-            "code" => {
-                style.properties.insert("font-family".to_string(), "monospace".to_string());
-            }
-            "pre" => {
-                style.properties.insert("font-family".to_string(), "monospace".to_string());
-                style.properties.insert("margin".to_string(), "1em 0.5em".to_string());
-            }
+            "b" | "strong" => "font-weight: bold",
+            "i" | "em" => "font-style: italic",
+            "u" => "text-decoration: underline",
+            "s" | "del" => "text-decoration: line-through",
+            "code" => "font-family: monospace",
+            "pre" => "font-family: monospace; margin: 1em 0.5em",
             // Headings - per HTML spec default margins
-            "h1" => {
-                style.properties.insert("font-size".to_string(), "2em".to_string());
-                style.properties.insert("font-weight".to_string(), "bold".to_string());
-                style.properties.insert("margin".to_string(), "0.3em 0.5em".to_string());
-            }
-            "h2" => {
-                style.properties.insert("font-size".to_string(), "1.5em".to_string());
-                style.properties.insert("font-weight".to_string(), "bold".to_string());
-                style.properties.insert("margin".to_string(), "0.25em 0.5em".to_string());
-            }
-            "h3" => {
-                style.properties.insert("font-size".to_string(), "1.17em".to_string());
-                style.properties.insert("font-weight".to_string(), "bold".to_string());
-                style.properties.insert("margin".to_string(), "0.2em 0.5em".to_string());
-            }
-            "h4" => {
-                style.properties.insert("font-size".to_string(), "1em".to_string());
-                style.properties.insert("font-weight".to_string(), "bold".to_string());
-                style.properties.insert("margin".to_string(), "0.2em 0.5em".to_string());
-            }
-            "h5" => {
-                style.properties.insert("font-size".to_string(), "0.83em".to_string());
-                style.properties.insert("font-weight".to_string(), "bold".to_string());
-                style.properties.insert("margin".to_string(), "0.2em 0.5em".to_string());
-            }
-            "h6" => {
-                style.properties.insert("font-size".to_string(), "0.67em".to_string());
-                style.properties.insert("font-weight".to_string(), "bold".to_string());
-                style.properties.insert("margin".to_string(), "0.2em 0.5em".to_string());
-            }
+            "h1" => "font-size: 2em; font-weight: bold; margin: 0.3em 0.5em",
+            "h2" => "font-size: 1.5em; font-weight: bold; margin: 0.25em 0.5em",
+            "h3" => "font-size: 1.17em; font-weight: bold; margin: 0.2em 0.5em",
+            "h4" => "font-size: 1em; font-weight: bold; margin: 0.2em 0.5em",
+            "h5" => "font-size: 0.83em; font-weight: bold; margin: 0.2em 0.5em",
+            "h6" => "font-size: 0.67em; font-weight: bold; margin: 0.2em 0.5em",
             // Paragraph
-            "p" => {
-                style.properties.insert("margin".to_string(), "0.3em 0.5em".to_string());
-            }
+            "p" => "margin: 0.3em 0.5em",
             // Lists - with horizontal margins and left padding for indentation
-            "ul" | "ol" => {
-                style.properties.insert("margin".to_string(), "0.3em 0.5em".to_string());
-                style.properties.insert("padding-left".to_string(), "40px".to_string());
-            }
-            "li" => {
-                // li typically has no margin by default, inherits from ul/ol
-                style.properties.insert("margin".to_string(), "0".to_string());
-            }
-            "dl" => {
-                style.properties.insert("margin".to_string(), "0.3em 0.5em".to_string());
-            }
-            "dt" => {
-                // Definition term - bold
-                style.properties.insert("font-weight".to_string(), "bold".to_string());
-            }
-            "dd" => {
-                // Definition data - indented with left margin
-                style.properties.insert("margin-left".to_string(), "2em".to_string());
-            }
+            "ul" | "ol" => "margin: 0.3em 0.5em; padding-left: 40px",
+            // li typically has no margin by default, inherits from ul/ol
+            "li" => "margin: 0",
+            "dl" => "margin: 0.3em 0.5em",
+            // Definition term - bold
+            "dt" => "font-weight: bold",
+            // Definition data - indented with left margin
+            "dd" => "margin-left: 2em",
             // Block elements - blockquote with left margin
-            "blockquote" => {
-                style.properties.insert("margin".to_string(), "0.3em 0 0.3em 2em".to_string());
-            }
-            "hr" => {
-                style.properties.insert("margin".to_string(), "0.3em 0.5em".to_string());
-                style.properties.insert("border".to_string(), "1px solid #ccc".to_string());
-            }
-            "address" => {
-                style.properties.insert("margin".to_string(), "0.3em 0.5em".to_string());
-                style.properties.insert("font-style".to_string(), "italic".to_string());
-            }
+            "blockquote" => "margin: 0.3em 0 0.3em 2em",
+            "hr" => "margin: 0.3em 0.5em; border: 1px solid #ccc",
+            "address" => "margin: 0.3em 0.5em; font-style: italic",
             // HTML5 semantic elements - minimal/no margins
-            "article" | "aside" | "section" | "header" | "footer" | "nav" | "main" => {
-                style.properties.insert("margin".to_string(), "0".to_string());
-            }
-            "figure" => {
-                // Figures have left/right margins for visual separation
-                style.properties.insert("margin".to_string(), "0.3em 2em".to_string());
-            }
-            "figcaption" => {
-                style.properties.insert("font-style".to_string(), "italic".to_string());
-                style.properties.insert("margin".to_string(), "0".to_string());
-            }
+            "article" | "aside" | "section" | "header" | "footer" | "nav" | "main" => "margin: 0",
+            // Figures have left/right margins for visual separation
+            "figure" => "margin: 0.3em 2em",
+            "figcaption" => "font-style: italic; margin: 0",
             // Form elements
-            "form" => {
-                style.properties.insert("margin".to_string(), "0.3em 0.5em".to_string());
-            }
-            "fieldset" => {
-                style.properties.insert("margin".to_string(), "0.3em 0.5em".to_string());
-                style.properties.insert("padding".to_string(), "0.5em".to_string());
-                style.properties.insert("border".to_string(), "1px solid #ccc".to_string());
-            }
-            "legend" => {
-                style.properties.insert("padding".to_string(), "0 0.25em".to_string());
-            }
+            "form" => "margin: 0.3em 0.5em",
+            "fieldset" => "margin: 0.3em 0.5em; padding: 0.5em; border: 1px solid #ccc",
+            "legend" => "padding: 0 0.25em",
             // Table elements
-            "table" => {
-                style.properties.insert("margin".to_string(), "0.3em 0.5em".to_string());
-                style.properties.insert("border-collapse".to_string(), "collapse".to_string());
-            }
+            "table" => "margin: 0.3em 0.5em; border-collapse: collapse",
             // Body element
-            "body" => {
-                style.properties.insert("margin".to_string(), "8px".to_string());
-            }
-            _ => {
-                // No specific defaults for other elements
-            }
+            "body" => "margin: 8px",
+            // No specific defaults for other elements
+            _ => "",
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,523 @@
+// Typed CSS values
+// Spec Reference: https://www.w3.org/TR/css-values-4/
+//
+// Declarations are parsed into a CssValue once, when their Style is built, and
+// style and layout read the typed values from there. Lengths are resolved to
+// pixels in one place, `Length::to_px`, so every property accepts the same units.
+//
+// Status:
+// ✅ Lengths in absolute, font-relative and viewport units
+// ✅ Colors: hex, named colors, rgb()/rgba() and hsl()/hsla()
+// ✅ Keywords, numbers, percentages, strings, urls, functions and lists
+// ⚠️ em and % in font-size resolve against the 16px default, not the parent
+// ✅ calc(), min(), max() and clamp() lengths (see `calc`)
+// ✅ var(), substituted before values are parsed (see `variables`)
+
+use std::fmt;
+
+use super::calc::MathExpr;
+use super::Viewport;
+use crate::parser::css::{ComponentValue, CssParser, CssToken, CssTokenizer};
+
+/// Font size used when nothing else sets one
+pub const DEFAULT_FONT_SIZE: f32 = 16.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthUnit {
+    Px,
+    Em,
+    Rem,
+    Ex,
+    Ch,
+    Vw,
+    Vh,
+    Vmin,
+    Vmax,
+    Cm,
+    Mm,
+    Q,
+    In,
+    Pt,
+    Pc,
+}
+
+impl LengthUnit {
+    /// The unit for a dimension's unit text, ignoring ASCII case
+    pub fn from_unit(unit: &str) -> Option<Self> {
+        let unit = match unit.to_ascii_lowercase().as_str() {
+            "px" => LengthUnit::Px,
+            "em" => LengthUnit::Em,
+            "rem" => LengthUnit::Rem,
+            "ex" => LengthUnit::Ex,
+            "ch" => LengthUnit::Ch,
+            "vw" => LengthUnit::Vw,
+            "vh" => LengthUnit::Vh,
+            "vmin" => LengthUnit::Vmin,
+            "vmax" => LengthUnit::Vmax,
+            "cm" => LengthUnit::Cm,
+            "mm" => LengthUnit::Mm,
+            "q" => LengthUnit::Q,
+            "in" => LengthUnit::In,
+            "pt" => LengthUnit::Pt,
+            "pc" => LengthUnit::Pc,
+            _ => return None,
+        };
+        Some(unit)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LengthUnit::Px => "px",
+            LengthUnit::Em => "em",
+            LengthUnit::Rem => "rem",
+            LengthUnit::Ex => "ex",
+            LengthUnit::Ch => "ch",
+            LengthUnit::Vw => "vw",
+            LengthUnit::Vh => "vh",
+            LengthUnit::Vmin => "vmin",
+            LengthUnit::Vmax => "vmax",
+            LengthUnit::Cm => "cm",
+            LengthUnit::Mm => "mm",
+            LengthUnit::Q => "q",
+            LengthUnit::In => "in",
+            LengthUnit::Pt => "pt",
+            LengthUnit::Pc => "pc",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Length {
+    pub value: f32,
+    pub unit: LengthUnit,
+}
+
+/// What relative lengths are resolved against
+#[derive(Debug, Clone, Copy)]
+pub struct LengthContext {
+    /// The element's font size, for em, ex and ch
+    pub font_size: f32,
+    /// The root element's font size, for rem
+    pub root_font_size: f32,
+    pub viewport: Viewport,
+}
+
+impl Default for LengthContext {
+    fn default() -> Self {
+        Self {
+            font_size: DEFAULT_FONT_SIZE,
+            root_font_size: DEFAULT_FONT_SIZE,
+            viewport: Viewport::default(),
+        }
+    }
+}
+
+impl Length {
+    pub fn px(value: f32) -> Self {
+        Self { value, unit: LengthUnit::Px }
+    }
+
+    /// The length in CSS pixels (spec 6.2: 1in = 96px)
+    pub fn to_px(&self, context: &LengthContext) -> f32 {
+        let viewport = &context.viewport;
+        let scale = match self.unit {
+            LengthUnit::Px => 1.0,
+            LengthUnit::Em => context.font_size,
+            LengthUnit::Rem => context.root_font_size,
+            // Without font metrics, use the usual approximations of half an em
+            LengthUnit::Ex | LengthUnit::Ch => context.font_size / 2.0,
+            LengthUnit::Vw => viewport.width / 100.0,
+            LengthUnit::Vh => viewport.height / 100.0,
+            LengthUnit::Vmin => viewport.width.min(viewport.height) / 100.0,
+            LengthUnit::Vmax => viewport.width.max(viewport.height) / 100.0,
+            LengthUnit::Cm => 96.0 / 2.54,
+            LengthUnit::Mm => 96.0 / 25.4,
+            LengthUnit::Q => 96.0 / 101.6,
+            LengthUnit::In => 96.0,
+            LengthUnit::Pt => 96.0 / 72.0,
+            LengthUnit::Pc => 16.0,
+        };
+        self.value * scale
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+    pub const TRANSPARENT: Color = Color { r: 0, g: 0, b: 0, a: 0 };
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b, a: 255 }
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.a == 0
+    }
+
+    /// Parse the digits of a hex color: 3, 4, 6 or 8 hex digits
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).unwrap();
+        let pair = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        match hex.len() {
+            3 | 4 => {
+                let a = if hex.len() == 4 { digit(3) * 17 } else { 255 };
+                Some(Color { r: digit(0) * 17, g: digit(1) * 17, b: digit(2) * 17, a })
+            }
+            6 | 8 => {
+                let a = if hex.len() == 8 { pair(6) } else { 255 };
+                Some(Color { r: pair(0), g: pair(2), b: pair(4), a })
+            }
+            _ => None,
+        }
+    }
+
+    /// A named color, including `transparent`, ignoring ASCII case
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if name == "transparent" {
+            return Some(Color::TRANSPARENT);
+        }
+        let index = NAMED_COLORS.binary_search_by(|(n, _)| n.cmp(&name.as_str())).ok()?;
+        let rgb = NAMED_COLORS[index].1;
+        Some(Color::rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+    }
+
+    /// Parse `rgb()`, `rgba()`, `hsl()` or `hsla()` from their arguments, in
+    /// either the legacy comma syntax or the space syntax with `/ alpha`
+    fn from_function(name: &str, arguments: &[ComponentValue]) -> Option<Self> {
+        let mut channels = Vec::new();
+        for value in arguments {
+            match value {
+                ComponentValue::Token(CssToken::Whitespace | CssToken::Comma | CssToken::Delim('/')) => {}
+                ComponentValue::Token(CssToken::Number(n)) => channels.push((n.value, false)),
+                ComponentValue::Token(CssToken::Percentage(n)) => channels.push((n.value, true)),
+                ComponentValue::Token(CssToken::Dimension { value, unit }) if unit.eq_ignore_ascii_case("deg") => {
+                    channels.push((value.value, false))
+                }
+                _ => return None,
+            }
+        }
+        if channels.len() != 3 && channels.len() != 4 {
+            return None;
+        }
+        let alpha = channels.get(3).map_or(1.0, |&(a, percent)| if percent { a / 100.0 } else { a });
+        let a = (alpha.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let byte = |v: f32| v.clamp(0.0, 255.0).round() as u8;
+        match name {
+            "rgb" | "rgba" => {
+                let channel = |(v, percent): (f32, bool)| byte(if percent { v * 2.55 } else { v });
+                Some(Color { r: channel(channels[0]), g: channel(channels[1]), b: channel(channels[2]), a })
+            }
+            "hsl" | "hsla" => {
+                let (r, g, b) = hsl_to_rgb(channels[0].0, channels[1].0 / 100.0, channels[2].0 / 100.0);
+                Some(Color { r: byte(r * 255.0), g: byte(g * 255.0), b: byte(b * 255.0), a })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// CSS Color 4 §7.1: convert hue in degrees and saturation/lightness in 0..1 to rgb in 0..1
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> (f32, f32, f32) {
+    let hue = hue.rem_euclid(360.0);
+    let saturation = saturation.clamp(0.0, 1.0);
+    let lightness = lightness.clamp(0.0, 1.0);
+    let f = |n: f32| {
+        let k = (n + hue / 30.0) % 12.0;
+        let a = saturation * lightness.min(1.0 - lightness);
+        lightness - a * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0)
+    };
+    (f(0.0), f(8.0), f(4.0))
+}
+
+/// A parsed property value
+#[derive(Debug, Clone, PartialEq)]
+pub enum CssValue {
+    /// An identifier, as written
+    Keyword(String),
+    Length(Length),
+    Percentage(f32),
+    Number(f32),
+    /// A dimension that is not a length, like `45deg` or `2s`
+    Dimension { value: f32, unit: String },
+    Color(Color),
+    String(String),
+    Url(String),
+    /// Any other function; each argument is one comma-separated part
+    Function { name: String, arguments: Vec<CssValue> },
+    /// A math function like `calc(100% - 2em)`, evaluated when resolved
+    Math(Box<MathExpr>),
+    /// Space-separated values, like `1px solid red`
+    List(Vec<CssValue>),
+    /// Comma-separated values, like `Arial, sans-serif`
+    CommaList(Vec<CssValue>),
+    /// Anything else, kept as its serialized text
+    Other(String),
+}
+
+impl CssValue {
+    /// Parse a declaration value from its component values
+    pub fn parse(values: &[ComponentValue]) -> CssValue {
+        let mut parts: Vec<CssValue> = values
+            .split(|v| matches!(v, ComponentValue::Token(CssToken::Comma)))
+            .map(parse_space_separated)
+            .collect();
+        if parts.len() == 1 {
+            parts.pop().unwrap()
+        } else {
+            CssValue::CommaList(parts)
+        }
+    }
+
+    /// Parse a value from CSS text, like `"1px solid red"`
+    pub fn parse_str(text: &str) -> CssValue {
+        let values = CssParser::new(CssTokenizer::new(text).tokenize()).component_values();
+        CssValue::parse(&values)
+    }
+
+    /// Whether this is the given keyword, ignoring ASCII case
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, CssValue::Keyword(k) if k.eq_ignore_ascii_case(keyword))
+    }
+
+    pub fn as_keyword(&self) -> Option<&str> {
+        match self {
+            CssValue::Keyword(k) => Some(k),
+            _ => None,
+        }
+    }
+
+    /// The value as a color, resolving named colors
+    pub fn as_color(&self) -> Option<Color> {
+        match self {
+            CssValue::Color(color) => Some(*color),
+            CssValue::Keyword(name) => Color::from_name(name),
+            _ => None,
+        }
+    }
+
+    /// The value as a length in pixels. Percentages resolve against
+    /// `percent_basis` and unitless numbers are taken as pixels, as browsers do
+    /// in quirks mode.
+    pub fn to_px(&self, context: &LengthContext, percent_basis: f32) -> Option<f32> {
+        match self {
+            CssValue::Length(length) => Some(length.to_px(context)),
+            CssValue::Percentage(p) => Some(percent_basis * p / 100.0),
+            CssValue::Number(n) => Some(*n),
+            CssValue::Math(expr) => expr.to_px(context, percent_basis),
+            _ => None,
+        }
+    }
+
+    /// The values of a list, or the value itself if it is not a list
+    pub fn items(&self) -> &[CssValue] {
+        match self {
+            CssValue::List(items) | CssValue::CommaList(items) => items,
+            value => std::slice::from_ref(value),
+        }
+    }
+}
+
+fn parse_space_separated(values: &[ComponentValue]) -> CssValue {
+    let mut items: Vec<CssValue> = values.iter().filter(|v| !v.is_whitespace()).map(parse_component).collect();
+    if items.len() == 1 {
+        items.pop().unwrap()
+    } else {
+        CssValue::List(items)
+    }
+}
+
+fn parse_component(value: &ComponentValue) -> CssValue {
+    match value {
+        ComponentValue::Token(token) => match token {
+            CssToken::Ident(name) => CssValue::Keyword(name.clone()),
+            CssToken::Number(n) => CssValue::Number(n.value),
+            CssToken::Percentage(n) => CssValue::Percentage(n.value),
+            CssToken::Dimension { value, unit } => match LengthUnit::from_unit(unit) {
+                Some(unit) => CssValue::Length(Length { value: value.value, unit }),
+                None => CssValue::Dimension { value: value.value, unit: unit.to_ascii_lowercase() },
+            },
+            CssToken::Hash { value, .. } => match Color::from_hex(value) {
+                Some(color) => CssValue::Color(color),
+                None => CssValue::Other(token.to_string()),
+            },
+            CssToken::String(s) => CssValue::String(s.clone()),
+            CssToken::Url(url) => CssValue::Url(url.clone()),
+            token => CssValue::Other(token.to_string()),
+        },
+        ComponentValue::Function { name, value } => {
+            let name = name.to_ascii_lowercase();
+            if let Some(expr) = MathExpr::parse_function(&name, value) {
+                return CssValue::Math(Box::new(expr));
+            }
+            if let Some(color) = Color::from_function(&name, value) {
+                return CssValue::Color(color);
+            }
+            let arguments: Vec<CssValue> = value
+                .split(|v| matches!(v, ComponentValue::Token(CssToken::Comma)))
+                .map(parse_space_separated)
+                .collect();
+            match arguments.as_slice() {
+                [CssValue::String(url)] if name == "url" => CssValue::Url(url.clone()),
+                _ => CssValue::Function { name, arguments },
+            }
+        }
+        block => CssValue::Other(block.to_string()),
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.a == 255 {
+            write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+        } else {
+            write!(f, "rgba({}, {}, {}, {})", self.r, self.g, self.b, self.a as f32 / 255.0)
+        }
+    }
+}
+
+impl fmt::Display for CssValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter<'_>, items: &[CssValue], separator: &str| {
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    write!(f, "{}", separator)?;
+                }
+                write!(f, "{}", item)?;
+            }
+            Ok(())
+        };
+        match self {
+            CssValue::Keyword(k) => write!(f, "{}", k),
+            CssValue::Length(length) => write!(f, "{}{}", length.value, length.unit.as_str()),
+            CssValue::Percentage(p) => write!(f, "{}%", p),
+            CssValue::Number(n) => write!(f, "{}", n),
+            CssValue::Dimension { value, unit } => write!(f, "{}{}", value, unit),
+            CssValue::Color(color) => write!(f, "{}", color),
+            CssValue::String(s) => write!(f, "{}", CssToken::String(s.clone())),
+            CssValue::Url(url) => write!(f, "url({})", CssToken::String(url.clone())),
+            CssValue::Function { name, arguments } => {
+                write!(f, "{}(", name)?;
+                join(f, arguments, ", ")?;
+                write!(f, ")")
+            }
+            CssValue::List(items) => join(f, items, " "),
+            CssValue::CommaList(items) => join(f, items, ", "),
+            CssValue::Math(expr) => write!(f, "{}", expr),
+            CssValue::Other(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// CSS Color 4 §6.1 named colors, sorted by name
+const NAMED_COLORS: &[(&str, u32)] = &[
+    ("aliceblue", 0xf0f8ff), ("antiquewhite", 0xfaebd7), ("aqua", 0x00ffff), ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff), ("beige", 0xf5f5dc), ("bisque", 0xffe4c4), ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd), ("blue", 0x0000ff), ("blueviolet", 0x8a2be2), ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887), ("cadetblue", 0x5f9ea0), ("chartreuse", 0x7fff00), ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50), ("cornflowerblue", 0x6495ed), ("cornsilk", 0xfff8dc), ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff), ("darkblue", 0x00008b), ("darkcyan", 0x008b8b), ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9), ("darkgreen", 0x006400), ("darkgrey", 0xa9a9a9), ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b), ("darkolivegreen", 0x556b2f), ("darkorange", 0xff8c00), ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000), ("darksalmon", 0xe9967a), ("darkseagreen", 0x8fbc8f), ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f), ("darkslategrey", 0x2f4f4f), ("darkturquoise", 0x00ced1), ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493), ("deepskyblue", 0x00bfff), ("dimgray", 0x696969), ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff), ("firebrick", 0xb22222), ("floralwhite", 0xfffaf0), ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff), ("gainsboro", 0xdcdcdc), ("ghostwhite", 0xf8f8ff), ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520), ("gray", 0x808080), ("green", 0x008000), ("greenyellow", 0xadff2f),
+    ("grey", 0x808080), ("honeydew", 0xf0fff0), ("hotpink", 0xff69b4), ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082), ("ivory", 0xfffff0), ("khaki", 0xf0e68c), ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5), ("lawngreen", 0x7cfc00), ("lemonchiffon", 0xfffacd), ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080), ("lightcyan", 0xe0ffff), ("lightgoldenrodyellow", 0xfafad2), ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90), ("lightgrey", 0xd3d3d3), ("lightpink", 0xffb6c1), ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa), ("lightskyblue", 0x87cefa), ("lightslategray", 0x778899), ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de), ("lightyellow", 0xffffe0), ("lime", 0x00ff00), ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6), ("magenta", 0xff00ff), ("maroon", 0x800000), ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd), ("mediumorchid", 0xba55d3), ("mediumpurple", 0x9370db), ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee), ("mediumspringgreen", 0x00fa9a), ("mediumturquoise", 0x48d1cc), ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970), ("mintcream", 0xf5fffa), ("mistyrose", 0xffe4e1), ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead), ("navy", 0x000080), ("oldlace", 0xfdf5e6), ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23), ("orange", 0xffa500), ("orangered", 0xff4500), ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa), ("palegreen", 0x98fb98), ("paleturquoise", 0xafeeee), ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5), ("peachpuff", 0xffdab9), ("peru", 0xcd853f), ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd), ("powderblue", 0xb0e0e6), ("purple", 0x800080), ("rebeccapurple", 0x663399),
+    ("red", 0xff0000), ("rosybrown", 0xbc8f8f), ("royalblue", 0x4169e1), ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072), ("sandybrown", 0xf4a460), ("seagreen", 0x2e8b57), ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d), ("silver", 0xc0c0c0), ("skyblue", 0x87ceeb), ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090), ("slategrey", 0x708090), ("snow", 0xfffafa), ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4), ("tan", 0xd2b48c), ("teal", 0x008080), ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347), ("turquoise", 0x40e0d0), ("violet", 0xee82ee), ("wheat", 0xf5deb3),
+    ("white", 0xffffff), ("whitesmoke", 0xf5f5f5), ("yellow", 0xffff00), ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_values() {
+        assert_eq!(CssValue::parse_str("12PX"), CssValue::Length(Length::px(12.0)));
+        assert_eq!(
+            CssValue::parse_str("1px solid red"),
+            CssValue::List(vec![
+                CssValue::Length(Length::px(1.0)),
+                CssValue::Keyword("solid".into()),
+                CssValue::Keyword("red".into()),
+            ])
+        );
+        assert_eq!(
+            CssValue::parse_str("\"Times New Roman\", serif"),
+            CssValue::CommaList(vec![CssValue::String("Times New Roman".into()), CssValue::Keyword("serif".into())])
+        );
+        assert_eq!(CssValue::parse_str("url('a b.png')"), CssValue::Url("a b.png".into()));
+        assert_eq!(CssValue::parse_str("45deg"), CssValue::Dimension { value: 45.0, unit: "deg".into() });
+        assert_eq!(
+            CssValue::parse_str("translate(1px, 50%)"),
+            CssValue::Function {
+                name: "translate".into(),
+                arguments: vec![CssValue::Length(Length::px(1.0)), CssValue::Percentage(50.0)],
+            }
+        );
+        assert_eq!(CssValue::parse_str("12px/1.5 serif").to_string(), "12px / 1.5 serif");
+    }
+
+    #[test]
+    fn test_colors() {
+        let parse = |text| CssValue::parse_str(text).as_color();
+        assert_eq!(parse("#0a0"), Some(Color::rgb(0, 170, 0)));
+        assert_eq!(parse("#FF000080"), Some(Color { r: 255, g: 0, b: 0, a: 128 }));
+        assert_eq!(parse("RebeccaPurple"), Some(Color::rgb(102, 51, 153)));
+        assert_eq!(parse("transparent"), Some(Color::TRANSPARENT));
+        assert_eq!(parse("rgb(255, 0, 51)"), Some(Color::rgb(255, 0, 51)));
+        assert_eq!(parse("rgb(100% 0% 20% / 50%)"), Some(Color { r: 255, g: 0, b: 51, a: 128 }));
+        assert_eq!(parse("rgba(0,0,0,0)"), Some(Color::TRANSPARENT));
+        assert_eq!(parse("hsl(120, 100%, 25%)"), Some(Color::rgb(0, 128, 0)));
+        assert_eq!(parse("#12345"), None);
+        assert_eq!(parse("bold"), None);
+    }
+
+    #[test]
+    fn test_lengths_resolve_consistently() {
+        let context = LengthContext { font_size: 20.0, root_font_size: 10.0, viewport: Viewport::new(1000.0, 500.0) };
+        let px = |text| CssValue::parse_str(text).to_px(&context, 200.0);
+        assert_eq!(px("2em"), Some(40.0));
+        assert_eq!(px("2rem"), Some(20.0));
+        assert_eq!(px("10vw"), Some(100.0));
+        assert_eq!(px("10vmin"), Some(50.0));
+        assert_eq!(px("1in"), Some(96.0));
+        assert_eq!(px("12pt"), Some(16.0));
+        assert_eq!(px("25%"), Some(50.0));
+        assert_eq!(px("7"), Some(7.0));
+        assert_eq!(px("auto"), None);
+    }
+}
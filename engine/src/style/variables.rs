@@ -0,0 +1,239 @@
+// Custom properties and var() substitution
+// Spec Reference: https://www.w3.org/TR/css-variables-1/
+//
+// Custom properties (`--name`) hold their value as component values. They are
+// always inherited, and var() references are substituted at computed-value
+// time, once the element's custom properties are known.
+//
+// Status:
+// ✅ Custom properties, inherited, with case-sensitive names
+// ✅ var() in custom properties and in any other property, with fallbacks
+// ✅ Dependency cycles make every custom property in the cycle invalid
+// ✅ Invalid at computed-value time declarations behave as `unset`
+// ❌ @property registrations (all custom properties are unregistered)
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::parser::css::{ComponentValue, CssToken};
+
+/// The computed value of a custom property, after var() substitution
+pub type CustomValue = Rc<Vec<ComponentValue>>;
+
+pub fn is_custom_property(property: &str) -> bool {
+    property.starts_with("--")
+}
+
+/// Whether any var() function appears in the values, at any depth
+pub fn contains_var(values: &[ComponentValue]) -> bool {
+    values.iter().any(|value| match value {
+        ComponentValue::Function { name, value } => name.eq_ignore_ascii_case("var") || contains_var(value),
+        ComponentValue::Block { value, .. } => contains_var(value),
+        ComponentValue::Token(_) => false,
+    })
+}
+
+/// Compute the custom properties declared on an element. `custom` starts as
+/// the inherited values and is updated in place; declarations that are
+/// invalid at computed-value time remove the property.
+pub(crate) fn resolve_custom_properties(custom: &mut HashMap<String, CustomValue>, declared: &HashMap<&str, &[ComponentValue]>) {
+    let mut resolver = Resolver {
+        declared,
+        inherited: custom,
+        resolved: HashMap::new(),
+        stack: Vec::new(),
+        cyclic: HashSet::new(),
+    };
+    let mut names: Vec<&str> = declared.keys().copied().collect();
+    names.sort_unstable();
+    for name in &names {
+        resolver.resolve(name);
+    }
+    let resolved = resolver.resolved;
+    for (name, value) in resolved {
+        match value {
+            Some(value) => custom.insert(name.to_string(), value),
+            None => custom.remove(name),
+        };
+    }
+}
+
+/// Replace every var() in the values with the custom property it names, or
+/// its fallback. None if a reference can't be resolved, which makes the
+/// declaration invalid at computed-value time.
+pub fn substitute(values: &[ComponentValue], custom: &HashMap<String, CustomValue>) -> Option<Vec<ComponentValue>> {
+    substitute_with(values, &mut |name| custom.get(name).cloned())
+}
+
+fn substitute_with(
+    values: &[ComponentValue],
+    lookup: &mut dyn FnMut(&str) -> Option<CustomValue>,
+) -> Option<Vec<ComponentValue>> {
+    let mut result = Vec::with_capacity(values.len());
+    for value in values {
+        match value {
+            ComponentValue::Function { name, value } if name.eq_ignore_ascii_case("var") => {
+                let (property, fallback) = parse_var(value)?;
+                match lookup(property) {
+                    Some(custom) => result.extend(custom.iter().cloned()),
+                    None => result.extend(substitute_with(fallback?, lookup)?),
+                }
+            }
+            ComponentValue::Function { name, value } => result.push(ComponentValue::Function {
+                name: name.clone(),
+                value: substitute_with(value, lookup)?,
+            }),
+            ComponentValue::Block { open, value } => result.push(ComponentValue::Block {
+                open: *open,
+                value: substitute_with(value, lookup)?,
+            }),
+            ComponentValue::Token(_) => result.push(value.clone()),
+        }
+    }
+    Some(result)
+}
+
+/// The arguments of var(): a custom property name, then optionally a comma
+/// and a fallback, which may be empty. None if the syntax is invalid.
+fn parse_var(arguments: &[ComponentValue]) -> Option<(&str, Option<&[ComponentValue]>)> {
+    let start = arguments.iter().position(|v| !v.is_whitespace())?;
+    let ComponentValue::Token(CssToken::Ident(name)) = &arguments[start] else {
+        return None;
+    };
+    if !is_custom_property(name) {
+        return None;
+    }
+    let rest = &arguments[start + 1..];
+    let comma = rest.iter().position(|v| matches!(v, ComponentValue::Token(CssToken::Comma)));
+    let before_comma = &rest[..comma.unwrap_or(rest.len())];
+    if !before_comma.iter().all(ComponentValue::is_whitespace) {
+        return None;
+    }
+    let fallback = comma.map(|comma| trim_whitespace(&rest[comma + 1..]));
+    Some((name, fallback))
+}
+
+fn trim_whitespace(values: &[ComponentValue]) -> &[ComponentValue] {
+    let start = values.iter().position(|v| !v.is_whitespace()).unwrap_or(values.len());
+    let end = values.iter().rposition(|v| !v.is_whitespace()).map_or(start, |end| end + 1);
+    &values[start..end]
+}
+
+/// Resolves custom properties depth-first, following var() references
+struct Resolver<'a, 'b> {
+    declared: &'a HashMap<&'a str, &'a [ComponentValue]>,
+    inherited: &'b HashMap<String, CustomValue>,
+    /// Properties declared on the element that are computed; None when invalid
+    resolved: HashMap<&'a str, Option<CustomValue>>,
+    /// Properties being resolved, outermost first
+    stack: Vec<&'a str>,
+    /// Properties found to be part of a dependency cycle
+    cyclic: HashSet<&'a str>,
+}
+
+impl<'a> Resolver<'a, '_> {
+    fn resolve(&mut self, name: &str) -> Option<CustomValue> {
+        let Some((&name, &declared)) = self.declared.get_key_value(name) else {
+            return self.inherited.get(name).cloned();
+        };
+        if let Some(resolved) = self.resolved.get(name) {
+            return resolved.clone();
+        }
+        if let Some(position) = self.stack.iter().position(|&entry| entry == name) {
+            self.cyclic.extend(&self.stack[position..]);
+            return None;
+        }
+
+        self.stack.push(name);
+        let value = if contains_var(declared) {
+            substitute_with(declared, &mut |reference| self.resolve(reference)).map(Rc::new)
+        } else {
+            Some(Rc::new(declared.to_vec()))
+        };
+        self.stack.pop();
+
+        let value = value.filter(|_| !self.cyclic.contains(name));
+        self.resolved.insert(name, value.clone());
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::css::parser::serialize;
+    use crate::parser::css::{CssParser, CssTokenizer};
+    use crate::parser::html::tree_builder::HtmlParser;
+    use crate::style::Stylesheet;
+
+    fn values(css: &str) -> Vec<ComponentValue> {
+        CssParser::new(CssTokenizer::new(css).tokenize()).component_values()
+    }
+
+    fn resolve(inherited: &[(&str, &str)], declared: &[(&str, &str)]) -> HashMap<String, String> {
+        let mut custom: HashMap<String, CustomValue> =
+            inherited.iter().map(|(name, css)| (name.to_string(), Rc::new(values(css)))).collect();
+        let declared_values: Vec<(&str, Vec<ComponentValue>)> = declared.iter().map(|(name, css)| (*name, values(css))).collect();
+        let declared: HashMap<&str, &[ComponentValue]> =
+            declared_values.iter().map(|(name, values)| (*name, values.as_slice())).collect();
+        resolve_custom_properties(&mut custom, &declared);
+        custom.into_iter().map(|(name, value)| (name, serialize(&value))).collect()
+    }
+
+    #[test]
+    fn test_substitution_and_fallbacks() {
+        let custom = resolve(
+            &[("--base", "4px")],
+            &[
+                ("--a", "calc(var(--base) * 2)"),
+                ("--b", "var(--missing, var(--also-missing, red))"),
+                ("--c", "var(--missing)"),
+                ("--d", "var(--missing,)"),
+            ],
+        );
+        assert_eq!(custom["--a"], "calc(4px * 2)");
+        assert_eq!(custom["--b"], "red");
+        assert!(!custom.contains_key("--c"));
+        assert_eq!(custom["--d"], "");
+        assert_eq!(custom["--base"], "4px");
+    }
+
+    #[test]
+    fn test_cycles() {
+        let custom = resolve(
+            &[("--a", "green")],
+            &[
+                ("--a", "var(--b, red)"),
+                ("--b", "var(--a)"),
+                ("--c", "var(--a, blue)"),
+                ("--self", "var(--self, red)"),
+            ],
+        );
+        assert!(!custom.contains_key("--a"));
+        assert!(!custom.contains_key("--b"));
+        assert!(!custom.contains_key("--self"));
+        assert_eq!(custom["--c"], "blue");
+    }
+
+    #[test]
+    fn test_var_in_stylesheet() {
+        let css = ":root { --Main-Color: rgb(0, 128, 0); --gap: 2px }
+            div { --gap: 6px; margin: var(--gap) }
+            p { color: var(--Main-Color); background-color: var(--main-color); margin: var(--gap) var(--unset) }";
+        let mut stylesheet = Stylesheet::new();
+        stylesheet.add_items(CssParser::new(CssTokenizer::new(css).tokenize()).parse());
+        let dom = HtmlParser::new("<body style='color: red'><div><p>x</p></div></body>").parse();
+        let div = dom.query_selector("div").unwrap();
+        let p = dom.query_selector("p").unwrap();
+
+        let style = stylesheet.compute_style(&dom, p);
+        assert_eq!(style.get_color(), (0, 128, 0));
+        // Invalid at computed-value time: background-color is reset to its
+        // initial value and margin is not inherited
+        assert_eq!(style.get("background-color"), None);
+        assert_eq!(style.get("margin-top"), None);
+        assert_eq!(style.get("margin-left"), None);
+        assert_eq!(style.get_custom_property("--gap").map(|v| serialize(v)), Some("6px".to_string()));
+        assert_eq!(stylesheet.compute_style(&dom, div).get("margin-left").unwrap().to_string(), "6px");
+    }
+}
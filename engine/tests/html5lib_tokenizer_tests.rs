@@ -0,0 +1,209 @@
+// Conformance harness for the html5lib-tests tokenizer fixtures
+//
+// The fixtures are not vendored. Point HTML5LIB_TESTS_DIR at a checkout of
+// https://github.com/html5lib/html5lib-tests and every `tokenizer/*.test`
+// file is run:
+//
+//   HTML5LIB_TESTS_DIR=../html5lib-tests cargo test --test html5lib_tokenizer_tests
+//
+// Without the variable the test is skipped. Parse errors are not compared,
+// only the emitted tokens.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use grob_engine::parser::html::tokenizer::{Token, Tokenizer, TokenizerState};
+use serde_json::{json, Map, Value};
+
+fn fixtures_dir() -> Option<PathBuf> {
+    let root = std::env::var_os("HTML5LIB_TESTS_DIR")?;
+    let dir = Path::new(&root).join("tokenizer");
+    if dir.is_dir() {
+        Some(dir)
+    } else {
+        eprintln!("HTML5LIB_TESTS_DIR has no tokenizer/ directory: {}", dir.display());
+        None
+    }
+}
+
+fn initial_state(name: &str) -> Option<TokenizerState> {
+    match name {
+        "Data state" => Some(TokenizerState::Data),
+        "PLAINTEXT state" => Some(TokenizerState::PlainText),
+        "RCDATA state" => Some(TokenizerState::RcData),
+        "RAWTEXT state" => Some(TokenizerState::RawText),
+        "Script data state" => Some(TokenizerState::ScriptData),
+        "CDATA section state" => Some(TokenizerState::CdataSection),
+        _ => None,
+    }
+}
+
+/// Undo the `\uXXXX` escaping used by `doubleEscaped` tests.
+/// Returns None for lone surrogates, which a Rust string cannot hold.
+fn unescape(s: &str) -> Option<String> {
+    let mut out = String::new();
+    let chars: Vec<char> = s.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == '\\' && chars.get(i + 1) == Some(&'u') && i + 6 <= chars.len() {
+            let hex: String = chars[i + 2..i + 6].iter().collect();
+            let code = u32::from_str_radix(&hex, 16).ok()?;
+            out.push(char::from_u32(code)?);
+            i += 6;
+        } else {
+            out.push(chars[i]);
+            i += 1;
+        }
+    }
+    Some(out)
+}
+
+fn unescape_value(value: &Value) -> Option<Value> {
+    Some(match value {
+        Value::String(s) => Value::String(unescape(s)?),
+        Value::Array(items) => Value::Array(items.iter().map(unescape_value).collect::<Option<_>>()?),
+        Value::Object(map) => {
+            let mut out = Map::new();
+            for (k, v) in map {
+                out.insert(unescape(k)?, unescape_value(v)?);
+            }
+            Value::Object(out)
+        }
+        other => other.clone(),
+    })
+}
+
+/// Drop parse error markers (older fixture format) and merge adjacent character runs
+fn normalize(tokens: Vec<Value>) -> Vec<Value> {
+    let mut out: Vec<Value> = Vec::new();
+    for token in tokens {
+        if token == "ParseError" {
+            continue;
+        }
+        if token[0] == "Character" {
+            if let Some(last) = out.last_mut() {
+                if last[0] == "Character" {
+                    let merged = format!("{}{}", last[1].as_str().unwrap(), token[1].as_str().unwrap());
+                    *last = json!(["Character", merged]);
+                    continue;
+                }
+            }
+        }
+        out.push(token);
+    }
+    out
+}
+
+fn token_to_json(token: &Token) -> Option<Value> {
+    Some(match token {
+        Token::Doctype { name, public_id, system_id, force_quirks } => {
+            json!(["DOCTYPE", name, public_id, system_id, !force_quirks])
+        }
+        Token::StartTag { name, attributes, self_closing } => {
+            let mut attrs = Map::new();
+            for attr in attributes {
+                attrs.insert(attr.name.clone(), Value::String(attr.value.clone()));
+            }
+            if *self_closing {
+                json!(["StartTag", name, attrs, true])
+            } else {
+                json!(["StartTag", name, attrs])
+            }
+        }
+        Token::EndTag { name } => json!(["EndTag", name]),
+        Token::Comment(data) => json!(["Comment", data]),
+        Token::Character(c) => json!(["Character", c.to_string()]),
+        Token::Eof => return None,
+    })
+}
+
+fn run_tokenizer(input: &str, state: TokenizerState, last_start_tag: Option<&str>) -> Vec<Value> {
+    let mut tokenizer = Tokenizer::new(input);
+    tokenizer.set_state(state);
+    tokenizer.set_last_start_tag(last_start_tag);
+    let tokens = tokenizer.tokenize();
+    normalize(tokens.iter().filter_map(token_to_json).collect())
+}
+
+/// Run one fixture; returns failure descriptions
+fn run_test(file: &str, test: &Value) -> Vec<String> {
+    let description = test["description"].as_str().unwrap_or("");
+    let double_escaped = test["doubleEscaped"].as_bool().unwrap_or(false);
+
+    let (input, output) = if double_escaped {
+        match (unescape(test["input"].as_str().unwrap()), unescape_value(&test["output"])) {
+            (Some(input), Some(output)) => (input, output),
+            // Lone surrogates can't be represented
+            _ => return Vec::new(),
+        }
+    } else {
+        (test["input"].as_str().unwrap().to_string(), test["output"].clone())
+    };
+    let expected = normalize(output.as_array().cloned().unwrap_or_default());
+
+    let states: Vec<String> = match test["initialStates"].as_array() {
+        Some(states) => states.iter().filter_map(|s| s.as_str().map(String::from)).collect(),
+        None => vec!["Data state".to_string()],
+    };
+
+    let mut failures = Vec::new();
+    for state_name in states {
+        let state = match initial_state(&state_name) {
+            Some(state) => state,
+            None => {
+                failures.push(format!("{}: {} - unknown initial state {}", file, description, state_name));
+                continue;
+            }
+        };
+        let actual = run_tokenizer(&input, state, test["lastStartTag"].as_str());
+        if actual != expected {
+            failures.push(format!(
+                "{}: {} ({})\n  input:    {:?}\n  expected: {}\n  actual:   {}",
+                file,
+                description,
+                state_name,
+                input,
+                Value::Array(expected.clone()),
+                Value::Array(actual)
+            ));
+        }
+    }
+    failures
+}
+
+#[test]
+fn html5lib_tokenizer_fixtures() {
+    let dir = match fixtures_dir() {
+        Some(dir) => dir,
+        None => {
+            eprintln!("Skipping html5lib tokenizer fixtures: HTML5LIB_TESTS_DIR not set");
+            return;
+        }
+    };
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(&dir)
+        .expect("readable fixtures directory")
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().map_or(false, |ext| ext == "test"))
+        .collect();
+    paths.sort();
+
+    let mut total = 0;
+    let mut failures = Vec::new();
+    for path in paths {
+        let file = path.file_name().unwrap().to_string_lossy().to_string();
+        let source = fs::read_to_string(&path).expect("readable fixture");
+        let json: Value = serde_json::from_str(&source).expect("valid fixture JSON");
+        // xmlViolationTests describe XML-coerced output, which is out of scope
+        for test in json["tests"].as_array().into_iter().flatten() {
+            total += 1;
+            failures.extend(run_test(&file, test));
+        }
+    }
+
+    eprintln!("html5lib tokenizer: {} tests, {} failures", total, failures.len());
+    for failure in failures.iter().take(50) {
+        eprintln!("{}", failure);
+    }
+    assert!(failures.is_empty(), "{} html5lib tokenizer fixtures failed", failures.len());
+}